@@ -12,7 +12,7 @@
 
 use image::codecs::png::PngEncoder;
 use image::{self, Pixel, Rgba, RgbaImage};
-use parley::layout::{Alignment, Glyph, GlyphRun, Layout, PositionedLayoutItem};
+use parley::layout::{Alignment, Glyph, GlyphRun, Layout, PositionedLayoutItem, StyleSpan};
 use parley::style::{FontStack, FontWeight, StyleProperty, TextStyle};
 use parley::{AlignmentOptions, FontContext, InlineBox, LayoutContext};
 use std::fs::File;
@@ -222,8 +222,6 @@ fn render_glyph_run(
     // Resolve properties of the GlyphRun
     let mut run_x = glyph_run.offset();
     let run_y = glyph_run.baseline();
-    let style = glyph_run.style();
-    let color = style.brush;
 
     // Get the "Run" from the "GlyphRun"
     let run = glyph_run.run();
@@ -245,35 +243,57 @@ fn render_glyph_run(
         .normalized_coords(normalized_coords)
         .build();
 
-    // Iterates over the glyphs in the GlyphRun
-    for glyph in glyph_run.glyphs() {
-        let glyph_x = run_x + glyph.x + (padding as f32);
-        let glyph_y = run_y - glyph.y + (padding as f32);
-        run_x += glyph.advance;
+    // Iterates over the glyphs in the GlyphRun. Glyphs with different brushes share a
+    // glyph run, so each span of glyphs is drawn with its own brush.
+    let glyphs: Vec<_> = glyph_run.glyphs().collect();
+    for span in glyph_run.style_spans() {
+        for glyph in &glyphs[span.glyph_range.clone()] {
+            let glyph_x = run_x + glyph.x + (padding as f32);
+            let glyph_y = run_y - glyph.y + (padding as f32);
+            run_x += glyph.advance;
 
-        render_glyph(img, &mut scaler, color, glyph, glyph_x, glyph_y);
+            render_glyph(img, &mut scaler, span.style.brush, *glyph, glyph_x, glyph_y);
+        }
     }
 
     // Draw decorations: underline & strikethrough
-    let style = glyph_run.style();
     let run_metrics = run.metrics();
-    if let Some(decoration) = &style.underline {
-        let offset = decoration.offset.unwrap_or(run_metrics.underline_offset);
-        let size = decoration.size.unwrap_or(run_metrics.underline_size);
-        render_decoration(img, glyph_run, decoration.brush, offset, size, padding);
-    }
-    if let Some(decoration) = &style.strikethrough {
-        let offset = decoration
-            .offset
-            .unwrap_or(run_metrics.strikethrough_offset);
-        let size = decoration.size.unwrap_or(run_metrics.strikethrough_size);
-        render_decoration(img, glyph_run, decoration.brush, offset, size, padding);
+    for span in glyph_run.style_spans() {
+        if let Some(decoration) = &span.style.underline {
+            let offset = decoration.offset.unwrap_or(run_metrics.underline_offset);
+            let size = decoration.size.unwrap_or(run_metrics.underline_size);
+            render_decoration(
+                img,
+                glyph_run,
+                &span,
+                decoration.brush,
+                offset,
+                size,
+                padding,
+            );
+        }
+        if let Some(decoration) = &span.style.strikethrough {
+            let offset = decoration
+                .offset
+                .unwrap_or(run_metrics.strikethrough_offset);
+            let size = decoration.size.unwrap_or(run_metrics.strikethrough_size);
+            render_decoration(
+                img,
+                glyph_run,
+                &span,
+                decoration.brush,
+                offset,
+                size,
+                padding,
+            );
+        }
     }
 }
 
 fn render_decoration(
     img: &mut RgbaImage,
     glyph_run: &GlyphRun<'_, ColorBrush>,
+    span: &StyleSpan<'_, ColorBrush>,
     brush: ColorBrush,
     offset: f32,
     width: f32,
@@ -281,8 +301,7 @@ fn render_decoration(
 ) {
     let y = glyph_run.baseline() - offset;
     for pixel_y in y as u32..(y + width) as u32 {
-        for pixel_x in glyph_run.offset() as u32..(glyph_run.offset() + glyph_run.advance()) as u32
-        {
+        for pixel_x in span.offset as u32..(span.offset + span.advance) as u32 {
             img.get_pixel_mut(pixel_x + padding, pixel_y + padding)
                 .blend(&brush.color);
         }
@@ -167,6 +167,24 @@ impl Collection {
         self.inner.append_fallbacks(key, families)
     }
 
+    /// Returns the family which is matched after the fallback families for
+    /// every script and locale, if any.
+    pub fn last_resort(&self) -> Option<FamilyId> {
+        self.inner.last_resort
+    }
+
+    /// Sets the family which is matched after the fallback families for
+    /// every script and locale.
+    ///
+    /// This is useful when system fonts are disabled, to ensure that text is
+    /// always rendered with a known font. Unlike generic families and
+    /// fallbacks, the last-resort family is not shared between clones of a
+    /// shared collection.
+    pub fn set_last_resort(&mut self, family: Option<FamilyId>) {
+        self.inner.generation = self.inner.generation.wrapping_add(1);
+        self.inner.last_resort = family;
+    }
+
     /// Returns an object for selecting fonts from this collection.
     pub fn query<'a>(&'a mut self, source_cache: &'a mut SourceCache) -> Query<'a> {
         Query::new(self, source_cache)
@@ -187,11 +205,20 @@ impl Collection {
     }
 
     /// Clears this collection. Un-registers all fonts previously registered via
-    /// [`Self::register_fonts`], and unsets all previously-set generic families
-    /// and fallbacks. This will not remove any system fonts.
+    /// [`Self::register_fonts`], and unsets all previously-set generic families,
+    /// fallbacks and the last-resort family. This will not remove any system fonts.
     pub fn clear(&mut self) {
         self.inner.clear();
     }
+
+    /// Returns a counter which changes whenever the contents of this collection
+    /// change, such as when fonts are registered or fallbacks are modified.
+    ///
+    /// This can be used to invalidate caches which depend on font selection.
+    pub fn generation(&mut self) -> u64 {
+        self.inner.sync_shared();
+        self.inner.generation
+    }
 }
 
 impl Default for Collection {
@@ -209,6 +236,8 @@ struct Inner {
     shared: Option<Arc<Shared>>,
     #[allow(unused)]
     shared_version: u64,
+    generation: u64,
+    last_resort: Option<FamilyId>,
     fallback_cache: FallbackCache,
 }
 
@@ -222,6 +251,8 @@ impl Inner {
             data: CommonData::default(),
             shared,
             shared_version: 0,
+            generation: 0,
+            last_resort: None,
             fallback_cache: Default::default(),
         }
     }
@@ -319,6 +350,7 @@ impl Inner {
         generic: GenericFamily,
         families: impl Iterator<Item = FamilyId>,
     ) {
+        self.generation = self.generation.wrapping_add(1);
         self.sync_shared();
         #[cfg(feature = "std")]
         if let Some(shared) = &self.shared {
@@ -342,6 +374,7 @@ impl Inner {
         generic: GenericFamily,
         families: impl Iterator<Item = FamilyId>,
     ) {
+        self.generation = self.generation.wrapping_add(1);
         self.sync_shared();
         #[cfg(feature = "std")]
         if let Some(shared) = &self.shared {
@@ -372,20 +405,24 @@ impl Inner {
             self.sync_shared();
             self.fallback_cache.reset();
             #[cfg(feature = "system")]
-            if let Some(families) = self.data.fallbacks.get(selector) {
-                self.fallback_cache.set(script, lang_key, families);
-            } else if let Some(system) = self.system.as_ref() {
-                // Some platforms don't need mut System
-                #[allow(unused_mut)]
-                let mut system = system.fonts.lock().unwrap();
-                if let Some(family) = system.fallback(selector) {
-                    self.data.fallbacks.set(selector, core::iter::once(family));
-                    self.fallback_cache.set(script, lang_key, &[family]);
+            if self.data.fallbacks.get(selector).is_none() {
+                if let Some(system) = self.system.as_ref() {
+                    // Some platforms don't need mut System
+                    #[allow(unused_mut)]
+                    let mut system = system.fonts.lock().unwrap();
+                    if let Some(family) = system.fallback(selector) {
+                        self.data.fallbacks.set(selector, core::iter::once(family));
+                    }
                 }
             }
-            #[cfg(not(feature = "system"))]
-            if let Some(families) = self.data.fallbacks.get(selector) {
-                self.fallback_cache.set(script, lang_key, families);
+            for family in self.data.fallbacks.ranked(selector) {
+                if !self.fallback_cache.families.contains(&family) {
+                    self.fallback_cache.families.push(family);
+                }
+            }
+            if !self.fallback_cache.families.is_empty() {
+                self.fallback_cache.script = Some(script);
+                self.fallback_cache.language = lang_key;
             }
         }
         self.fallback_cache.families.iter().copied()
@@ -398,6 +435,7 @@ impl Inner {
         key: impl Into<FallbackKey>,
         families: impl Iterator<Item = FamilyId>,
     ) -> bool {
+        self.generation = self.generation.wrapping_add(1);
         self.sync_shared();
         #[cfg(feature = "std")]
         if let Some(shared) = &self.shared {
@@ -417,6 +455,7 @@ impl Inner {
         key: impl Into<FallbackKey>,
         families: impl Iterator<Item = FamilyId>,
     ) -> bool {
+        self.generation = self.generation.wrapping_add(1);
         self.sync_shared();
         #[cfg(feature = "std")]
         if let Some(shared) = &self.shared {
@@ -439,6 +478,7 @@ impl Inner {
         data: Blob<u8>,
         info_override: Option<FontInfoOverride<'_>>,
     ) -> Vec<(FamilyId, Vec<FontInfo>)> {
+        self.generation = self.generation.wrapping_add(1);
         #[cfg(feature = "std")]
         if let Some(shared) = &self.shared {
             let result = shared
@@ -459,6 +499,8 @@ impl Inner {
     /// [`Self::register_fonts`], and unsets all previously-set generic families
     /// and fallbacks. This will not remove any system fonts.
     pub fn clear(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.last_resort = None;
         #[cfg(feature = "std")]
         if let Some(shared) = &self.shared {
             shared.data.lock().unwrap().clear();
@@ -480,6 +522,7 @@ impl Inner {
                 // of changes.
                 self.data = shared.data.lock().unwrap().clone();
                 self.shared_version = version;
+                self.generation = self.generation.wrapping_add(1);
                 self.fallback_cache.reset();
             }
         }
@@ -539,13 +582,6 @@ impl FallbackCache {
         self.language = None;
         self.families.clear();
     }
-
-    fn set(&mut self, script: Script, language: Option<&'static str>, families: &[FamilyId]) {
-        self.script = Some(script);
-        self.language = language;
-        self.families.clear();
-        self.families.extend_from_slice(families);
-    }
 }
 
 /// Data taken from the system font collection.
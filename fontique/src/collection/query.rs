@@ -86,6 +86,9 @@ impl<'a> Query<'a> {
     }
 
     /// Sets the script and locale for fallback fonts.
+    ///
+    /// The last-resort family of the collection, if any, is matched after the
+    /// fallback families.
     pub fn set_fallbacks(&mut self, key: impl Into<FallbackKey>) {
         let key = key.into();
         if self.fallbacks != Some(key) {
@@ -95,6 +98,18 @@ impl<'a> Query<'a> {
                     .fallback_families(key)
                     .map(CachedFamily::new),
             );
+            if let Some(last_resort) = self.collection.last_resort {
+                if !self
+                    .state
+                    .fallback_families
+                    .iter()
+                    .any(|family| family.id == last_resort)
+                {
+                    self.state
+                        .fallback_families
+                        .push(CachedFamily::new(last_resort));
+                }
+            }
             self.fallbacks = Some(key);
         }
     }
@@ -33,6 +33,37 @@ impl FallbackMap {
         }
     }
 
+    /// Returns the fallback families for the script of the given key, ranked
+    /// by how well their locale matches the key.
+    ///
+    /// The families for the requested locale come first, followed by those for
+    /// the default locale of the script and then those for its other locales,
+    /// as they may still cover text in the script. A family is repeated if it
+    /// is registered for several locales.
+    pub fn ranked(&self, key: impl Into<FallbackKey>) -> impl Iterator<Item = FamilyId> + '_ {
+        let key = key.into();
+        let entry = self.fallbacks.get(&key.script);
+        let requested: &[FamilyId] = if key.is_default() {
+            &[]
+        } else {
+            self.get(key).unwrap_or_default()
+        };
+        let default = entry
+            .and_then(|entry| entry.default.as_deref())
+            .unwrap_or_default();
+        let others = entry
+            .map(|entry| entry.others.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter(move |other| key.locale() != Some(other.0))
+            .flat_map(|other| other.1.iter().copied());
+        requested
+            .iter()
+            .copied()
+            .chain(default.iter().copied())
+            .chain(others)
+    }
+
     /// Inserts or replaces the fallback families for the given script and
     /// language.
     ///
@@ -292,3 +323,29 @@ fn canonical_locale(
     };
     Some((is_default, token))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{FallbackKey, FallbackMap};
+    use crate::FamilyId;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn ranked_prefers_requested_locale() {
+        let simplified = FamilyId::new();
+        let japanese = FamilyId::new();
+        let mut map = FallbackMap::default();
+        assert!(map.set(("Hani", "zh-Hans"), [simplified].into_iter()));
+        assert!(map.set(("Hani", "ja"), [japanese].into_iter()));
+
+        let ranked = |locale: &str| map.ranked(("Hani", locale)).collect::<Vec<_>>();
+        assert_eq!(ranked("zh-Hans"), [simplified, japanese]);
+        assert_eq!(ranked("ja"), [japanese, simplified]);
+        assert_eq!(
+            ranked("zh-TW"),
+            [simplified, japanese],
+            "locales without their own fallbacks prefer the script's default locale"
+        );
+        assert_eq!(map.ranked(FallbackKey::from("Arab")).count(), 0);
+    }
+}
@@ -3,7 +3,7 @@
 
 //! Model for a font.
 
-use super::attributes::{FontStyle, FontWeight, FontWidth};
+use super::attributes::{Attributes, FontStyle, FontWeight, FontWidth};
 use super::source::{SourceInfo, SourceKind};
 use super::{Blob, source_cache::SourceCache};
 use read_fonts::{FontRef, TableProvider as _, types::Tag};
@@ -356,6 +356,18 @@ impl Synthesis {
     }
 }
 
+impl Attributes {
+    /// Reads the attributes of the font at `index` in `data`, as used for matching.
+    ///
+    /// These are the attributes of the font's default instance, from its `OS/2` table,
+    /// or its `head` table if it has none. Returns `None` if the font can't be read.
+    pub fn from_font_data(data: &[u8], index: u32) -> Option<Self> {
+        let font = FontRef::from_index(data, index).ok()?;
+        let (width, style, weight) = read_attributes(&font);
+        Some(Self::new(width, style, weight))
+    }
+}
+
 fn read_attributes(font: &FontRef<'_>) -> (FontWidth, FontStyle, FontWeight) {
     use read_fonts::{
         TableProvider,
@@ -0,0 +1,311 @@
+// Copyright 2025 the Parley Authors
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Benchmarks of building, breaking and querying layouts.
+//!
+//! Run with `cargo bench -p parley`. Each case is run repeatedly for a fixed time and its
+//! mean duration is printed, so that the cases of a benchmark can be compared with each
+//! other. The fonts are those of the tests, so the results don't depend on the system.
+
+use std::borrow::Cow;
+use std::hint::black_box;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parley::fontique::{Blob, Collection, CollectionOptions};
+use parley::{
+    Alignment, AlignmentOptions, FontContext, FontStack, GlyphRun, Layout, LayoutContext, Line,
+    PositionedLayoutItem, StyleProperty, TextStyle,
+};
+
+const FONT_STACK: FontStack<'static> = FontStack::Source(Cow::Borrowed("Roboto, Noto Kufi Arabic"));
+
+const LOREM: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \
+    tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud \
+    exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat.";
+
+fn main() {
+    let mut fcx = font_context();
+    let mut lcx = LayoutContext::new();
+    paragraph_cache(&mut fcx, &mut lcx);
+    line_for_y(&mut fcx, &mut lcx);
+    decorated_runs(&mut fcx, &mut lcx);
+    visible_lines(&mut fcx, &mut lcx);
+    candidate_widths(&mut fcx, &mut lcx);
+    style_spans(&mut fcx, &mut lcx);
+    resize(&mut fcx, &mut lcx);
+}
+
+/// Rebuilds a document of 1,000 paragraphs after editing one of them, with and without
+/// the paragraph cache.
+fn paragraph_cache(fcx: &mut FontContext, lcx: &mut LayoutContext) {
+    let mut paragraphs: Vec<String> = (0..1000)
+        .map(|index| format!("Paragraph {index}. {LOREM}"))
+        .collect();
+    let text = paragraphs.join("\n");
+    paragraphs[500].push_str(" Edited.");
+    let edited = paragraphs.join("\n");
+
+    for (name, cached) in [("uncached", false), ("cached", true)] {
+        if cached {
+            lcx.enable_paragraph_cache(2048);
+        } else {
+            lcx.disable_paragraph_cache();
+        }
+        let mut is_edited = false;
+        bench(&format!("edit one of 1,000 paragraphs, {name}"), || {
+            is_edited = !is_edited;
+            let text = if is_edited { &edited } else { &text };
+            black_box(build(fcx, lcx, text, Some(600.)));
+        });
+    }
+    lcx.disable_paragraph_cache();
+}
+
+/// Finds the lines at 1,000 vertical positions in a layout of 10,000 lines, with
+/// `Layout::line_for_y` and with a linear scan over the lines.
+fn line_for_y(fcx: &mut FontContext, lcx: &mut LayoutContext) {
+    let text = (0..10_000)
+        .map(|index| format!("Line {index}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let layout = build(fcx, lcx, &text, None);
+    let ys: Vec<f32> = (0..1000)
+        .map(|index| layout.height() * index as f32 / 1000.)
+        .collect();
+
+    bench("line at y of 10,000 lines, line_for_y", || {
+        for &y in &ys {
+            black_box(layout.line_for_y(y));
+        }
+    });
+    bench("line at y of 10,000 lines, linear scan", || {
+        for &y in &ys {
+            black_box(layout.lines().position(|line| line.y_range().contains(&y)));
+        }
+    });
+}
+
+/// Counts the glyph runs of a document with every other word underlined, as for spell
+/// checking, and times iterating over the style spans of its glyph runs.
+fn decorated_runs(fcx: &mut FontContext, lcx: &mut LayoutContext) {
+    let text = [LOREM; 100].join(" ");
+    let mut build_underlined = |underline: bool| {
+        let mut builder = lcx.ranged_builder(fcx, &text, 1.0);
+        builder.push_default(StyleProperty::FontStack(FONT_STACK));
+        let mut start = 0;
+        for (index, word) in text.split(' ').enumerate() {
+            if underline && index % 2 == 0 {
+                builder.push(StyleProperty::Underline(true), start..start + word.len());
+            }
+            start += word.len() + 1;
+        }
+        let mut layout = builder.build(&text);
+        layout.break_all_lines(Some(600.));
+        layout
+    };
+    let plain = build_underlined(false);
+    let underlined = build_underlined(true);
+
+    let span_count = glyph_runs(&underlined)
+        .map(|run| run.style_spans().count())
+        .sum::<usize>();
+    println!(
+        "glyph runs of 3,600 words: {} plain, {} underlined, in {} style spans",
+        glyph_runs(&plain).count(),
+        glyph_runs(&underlined).count(),
+        span_count
+    );
+    bench("style spans of 3,600 words, every other underlined", || {
+        for run in glyph_runs(&underlined) {
+            for span in run.style_spans() {
+                black_box(span);
+            }
+        }
+    });
+}
+
+/// Iterates over the glyphs of the 40 visible lines of a layout of 50,000 lines, with
+/// `Layout::slice_lines` and by skipping the lines above them.
+fn visible_lines(fcx: &mut FontContext, lcx: &mut LayoutContext) {
+    let text = (0..50_000)
+        .map(|index| format!("Line {index}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let layout = build(fcx, lcx, &text, None);
+    let first = layout.line_for_y(layout.height() * 0.6).unwrap_or(0);
+    let visible = first..first + 40;
+
+    let draw = |line: Line<'_, [u8; 4]>| {
+        for item in line.items() {
+            if let PositionedLayoutItem::GlyphRun(run) = item {
+                for glyph in run.positioned_glyphs() {
+                    black_box(glyph);
+                }
+            }
+        }
+    };
+    bench("40 visible of 50,000 lines, slice_lines", || {
+        layout.slice_lines(visible.clone()).for_each(draw);
+    });
+    bench("40 visible of 50,000 lines, skipping lines", || {
+        layout.lines().skip(first).take(40).for_each(draw);
+    });
+}
+
+/// Lays out a paragraph at three candidate widths, by shaping it once and breaking the
+/// `ShapedText` at each width, and by building a layout for each width.
+fn candidate_widths(fcx: &mut FontContext, lcx: &mut LayoutContext) {
+    let text = [LOREM; 10].join(" ");
+    let widths = [200., 400., 800.];
+
+    bench("3 widths of a paragraph, shaped once", || {
+        let shaped = lcx.shape(
+            fcx,
+            &text,
+            1.0,
+            [(StyleProperty::FontStack(FONT_STACK), 0..text.len())],
+        );
+        for width in widths {
+            black_box(shaped.break_and_align(Some(width), Alignment::Start));
+        }
+    });
+    bench("3 widths of a paragraph, built for each", || {
+        for width in widths {
+            black_box(build(fcx, lcx, &text, Some(width)));
+        }
+    });
+}
+
+/// Builds a document styled with 3,600 spans, one for each word, by pushing them as
+/// sorted spans of complete styles and by pushing a property for each.
+fn style_spans(fcx: &mut FontContext, lcx: &mut LayoutContext) {
+    let text = [LOREM; 100].join(" ");
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for word in text.split(' ') {
+        ranges.push(start..start + word.len() + 1);
+        start += word.len() + 1;
+    }
+    if let Some(last) = ranges.last_mut() {
+        last.end = text.len();
+    }
+    let brush = |index: usize| [index.to_le_bytes()[0], 0, 0, 255];
+    let spans: Vec<_> = ranges
+        .iter()
+        .enumerate()
+        .map(|(index, range)| {
+            let style = TextStyle {
+                font_stack: FONT_STACK,
+                brush: brush(index),
+                ..TextStyle::default()
+            };
+            (range.clone(), style)
+        })
+        .collect();
+
+    bench("3,600 styled words, push_spans", || {
+        let mut builder = lcx.ranged_builder(fcx, &text, 1.0);
+        builder.push_spans(&spans);
+        black_box(builder.build(&text));
+    });
+    bench("3,600 styled words, push", || {
+        let mut builder = lcx.ranged_builder(fcx, &text, 1.0);
+        builder.push_default(StyleProperty::FontStack(FONT_STACK));
+        for (index, range) in ranges.iter().enumerate() {
+            builder.push(StyleProperty::Brush(brush(index)), range.clone());
+        }
+        black_box(builder.build(&text));
+    });
+}
+
+/// Breaks a paragraph at 20 widths, as during a drag resize, by breaking shaped text
+/// with `ShapedText::break_lines` and by building a layout for each width.
+fn resize(fcx: &mut FontContext, lcx: &mut LayoutContext) {
+    let text = [LOREM; 10].join(" ");
+    let widths: Vec<f32> = (0..20).map(|step| 200. + 30. * step as f32).collect();
+    let shaped = lcx.shape(
+        fcx,
+        &text,
+        1.0,
+        [(StyleProperty::FontStack(FONT_STACK), 0..text.len())],
+    );
+
+    bench("20 widths of a paragraph, break_lines", || {
+        for &width in &widths {
+            black_box(shaped.break_lines(Some(width)));
+        }
+    });
+    bench("20 widths of a paragraph, built for each", || {
+        for &width in &widths {
+            let mut builder = lcx.ranged_builder(fcx, &text, 1.0);
+            builder.push_default(StyleProperty::FontStack(FONT_STACK));
+            let mut layout = builder.build(&text);
+            layout.break_all_lines(Some(width));
+            black_box(layout);
+        }
+    });
+}
+
+/// Returns the glyph runs of every line of `layout`.
+fn glyph_runs<'a>(layout: &'a Layout<[u8; 4]>) -> impl Iterator<Item = GlyphRun<'a, [u8; 4]>> {
+    layout
+        .lines()
+        .flat_map(|line| line.items())
+        .filter_map(|item| match item {
+            PositionedLayoutItem::GlyphRun(run) => Some(run),
+            PositionedLayoutItem::InlineBox(_) => None,
+        })
+}
+
+/// Builds a layout of `text` in the test fonts, broken at `max_advance` and aligned.
+fn build(
+    fcx: &mut FontContext,
+    lcx: &mut LayoutContext,
+    text: &str,
+    max_advance: Option<f32>,
+) -> Layout<[u8; 4]> {
+    let mut builder = lcx.ranged_builder(fcx, text, 1.0);
+    builder.push_default(StyleProperty::FontStack(FONT_STACK));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(max_advance);
+    layout.align(max_advance, Alignment::Start, AlignmentOptions::default());
+    layout
+}
+
+/// Runs `f` repeatedly for half a second, after running it once to warm up, and prints
+/// its mean duration.
+fn bench(name: &str, mut f: impl FnMut()) {
+    f();
+    let start = Instant::now();
+    let mut iterations = 0_u32;
+    while start.elapsed() < Duration::from_millis(500) {
+        f();
+        iterations += 1;
+    }
+    println!("{name:<56} {:>12.3?}", start.elapsed() / iterations);
+}
+
+/// Returns a font context with only the fonts of the tests.
+fn font_context() -> FontContext {
+    let mut collection = Collection::new(CollectionOptions {
+        shared: false,
+        system_fonts: false,
+    });
+    let assets = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("assets");
+    for path in [
+        assets.join("roboto_fonts").join("Roboto-Regular.ttf"),
+        assets.join("noto_fonts").join("NotoKufiArabic-Regular.otf"),
+    ] {
+        let data = std::fs::read(&path)
+            .unwrap_or_else(|err| panic!("failed to read {}: {err}", path.display()));
+        collection.register_fonts(Blob::new(Arc::new(data)), None);
+    }
+    FontContext {
+        collection,
+        source_cache: Default::default(),
+    }
+}
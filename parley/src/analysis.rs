@@ -0,0 +1,407 @@
+// Copyright 2025 the Parley Authors
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Boundary and bidirectional analysis of text.
+
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+use core::ops::Range;
+
+use swash::text::cluster::{Boundary, CharCluster, CharInfo, Parser, Token};
+use swash::text::{BidiClass, Script};
+
+use crate::bidi::{BidiLevel, BidiResolver, is_removed_by_x9};
+use crate::shape::real_script;
+use crate::style::{HardBreak, TextDirection, WordBreakStrength};
+use crate::util::StableHasher;
+
+/// The boundaries which occur before a character of analyzed text.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct TextBoundary {
+    /// The character starts a grapheme cluster.
+    pub grapheme: bool,
+    /// The character starts or ends a word.
+    pub word: bool,
+    /// A line may be broken before the character.
+    pub line_break: bool,
+    /// A line must be broken before the character.
+    pub mandatory_break: bool,
+}
+
+/// Boundaries and bidirectional levels of a text.
+///
+/// This is the same analysis that is used to build a [`Layout`](crate::Layout), so
+/// editors can segment text exactly as it is laid out. It is created with
+/// [`LayoutContext::analyze`](crate::LayoutContext::analyze), and can be passed to
+/// [`RangedBuilder::build_with_analysis`](crate::RangedBuilder::build_with_analysis)
+/// to avoid analyzing the same text again.
+#[derive(Clone, Default)]
+pub struct TextAnalysis {
+    text_len: usize,
+    /// Hash of the analyzed text, to check that the analysis is reused for the same text.
+    text_hash: u64,
+    base_direction: Option<TextDirection>,
+    /// Byte offsets within paragraphs whose base direction is forced, overriding
+    /// `base_direction`, with later entries taking precedence.
+    paragraph_directions: Vec<(usize, TextDirection)>,
+    /// Ranges with a word break strength other than the default, in text order.
+    word_breaks: Vec<(Range<usize>, WordBreakStrength)>,
+    /// Additional characters which force a line or paragraph break.
+    hard_breaks: Vec<(char, HardBreak)>,
+    /// Ranges with `Hyphens::None`, where lines are not broken after soft hyphens.
+    no_hyphens: Vec<Range<usize>>,
+    /// Byte offset and properties of each character.
+    ///
+    /// Empty text is analyzed as a single space, so that it can still be shaped.
+    pub(crate) chars: Vec<(usize, CharInfo)>,
+    /// Whether each character starts a grapheme cluster.
+    graphemes: Vec<bool>,
+    /// Level of each character, or empty if no bidi resolution was needed.
+    pub(crate) bidi_levels: Vec<BidiLevel>,
+    /// Resolved bidi class of each character, or empty if no bidi resolution was needed.
+    bidi_classes: Vec<BidiClass>,
+    pub(crate) paragraphs: Vec<(Range<usize>, BidiLevel)>,
+}
+
+impl TextAnalysis {
+    /// Returns the length of the analyzed text in bytes.
+    pub fn text_len(&self) -> usize {
+        self.text_len
+    }
+
+    /// Returns the boundaries before the character at `byte_index`.
+    ///
+    /// Returns `None` if `byte_index` is not the start of a character in the text.
+    pub fn boundary(&self, byte_index: usize) -> Option<TextBoundary> {
+        let index = self.char_index(byte_index)?;
+        Some(self.boundary_at(index))
+    }
+
+    /// Returns an iterator over the byte offset of each character in the text and
+    /// the boundaries before it.
+    pub fn boundaries(&self) -> impl Iterator<Item = (usize, TextBoundary)> + '_ + Clone {
+        self.chars[..self.char_count()]
+            .iter()
+            .enumerate()
+            .map(|(index, (offset, _))| (*offset, self.boundary_at(index)))
+    }
+
+    /// Returns the bidirectional embedding level of the character at `byte_index`.
+    ///
+    /// Even levels are left-to-right and odd levels are right-to-left. Returns
+    /// `None` if `byte_index` is not the start of a character in the text.
+    pub fn bidi_level(&self, byte_index: usize) -> Option<u8> {
+        let index = self.char_index(byte_index)?;
+        Some(self.bidi_levels.get(index).copied().unwrap_or(0))
+    }
+
+    /// Returns the bidirectional class of the character at `byte_index` after the
+    /// weak and neutral types are resolved, which determines its level.
+    ///
+    /// This is one of `L`, `R`, `EN` or `AN`, except for characters which are
+    /// removed by rule X9 of the Unicode bidirectional algorithm, which keep their
+    /// original class. Returns `None` if `byte_index` is not the start of a character
+    /// in the text.
+    pub fn bidi_class(&self, byte_index: usize) -> Option<BidiClass> {
+        let index = self.char_index(byte_index)?;
+        Some(self.bidi_classes.get(index).copied().unwrap_or_else(|| {
+            let class = self.chars[index].1.bidi_class();
+            if is_removed_by_x9(class) {
+                class
+            } else {
+                BidiClass::L
+            }
+        }))
+    }
+
+    /// Returns an iterator over the text range and base direction of each paragraph.
+    pub fn paragraphs(&self) -> impl Iterator<Item = (Range<usize>, TextDirection)> + '_ + Clone {
+        self.paragraphs
+            .iter()
+            .map(|(range, level)| (range.clone(), TextDirection::from_bidi_level(*level)))
+    }
+
+    fn char_count(&self) -> usize {
+        if self.text_len == 0 {
+            0
+        } else {
+            self.chars.len()
+        }
+    }
+
+    fn char_index(&self, byte_index: usize) -> Option<usize> {
+        self.chars[..self.char_count()]
+            .binary_search_by_key(&byte_index, |(offset, _)| *offset)
+            .ok()
+    }
+
+    fn boundary_at(&self, index: usize) -> TextBoundary {
+        let boundary = self.chars[index].1.boundary();
+        TextBoundary {
+            grapheme: self.graphemes[index] || boundary != Boundary::None,
+            word: boundary != Boundary::None,
+            line_break: matches!(boundary, Boundary::Line | Boundary::Mandatory),
+            mandatory_break: boundary == Boundary::Mandatory,
+        }
+    }
+
+    /// Returns `true` if this analysis is what [`TextAnalysis::compute`] would produce
+    /// for `text` with the given parameters.
+    pub(crate) fn matches(
+        &self,
+        text: &str,
+        base_direction: Option<TextDirection>,
+        paragraph_directions: &[(usize, TextDirection)],
+        word_breaks: impl Iterator<Item = (Range<usize>, WordBreakStrength)>,
+        hard_breaks: &[(char, HardBreak)],
+        no_hyphens: impl Iterator<Item = Range<usize>>,
+    ) -> bool {
+        let mut expected = Vec::new();
+        push_word_breaks(&mut expected, word_breaks);
+        let mut expected_no_hyphens = Vec::new();
+        push_ranges(&mut expected_no_hyphens, no_hyphens);
+        self.text_len == text.len()
+            && self.text_hash == hash_text(text)
+            && self.char_count() == text.chars().count()
+            && self.base_direction == base_direction
+            && self.paragraph_directions == paragraph_directions
+            && self.word_breaks == expected
+            && self.hard_breaks == hard_breaks
+            && self.no_hyphens == expected_no_hyphens
+    }
+
+    /// Returns the forced base direction of the paragraph with the given range, if any.
+    pub(crate) fn forced_direction(&self, range: &Range<usize>) -> Option<TextDirection> {
+        self.paragraph_directions
+            .iter()
+            .rev()
+            .find(|(index, _)| {
+                range.contains(index) || (range.end >= self.text_len && *index >= range.end)
+            })
+            .map(|(_, direction)| *direction)
+            .or(self.base_direction)
+    }
+
+    /// Analyzes `text`, replacing the previous contents.
+    ///
+    /// The base direction of the paragraph containing each offset in
+    /// `paragraph_directions` is forced, overriding `base_direction`. `word_breaks`
+    /// gives the word break strength for ranges of the text, and the default strength
+    /// is used elsewhere. Characters in `hard_breaks` force a break after them, in
+    /// addition to the mandatory breaks of the Unicode line breaking algorithm. Soft
+    /// hyphens in the `no_hyphens` ranges are not break opportunities.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn compute(
+        &mut self,
+        bidi: &mut BidiResolver,
+        text: &str,
+        base_direction: Option<TextDirection>,
+        paragraph_directions: &[(usize, TextDirection)],
+        word_breaks: impl Iterator<Item = (Range<usize>, WordBreakStrength)>,
+        hard_breaks: &[(char, HardBreak)],
+        no_hyphens: impl Iterator<Item = Range<usize>>,
+    ) {
+        self.text_len = text.len();
+        self.text_hash = hash_text(text);
+        self.base_direction = base_direction;
+        self.paragraph_directions.clear();
+        self.paragraph_directions
+            .extend_from_slice(paragraph_directions);
+        self.word_breaks.clear();
+        push_word_breaks(&mut self.word_breaks, word_breaks);
+        self.hard_breaks.clear();
+        self.hard_breaks.extend_from_slice(hard_breaks);
+        self.no_hyphens.clear();
+        push_ranges(&mut self.no_hyphens, no_hyphens);
+        self.chars.clear();
+        self.graphemes.clear();
+        self.bidi_levels.clear();
+        self.bidi_classes.clear();
+        self.paragraphs.clear();
+        bidi.clear();
+
+        let is_empty = text.is_empty();
+        let text = if is_empty { " " } else { text };
+        let mut a = swash::text::analyze(text.chars());
+
+        let mut word_break_index = 0;
+        let mut after_hard_break = false;
+        let mut after_soft_hyphen = false;
+        for (byte_idx, ch) in text.char_indices() {
+            while self
+                .word_breaks
+                .get(word_break_index)
+                .is_some_and(|(range, _)| range.end <= byte_idx)
+            {
+                word_break_index += 1;
+            }
+            let word_break = match self.word_breaks.get(word_break_index) {
+                Some((range, strength)) if range.start <= byte_idx => *strength,
+                _ => WordBreakStrength::default(),
+            };
+            a.set_break_strength(word_break);
+
+            let Some((properties, mut boundary)) = a.next() else {
+                break;
+            };
+            if after_hard_break {
+                boundary = Boundary::Mandatory;
+            } else if after_soft_hyphen && boundary == Boundary::Line {
+                boundary = Boundary::None;
+            }
+            after_hard_break = is_hard_break(ch, hard_breaks);
+            after_soft_hyphen = ch == '\u{AD}'
+                && self
+                    .no_hyphens
+                    .iter()
+                    .any(|range| range.contains(&byte_idx));
+
+            self.chars
+                .push((byte_idx, CharInfo::new(properties, boundary)));
+        }
+
+        // Each paragraph is resolved independently, so that it gets its own base level.
+        let needs_bidi = a.needs_bidi_resolution()
+            || base_direction == Some(TextDirection::RightToLeft)
+            || paragraph_directions
+                .iter()
+                .any(|(_, direction)| *direction == TextDirection::RightToLeft);
+        let mut char_start = 0;
+        let mut byte_start = 0;
+        let mut chars = text.char_indices().enumerate().peekable();
+        while let Some((char_idx, (byte_idx, ch))) = chars.next() {
+            let is_separator = (self.chars[char_idx].1.bidi_class() == BidiClass::B
+                || hard_breaks.contains(&(ch, HardBreak::Paragraph)))
+                // Keep CRLF together as a single paragraph separator.
+                && !(ch == '\r' && matches!(chars.peek(), Some((_, (_, '\n')))));
+            if !is_separator && chars.peek().is_some() {
+                continue;
+            }
+            let char_end = char_idx + 1;
+            let byte_end = byte_idx + ch.len_utf8();
+            let level = if needs_bidi {
+                let base_level = self
+                    .forced_direction(&(byte_start..byte_end))
+                    .map(TextDirection::bidi_level);
+                bidi.resolve(
+                    text[byte_start..byte_end].chars().zip(
+                        self.chars[char_start..char_end]
+                            .iter()
+                            .map(|(_, info)| info.bidi_class()),
+                    ),
+                    base_level,
+                );
+                self.bidi_levels.extend_from_slice(bidi.levels());
+                self.bidi_classes.extend_from_slice(bidi.resolved_types());
+                bidi.base_level()
+            } else {
+                0
+            };
+            let range = if is_empty { 0..0 } else { byte_start..byte_end };
+            self.paragraphs.push((range, level));
+            char_start = char_end;
+            byte_start = byte_end;
+        }
+
+        self.graphemes.resize(self.chars.len(), false);
+        mark_graphemes(text, &self.chars, &mut self.graphemes);
+    }
+}
+
+/// Returns `true` if `ch` forces a break after it, either because it is a line or
+/// paragraph separator or because it is one of the caller's `hard_breaks`.
+pub(crate) fn is_hard_break(ch: char, hard_breaks: &[(char, HardBreak)]) -> bool {
+    matches!(ch, '\u{2028}' | '\u{2029}') || hard_breaks.iter().any(|(c, _)| *c == ch)
+}
+
+/// Appends the ranges of `word_breaks` which don't use the default strength,
+/// merging adjacent ranges with the same strength.
+fn push_word_breaks(
+    out: &mut Vec<(Range<usize>, WordBreakStrength)>,
+    word_breaks: impl Iterator<Item = (Range<usize>, WordBreakStrength)>,
+) {
+    for (range, strength) in word_breaks {
+        if range.is_empty() || strength == WordBreakStrength::default() {
+            continue;
+        }
+        match out.last_mut() {
+            Some((last, last_strength))
+                if last.end == range.start && *last_strength == strength =>
+            {
+                last.end = range.end;
+            }
+            _ => out.push((range, strength)),
+        }
+    }
+}
+
+/// Appends the non-empty ranges of `ranges` to `out`, merging adjacent ranges.
+fn push_ranges(out: &mut Vec<Range<usize>>, ranges: impl Iterator<Item = Range<usize>>) {
+    for range in ranges {
+        if range.is_empty() {
+            continue;
+        }
+        match out.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => out.push(range),
+        }
+    }
+}
+
+/// Marks the first character of each cluster, parsing runs of the same script
+/// separately as shaping does.
+fn mark_graphemes(text: &str, chars: &[(usize, CharInfo)], graphemes: &mut [bool]) {
+    let mut cluster = CharCluster::new();
+    let mut parse = |range: Range<usize>, script: Script| {
+        let start = chars[range.start].0;
+        let end = chars
+            .get(range.end)
+            .map_or(text.len(), |(offset, _)| *offset);
+        let mut parser = Parser::new(
+            script,
+            text[start..end]
+                .chars()
+                .zip(&chars[range])
+                .map(|(ch, (offset, info))| Token {
+                    ch,
+                    offset: *offset as u32,
+                    len: ch.len_utf8() as u8,
+                    info: *info,
+                    data: 0,
+                }),
+        );
+        while parser.next(&mut cluster) {
+            let offset = cluster.range().start as usize;
+            if let Ok(index) = chars.binary_search_by_key(&offset, |(offset, _)| *offset) {
+                graphemes[index] = true;
+            }
+        }
+    };
+
+    let mut run_start = 0;
+    let mut run_script = chars
+        .iter()
+        .map(|(_, info)| info.script())
+        .find(|&script| real_script(script))
+        .unwrap_or(Script::Latin);
+    for (index, (_, info)) in chars.iter().enumerate() {
+        let script = info.script();
+        if real_script(script) && script != run_script {
+            if index > run_start {
+                parse(run_start..index, run_script);
+            }
+            run_start = index;
+            run_script = script;
+        }
+    }
+    if run_start < chars.len() {
+        parse(run_start..chars.len(), run_script);
+    }
+}
+
+/// Returns a hash of `text` which doesn't depend on a random seed.
+fn hash_text(text: &str) -> u64 {
+    let mut hasher = StableHasher::default();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
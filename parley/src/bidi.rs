@@ -17,6 +17,7 @@ pub(crate) struct BidiResolver {
     base_level: BidiLevel,
     levels: Vec<BidiLevel>,
     initial_types: Vec<BidiClass>,
+    resolved_types: Vec<BidiClass>,
     types: Vec<BidiClass>,
     brackets: Vec<(usize, char, BracketType)>,
     bracket_pairs: Vec<(usize, usize)>,
@@ -32,6 +33,7 @@ impl BidiResolver {
             base_level: 0,
             levels: Vec::new(),
             initial_types: Vec::new(),
+            resolved_types: Vec::new(),
             types: Vec::new(),
             brackets: Vec::new(),
             bracket_pairs: Vec::new(),
@@ -52,9 +54,18 @@ impl BidiResolver {
         &self.levels
     }
 
+    /// Returns the class of each character in the paragraph after the weak and
+    /// neutral types are resolved, which is one of `L`, `R`, `EN` or `AN`.
+    ///
+    /// Characters which are removed by rule X9 keep their original class.
+    pub(crate) fn resolved_types(&self) -> &[BidiClass] {
+        &self.resolved_types
+    }
+
     /// Clears the resolver state.
     pub(crate) fn clear(&mut self) {
         self.initial_types.clear();
+        self.resolved_types.clear();
         self.levels.clear();
         self.types.clear();
         self.brackets.clear();
@@ -89,9 +100,17 @@ impl BidiResolver {
         if !needs_bidi && self.base_level == 0 {
             self.flags |= 1;
             self.levels.resize(len, self.base_level);
+            // Without strong right-to-left types, everything but the characters removed
+            // by X9 resolves to L, including European numbers by W7.
+            self.resolved_types.extend(
+                self.initial_types
+                    .iter()
+                    .map(|ty| if is_removed_by_x9(*ty) { *ty } else { L }),
+            );
             return;
         }
         self.types.extend_from_slice(&self.initial_types);
+        self.resolved_types.extend_from_slice(&self.initial_types);
         self.resolve_levels();
         self.resolve_runs();
         //self.dump_sequences();
@@ -145,12 +164,15 @@ impl BidiResolver {
                 //self.levels[i] = 0xFF;
             }
         }
+        // L1: whitespace and isolate formatting characters at the end of the paragraph
+        // also return to the paragraph level, such as a space inside an isolate which
+        // is never terminated.
         for i in (0..len).rev() {
             let t = self.initial_types[i];
             if is_removed_by_x9(t) {
                 continue;
             } else if t == WS || is_isolate_initiator(t) || t == PDI {
-                //self.levels[i] = self.base_level;
+                self.levels[i] = self.base_level;
             } else {
                 break;
             }
@@ -608,6 +630,9 @@ impl BidiResolver {
             }
             i += 1;
         }
+        for (index, ty) in self.indices.iter().zip(&types[..len]) {
+            self.resolved_types[*index] = *ty;
+        }
         // Implicit levels
         if level & 1 == 0 {
             // I1
@@ -4,16 +4,26 @@
 //! Context for layout.
 
 use super::FontContext;
-use super::context::LayoutContext;
-use super::style::{Brush, StyleProperty, TextStyle, WhiteSpaceCollapse};
+use super::analysis::TextAnalysis;
+use super::context::{LayoutContext, LineMetricsKey};
+use super::font::FontError;
+use super::style::{
+    BoundarySpace, Brush, HardBreak, ReplacementCharPolicy, StyleProperty, TextDirection,
+    TextIndent, TextStyle, Visibility, WhiteSpaceCollapse,
+};
 
-use super::layout::Layout;
+use super::layout::data::{LeadingBoxData, ParagraphData};
+use super::layout::{Alignment, Layout, LayoutWarning, LinkData, ShapedText};
+use super::paragraph_cache::ShapedMark;
 
 use alloc::string::String;
-use core::ops::RangeBounds;
+use alloc::vec::Vec;
+use core::ops::{Range, RangeBounds};
+use core::sync::atomic::{AtomicU64, Ordering};
 
-use crate::inline_box::InlineBox;
-use crate::resolve::tree::ItemKind;
+use crate::inline_box::{InlineBox, LeadingBox};
+use crate::resolve::range::resolve_range;
+use crate::resolve::{RangedStyle, ResolvedStyle};
 
 /// Builder for constructing a text layout with ranged attributes.
 pub struct RangedBuilder<'a, B: Brush> {
@@ -43,23 +53,203 @@ impl<B: Brush> RangedBuilder<'_, B> {
         self.lcx.ranged_style_builder.push(resolved, range);
     }
 
+    /// Styles the text with a complete style for each of a sequence of ranges, which
+    /// must be sorted and must not overlap.
+    ///
+    /// This is faster than pushing the properties of each range, as the spans are
+    /// used as they are rather than being split and merged with each other. Text which
+    /// is not covered by a span has the default style, and properties pushed with
+    /// [`Self::push`] still apply on top of the spans.
+    ///
+    /// Ranges are clamped to the text, and the part of a span which overlaps the spans
+    /// before it is dropped.
+    pub fn push_spans(&mut self, spans: &[(Range<usize>, TextStyle<'_, B>)]) {
+        let scale = self.scale;
+        let resolved = spans
+            .iter()
+            .map(|(range, style)| RangedStyle {
+                style: self
+                    .lcx
+                    .rcx
+                    .resolve_entire_style_set(self.fcx, style, scale),
+                range: range.clone(),
+            })
+            .collect::<Vec<_>>();
+        self.lcx.ranged_style_builder.push_spans(resolved);
+    }
+
     pub fn push_inline_box(&mut self, inline_box: InlineBox) {
         self.lcx.inline_boxes.push(inline_box);
     }
 
+    /// Adds a box at the leading edge of the paragraph containing `leading_box.index`.
+    ///
+    /// See [`LeadingBox`] for details.
+    pub fn push_leading_box(&mut self, leading_box: LeadingBox) {
+        self.lcx.leading_boxes.push(leading_box);
+    }
+
+    /// Sets the indentation of the first line of the text, or of every paragraph.
+    ///
+    /// See [`TextIndent`] for details.
+    pub fn set_text_indent(&mut self, indent: TextIndent) {
+        self.lcx.text_indent = indent;
+    }
+
+    /// Tags the specified range of text as a link with the given identifier.
+    ///
+    /// Links can be found with [`Layout::link_at_point`], and keep their text range
+    /// when they are wrapped across several lines.
+    pub fn push_link(&mut self, id: u64, range: impl RangeBounds<usize>) {
+        let range = resolve_range(range, self.lcx.ranged_style_builder.len());
+        self.lcx.links.push(LinkData {
+            id,
+            text_range: range,
+        });
+    }
+
+    /// Forces the base direction of every paragraph, rather than inferring it
+    /// from the first strong character of each paragraph.
+    pub fn set_base_direction(&mut self, direction: TextDirection) {
+        self.lcx.base_direction = Some(direction);
+    }
+
+    /// Forces the base direction of the paragraph containing the byte `index`,
+    /// overriding [`RangedBuilder::set_base_direction`].
+    ///
+    /// Each paragraph is analyzed independently with its own base direction, so the
+    /// text of one paragraph doesn't affect the ordering of another.
+    pub fn set_paragraph_direction(&mut self, index: usize, direction: TextDirection) {
+        self.lcx.paragraph_directions.push((index, direction));
+    }
+
+    /// Sets the alignment of the paragraph containing the byte `index`, overriding the
+    /// alignment passed to [`Layout::align`] for its lines.
+    pub fn set_paragraph_alignment(&mut self, index: usize, alignment: Alignment) {
+        self.lcx.paragraph_alignments.push((index, alignment));
+    }
+
+    /// Marks the specified range of text as ignorable.
+    ///
+    /// Ignorable text keeps its source offsets, so it can still be mapped to
+    /// clusters and cursors, but it contributes no advance and produces no glyphs.
+    ///
+    /// The text is still shaped, as its own clusters, and is only stripped
+    /// afterwards. Kerning and ligatures are therefore not applied across it, so
+    /// text such as `AV` is a little wider with an ignorable character between its
+    /// letters than without one.
+    pub fn push_ignorable(&mut self, range: impl RangeBounds<usize>) {
+        let range = resolve_range(range, self.lcx.ranged_style_builder.len());
+        self.lcx.ignorable_ranges.push(range);
+    }
+
+    /// Marks every occurrence of the specified character as ignorable.
+    ///
+    /// See [`RangedBuilder::push_ignorable`] for details.
+    pub fn push_ignorable_char(&mut self, ch: char) {
+        self.lcx.ignorable_chars.push(ch);
+    }
+
+    /// Sets the visibility of the specified range of text.
+    ///
+    /// Only clusters which lie entirely within the range are affected. Where ranges
+    /// overlap, the one pushed last takes precedence.
+    pub fn push_visibility(&mut self, range: impl RangeBounds<usize>, visibility: Visibility) {
+        let range = resolve_range(range, self.lcx.ranged_style_builder.len());
+        self.lcx.visibility_ranges.push((range, visibility));
+    }
+
+    /// Sets how U+FFFD REPLACEMENT CHARACTER is laid out.
+    pub fn replacement_char_policy(&mut self, policy: ReplacementCharPolicy<B>) {
+        self.lcx.replacement_char_policy = policy;
+    }
+
+    /// Treats every occurrence of the specified character as a hard break of the
+    /// given kind, like a newline.
+    ///
+    /// U+2028 LINE SEPARATOR and U+2029 PARAGRAPH SEPARATOR are always hard breaks.
+    /// The character contributes no advance and produces no glyphs.
+    pub fn push_hard_break_char(&mut self, ch: char, kind: HardBreak) {
+        self.lcx.hard_break_chars.push((ch, kind));
+    }
+
     pub fn build_into(&mut self, layout: &mut Layout<B>, text: impl AsRef<str>) {
         // Apply RangedStyleBuilder styles to LayoutContext
         self.lcx.ranged_style_builder.finish(&mut self.lcx.styles);
+        let default_style = self.lcx.ranged_style_builder.default_style().clone();
 
         // Call generic layout builder method
-        build_into_layout(layout, self.scale, text.as_ref(), self.lcx, self.fcx);
+        build_into_layout(
+            layout,
+            self.scale,
+            text.as_ref(),
+            self.lcx,
+            self.fcx,
+            None,
+            &default_style,
+        );
     }
 
     pub fn build(&mut self, text: impl AsRef<str>) -> Layout<B> {
+        self.shape(text).into_layout()
+    }
+
+    /// Shapes the text without breaking it into lines, so that it can be broken at
+    /// several widths.
+    pub fn shape(&mut self, text: impl AsRef<str>) -> ShapedText<B> {
         let mut layout = Layout::default();
         self.build_into(&mut layout, text);
+        ShapedText::new(layout)
+    }
+
+    /// Builds the layout using an analysis of `text` from [`LayoutContext::analyze`],
+    /// rather than analyzing it again.
+    ///
+    /// `analysis` is only used if it was computed for the same text and is compatible
+    /// with the builder, which requires the default base direction and the default word
+    /// break strength for all of the text. Otherwise the text is analyzed as usual.
+    pub fn build_with_analysis(
+        &mut self,
+        text: impl AsRef<str>,
+        analysis: &TextAnalysis,
+    ) -> Layout<B> {
+        // Apply RangedStyleBuilder styles to LayoutContext
+        self.lcx.ranged_style_builder.finish(&mut self.lcx.styles);
+        let default_style = self.lcx.ranged_style_builder.default_style().clone();
+
+        let mut layout = Layout::default();
+        build_into_layout(
+            &mut layout,
+            self.scale,
+            text.as_ref(),
+            self.lcx,
+            self.fcx,
+            Some(analysis),
+            &default_style,
+        );
         layout
     }
+
+    /// Builds the layout, failing if any of the text is styled with a generic family
+    /// for which no families are registered.
+    pub fn try_build(&mut self, text: impl AsRef<str>) -> Result<Layout<B>, FontError> {
+        // Apply RangedStyleBuilder styles to LayoutContext
+        self.lcx.ranged_style_builder.finish(&mut self.lcx.styles);
+        check_font_stacks(self.lcx)?;
+        let default_style = self.lcx.ranged_style_builder.default_style().clone();
+
+        let mut layout = Layout::default();
+        build_into_layout(
+            &mut layout,
+            self.scale,
+            text.as_ref(),
+            self.lcx,
+            self.fcx,
+            None,
+            &default_style,
+        );
+        Ok(layout)
+    }
 }
 
 /// Builder for constructing a text layout with a tree of attributes.
@@ -101,28 +291,114 @@ impl<B: Brush> TreeBuilder<'_, B> {
     }
 
     pub fn push_inline_box(&mut self, mut inline_box: InlineBox) {
-        self.lcx.tree_style_builder.push_uncommitted_text(false);
-        self.lcx.tree_style_builder.set_is_span_first(false);
-        self.lcx
-            .tree_style_builder
-            .set_last_item_kind(ItemKind::InlineBox);
-        // TODO: arrange type better here to factor out the index
-        inline_box.index = self.lcx.tree_style_builder.current_text_len();
+        inline_box.index = self.lcx.tree_style_builder.push_inline_box();
         self.lcx.inline_boxes.push(inline_box);
     }
 
+    /// Adds a box at the leading edge of the paragraph containing the text pushed so far.
+    ///
+    /// The index of the box is ignored. See [`LeadingBox`] for details.
+    pub fn push_leading_box(&mut self, mut leading_box: LeadingBox) {
+        leading_box.index = self.lcx.tree_style_builder.commit_text();
+        self.lcx.leading_boxes.push(leading_box);
+    }
+
+    /// Sets the indentation of the first line of the text, or of every paragraph.
+    ///
+    /// See [`TextIndent`] for details.
+    pub fn set_text_indent(&mut self, indent: TextIndent) {
+        self.lcx.text_indent = indent;
+    }
+
+    /// Pushes `text` and tags it as a link with the given identifier.
+    ///
+    /// See [`RangedBuilder::push_link`] for details.
+    pub fn push_link(&mut self, id: u64, text: &str) {
+        let start = self.lcx.tree_style_builder.commit_text();
+        self.lcx.tree_style_builder.push_text(text);
+        let end = self.lcx.tree_style_builder.commit_text();
+        self.lcx.links.push(LinkData {
+            id,
+            text_range: start..end,
+        });
+    }
+
+    /// Marks every occurrence of the specified character as ignorable.
+    ///
+    /// See [`RangedBuilder::push_ignorable`] for details.
+    pub fn push_ignorable_char(&mut self, ch: char) {
+        self.lcx.ignorable_chars.push(ch);
+    }
+
+    /// Forces the base direction of every paragraph, rather than inferring it
+    /// from the first strong character of each paragraph.
+    pub fn set_base_direction(&mut self, direction: TextDirection) {
+        self.lcx.base_direction = Some(direction);
+    }
+
+    /// Forces the base direction of the paragraph containing the text pushed so far,
+    /// or of the next paragraph if the text pushed so far ends with a paragraph break.
+    ///
+    /// See [`RangedBuilder::set_paragraph_direction`] for details.
+    pub fn set_paragraph_direction(&mut self, direction: TextDirection) {
+        let index = self.lcx.tree_style_builder.commit_text();
+        self.lcx.paragraph_directions.push((index, direction));
+    }
+
+    /// Sets the alignment of the paragraph containing the text pushed so far, or of the
+    /// next paragraph if the text pushed so far ends with a paragraph break.
+    ///
+    /// See [`RangedBuilder::set_paragraph_alignment`] for details.
+    pub fn set_paragraph_alignment(&mut self, alignment: Alignment) {
+        let index = self.lcx.tree_style_builder.commit_text();
+        self.lcx.paragraph_alignments.push((index, alignment));
+    }
+
+    /// Sets how U+FFFD REPLACEMENT CHARACTER is laid out.
+    pub fn replacement_char_policy(&mut self, policy: ReplacementCharPolicy<B>) {
+        self.lcx.replacement_char_policy = policy;
+    }
+
+    /// Treats every occurrence of the specified character as a hard break of the
+    /// given kind.
+    ///
+    /// See [`RangedBuilder::push_hard_break_char`] for details.
+    pub fn push_hard_break_char(&mut self, ch: char, kind: HardBreak) {
+        self.lcx.hard_break_chars.push((ch, kind));
+    }
+
     pub fn set_white_space_mode(&mut self, white_space_collapse: WhiteSpaceCollapse) {
         self.lcx
             .tree_style_builder
             .set_white_space_mode(white_space_collapse);
     }
 
+    /// Sets which style a collapsed space takes when white space from differently-styled
+    /// spans is collapsed together. This applies to text pushed afterwards.
+    ///
+    /// Spaces which are not collapsed always take the style of the span they were pushed
+    /// in, as do all characters of a [`RangedBuilder`].
+    pub fn set_boundary_space(&mut self, boundary_space: BoundarySpace) {
+        self.lcx
+            .tree_style_builder
+            .set_boundary_space(boundary_space);
+    }
+
     pub fn build_into(&mut self, layout: &mut Layout<B>) -> String {
         // Apply TreeStyleBuilder styles to LayoutContext
         let text = self.lcx.tree_style_builder.finish(&mut self.lcx.styles);
+        let default_style = self.lcx.tree_style_builder.root_style().clone();
 
         // Call generic layout builder method
-        build_into_layout(layout, self.scale, &text, self.lcx, self.fcx);
+        build_into_layout(
+            layout,
+            self.scale,
+            &text,
+            self.lcx,
+            self.fcx,
+            None,
+            &default_style,
+        );
 
         text
     }
@@ -132,6 +408,41 @@ impl<B: Brush> TreeBuilder<'_, B> {
         let text = self.build_into(&mut layout);
         (layout, text)
     }
+
+    /// Builds the layout, failing if any of the text is styled with a generic family
+    /// for which no families are registered.
+    pub fn try_build(&mut self) -> Result<(Layout<B>, String), FontError> {
+        // Apply TreeStyleBuilder styles to LayoutContext
+        let text = self.lcx.tree_style_builder.finish(&mut self.lcx.styles);
+        check_font_stacks(self.lcx)?;
+        let default_style = self.lcx.tree_style_builder.root_style().clone();
+
+        let mut layout = Layout::default();
+        build_into_layout(
+            &mut layout,
+            self.scale,
+            &text,
+            self.lcx,
+            self.fcx,
+            None,
+            &default_style,
+        );
+        Ok((layout, text))
+    }
+}
+
+/// Checks that every style which covers text has a font stack without missing
+/// generic families.
+fn check_font_stacks<B: Brush>(lcx: &LayoutContext<B>) -> Result<(), FontError> {
+    for style in &lcx.styles {
+        if style.range.is_empty() {
+            continue;
+        }
+        if let Some(family) = lcx.rcx.missing_generic(style.style.font_stack) {
+            return Err(FontError::MissingGenericFamily(family));
+        }
+    }
+    Ok(())
 }
 
 fn build_into_layout<B: Brush>(
@@ -140,14 +451,81 @@ fn build_into_layout<B: Brush>(
     text: &str,
     lcx: &mut LayoutContext<B>,
     fcx: &mut FontContext,
+    analysis: Option<&TextAnalysis>,
+    default_style: &ResolvedStyle<B>,
 ) {
-    lcx.analyze_text(text);
+    #[cfg(feature = "stats")]
+    let start = std::time::Instant::now();
+    lcx.analyze_text(text, analysis);
 
     layout.data.clear();
+    #[cfg(feature = "stats")]
+    {
+        layout.data.stats.analysis = start.elapsed();
+    }
+    let build_id = next_build_id();
+    layout.data.build_id = build_id;
+    lcx.last_build_id = Some(build_id);
     layout.data.scale = scale;
-    layout.data.has_bidi = !lcx.bidi.levels().is_empty();
-    layout.data.base_level = lcx.bidi.base_level();
+    layout.data.has_bidi = !lcx.analysis.bidi_levels.is_empty();
+    layout.data.base_level = lcx.analysis.paragraphs.first().map_or(0, |p| p.1);
     layout.data.text_len = text.len();
+    layout
+        .data
+        .paragraphs
+        .extend(
+            lcx.analysis
+                .paragraphs
+                .iter()
+                .map(|(text_range, base_level)| ParagraphData {
+                    text_range: text_range.clone(),
+                    base_level: *base_level,
+                    alignment: None,
+                }),
+        );
+    let paragraphs = &mut layout.data.paragraphs;
+    for (index, alignment) in lcx.paragraph_alignments.drain(..) {
+        let paragraph = paragraphs
+            .partition_point(|paragraph| paragraph.text_range.end <= index)
+            .min(paragraphs.len().saturating_sub(1));
+        if let Some(paragraph) = paragraphs.get_mut(paragraph) {
+            paragraph.alignment = Some(alignment);
+        }
+    }
+
+    let paragraphs = &layout.data.paragraphs;
+    layout
+        .data
+        .leading_boxes
+        .extend(lcx.leading_boxes.drain(..).map(|leading_box| {
+            let paragraph = paragraphs
+                .partition_point(|paragraph| paragraph.text_range.end <= leading_box.index)
+                .min(paragraphs.len().saturating_sub(1));
+            LeadingBoxData {
+                is_rtl: paragraphs
+                    .get(paragraph)
+                    .is_some_and(|paragraph| paragraph.base_level & 1 == 1),
+                paragraph,
+                y: 0.,
+                leading_box,
+            }
+        }));
+
+    layout.data.text_indent = lcx.text_indent;
+    layout.data.diagnostics = lcx.diagnostics;
+    layout.data.spacing_rounding = lcx.spacing_rounding;
+    layout.data.tab_stops = lcx.tab_stops;
+    layout.data.hyphenation_limits = lcx.hyphenation_limits;
+    layout.data.snap_inline_boxes = lcx.snap_inline_boxes;
+    layout.data.links.append(&mut lcx.links);
+    layout.data.text.push_str(text);
+
+    if let Some(brush) = &lcx.replacement_char_policy.brush {
+        apply_replacement_brush(&mut lcx.styles, text, brush);
+    }
+    if lcx.replacement_char_policy.collapse {
+        collapse_replacement_chars(text, &mut lcx.ignorable_ranges);
+    }
 
     let mut char_index = 0;
     for (i, style) in lcx.styles.iter().enumerate() {
@@ -167,7 +545,9 @@ fn build_into_layout<B: Brush>(
     // Note: It's important that this is a stable sort to allow users to control the order of contiguous inline boxes
     lcx.inline_boxes.sort_by_key(|b| b.index);
 
-    {
+    if lcx.paragraph_cache.is_some() && !text.is_empty() {
+        shape_paragraphs_cached(layout, text, lcx, fcx);
+    } else {
         let query = fcx.collection.query(&mut fcx.source_cache);
         super::shape::shape_text(
             &lcx.rcx,
@@ -175,16 +555,252 @@ fn build_into_layout<B: Brush>(
             &lcx.styles,
             &lcx.inline_boxes,
             &lcx.info,
-            lcx.bidi.levels(),
+            &lcx.analysis.bidi_levels,
+            &lcx.glyph_overrides,
+            lcx.fallback_allowlist.as_deref(),
             &mut lcx.scx,
             text,
             layout,
         );
     }
+    layout
+        .data
+        .apply_glyph_overrides(text, &lcx.glyph_overrides);
+
+    layout
+        .data
+        .apply_ignorables(text, &lcx.ignorable_chars, &lcx.ignorable_ranges);
+    layout.data.apply_visibility(&lcx.visibility_ranges);
+    layout.data.apply_hard_breaks(text, &lcx.hard_break_chars);
+    layout.data.mark_soft_hyphens(text);
+    layout.data.mark_emoji_continuations(text);
+    layout.data.mark_grapheme_continuations(text, &lcx.analysis);
+    layout.data.mark_joining_clusters(text);
+    if lcx.merge_runs {
+        layout.data.merge_runs();
+    }
 
     // Move inline boxes into the layout
     layout.data.inline_boxes.clear();
     core::mem::swap(&mut layout.data.inline_boxes, &mut lcx.inline_boxes);
 
     layout.data.finish();
+    #[cfg(feature = "stats")]
+    lcx.stats.clone_from(&layout.data.stats);
+
+    lcx.default_style = Some((default_style.clone(), scale));
+}
+
+/// Returns an identifier which is unique to a build within the program.
+fn next_build_id() -> u64 {
+    static ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+    ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Measures the metrics of an empty line in `style` by shaping a probe, like the layout
+/// of empty text, unless they are already cached for an equivalent style.
+pub(crate) fn update_default_line_metrics<B: Brush>(
+    lcx: &mut LayoutContext<B>,
+    fcx: &mut FontContext,
+    scale: f32,
+    style: &ResolvedStyle<B>,
+) {
+    let key = LineMetricsKey::new(&lcx.rcx, style, fcx.collection.generation());
+    if lcx
+        .default_line_metrics
+        .as_ref()
+        .is_some_and(|(cached, _)| *cached == key)
+    {
+        return;
+    }
+
+    let mut analysis = TextAnalysis::default();
+    analysis.compute(
+        &mut lcx.bidi,
+        "",
+        None,
+        &[],
+        core::iter::empty(),
+        &[],
+        core::iter::empty(),
+    );
+    let info: Vec<_> = analysis.chars.iter().map(|(_, info)| (*info, 0)).collect();
+    let styles = [RangedStyle {
+        style: style.clone(),
+        range: 0..0,
+    }];
+
+    let mut probe = Layout::new();
+    probe.data.scale = scale;
+    probe.data.paragraphs.push(ParagraphData {
+        text_range: 0..0,
+        base_level: 0,
+        alignment: None,
+    });
+    probe.data.styles.push(style.as_layout_style());
+    let query = fcx.collection.query(&mut fcx.source_cache);
+    super::shape::shape_text(
+        &lcx.rcx,
+        query,
+        &styles,
+        &[],
+        &info,
+        &[],
+        &lcx.glyph_overrides,
+        lcx.fallback_allowlist.as_deref(),
+        &mut lcx.scx,
+        "",
+        &mut probe,
+    );
+    probe.data.finish();
+    probe.break_all_lines(None);
+    let metrics = probe
+        .lines()
+        .next()
+        .map(|line| line.metrics())
+        .unwrap_or_default();
+    lcx.default_line_metrics = Some((key, metrics));
+}
+
+/// Splits the styles so that runs of replacement characters use `brush`.
+fn apply_replacement_brush<B: Brush>(styles: &mut Vec<RangedStyle<B>>, text: &str, brush: &B) {
+    if !text.contains(char::REPLACEMENT_CHARACTER) {
+        return;
+    }
+    let mut split = Vec::with_capacity(styles.len());
+    let mut push_segment = |style: &RangedStyle<B>, range, is_replacement| {
+        let mut segment = RangedStyle {
+            style: style.style.clone(),
+            range,
+        };
+        if is_replacement {
+            segment.style.brush = brush.clone();
+        }
+        split.push(segment);
+    };
+    for style in styles.iter() {
+        let mut segment_start = style.range.start;
+        let mut in_replacement = false;
+        for (offset, ch) in text[style.range.clone()].char_indices() {
+            let index = style.range.start + offset;
+            let is_replacement = ch == char::REPLACEMENT_CHARACTER;
+            if is_replacement != in_replacement && index != segment_start {
+                push_segment(style, segment_start..index, in_replacement);
+                segment_start = index;
+            }
+            in_replacement = is_replacement;
+        }
+        push_segment(style, segment_start..style.range.end, in_replacement);
+    }
+    *styles = split;
+}
+
+/// Marks every replacement character which directly follows another one as ignorable.
+fn collapse_replacement_chars(text: &str, ignorable_ranges: &mut Vec<Range<usize>>) {
+    let mut prev_is_replacement = false;
+    for (index, ch) in text.char_indices() {
+        let is_replacement = ch == char::REPLACEMENT_CHARACTER;
+        if is_replacement && prev_is_replacement {
+            ignorable_ranges.push(index..index + ch.len_utf8());
+        }
+        prev_is_replacement = is_replacement;
+    }
+}
+
+/// Shapes each paragraph separately, reusing the results for paragraphs which are
+/// in the paragraph cache.
+///
+/// Paragraphs which contain inline boxes are not cached.
+fn shape_paragraphs_cached<B: Brush>(
+    layout: &mut Layout<B>,
+    text: &str,
+    lcx: &mut LayoutContext<B>,
+    fcx: &mut FontContext,
+) {
+    let Some(cache) = lcx.paragraph_cache.as_mut() else {
+        return;
+    };
+    cache.begin(fcx.collection.generation());
+    let mut query = fcx.collection.query(&mut fcx.source_cache);
+
+    let mut char_start = 0;
+    let mut box_start = 0;
+    for (index, (range, _)) in lcx.analysis.paragraphs.iter().enumerate() {
+        let is_last = index + 1 == lcx.analysis.paragraphs.len();
+        let box_end = if is_last {
+            lcx.inline_boxes.len()
+        } else {
+            box_start
+                + lcx.inline_boxes[box_start..]
+                    .iter()
+                    .take_while(|inline_box| inline_box.index < range.end)
+                    .count()
+        };
+        let boxes = &lcx.inline_boxes[box_start..box_end];
+
+        // Caller-defined hard breaks change the boundaries within the paragraph, so
+        // paragraphs containing them are not cached.
+        let has_hard_breaks = lcx
+            .hard_break_chars
+            .iter()
+            .any(|(ch, _)| text[range.clone()].contains(*ch));
+        let lookup = (boxes.is_empty() && !has_hard_breaks).then(|| {
+            cache.lookup(
+                &lcx.rcx,
+                &lcx.styles,
+                text,
+                range.clone(),
+                lcx.analysis.forced_direction(range),
+            )
+        });
+        #[cfg(feature = "stats")]
+        let run_count = layout.data.runs.len();
+        let is_cached = lookup
+            .as_ref()
+            .is_some_and(|lookup| cache.splice(lookup, range.start, &mut layout.data));
+        #[cfg(feature = "stats")]
+        if is_cached {
+            layout
+                .data
+                .record_runs(run_count, core::time::Duration::ZERO, true);
+        }
+        if !is_cached {
+            let mark = ShapedMark::new(&layout.data);
+            // The warnings of the paragraph are collected on their own, so that they can
+            // be cached with it.
+            let warnings = core::mem::take(&mut layout.data.warnings);
+            super::shape::shape_range(
+                &lcx.rcx,
+                &mut query,
+                &lcx.styles,
+                boxes,
+                box_start,
+                &lcx.info,
+                &lcx.analysis.bidi_levels,
+                &lcx.glyph_overrides,
+                lcx.fallback_allowlist.as_deref(),
+                &mut lcx.scx,
+                text,
+                range.clone(),
+                char_start,
+                layout,
+            );
+            let paragraph_warnings = core::mem::replace(&mut layout.data.warnings, warnings);
+            if let Some(lookup) = lookup {
+                cache.insert(
+                    lookup,
+                    range.start,
+                    &mark,
+                    &paragraph_warnings,
+                    &layout.data,
+                );
+            }
+            for warning in paragraph_warnings {
+                LayoutWarning::push(&mut layout.data.warnings, warning);
+            }
+        }
+
+        char_start += text[range.clone()].chars().count();
+        box_start = box_end;
+    }
 }
@@ -3,30 +3,68 @@
 
 //! Context for layout.
 
-use alloc::{vec, vec::Vec};
+use alloc::{borrow::ToOwned, vec, vec::Vec};
+use core::fmt::Write;
+use core::hash::{Hash, Hasher};
+use core::ops::Range;
 
 use self::tree::TreeStyleBuilder;
 
 use super::FontContext;
+use super::analysis::TextAnalysis;
 use super::bidi;
 use super::builder::RangedBuilder;
+use super::layout::{Alignment, AlignmentOptions, Layout, LineMetrics, ShapedText};
+use super::paragraph_cache::ParagraphCache;
 use super::resolve::{RangedStyle, RangedStyleBuilder, ResolveContext, ResolvedStyle, tree};
-use super::style::{Brush, TextStyle};
+use super::shape;
+use super::style::{
+    Brush, FontFamily, FontSettings, FontStack, FontStyle, FontVariation, FontWeight, FontWidth,
+    HardBreak, HyphenationLimits, Hyphens, ReplacementCharPolicy, SpacingRounding, StyleProperty,
+    TabStops, TextDirection, TextIndent, TextStyle, Visibility,
+};
 
+use fontique::FamilyId;
+use hashbrown::HashMap;
 use swash::shape::ShapeContext;
+use swash::text::Language;
 use swash::text::cluster::CharInfo;
 
+use crate::Font;
 use crate::builder::TreeBuilder;
-use crate::inline_box::InlineBox;
+use crate::inline_box::{InlineBox, LeadingBox};
+use crate::layout::LinkData;
+use crate::util::StableHasher;
 
 /// Shared scratch space used when constructing text layouts.
 ///
 /// This type is designed to be a global resource with only one per-application (or per-thread).
 pub struct LayoutContext<B: Brush = [u8; 4]> {
     pub(crate) bidi: bidi::BidiResolver,
+    /// Forced base direction for all paragraphs, if any.
+    pub(crate) base_direction: Option<TextDirection>,
+    /// Byte offsets within paragraphs whose base direction is forced.
+    pub(crate) paragraph_directions: Vec<(usize, TextDirection)>,
+    /// Byte offsets within paragraphs whose alignment is overridden.
+    pub(crate) paragraph_alignments: Vec<(usize, Alignment)>,
+    /// Boundaries, bidi levels and paragraphs of the text being built.
+    pub(crate) analysis: TextAnalysis,
     pub(crate) rcx: ResolveContext,
     pub(crate) styles: Vec<RangedStyle<B>>,
     pub(crate) inline_boxes: Vec<InlineBox>,
+    pub(crate) leading_boxes: Vec<LeadingBox>,
+    pub(crate) text_indent: TextIndent,
+    pub(crate) links: Vec<LinkData>,
+
+    // Caller-defined text which contributes no advance or glyphs
+    pub(crate) ignorable_chars: Vec<char>,
+    pub(crate) ignorable_ranges: Vec<Range<usize>>,
+    pub(crate) visibility_ranges: Vec<(Range<usize>, Visibility)>,
+
+    pub(crate) replacement_char_policy: ReplacementCharPolicy<B>,
+
+    // Caller-defined characters which force a line or paragraph break
+    pub(crate) hard_break_chars: Vec<(char, HardBreak)>,
 
     // Reusable style builders (to amortise allocations)
     pub(crate) ranged_style_builder: RangedStyleBuilder<B>,
@@ -34,20 +72,557 @@ pub struct LayoutContext<B: Brush = [u8; 4]> {
 
     pub(crate) info: Vec<(CharInfo, u16)>,
     pub(crate) scx: ShapeContext,
+
+    // Opt-in cache of shaped paragraphs
+    pub(crate) paragraph_cache: Option<ParagraphCache>,
+
+    // Glyphs which replace the shaping of individual characters
+    pub(crate) glyph_overrides: HashMap<char, GlyphOverride>,
+
+    // The only fonts which are used as fallbacks, if restricted
+    pub(crate) fallback_allowlist: Option<Vec<Font>>,
+
+    // Opt-in coalescing of adjacent runs after shaping
+    pub(crate) merge_runs: bool,
+
+    // Opt-in reporting of font fallback and missing glyphs
+    pub(crate) diagnostics: bool,
+
+    // Rounding of word and letter spacing
+    pub(crate) spacing_rounding: SpacingRounding,
+
+    // Positions that tabs advance to
+    pub(crate) tab_stops: TabStops,
+
+    // Limits on hyphenation at soft hyphens
+    pub(crate) hyphenation_limits: HyphenationLimits,
+
+    // Whether inline box positions are rounded to whole pixels
+    pub(crate) snap_inline_boxes: bool,
+
+    // Statistics of the most recent build
+    #[cfg(feature = "stats")]
+    pub(crate) stats: crate::LayoutStats,
+
+    // Metrics of an empty line in the default style that they were last measured for
+    pub(crate) default_line_metrics: Option<(LineMetricsKey, LineMetrics)>,
+
+    // Default style and scale of the most recent build, while its styles are retained
+    pub(crate) default_style: Option<(ResolvedStyle<B>, f32)>,
+
+    // Identifier of the most recent build, while its styles and analysis are retained
+    pub(crate) last_build_id: Option<u64>,
+}
+
+/// The properties of a default style which determine the metrics of its lines.
+///
+/// Font stacks and settings are stored by value, as their resolved handles are only
+/// valid until the next build.
+#[derive(Clone, PartialEq)]
+pub(crate) struct LineMetricsKey {
+    /// Generation of the font collection that the metrics were measured with.
+    generation: u64,
+    families: Vec<FamilyId>,
+    font_size: f32,
+    font_width: FontWidth,
+    font_style: FontStyle,
+    font_weight: FontWeight,
+    variations: Vec<FontVariation>,
+    locale: Option<Language>,
+    line_height: f32,
+    min_line_height: f32,
+}
+
+impl LineMetricsKey {
+    pub(crate) fn new<B: Brush>(
+        rcx: &ResolveContext,
+        style: &ResolvedStyle<B>,
+        generation: u64,
+    ) -> Self {
+        Self {
+            generation,
+            families: rcx.stack(style.font_stack).unwrap_or(&[]).into(),
+            font_size: style.font_size,
+            font_width: style.font_width,
+            font_style: style.font_style,
+            font_weight: style.font_weight,
+            variations: rcx.variations(style.font_variations).unwrap_or(&[]).into(),
+            locale: style.locale,
+            line_height: style.line_height,
+            min_line_height: style.min_line_height,
+        }
+    }
+}
+
+/// A glyph which is used for a character instead of shaping it.
+///
+/// This is useful for icon fonts which place their glyphs at private use code points.
+/// See [`LayoutContext::set_glyph_override`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct GlyphOverride {
+    /// The font containing the glyph.
+    pub font: Font,
+    /// Identifier of the glyph in the font.
+    pub glyph_id: u16,
+    /// Advance of the glyph, as a multiple of the font size.
+    pub advance: f32,
 }
 
 impl<B: Brush> LayoutContext<B> {
     pub fn new() -> Self {
         Self {
             bidi: bidi::BidiResolver::new(),
+            base_direction: None,
+            paragraph_directions: vec![],
+            paragraph_alignments: vec![],
+            analysis: TextAnalysis::default(),
             rcx: ResolveContext::default(),
             styles: vec![],
             inline_boxes: vec![],
+            leading_boxes: vec![],
+            text_indent: TextIndent::default(),
+            links: vec![],
+            ignorable_chars: vec![],
+            ignorable_ranges: vec![],
+            visibility_ranges: vec![],
+            replacement_char_policy: ReplacementCharPolicy::default(),
+            hard_break_chars: vec![],
             ranged_style_builder: RangedStyleBuilder::default(),
             tree_style_builder: TreeStyleBuilder::default(),
             info: vec![],
             scx: ShapeContext::default(),
+            paragraph_cache: None,
+            glyph_overrides: HashMap::new(),
+            fallback_allowlist: None,
+            merge_runs: false,
+            diagnostics: false,
+            spacing_rounding: SpacingRounding::None,
+            tab_stops: TabStops::None,
+            hyphenation_limits: HyphenationLimits::default(),
+            snap_inline_boxes: false,
+            #[cfg(feature = "stats")]
+            stats: crate::LayoutStats::default(),
+            default_line_metrics: None,
+            default_style: None,
+            last_build_id: None,
+        }
+    }
+
+    /// Enables caching of shaped paragraphs, keeping at most `capacity` of them.
+    ///
+    /// Paragraphs (segments of text separated by hard breaks) whose text and
+    /// styles are unchanged since a previous build reuse their shaping results
+    /// instead of being shaped again. The cache is invalidated automatically
+    /// whenever the font collection changes.
+    ///
+    /// Only shaping is cached. Text analysis, line breaking and alignment are
+    /// always recomputed for the whole text, so the cost of a build still grows
+    /// with the length of the text. When the cache is full, the least recently
+    /// used paragraph is found by scanning every entry, so `capacity` should be
+    /// kept moderate.
+    ///
+    /// This is useful for large documents where only a few paragraphs change
+    /// between builds, and shaping dominates the cost of building them.
+    pub fn enable_paragraph_cache(&mut self, capacity: usize) {
+        self.paragraph_cache = Some(ParagraphCache::new(capacity));
+    }
+
+    /// Disables caching of shaped paragraphs and frees the cache.
+    pub fn disable_paragraph_cache(&mut self) {
+        self.paragraph_cache = None;
+    }
+
+    /// Removes all paragraphs from the cache, if enabled.
+    pub fn clear_paragraph_cache(&mut self) {
+        if let Some(cache) = &mut self.paragraph_cache {
+            cache.clear();
+        }
+    }
+
+    /// Uses `glyph` for every occurrence of `ch` in layouts built afterwards, rather
+    /// than shaping it with the fonts of its style.
+    ///
+    /// The character is laid out in its own run using the font of the glyph, at the
+    /// font size of its style.
+    pub fn set_glyph_override(&mut self, ch: char, glyph: GlyphOverride) {
+        self.glyph_overrides.insert(ch, glyph);
+        self.clear_paragraph_cache();
+    }
+
+    /// Removes the glyph override for `ch`, returning it if there was one.
+    pub fn remove_glyph_override(&mut self, ch: char) -> Option<GlyphOverride> {
+        let glyph = self.glyph_overrides.remove(&ch);
+        if glyph.is_some() {
+            self.clear_paragraph_cache();
+        }
+        glyph
+    }
+
+    /// Restricts font fallback in layouts built afterwards to `fonts`, which are tried
+    /// in order.
+    ///
+    /// Text is shaped with the families of its font stack as usual, but the fallback
+    /// families of the font collection, including system fonts, are not searched.
+    /// Characters which none of these fonts cover are shown as missing glyphs from the
+    /// closest match. This makes layouts reproducible regardless of the fonts installed,
+    /// and bounds the cost of fallback.
+    pub fn set_fallback_allowlist(&mut self, fonts: Vec<Font>) {
+        self.fallback_allowlist = Some(fonts);
+        self.clear_paragraph_cache();
+        self.default_line_metrics = None;
+    }
+
+    /// Removes the restriction of [`Self::set_fallback_allowlist`], so that font
+    /// fallback searches the font collection again.
+    pub fn clear_fallback_allowlist(&mut self) {
+        if self.fallback_allowlist.take().is_some() {
+            self.clear_paragraph_cache();
+            self.default_line_metrics = None;
+        }
+    }
+
+    /// Sets whether adjacent runs which share a font, font size, brush and direction
+    /// are merged into a single run in layouts built afterwards.
+    ///
+    /// Shaping can split text which looks uniform into several runs, such as where the
+    /// locale or script changes. Merging them reduces the number of glyph runs that a
+    /// renderer has to draw. Glyph order and the mapping of clusters onto the text are
+    /// unchanged.
+    pub fn set_merge_runs(&mut self, merge: bool) {
+        self.merge_runs = merge;
+    }
+
+    /// Sets whether layouts built afterwards report the diagnostic warnings
+    /// [`LayoutWarning::FallbackFont`] and [`LayoutWarning::MissingGlyphs`].
+    ///
+    /// These explain why text looks wrong, such as being shown as notdef boxes, and are
+    /// retrieved with [`Layout::warnings`](crate::Layout::warnings). Building still
+    /// succeeds either way. Changing this setting clears the paragraph cache, as its
+    /// paragraphs were shaped with the previous setting.
+    ///
+    /// [`LayoutWarning::FallbackFont`]: crate::LayoutWarning::FallbackFont
+    /// [`LayoutWarning::MissingGlyphs`]: crate::LayoutWarning::MissingGlyphs
+    pub fn set_diagnostics(&mut self, enabled: bool) {
+        if self.diagnostics != enabled {
+            self.diagnostics = enabled;
+            self.clear_paragraph_cache();
+        }
+    }
+
+    /// Sets how word and letter spacing are rounded in layouts built afterwards.
+    ///
+    /// Fractional spacing makes the positions of clusters drift off a pixel or character
+    /// grid. With rounding, the spacing of each cluster is adjusted when the layout is
+    /// aligned, so that it ends on the grid, measured from the start of its line. The
+    /// rounding error is carried to the next cluster, so the width of each line stays
+    /// within half a grid unit of its exact width. Clusters without spacing are
+    /// unchanged.
+    pub fn set_spacing_rounding(&mut self, rounding: SpacingRounding) {
+        self.spacing_rounding = rounding;
+    }
+
+    /// Sets whether the positions of inline boxes are rounded to whole device pixels in
+    /// layouts built afterwards, so that images and widgets are not drawn blurred.
+    ///
+    /// Layouts are measured in device pixels, as font sizes are multiplied by the scale
+    /// of the layout, so the rounded positions are integers. Only the position reported
+    /// for each box is rounded, and the text around it is not moved. Text is rounded
+    /// separately, with [`Self::set_spacing_rounding`].
+    pub fn set_snap_inline_boxes(&mut self, enabled: bool) {
+        self.snap_inline_boxes = enabled;
+    }
+
+    /// Sets the tab stops that tabs advance to in layouts built afterwards.
+    ///
+    /// Each tab is widened to reach the next tab stop after its position on the line,
+    /// which is measured from the start of the line's content. See [`TabStops::Every`]
+    /// for how this composes with the text indent.
+    pub fn set_tab_stops(&mut self, tab_stops: TabStops) {
+        self.tab_stops = tab_stops;
+    }
+
+    /// Sets the limits on hyphenation at soft hyphens in layouts built afterwards, such
+    /// as to avoid leaving a short fragment of a word on either line.
+    ///
+    /// Soft hyphens which the limits don't allow are not line break opportunities, like
+    /// those with [`Hyphens::None`].
+    pub fn set_hyphenation_limits(&mut self, limits: HyphenationLimits) {
+        self.hyphenation_limits = limits;
+    }
+
+    /// Returns the advance of a tab which starts `x` from the start of a line, with the
+    /// tab stops set by [`Self::set_tab_stops`].
+    ///
+    /// This matches the advance of tabs in layouts built with this context, for callers
+    /// measuring text of their own. Returns `0.0` if tabs keep the advance of the font,
+    /// which isn't known without shaping.
+    pub fn tab_advance(&self, x: f32) -> f32 {
+        self.tab_stops.advance(x).unwrap_or(0.)
+    }
+
+    /// Returns a fingerprint of the inputs of a layout, for use as a cache key.
+    ///
+    /// The inputs are those of a layout built with [`Self::ranged_builder`] from `text`
+    /// at `scale`, with each property of `styles` pushed for its range, then broken at
+    /// `max_width` and aligned with `alignment` and `options`. The fingerprint also covers
+    /// the settings of this context which affect the layout, such as tab stops and glyph
+    /// overrides.
+    ///
+    /// Identical inputs have the same fingerprint in every run of a program, and a change
+    /// to any of them changes it, barring hash collisions. Changes to the font collection
+    /// are not covered, so a cache keyed by fingerprints must be cleared when fonts are
+    /// registered. Fonts given to this context are fingerprinted by the checksums of
+    /// their tables, and brushes by their `Debug` representation.
+    pub fn input_fingerprint(
+        &self,
+        text: &str,
+        scale: f32,
+        styles: &[(StyleProperty<'_, B>, Range<usize>)],
+        max_width: Option<f32>,
+        alignment: Alignment,
+        options: &AlignmentOptions,
+    ) -> u64 {
+        let mut hasher = StableHasher::default();
+        text.hash(&mut hasher);
+        scale.to_bits().hash(&mut hasher);
+        styles.len().hash(&mut hasher);
+        for (property, range) in styles {
+            hash_property(property, &mut hasher);
+            range.hash(&mut hasher);
         }
+        max_width.map(f32::to_bits).hash(&mut hasher);
+        core::mem::discriminant(&alignment).hash(&mut hasher);
+        if let Alignment::Numeric { separator } = alignment {
+            separator.hash(&mut hasher);
+        }
+        options.align_when_overflowing.hash(&mut hasher);
+        core::mem::discriminant(&options.justify_content).hash(&mut hasher);
+        options.numeric_position.to_bits().hash(&mut hasher);
+        core::mem::discriminant(&self.tab_stops).hash(&mut hasher);
+        if let TabStops::Every(interval) = self.tab_stops {
+            interval.to_bits().hash(&mut hasher);
+        }
+        let limits = &self.hyphenation_limits;
+        (limits.min_word_len, limits.min_prefix, limits.min_suffix).hash(&mut hasher);
+        limits.max_consecutive_hyphenated_lines.hash(&mut hasher);
+        core::mem::discriminant(&self.spacing_rounding).hash(&mut hasher);
+        if let SpacingRounding::Grid(unit) = self.spacing_rounding {
+            unit.to_bits().hash(&mut hasher);
+        }
+        (self.merge_runs, self.snap_inline_boxes).hash(&mut hasher);
+        // Overrides are sorted, as the iteration order of the map is not stable.
+        let mut overrides = self.glyph_overrides.iter().collect::<Vec<_>>();
+        overrides.sort_unstable_by_key(|(ch, _)| **ch);
+        for (ch, glyph) in overrides {
+            ch.hash(&mut hasher);
+            hash_font(&glyph.font, &mut hasher);
+            glyph.glyph_id.hash(&mut hasher);
+            glyph.advance.to_bits().hash(&mut hasher);
+        }
+        // An empty allowlist disables fallback, unlike no allowlist.
+        self.fallback_allowlist
+            .as_ref()
+            .map(Vec::len)
+            .hash(&mut hasher);
+        for font in self.fallback_allowlist.iter().flatten() {
+            hash_font(font, &mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Returns the statistics of the most recent build with this context.
+    ///
+    /// Line breaking and alignment happen after building, so they are recorded in
+    /// [`Layout::stats`] instead.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &crate::LayoutStats {
+        &self.stats
+    }
+
+    /// Returns the metrics of an empty line in the default style of the most recent
+    /// build with this context.
+    ///
+    /// This is the size of the caret in an empty document or on a final empty line. The
+    /// metrics are measured by shaping a probe when they are first requested, which is
+    /// only repeated when the default style or the font collection changes. Once another
+    /// builder has been created, the metrics last measured are returned. Returns zeroed
+    /// metrics if nothing has been measured yet.
+    pub fn default_line_metrics(&mut self, fcx: &mut FontContext) -> LineMetrics {
+        if let Some((style, scale)) = self.default_style.take() {
+            crate::builder::update_default_line_metrics(self, fcx, scale, &style);
+            self.default_style = Some((style, scale));
+        }
+        self.default_line_metrics
+            .as_ref()
+            .map(|(_, metrics)| *metrics)
+            .unwrap_or_default()
+    }
+
+    /// Returns how much the width of `range` would change if `property` were applied
+    /// to it, such as for previewing a style change.
+    ///
+    /// Only the runs which intersect `range` are shaped again, which is much cheaper than
+    /// building the layout again. The result does not account for any change in line
+    /// breaking.
+    ///
+    /// The runs are shaped with the styles which `layout` was built with, which this
+    /// context only retains until the next builder is created. Returns `None` if `layout`
+    /// is not the layout most recently built with this context.
+    pub fn width_delta<'a>(
+        &mut self,
+        fcx: &mut FontContext,
+        layout: &Layout<B>,
+        range: Range<usize>,
+        property: impl Into<StyleProperty<'a, B>>,
+    ) -> Option<f32> {
+        if self.last_build_id != Some(layout.data.build_id) {
+            return None;
+        }
+        let text = layout.data.text.as_str();
+        let range = range.start.min(text.len())..range.end.min(text.len());
+        if range.is_empty() {
+            return Some(0.);
+        }
+        let Some(span) = layout
+            .data
+            .runs
+            .iter()
+            .map(|run| run.text_range.clone())
+            .filter(|run_range| run_range.start < range.end && range.start < run_range.end)
+            .reduce(|span, run_range| span.start.min(run_range.start)..span.end.max(run_range.end))
+        else {
+            return Some(0.);
+        };
+
+        // Apply the property to a copy of each style, and use the copies within the range.
+        let property = self
+            .rcx
+            .resolve_property(fcx, &property.into(), layout.data.scale);
+        let style_count = self.styles.len() as u16;
+        let mut styles = self.styles.clone();
+        styles.extend(self.styles.iter().map(|style| {
+            let mut style = style.clone();
+            style.style.apply(property.clone());
+            style
+        }));
+        let mut info = self.info.clone();
+        for ((offset, _), (_, style_index)) in self.analysis.chars.iter().zip(&mut info) {
+            if range.contains(offset) {
+                *style_index += style_count;
+            }
+        }
+
+        let char_start = self
+            .analysis
+            .chars
+            .partition_point(|(offset, _)| *offset < span.start);
+        let mut measure = |styles: &[RangedStyle<B>], info: &[(CharInfo, u16)]| {
+            let mut scratch = Layout::new();
+            let mut query = fcx.collection.query(&mut fcx.source_cache);
+            shape::shape_range(
+                &self.rcx,
+                &mut query,
+                styles,
+                &[],
+                0,
+                info,
+                &self.analysis.bidi_levels,
+                &self.glyph_overrides,
+                self.fallback_allowlist.as_deref(),
+                &mut self.scx,
+                text,
+                span.clone(),
+                char_start,
+                &mut scratch,
+            );
+            let data = &mut scratch.data;
+            data.apply_glyph_overrides(text, &self.glyph_overrides);
+            data.apply_ignorables(text, &self.ignorable_chars, &self.ignorable_ranges);
+            data.apply_visibility(&self.visibility_ranges);
+            data.apply_hard_breaks(text, &self.hard_break_chars);
+            data.mark_joining_clusters(text);
+            data.finish();
+            data.clusters
+                .iter()
+                .map(|cluster| cluster.advance)
+                .sum::<f32>()
+        };
+        let before = measure(&self.styles, &self.info);
+        Some(measure(&styles, &info) - before)
+    }
+
+    /// Returns the length in bytes of the longest prefix of `text` which fits on a
+    /// single line of `max_width` and has at most `max_chars` characters, such as for an
+    /// input field with a character limit.
+    ///
+    /// The prefix always ends at a grapheme boundary, so a grapheme which would exceed
+    /// either limit is excluded entirely.
+    pub fn fit_text(
+        &mut self,
+        fcx: &mut FontContext,
+        text: &str,
+        style: &TextStyle<'_, B>,
+        max_width: f32,
+        max_chars: usize,
+    ) -> usize {
+        if text.is_empty() {
+            return 0;
+        }
+        let mut builder = self.tree_builder(fcx, 1.0, style);
+        builder.push_text(text);
+        let (layout, _) = builder.build();
+
+        let mut width = 0.;
+        let mut chars = 0;
+        let mut fit = 0;
+        for run in &layout.data.runs {
+            for cluster in &layout.data.clusters[run.cluster_range.clone()] {
+                let range = cluster.text_range(run);
+                if self
+                    .analysis
+                    .boundary(range.start)
+                    .is_some_and(|boundary| boundary.grapheme)
+                {
+                    fit = range.start;
+                }
+                width += cluster.advance;
+                chars += text[range].chars().count();
+                if width > max_width || chars > max_chars {
+                    return fit;
+                }
+            }
+        }
+        text.len()
+    }
+
+    /// Returns the height of `text` in `style` when its lines are broken at `width` and
+    /// only the first `max_lines` are kept, such as for sizing a container with a line
+    /// clamp.
+    ///
+    /// Lines after the first `max_lines` are not broken at all.
+    pub fn auto_height(
+        &mut self,
+        fcx: &mut FontContext,
+        text: &str,
+        style: &TextStyle<'_, B>,
+        width: f32,
+        max_lines: usize,
+    ) -> f32 {
+        if max_lines == 0 {
+            return 0.;
+        }
+        let mut builder = self.tree_builder(fcx, 1.0, style);
+        builder.push_text(text);
+        let (mut layout, _) = builder.build();
+
+        let mut lines = layout.break_lines();
+        for _ in 0..max_lines {
+            if lines.break_next(width).is_none() {
+                break;
+            }
+        }
+        lines.finish();
+        layout.height()
     }
 
     fn resolve_style_set(
@@ -78,6 +653,26 @@ impl<B: Brush> LayoutContext<B> {
         }
     }
 
+    /// Shapes `text` with the given styles, without breaking it into lines.
+    ///
+    /// Each style applies to its range of the text, and later styles take precedence
+    /// where they overlap. The result can be broken at several widths with
+    /// [`ShapedText::break_and_align`], which is much cheaper than building a layout for
+    /// each width.
+    pub fn shape<'a>(
+        &mut self,
+        fcx: &mut FontContext,
+        text: &str,
+        scale: f32,
+        styles: impl IntoIterator<Item = (StyleProperty<'a, B>, Range<usize>)>,
+    ) -> ShapedText<B> {
+        let mut builder = self.ranged_builder(fcx, text, scale);
+        for (property, range) in styles {
+            builder.push(property, range);
+        }
+        builder.shape(text)
+    }
+
     pub fn tree_builder<'a>(
         &'a mut self,
         fcx: &'a mut FontContext,
@@ -98,51 +693,185 @@ impl<B: Brush> LayoutContext<B> {
         }
     }
 
-    pub(crate) fn analyze_text(&mut self, text: &str) {
-        let text = if text.is_empty() { " " } else { text };
-        let mut a = swash::text::analyze(text.chars());
-
-        let mut word_break = Default::default();
-        let mut style_idx = 0;
+    /// Analyzes the boundaries and bidi levels of `text`, independently of building
+    /// a layout.
+    ///
+    /// Boundaries follow the default Unicode rules, which layout applies for every
+    /// locale.
+    pub fn analyze(&mut self, text: &str) -> TextAnalysis {
+        let mut analysis = TextAnalysis::default();
+        analysis.compute(
+            &mut self.bidi,
+            text,
+            None,
+            &[],
+            core::iter::empty(),
+            &[],
+            core::iter::empty(),
+        );
+        analysis
+    }
 
-        let mut char_indices = text.char_indices();
-        loop {
-            let Some((char_idx, _)) = char_indices.next() else {
-                break;
-            };
-
-            // Find the style for this character. If the text is empty, we may not have any styles. Otherwise,
-            // self.styles should span the entire range of the text.
-            while let Some(style) = self.styles.get(style_idx) {
-                if style.range.end > char_idx {
-                    word_break = style.style.word_break;
-                    break;
-                }
-                style_idx += 1;
+    /// Analyzes the text being built, reusing `analysis` if it was computed for the
+    /// same text and styles.
+    pub(crate) fn analyze_text(&mut self, text: &str, analysis: Option<&TextAnalysis>) {
+        let styles = &self.styles;
+        let word_breaks = || {
+            styles
+                .iter()
+                .map(|style| (style.range.clone(), style.style.word_break))
+        };
+        let no_hyphens = || {
+            styles
+                .iter()
+                .filter(|style| style.style.hyphens == Hyphens::None)
+                .map(|style| style.range.clone())
+        };
+        match analysis {
+            Some(analysis)
+                if analysis.matches(
+                    text,
+                    self.base_direction,
+                    &self.paragraph_directions,
+                    word_breaks(),
+                    &self.hard_break_chars,
+                    no_hyphens(),
+                ) =>
+            {
+                self.analysis.clone_from(analysis);
             }
-            a.set_break_strength(word_break);
-
-            let Some((properties, boundary)) = a.next() else {
-                break;
-            };
-
-            self.info.push((CharInfo::new(properties, boundary), 0));
-        }
-        if a.needs_bidi_resolution() {
-            self.bidi.resolve(
-                text.chars()
-                    .zip(self.info.iter().map(|info| info.0.bidi_class())),
-                None,
-            );
+            _ => self.analysis.compute(
+                &mut self.bidi,
+                text,
+                self.base_direction,
+                &self.paragraph_directions,
+                word_breaks(),
+                &self.hard_break_chars,
+                no_hyphens(),
+            ),
         }
+        self.info
+            .extend(self.analysis.chars.iter().map(|(_, info)| (*info, 0)));
     }
 
     fn begin(&mut self) {
+        self.last_build_id = None;
+        self.default_style = None;
         self.rcx.clear();
         self.styles.clear();
         self.inline_boxes.clear();
+        self.leading_boxes.clear();
+        self.text_indent = TextIndent::default();
+        self.links.clear();
+        self.ignorable_chars.clear();
+        self.ignorable_ranges.clear();
+        self.visibility_ranges.clear();
+        self.replacement_char_policy = ReplacementCharPolicy::default();
+        self.hard_break_chars.clear();
         self.info.clear();
         self.bidi.clear();
+        self.base_direction = None;
+        self.paragraph_directions.clear();
+        self.paragraph_alignments.clear();
+    }
+}
+
+/// Feeds the fields of `property` to `hasher`.
+///
+/// Brushes are only required to implement `Debug`, so they are hashed by their debug
+/// representation.
+fn hash_property<B: Brush>(property: &StyleProperty<'_, B>, hasher: &mut StableHasher) {
+    fn hash_family(family: &FontFamily<'_>, hasher: &mut StableHasher) {
+        match family {
+            FontFamily::Named(name) => (0_u8, name.as_ref()).hash(hasher),
+            FontFamily::Generic(generic) => (1_u8, *generic as u8).hash(hasher),
+        }
+    }
+    fn hash_settings<T>(
+        settings: &FontSettings<'_, swash::Setting<T>>,
+        value: impl Fn(&T) -> u32,
+        hasher: &mut StableHasher,
+    ) where
+        [swash::Setting<T>]: ToOwned,
+        <[swash::Setting<T>] as ToOwned>::Owned: core::fmt::Debug + PartialEq + Clone,
+    {
+        match settings {
+            FontSettings::Source(source) => (0_u8, source.as_ref()).hash(hasher),
+            FontSettings::List(list) => {
+                (1_u8, list.len()).hash(hasher);
+                for setting in list.iter() {
+                    (setting.tag, value(&setting.value)).hash(hasher);
+                }
+            }
+        }
+    }
+
+    core::mem::discriminant(property).hash(hasher);
+    match property {
+        StyleProperty::FontStack(stack) => match stack {
+            FontStack::Source(source) => (0_u8, source.as_ref()).hash(hasher),
+            FontStack::Single(family) => {
+                1_u8.hash(hasher);
+                hash_family(family, hasher);
+            }
+            FontStack::List(families) => {
+                (2_u8, families.len()).hash(hasher);
+                for family in families.iter() {
+                    hash_family(family, hasher);
+                }
+            }
+        },
+        StyleProperty::FontSize(value)
+        | StyleProperty::LineHeight(value)
+        | StyleProperty::MinLineHeight(value)
+        | StyleProperty::WordSpacing(value)
+        | StyleProperty::LetterSpacing(value) => value.to_bits().hash(hasher),
+        StyleProperty::FontWidth(width) => width.ratio().to_bits().hash(hasher),
+        StyleProperty::FontStyle(style) => {
+            core::mem::discriminant(style).hash(hasher);
+            if let FontStyle::Oblique(angle) = style {
+                angle.map(f32::to_bits).hash(hasher);
+            }
+        }
+        StyleProperty::FontWeight(weight) => weight.value().to_bits().hash(hasher),
+        StyleProperty::FontVariations(settings) => {
+            hash_settings(settings, |value| value.to_bits(), hasher);
+        }
+        StyleProperty::FontFeatures(settings) => {
+            hash_settings(settings, |value| u32::from(*value), hasher);
+        }
+        StyleProperty::Locale(locale) => locale.hash(hasher),
+        StyleProperty::Brush(brush) => {
+            let _ = write!(hasher, "{brush:?}");
+        }
+        StyleProperty::UnderlineBrush(brush) | StyleProperty::StrikethroughBrush(brush) => {
+            let _ = write!(hasher, "{brush:?}");
+        }
+        StyleProperty::Underline(enabled) | StyleProperty::Strikethrough(enabled) => {
+            enabled.hash(hasher);
+        }
+        StyleProperty::UnderlineOffset(value)
+        | StyleProperty::UnderlineSize(value)
+        | StyleProperty::StrikethroughOffset(value)
+        | StyleProperty::StrikethroughSize(value) => value.map(f32::to_bits).hash(hasher),
+        StyleProperty::WordBreak(strength) => core::mem::discriminant(strength).hash(hasher),
+        StyleProperty::OverflowWrap(wrap) => (*wrap as u8).hash(hasher),
+        StyleProperty::TextTransform(transform) => core::mem::discriminant(transform).hash(hasher),
+        StyleProperty::Hyphens(hyphens) => core::mem::discriminant(hyphens).hash(hasher),
+    }
+}
+
+/// Feeds a fingerprint of the data of `font` to `hasher`, from the checksums and
+/// lengths of its tables.
+///
+/// The identifier of a blob is assigned when it is created, so it differs between runs
+/// of a program.
+fn hash_font(font: &Font, hasher: &mut StableHasher) {
+    (font.data.as_ref().len(), font.index).hash(hasher);
+    if let Ok(font_ref) = skrifa::FontRef::from_index(font.data.as_ref(), font.index) {
+        for record in font_ref.table_directory.table_records() {
+            (record.tag(), record.checksum(), record.length()).hash(hasher);
+        }
     }
 }
 
@@ -154,7 +883,18 @@ impl<B: Brush> Default for LayoutContext<B> {
 
 impl<B: Brush> Clone for LayoutContext<B> {
     fn clone(&self) -> Self {
-        // None of the internal state is visible so just return a new instance.
-        Self::new()
+        // Only the settings are cloned, as the rest of the state is reset by each build.
+        Self {
+            paragraph_cache: self.paragraph_cache.clone(),
+            glyph_overrides: self.glyph_overrides.clone(),
+            fallback_allowlist: self.fallback_allowlist.clone(),
+            merge_runs: self.merge_runs,
+            diagnostics: self.diagnostics,
+            spacing_rounding: self.spacing_rounding,
+            tab_stops: self.tab_stops,
+            hyphenation_limits: self.hyphenation_limits,
+            snap_inline_boxes: self.snap_inline_boxes,
+            ..Self::new()
+        }
     }
 }
@@ -1,7 +1,9 @@
 // Copyright 2021 the Parley Authors
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use fontique::Collection;
+use core::fmt;
+
+use fontique::{Blob, Collection, CollectionOptions, GenericFamily};
 
 use fontique::SourceCache;
 
@@ -19,4 +21,60 @@ impl FontContext {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Create a new `FontContext` which never uses system fonts, so that identical
+    /// inputs produce identical layouts on every platform.
+    ///
+    /// The fonts in `last_resort` are registered and the first of their families is
+    /// used for any text which no font in the style's font stack or the registered
+    /// fallbacks supports. Other fonts, generic families and fallbacks must be
+    /// registered with the [`Collection`]. Parley never consults the platform's
+    /// locale, so text without a [`Locale`](crate::StyleProperty::Locale) style is
+    /// shaped the same way everywhere.
+    ///
+    /// Use [`RangedBuilder::try_build`](crate::RangedBuilder::try_build) or
+    /// [`TreeBuilder::try_build`](crate::TreeBuilder::try_build) to detect styles
+    /// which use generic families that have not been registered.
+    pub fn hermetic(last_resort: Blob<u8>) -> Result<Self, FontError> {
+        let mut collection = Collection::new(CollectionOptions {
+            shared: false,
+            system_fonts: false,
+        });
+        let family = collection
+            .register_fonts(last_resort, None)
+            .first()
+            .map(|(family, _)| *family)
+            .ok_or(FontError::NoLastResortFont)?;
+        collection.set_last_resort(Some(family));
+        Ok(Self {
+            collection,
+            source_cache: SourceCache::default(),
+        })
+    }
+}
+
+/// Errors from font selection which would otherwise make layout silently depend on
+/// the available fonts.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FontError {
+    /// The data for the last-resort font did not contain any fonts.
+    NoLastResortFont,
+    /// Text was styled with a generic family for which no families are registered.
+    MissingGenericFamily(GenericFamily),
 }
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoLastResortFont => f.write_str("the last-resort font data contains no fonts"),
+            Self::MissingGenericFamily(family) => {
+                write!(
+                    f,
+                    "no families are registered for the generic family {family:?}"
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for FontError {}
@@ -15,3 +15,22 @@ pub struct InlineBox {
     /// The height of the box in pixels
     pub height: f32,
 }
+
+/// A box at the leading edge of a paragraph, which the first lines of the paragraph
+/// wrap beside, such as an avatar next to a chat message.
+///
+/// The box reserves its width at the left edge of left-to-right paragraphs and at
+/// the right edge of right-to-left paragraphs, for every line which starts above its
+/// bottom edge.
+#[derive(Debug, Clone)]
+pub struct LeadingBox {
+    /// User-specified identifier for the box, which can be used by the user to determine which box in
+    /// parley's output corresponds to which box in its input.
+    pub id: u64,
+    /// A byte offset into the paragraph that the box is attached to.
+    pub index: usize,
+    /// The width of the box in pixels
+    pub width: f32,
+    /// The height of the box in pixels
+    pub height: f32,
+}
@@ -0,0 +1,159 @@
+// Copyright 2025 the Parley Authors
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Convenience API for laying out labels.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::{
+    Alignment, AlignmentOptions, Brush, EllipsisPosition, FontContext, Layout, LayoutContext,
+    TextStyle, TruncationMarker,
+};
+
+const ELLIPSIS: &str = "\u{2026}";
+
+/// Lays out `text` in a single style, breaking lines at `max_width` and aligning them
+/// within it.
+///
+/// This builds the layout with [`LayoutContext::tree_builder`], [`Layout::break_all_lines`]
+/// and [`Layout::align`], which remain available for layouts that need more control. The
+/// contexts are reused between calls, as they would be by those methods.
+pub fn layout_label<B: Brush>(
+    text: &str,
+    style: &TextStyle<'_, B>,
+    max_width: Option<f32>,
+    alignment: Alignment,
+    fcx: &mut FontContext,
+    lcx: &mut LayoutContext<B>,
+) -> Layout<B> {
+    let mut builder = lcx.tree_builder(fcx, 1.0, style);
+    builder.push_text(text);
+    let (mut layout, _) = builder.build();
+    layout.break_all_lines(max_width);
+    layout.align(max_width, alignment, AlignmentOptions::default());
+    layout
+}
+
+/// Lays out `text` in a single style on one line, replacing the text which doesn't fit
+/// in `max_width` with a marker at `position`, and aligns it within `max_width`.
+///
+/// The marker is an ellipsis in the style of the text, unless `marker` is given. Its
+/// width is reserved before the text is truncated. Text is removed in whole grapheme
+/// clusters, in logical order, so the remaining text is reordered for bidi as usual.
+/// Returns the layout along with the text it was built from, which includes the marker
+/// if the text was truncated.
+#[allow(clippy::too_many_arguments)]
+pub fn layout_truncated_label<B: Brush>(
+    text: &str,
+    style: &TextStyle<'_, B>,
+    max_width: f32,
+    alignment: Alignment,
+    position: EllipsisPosition,
+    marker: Option<&TruncationMarker<'_, B>>,
+    fcx: &mut FontContext,
+    lcx: &mut LayoutContext<B>,
+) -> (Layout<B>, String) {
+    let (layout, text) = build_line(text, style, fcx, lcx);
+    let (mut layout, text) = if layout.width() <= max_width {
+        (layout, text)
+    } else {
+        let (marker_text, marker_style) = match marker {
+            Some(marker) => (marker.text, &marker.style),
+            None => (ELLIPSIS, style),
+        };
+        let (marker_layout, marker_text) = build_line(marker_text, marker_style, fcx, lcx);
+        let (head, tail) = truncate(&layout, max_width - marker_layout.width(), position);
+        let mut builder = lcx.tree_builder(fcx, 1.0, style);
+        builder.push_text(&text[..head]);
+        builder.push_style_span(marker_style.clone());
+        builder.push_text(&marker_text);
+        builder.pop_style_span();
+        builder.push_text(&text[tail.max(head)..]);
+        let (mut layout, text) = builder.build();
+        layout.break_all_lines(None);
+        (layout, text)
+    };
+    layout.align(Some(max_width), alignment, AlignmentOptions::default());
+    (layout, text)
+}
+
+/// Builds `text` in `style` without breaking its lines.
+fn build_line<B: Brush>(
+    text: &str,
+    style: &TextStyle<'_, B>,
+    fcx: &mut FontContext,
+    lcx: &mut LayoutContext<B>,
+) -> (Layout<B>, String) {
+    let mut builder = lcx.tree_builder(fcx, 1.0, style);
+    builder.push_text(text);
+    let (mut layout, text) = builder.build();
+    layout.break_all_lines(None);
+    (layout, text)
+}
+
+/// Returns the end of the text kept before the ellipsis and the start of the text kept
+/// after it, so that the kept text is at most `available` wide.
+fn truncate<B: Brush>(
+    layout: &Layout<B>,
+    available: f32,
+    position: EllipsisPosition,
+) -> (usize, usize) {
+    let graphemes = graphemes(layout);
+    let (mut head, mut tail) = (0, graphemes.len());
+    let mut width = 0.;
+    while head < tail {
+        let front = match position {
+            EllipsisPosition::Start => false,
+            EllipsisPosition::Middle => head <= graphemes.len() - tail,
+            EllipsisPosition::End => true,
+        };
+        let index = if front { head } else { tail - 1 };
+        width += graphemes[index].1;
+        if width > available {
+            break;
+        }
+        if front {
+            head += 1;
+        } else {
+            tail -= 1;
+        }
+    }
+    let head = head
+        .checked_sub(1)
+        .map_or(0, |index| graphemes[index].0.end);
+    let tail = graphemes
+        .get(tail)
+        .map_or(layout.data.text_len, |grapheme| grapheme.0.start);
+    (head, tail)
+}
+
+/// Returns the text range and advance of each grapheme cluster of `layout`, in logical
+/// order.
+///
+/// Ligatures are kept whole, as their advance belongs to the cluster that starts them.
+fn graphemes<B: Brush>(layout: &Layout<B>) -> Vec<(Range<usize>, f32)> {
+    let mut clusters = Vec::new();
+    for line in layout.lines() {
+        for run in line.runs() {
+            for cluster in run.clusters() {
+                let is_continuation =
+                    cluster.is_grapheme_continuation() || cluster.is_ligature_continuation();
+                clusters.push((cluster.text_range(), cluster.advance(), is_continuation));
+            }
+        }
+    }
+    clusters.sort_by_key(|(range, ..)| range.start);
+    let mut graphemes: Vec<(Range<usize>, f32)> = Vec::with_capacity(clusters.len());
+    for (range, advance, is_continuation) in clusters {
+        match graphemes.last_mut() {
+            Some((last, last_advance)) if is_continuation => {
+                last.end = last.end.max(range.end);
+                *last_advance += advance;
+            }
+            _ => graphemes.push((range, advance)),
+        }
+    }
+    graphemes
+}
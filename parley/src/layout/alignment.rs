@@ -3,9 +3,15 @@
 
 use super::{
     Alignment, BreakReason, LayoutData,
-    data::{ClusterData, LineItemData},
+    data::{ClusterData, LineData, LineItemData, RunData, joins_of},
 };
 use crate::style::Brush;
+use crate::util::nearly_zero;
+use alloc::vec::Vec;
+
+#[cfg(feature = "libm")]
+#[allow(unused_imports)]
+use core_maths::CoreFloat;
 
 /// Additional options to fine tune alignment
 #[derive(Debug, Clone, Copy)]
@@ -14,6 +20,28 @@ pub struct AlignmentOptions {
     /// wider than the alignment width. If it is set to `false`, all overflowing lines will be
     /// [`Alignment::Start`] aligned.
     pub align_when_overflowing: bool,
+    /// How free space is distributed within lines with [`Alignment::Justified`].
+    pub justify_content: JustifyContent,
+    /// The horizontal position of the separator in lines with [`Alignment::Numeric`],
+    /// measured from the start of the line's content area, after any leading boxes and
+    /// text indent.
+    pub numeric_position: f32,
+}
+
+/// How [`Alignment::Justified`] distributes the free space within a line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JustifyContent {
+    /// Free space is added to the spaces between words. The last line of the text is
+    /// not justified.
+    #[default]
+    InterWord,
+    /// Free space is distributed evenly between all grapheme clusters, such as for CJK
+    /// text which has no spaces between words. Letters which join each other, such as
+    /// in Arabic, are not separated. The last line of the text is not justified.
+    InterCharacter,
+    /// Like [`JustifyContent::InterCharacter`], but the last line of the text is
+    /// justified too.
+    Distribute,
 }
 
 #[expect(
@@ -24,6 +52,8 @@ impl Default for AlignmentOptions {
     fn default() -> Self {
         Self {
             align_when_overflowing: false,
+            justify_content: JustifyContent::InterWord,
+            numeric_position: 0.0,
         }
     }
 }
@@ -39,18 +69,34 @@ pub(crate) fn align<B: Brush>(
     options: AlignmentOptions,
 ) {
     layout.alignment_width = alignment_width.unwrap_or(layout.width);
-    layout.is_aligned_justified = alignment == Alignment::Justified;
+    layout.justify_content = options.justify_content;
 
     align_impl::<_, false>(layout, alignment, options);
+    layout.is_aligned_justified = layout
+        .lines
+        .iter()
+        .any(|line| line.alignment == Alignment::Justified);
+    round_spacing(layout);
 }
 
-/// Removes previous justification applied to clusters.
+/// Removes previous justification and spacing rounding applied to clusters.
 ///
 /// This is part of resetting state in preparation for re-line-breaking or re-aligning the same
 /// layout.
 pub(crate) fn unjustify<B: Brush>(layout: &mut LayoutData<B>) {
+    for (item_index, cluster_index, glyph_index, delta) in layout.spacing_adjustments.drain(..) {
+        layout.line_items[item_index].advance -= delta;
+        layout.clusters[cluster_index].advance -= delta;
+        if let Some(glyph_index) = glyph_index {
+            layout.glyphs[glyph_index].advance -= delta;
+        }
+    }
     if layout.is_aligned_justified {
-        align_impl::<_, true>(layout, Alignment::Justified, Default::default());
+        let options = AlignmentOptions {
+            justify_content: layout.justify_content,
+            ..Default::default()
+        };
+        align_impl::<_, true>(layout, Alignment::Justified, options);
         layout.is_aligned_justified = false;
     }
 }
@@ -72,19 +118,55 @@ fn align_impl<B: Brush, const UNDO_JUSTIFICATION: bool>(
     // Whether the text base direction is right-to-left.
     let is_rtl = layout.base_level & 1 == 1;
 
+    // Lines take the alignment of their paragraph if it is overridden. Justification is
+    // only undone for the lines which were justified.
+    let alignments: Vec<Alignment> = layout
+        .lines
+        .iter()
+        .map(|line| {
+            if UNDO_JUSTIFICATION {
+                return line.alignment;
+            }
+            let paragraph = layout
+                .paragraphs
+                .partition_point(|paragraph| paragraph.text_range.end <= line.text_range.start)
+                .min(layout.paragraphs.len().saturating_sub(1));
+            layout
+                .paragraphs
+                .get(paragraph)
+                .and_then(|paragraph| paragraph.alignment)
+                .unwrap_or(alignment)
+        })
+        .collect();
+    let separator_advances: Vec<Option<f32>> = layout
+        .lines
+        .iter()
+        .zip(&alignments)
+        .map(|(line, alignment)| match alignment {
+            Alignment::Numeric { separator } => separator_advance(layout, line, *separator),
+            _ => None,
+        })
+        .collect();
+
     // Apply alignment to line items
-    for line in &mut layout.lines {
-        line.metrics.offset = 0.;
+    for (line_index, line) in layout.lines.iter_mut().enumerate() {
+        let alignment = alignments[line_index];
+        if UNDO_JUSTIFICATION && alignment != Alignment::Justified {
+            continue;
+        }
+        line.alignment = alignment;
+        line.metrics.offset = line.inset.0;
 
         if is_rtl {
             // In RTL text, trailing whitespace is on the left. As we hang that whitespace, offset
             // the line to the left.
-            line.metrics.offset = -line.metrics.trailing_whitespace;
+            line.metrics.offset = line.inset.0 - line.metrics.trailing_whitespace;
         }
 
         // Compute free space.
         let free_space =
-            layout.alignment_width - line.metrics.advance + line.metrics.trailing_whitespace;
+            layout.alignment_width - line.inset.0 - line.inset.1 - line.metrics.advance
+                + line.metrics.trailing_whitespace;
 
         if !options.align_when_overflowing && free_space <= 0.0 {
             if is_rtl {
@@ -104,6 +186,13 @@ fn align_impl<B: Brush, const UNDO_JUSTIFICATION: bool>(
             (Alignment::Middle, _) => {
                 line.metrics.offset += free_space * 0.5;
             }
+            (Alignment::Numeric { .. }, _) => match separator_advances[line_index] {
+                Some(advance) => {
+                    line.metrics.offset = line.inset.0 + options.numeric_position - advance;
+                }
+                None if !is_rtl => line.metrics.offset += free_space,
+                None => {}
+            },
             (Alignment::Justified, _) => {
                 // Justified alignment doesn't have any effect if free_space is negative or zero
                 if free_space <= 0.0 {
@@ -111,9 +200,26 @@ fn align_impl<B: Brush, const UNDO_JUSTIFICATION: bool>(
                 }
 
                 // Justified alignment doesn't apply to the last line of a paragraph
-                // (`BreakReason::None`) or if there are no whitespace gaps to adjust. In that
-                // case, start-align, i.e., left-align for LTR text and right-align for RTL text.
-                if line.break_reason == BreakReason::None || line.num_spaces == 0 {
+                // (`BreakReason::None`) unless distributing, or if there are no gaps to
+                // adjust. In that case, start-align, i.e., left-align for LTR text and
+                // right-align for RTL text.
+                let is_last_line = line.break_reason == BreakReason::None
+                    && options.justify_content != JustifyContent::Distribute;
+                let gap_clusters = match options.justify_content {
+                    JustifyContent::InterWord => Vec::new(),
+                    JustifyContent::InterCharacter | JustifyContent::Distribute => character_gaps(
+                        &layout.text,
+                        &layout.line_items[line.item_range.clone()],
+                        &layout.runs,
+                        &layout.clusters,
+                        is_rtl,
+                    ),
+                };
+                let gaps = match options.justify_content {
+                    JustifyContent::InterWord => line.num_spaces,
+                    _ => gap_clusters.len(),
+                };
+                if is_last_line || gaps == 0 {
                     if is_rtl {
                         line.metrics.offset += free_space;
                     }
@@ -121,7 +227,23 @@ fn align_impl<B: Brush, const UNDO_JUSTIFICATION: bool>(
                 }
 
                 let adjustment =
-                    free_space / line.num_spaces as f32 * if UNDO_JUSTIFICATION { -1. } else { 1. };
+                    free_space / gaps as f32 * if UNDO_JUSTIFICATION { -1. } else { 1. };
+                if options.justify_content != JustifyContent::InterWord {
+                    for (cluster_index, glyph_start) in gap_clusters {
+                        let cluster = &mut layout.clusters[cluster_index];
+                        cluster.advance += adjustment;
+                        // The advance of the glyphs must match, as glyph runs are
+                        // positioned by them.
+                        if cluster.glyph_len != 0xFF && cluster.glyph_len != 0 {
+                            let glyph = glyph_start
+                                + cluster.glyph_offset as usize
+                                + cluster.glyph_len as usize
+                                - 1;
+                            layout.glyphs[glyph].advance += adjustment;
+                        }
+                    }
+                    continue;
+                }
                 let mut applied = 0;
                 // Iterate over text runs in the line and clusters in the text run
                 //   - Iterate forwards for even bidi levels (which represent LTR runs)
@@ -156,3 +278,166 @@ fn align_impl<B: Brush, const UNDO_JUSTIFICATION: bool>(
         }
     }
 }
+
+/// Rounds the spacing of the clusters with word or letter spacing, so that each of them
+/// ends on the grid of the layout's [`SpacingRounding`](crate::SpacingRounding),
+/// measured from the start of its line.
+///
+/// The change to each advance is recorded, so that [`unjustify`] can undo it.
+fn round_spacing<B: Brush>(layout: &mut LayoutData<B>) {
+    let Some(unit) = layout.spacing_rounding.unit() else {
+        return;
+    };
+    for line in &layout.lines {
+        let mut x = 0.;
+        for item_index in line.item_range.clone() {
+            let item = &mut layout.line_items[item_index];
+            if !item.is_text_run() {
+                x += item.advance;
+                continue;
+            }
+            let run = &layout.runs[item.index];
+            let range = item.cluster_range.clone();
+            let indices: &mut dyn Iterator<Item = usize> = if item.bidi_level & 1 != 0 {
+                &mut range.rev()
+            } else {
+                &mut range.into_iter()
+            };
+            for cluster_index in indices {
+                let cluster = &mut layout.clusters[cluster_index];
+                x += cluster.advance;
+                if !cluster.is_letter_spaced() && !cluster.is_word_spaced() {
+                    continue;
+                }
+                let delta = (x / unit).round() * unit - x;
+                if nearly_zero(delta) {
+                    continue;
+                }
+                cluster.advance += delta;
+                // The advance of the glyphs must match, as glyph runs are positioned by
+                // them.
+                let glyph_index =
+                    (cluster.glyph_len != 0xFF && cluster.glyph_len != 0).then(|| {
+                        run.glyph_start + cluster.glyph_offset as usize + cluster.glyph_len as usize
+                            - 1
+                    });
+                if let Some(glyph_index) = glyph_index {
+                    layout.glyphs[glyph_index].advance += delta;
+                }
+                item.advance += delta;
+                x += delta;
+                layout
+                    .spacing_adjustments
+                    .push((item_index, cluster_index, glyph_index, delta));
+            }
+        }
+    }
+}
+
+/// Returns the clusters of a line which are followed by a gap for inter-character
+/// justification, with the glyph base of their runs.
+///
+/// These are the clusters which end a grapheme, in the order of the base direction,
+/// other than the last one which is not trailing whitespace. Like letter spacing, a gap
+/// never separates a cluster from the cluster after it when that one continues its
+/// grapheme, such as a combining mark shaped apart from its base, or when their letters
+/// join each other, such as in Arabic.
+fn character_gaps(
+    text: &str,
+    line_items: &[LineItemData],
+    runs: &[RunData],
+    clusters: &[ClusterData],
+    is_rtl: bool,
+) -> Vec<(usize, usize)> {
+    let mut gaps = Vec::new();
+    let mut last_visible = 0;
+    let line_items: &mut dyn Iterator<Item = &LineItemData> = if is_rtl {
+        &mut line_items.iter().rev()
+    } else {
+        &mut line_items.iter()
+    };
+    for line_item in line_items.filter(|item| item.is_text_run()) {
+        let run = &runs[line_item.index];
+        let range = line_item.cluster_range.clone();
+        let indices: &mut dyn Iterator<Item = usize> = if line_item.bidi_level & 1 != 0 {
+            &mut range.rev()
+        } else {
+            &mut range.into_iter()
+        };
+        for index in indices {
+            let cluster = &clusters[index];
+            if cluster.is_ligature_component()
+                || cluster.is_emoji_continuation()
+                || cluster.is_ignorable()
+                || cluster.is_hard_break()
+            {
+                continue;
+            }
+            // The cluster after the last one of a run is the first one of the next run,
+            // if it continues the text.
+            let next = match clusters[index + 1..run.cluster_range.end].first() {
+                Some(next) => Some((next, run)),
+                None => runs
+                    .get(line_item.index + 1)
+                    .filter(|next_run| next_run.text_range.start == run.text_range.end)
+                    .and_then(|next_run| {
+                        Some((clusters.get(next_run.cluster_range.start)?, next_run))
+                    }),
+            };
+            if let Some((next, next_run)) = next {
+                let joins = joins_of(&text[cluster.text_range(run)]).unwrap_or(0);
+                let next_joins = joins_of(&text[next.text_range(next_run)]).unwrap_or(joins);
+                let is_joined = joins & ClusterData::JOINS_NEXT != 0
+                    && next_joins & ClusterData::JOINS_PREVIOUS != 0;
+                if next.is_grapheme_continuation() || is_joined {
+                    continue;
+                }
+            }
+            gaps.push((index, run.glyph_start));
+            if !cluster.info.is_whitespace() {
+                last_visible = gaps.len();
+            }
+        }
+    }
+    // There is no gap after the last cluster, nor after trailing whitespace.
+    gaps.truncate(last_visible.saturating_sub(1));
+    gaps
+}
+
+/// Returns the advance from the visual start of `line` to its first `separator`, or to
+/// the end of its last digit if it has no separator.
+///
+/// Returns `None` if the line has neither.
+pub(crate) fn separator_advance<B: Brush>(
+    layout: &LayoutData<B>,
+    line: &LineData,
+    separator: char,
+) -> Option<f32> {
+    let text = layout.text.get(line.text_range.clone())?;
+    let (byte_index, after) = match text.find(separator) {
+        Some(index) => (line.text_range.start + index, false),
+        None => (line.text_range.start + text.rfind(char::is_numeric)?, true),
+    };
+
+    let mut x = 0.;
+    for item in &layout.line_items[line.item_range.clone()] {
+        if !item.is_text_run() {
+            x += item.advance;
+            continue;
+        }
+        let run = &layout.runs[item.index];
+        let clusters = &layout.clusters[item.cluster_range.clone()];
+        let clusters: &mut dyn Iterator<Item = &ClusterData> = if item.bidi_level & 1 != 0 {
+            &mut clusters.iter().rev()
+        } else {
+            &mut clusters.iter()
+        };
+        for cluster in clusters {
+            if cluster.text_range(run).contains(&byte_index) {
+                return Some(if after { x + cluster.advance } else { x });
+            }
+            x += cluster.advance;
+        }
+    }
+    None
+}
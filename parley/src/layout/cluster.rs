@@ -4,7 +4,6 @@
 use super::{
     BreakReason, Brush, Cluster, ClusterInfo, Glyph, Layout, Line, LineItem, Range, Run, Style,
 };
-use swash::text::cluster::Whitespace;
 
 /// Defines the visual side of the cluster for hit testing.
 ///
@@ -17,6 +16,17 @@ pub enum ClusterSide {
     Right,
 }
 
+/// The extra spacing which was added to the advance of a cluster.
+///
+/// See [`Cluster::extra_spacing`].
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct ExtraSpacing {
+    /// The letter spacing which follows the cluster.
+    pub letter: f32,
+    /// The word spacing which follows the cluster.
+    pub word: f32,
+}
+
 impl<'a, B: Brush> Cluster<'a, B> {
     /// Returns the cluster for the given layout and byte index.
     pub fn from_byte_index(layout: &'a Layout<B>, byte_index: usize) -> Option<Self> {
@@ -41,10 +51,21 @@ impl<'a, B: Brush> Cluster<'a, B> {
 
     /// Returns the cluster and side for the given layout and point.
     pub fn from_point(layout: &'a Layout<B>, x: f32, y: f32) -> Option<(Self, ClusterSide)> {
+        let (x, y) = layout.point_to_layout(x, y);
+        Self::from_layout_point(layout, x, y)
+    }
+
+    /// Returns the cluster and side for a point in layout space, ignoring the origin
+    /// and y-axis of the layout.
+    pub(crate) fn from_layout_point(
+        layout: &'a Layout<B>,
+        x: f32,
+        y: f32,
+    ) -> Option<(Self, ClusterSide)> {
         let mut path = ClusterPath::default();
         if let Some((line_index, line)) = layout.line_for_offset(y) {
             path.line_index = line_index as u32;
-            let mut offset = line.metrics().offset;
+            let mut offset = line.data.metrics.offset;
             let last_run_index = line.len().saturating_sub(1);
             for item in line.items_nonpositioned() {
                 match item {
@@ -65,7 +86,12 @@ impl<'a, B: Brush> Cluster<'a, B> {
                             let cluster_advance = cluster.advance();
                             let edge = offset;
                             offset += cluster_advance;
-                            if x > offset && !is_last_cluster {
+                            // Clusters without glyphs, such as format characters, can't be
+                            // clicked on, so the point belongs to the next visible cluster.
+                            let is_invisible = cluster_advance == 0.0
+                                && cluster.glyph_count() == 0
+                                && !cluster.is_hard_line_break();
+                            if (x > offset || is_invisible) && !is_last_cluster {
                                 continue;
                             }
                             let side = if x <= edge + cluster_advance * 0.5 {
@@ -121,6 +147,30 @@ impl<'a, B: Brush> Cluster<'a, B> {
         self.data.advance
     }
 
+    /// Returns the letter and word spacing which was added to the advance of the
+    /// cluster, which is zero where spacing doesn't apply.
+    ///
+    /// Letter spacing is only added after the last cluster of each grapheme cluster,
+    /// and not between letters which join each other by their Unicode joining type,
+    /// such as in Arabic. Word spacing is only added to spaces. The amounts are those of
+    /// the style, before any rounding by
+    /// [`LayoutContext::set_spacing_rounding`](crate::LayoutContext::set_spacing_rounding).
+    pub fn extra_spacing(&self) -> ExtraSpacing {
+        let run = self.run.data;
+        ExtraSpacing {
+            letter: if self.data.is_letter_spaced() {
+                run.letter_spacing
+            } else {
+                0.
+            },
+            word: if self.data.is_word_spaced() {
+                run.word_spacing
+            } else {
+                0.
+            },
+        }
+    }
+
     /// Returns `true` if this is a right-to-left cluster.
     pub fn is_rtl(&self) -> bool {
         self.run.is_rtl()
@@ -136,6 +186,11 @@ impl<'a, B: Brush> Cluster<'a, B> {
         self.data.is_ligature_component()
     }
 
+    /// Returns `true` if the cluster continues the grapheme cluster of the previous one.
+    pub(crate) fn is_grapheme_continuation(&self) -> bool {
+        self.data.is_grapheme_continuation()
+    }
+
     /// Returns `true` if the cluster is a word boundary.
     pub fn is_word_boundary(&self) -> bool {
         self.data.info.is_boundary()
@@ -146,13 +201,13 @@ impl<'a, B: Brush> Cluster<'a, B> {
         self.is_end_of_line()
             && matches!(
                 self.line().data.break_reason,
-                BreakReason::Regular | BreakReason::Emergency
+                BreakReason::Regular | BreakReason::Emergency | BreakReason::Hyphenated
             )
     }
 
     /// Returns `true` if the cluster is a hard line break.
     pub fn is_hard_line_break(&self) -> bool {
-        self.data.info.whitespace() == Whitespace::Newline
+        self.data.is_hard_break()
     }
 
     /// Returns `true` if the cluster is a space or no-break space.
@@ -165,6 +220,35 @@ impl<'a, B: Brush> Cluster<'a, B> {
         self.data.info.is_emoji()
     }
 
+    /// Returns `true` if the cluster stands in for a redacted range of text.
+    ///
+    /// See [`Visibility::Redacted`](crate::Visibility::Redacted).
+    pub fn is_redacted(&self) -> bool {
+        self.data.is_redacted()
+    }
+
+    /// Returns the number of glyphs that the shaper produced for the cluster.
+    ///
+    /// This is more than one when a character is decomposed into several glyphs.
+    /// When a ligature covers several clusters, its glyphs belong to the cluster that
+    /// starts it and the continuations have no glyphs.
+    pub fn glyph_count(&self) -> usize {
+        self.data.glyph_count()
+    }
+
+    /// Returns the range of the cluster's glyphs within the run.
+    ///
+    /// The glyphs of a run are indexed in logical order, counting the glyphs of each
+    /// cluster in the run.
+    pub fn glyph_range(&self) -> Range<usize> {
+        let clusters = &self.run.layout.data.clusters[self.run.cluster_range()];
+        let start = clusters[..self.path.logical_index()]
+            .iter()
+            .map(|cluster| cluster.glyph_count())
+            .sum();
+        start..start + self.glyph_count()
+    }
+
     /// Returns an iterator over the glyphs in the cluster.
     pub fn glyphs(&self) -> impl Iterator<Item = Glyph> + 'a + Clone {
         if self.data.glyph_len == 0xFF {
@@ -367,8 +451,15 @@ impl<'a, B: Brush> Cluster<'a, B> {
     /// This cost of this function is roughly linear in the number of clusters
     /// on the containing line.
     pub fn visual_offset(&self) -> Option<f32> {
+        self.layout_visual_offset()
+            .map(|offset| self.run.layout.x_from_layout(offset))
+    }
+
+    /// Returns the visual offset of this cluster in layout space, ignoring the origin
+    /// of the layout.
+    pub(crate) fn layout_visual_offset(&self) -> Option<f32> {
         let line = self.path.line(self.run.layout)?;
-        let mut offset = line.metrics().offset;
+        let mut offset = line.data.metrics.offset;
         for run_index in 0..=self.path.run_index() {
             let item = line.item(run_index)?;
             match item {
@@ -397,6 +488,7 @@ impl<'a, B: Brush> Cluster<'a, B> {
 
 /// Determines how a cursor attaches to a cluster.
 #[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Affinity {
     /// Cursor is attached to the character that is logically following in the
     /// text stream.
@@ -11,8 +11,18 @@ use accesskit::TextPosition;
 use alloc::vec::Vec;
 use core::ops::Range;
 use peniko::kurbo::Rect;
-#[cfg(feature = "accesskit")]
-use swash::text::cluster::Whitespace;
+
+/// Convention for moving the caret left and right in bidirectional text.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum CaretMovement {
+    /// Left and right move to the visually adjacent cluster boundary, crossing between runs
+    /// of different directions in visual order.
+    #[default]
+    Visual,
+    /// Left moves to the previous cluster boundary in the text and right to the next one,
+    /// regardless of the direction of the text.
+    Logical,
+}
 
 /// Defines a position with a text layout.
 #[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
@@ -22,6 +32,12 @@ pub struct Cursor {
 }
 
 impl Cursor {
+    /// Creates a cursor at the given byte index without snapping it to a cluster
+    /// boundary, for when the layout is not up-to-date.
+    pub(crate) fn new(index: usize, affinity: Affinity) -> Self {
+        Self { index, affinity }
+    }
+
     /// Creates a new cursor from the given byte index and affinity.
     pub fn from_byte_index<B: Brush>(layout: &Layout<B>, index: usize, affinity: Affinity) -> Self {
         if let Some(cluster) = Cluster::from_byte_index(layout, index) {
@@ -39,27 +55,35 @@ impl Cursor {
 
     /// Creates a new cursor from the given coordinates.
     pub fn from_point<B: Brush>(layout: &Layout<B>, x: f32, y: f32) -> Self {
-        let (index, affinity) = if let Some((cluster, side)) = Cluster::from_point(layout, x, y) {
-            let is_leading = side == ClusterSide::Left;
-            if cluster.is_rtl() {
-                if is_leading {
-                    (cluster.text_range().end, Affinity::Upstream)
+        let (x, y) = layout.point_to_layout(x, y);
+        Self::from_layout_point(layout, x, y)
+    }
+
+    /// Creates a new cursor from a point in layout space, ignoring the origin and
+    /// y-axis of the layout.
+    pub(crate) fn from_layout_point<B: Brush>(layout: &Layout<B>, x: f32, y: f32) -> Self {
+        let (index, affinity) =
+            if let Some((cluster, side)) = Cluster::from_layout_point(layout, x, y) {
+                let is_leading = side == ClusterSide::Left;
+                if cluster.is_rtl() {
+                    if is_leading {
+                        (cluster.text_range().end, Affinity::Upstream)
+                    } else {
+                        (cluster.text_range().start, Affinity::Downstream)
+                    }
                 } else {
-                    (cluster.text_range().start, Affinity::Downstream)
+                    // We never want to position the cursor _after_ a hard
+                    // line since that cursor appears visually at the start
+                    // of the next line
+                    if is_leading || cluster.is_line_break() == Some(BreakReason::Explicit) {
+                        (cluster.text_range().start, Affinity::Downstream)
+                    } else {
+                        (cluster.text_range().end, Affinity::Upstream)
+                    }
                 }
             } else {
-                // We never want to position the cursor _after_ a hard
-                // line since that cursor appears visually at the start
-                // of the next line
-                if is_leading || cluster.is_line_break() == Some(BreakReason::Explicit) {
-                    (cluster.text_range().start, Affinity::Downstream)
-                } else {
-                    (cluster.text_range().end, Affinity::Upstream)
-                }
-            }
-        } else {
-            (layout.data.text_len, Affinity::Downstream)
-        };
+                (layout.data.text_len, Affinity::Downstream)
+            };
         Self { index, affinity }
     }
 
@@ -71,11 +95,22 @@ impl Cursor {
     ) -> Option<Self> {
         let (line_index, run_index) = *layout_access.run_paths_by_access_id.get(&pos.node)?;
         let line = layout.get(line_index)?;
-        let run = line.item(run_index)?.run()?;
+        let run = match line.item(run_index)? {
+            LineItem::Run(run) => run,
+            // An inline box is a single U+FFFC, which takes up no text.
+            LineItem::InlineBox(inline_box) => {
+                let affinity = if pos.character_index == 0 {
+                    Affinity::Upstream
+                } else {
+                    Affinity::Downstream
+                };
+                return Some(Self::from_byte_index(layout, inline_box.index, affinity));
+            }
+        };
         let index = run
             .get(pos.character_index)
             .map(|cluster| cluster.text_range().start)
-            .unwrap_or(layout.data.text_len);
+            .unwrap_or(run.text_range().end);
         Some(Self::from_byte_index(layout, index, Affinity::Downstream))
     }
 
@@ -187,6 +222,50 @@ impl Cursor {
         *self
     }
 
+    /// Returns a new cursor that is positioned at the previous cluster boundary
+    /// in logical order.
+    #[must_use]
+    pub fn previous_logical<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        match self.upstream_cluster(layout) {
+            Some(cluster) => {
+                Self::from_byte_index(layout, cluster.text_range().start, Affinity::Downstream)
+            }
+            None => *self,
+        }
+    }
+
+    /// Returns a new cursor that is positioned at the next cluster boundary
+    /// in logical order.
+    #[must_use]
+    pub fn next_logical<B: Brush>(&self, layout: &Layout<B>) -> Self {
+        match self.downstream_cluster(layout) {
+            Some(cluster) => {
+                Self::from_byte_index(layout, cluster.text_range().end, Affinity::Downstream)
+            }
+            None => *self,
+        }
+    }
+
+    /// Returns a new cursor that is moved one cluster to the left according to
+    /// the given convention.
+    #[must_use]
+    pub fn move_left<B: Brush>(&self, layout: &Layout<B>, movement: CaretMovement) -> Self {
+        match movement {
+            CaretMovement::Visual => self.previous_visual(layout),
+            CaretMovement::Logical => self.previous_logical(layout),
+        }
+    }
+
+    /// Returns a new cursor that is moved one cluster to the right according to
+    /// the given convention.
+    #[must_use]
+    pub fn move_right<B: Brush>(&self, layout: &Layout<B>, movement: CaretMovement) -> Self {
+        match movement {
+            CaretMovement::Visual => self.next_visual(layout),
+            CaretMovement::Logical => self.next_logical(layout),
+        }
+    }
+
     /// Returns a new cursor that is positioned at the next word boundary
     /// in visual order.
     #[must_use]
@@ -273,6 +352,33 @@ impl Cursor {
     ///
     /// The `width` parameter defines the width of the resulting rectangle.
     pub fn geometry<B: Brush>(&self, layout: &Layout<B>, width: f32) -> Rect {
+        layout.rect_from_layout(self.layout_geometry(layout, width))
+    }
+
+    /// Returns the region to repaint for the cursor, which is its [geometry](Self::geometry)
+    /// expanded to whole pixels, including the full `width` of the caret.
+    pub fn damage_rect<B: Brush>(&self, layout: &Layout<B>, width: f32) -> Rect {
+        self.geometry(layout, width).expand()
+    }
+
+    /// Returns the region to repaint when the cursor has moved from a caret whose
+    /// damage rect was `previous`, such as one from [`damage_rect`](Self::damage_rect)
+    /// before an edit or movement.
+    ///
+    /// This is the union of both regions, so it covers erasing the previous caret and
+    /// drawing the new one.
+    pub fn damage_rect_since<B: Brush>(
+        &self,
+        layout: &Layout<B>,
+        width: f32,
+        previous: Rect,
+    ) -> Rect {
+        self.damage_rect(layout, width).union(previous)
+    }
+
+    /// Returns the geometry of the cursor in layout space, ignoring the origin and
+    /// y-axis of the layout.
+    pub(crate) fn layout_geometry<B: Brush>(&self, layout: &Layout<B>, width: f32) -> Rect {
         match self.visual_clusters(layout) {
             [Some(left), Some(right)] => {
                 if left.is_end_of_line() {
@@ -358,7 +464,7 @@ impl Cursor {
     }
 
     fn line<B: Brush>(self, layout: &Layout<B>) -> Option<(usize, Line<'_, B>)> {
-        let geometry = self.geometry(layout, 0.0);
+        let geometry = self.layout_geometry(layout, 0.0);
         layout.line_for_offset(geometry.y0 as f32)
     }
 
@@ -386,6 +492,23 @@ impl Cursor {
                 character_index: 0,
             });
         }
+        // An inline box at the end of the text has no downstream cluster, so place
+        // the cursor after its U+FFFC, as for boxes followed by text.
+        if self.index == layout.data.text_len {
+            let box_path = layout.lines().enumerate().find_map(|(line_index, line)| {
+                line.items_nonpositioned()
+                    .position(|item| {
+                        matches!(item, LineItem::InlineBox(inline_box) if inline_box.index == self.index)
+                    })
+                    .map(|item_index| (line_index, item_index))
+            });
+            if let Some(box_path) = box_path {
+                return Some(TextPosition {
+                    node: *layout_access.access_ids_by_run_path.get(&box_path)?,
+                    character_index: 1,
+                });
+            }
+        }
         // Prefer the downstream cluster except at the end of the text
         // where we'll choose the upstream cluster and add 1 to the
         // character index.
@@ -404,7 +527,7 @@ impl Cursor {
                 .data
                 .clusters
                 .last()
-                .map(|cluster| cluster.info.whitespace() == Whitespace::Newline)
+                .map(|cluster| cluster.is_hard_break())
                 .unwrap_or_default()
         {
             ((path.line_index() + 1, 0), 0)
@@ -422,6 +545,16 @@ impl Cursor {
     }
 }
 
+/// Additional options for the geometry of a [`Selection`].
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct SelectionGeometryOptions {
+    /// If set to `true`, the rectangles of consecutive lines which have the same
+    /// horizontal extent and no vertical gap between them are merged into one, which
+    /// reduces overdraw for large selections. The merged rectangle reports the index of
+    /// its first line.
+    pub merge_vertical: bool,
+}
+
 /// Defines a range within a text layout.
 #[derive(Copy, Clone, Default, Debug)]
 pub struct Selection {
@@ -506,6 +639,22 @@ impl Selection {
         self.anchor.index == self.focus.index
     }
 
+    /// Returns `true` if the selection contains no text.
+    ///
+    /// This is the same as [`is_collapsed`](Self::is_collapsed).
+    pub fn is_empty(&self) -> bool {
+        self.is_collapsed()
+    }
+
+    /// Returns the cursor of the selection if it is collapsed, or `None` if it
+    /// is a range.
+    ///
+    /// The [geometry](Self::geometry) of a collapsed selection is empty, so the
+    /// caret is drawn with the [geometry](Cursor::geometry) of this cursor.
+    pub fn caret(&self) -> Option<Cursor> {
+        self.is_collapsed().then_some(self.focus)
+    }
+
     /// Returns the anchor of the selection.
     ///
     /// In a non-collapsed selection, this indicates where the selection was
@@ -521,6 +670,16 @@ impl Selection {
         self.focus
     }
 
+    /// Returns the horizontal position that vertical movement keeps the focus at, if
+    /// any.
+    ///
+    /// This "sticky" position is set by the first move to another line, and kept by
+    /// subsequent ones, so that the caret returns to the same column after passing
+    /// through shorter lines. Any other movement or click clears it.
+    pub fn sticky_x<B: Brush>(&self, layout: &Layout<B>) -> Option<f32> {
+        self.h_pos.map(|x| layout.x_from_layout(x))
+    }
+
     /// Returns a new collapsed selection at the position of the current
     /// focus.
     #[must_use]
@@ -567,8 +726,8 @@ impl Selection {
     #[must_use]
     pub fn next_visual<B: Brush>(&self, layout: &Layout<B>, extend: bool) -> Self {
         if !self.is_collapsed() && !extend {
-            let anchor_geom = self.anchor.geometry(layout, 0.0);
-            let focus_geom = self.focus.geometry(layout, 0.0);
+            let anchor_geom = self.anchor.layout_geometry(layout, 0.0);
+            let focus_geom = self.focus.layout_geometry(layout, 0.0);
             let new_focus = if (anchor_geom.y0, anchor_geom.x0) > (focus_geom.y0, focus_geom.x0) {
                 self.anchor
             } else {
@@ -588,8 +747,8 @@ impl Selection {
     #[must_use]
     pub fn previous_visual<B: Brush>(&self, layout: &Layout<B>, extend: bool) -> Self {
         if !self.is_collapsed() && !extend {
-            let anchor_geom = self.anchor.geometry(layout, 0.0);
-            let focus_geom = self.focus.geometry(layout, 0.0);
+            let anchor_geom = self.anchor.layout_geometry(layout, 0.0);
+            let focus_geom = self.focus.layout_geometry(layout, 0.0);
             let new_focus = if (anchor_geom.y0, anchor_geom.x0) < (focus_geom.y0, focus_geom.x0) {
                 self.anchor
             } else {
@@ -601,6 +760,72 @@ impl Selection {
         }
     }
 
+    /// Returns a new selection with the focus at the next cluster in logical
+    /// order.
+    ///
+    /// If `extend` is `true` then the current anchor will be retained,
+    /// otherwise the new selection will be collapsed.
+    #[must_use]
+    pub fn next_logical<B: Brush>(&self, layout: &Layout<B>, extend: bool) -> Self {
+        if !self.is_collapsed() && !extend {
+            let end = self.text_range().end;
+            Cursor::from_byte_index(layout, end, Affinity::Downstream).into()
+        } else {
+            self.maybe_extend(self.focus.next_logical(layout), extend)
+        }
+    }
+
+    /// Returns a new selection with the focus at the previous cluster in logical
+    /// order.
+    ///
+    /// If `extend` is `true` then the current anchor will be retained,
+    /// otherwise the new selection will be collapsed.
+    #[must_use]
+    pub fn previous_logical<B: Brush>(&self, layout: &Layout<B>, extend: bool) -> Self {
+        if !self.is_collapsed() && !extend {
+            let start = self.text_range().start;
+            Cursor::from_byte_index(layout, start, Affinity::Downstream).into()
+        } else {
+            self.maybe_extend(self.focus.previous_logical(layout), extend)
+        }
+    }
+
+    /// Returns a new selection with the focus moved one cluster to the left
+    /// according to the given convention.
+    ///
+    /// If `extend` is `true` then the current anchor will be retained,
+    /// otherwise the new selection will be collapsed.
+    #[must_use]
+    pub fn move_left<B: Brush>(
+        &self,
+        layout: &Layout<B>,
+        movement: CaretMovement,
+        extend: bool,
+    ) -> Self {
+        match movement {
+            CaretMovement::Visual => self.previous_visual(layout, extend),
+            CaretMovement::Logical => self.previous_logical(layout, extend),
+        }
+    }
+
+    /// Returns a new selection with the focus moved one cluster to the right
+    /// according to the given convention.
+    ///
+    /// If `extend` is `true` then the current anchor will be retained,
+    /// otherwise the new selection will be collapsed.
+    #[must_use]
+    pub fn move_right<B: Brush>(
+        &self,
+        layout: &Layout<B>,
+        movement: CaretMovement,
+        extend: bool,
+    ) -> Self {
+        match movement {
+            CaretMovement::Visual => self.next_visual(layout, extend),
+            CaretMovement::Logical => self.next_logical(layout, extend),
+        }
+    }
+
     /// Returns a new selection with the focus moved to the next word in visual
     /// order.
     ///
@@ -656,7 +881,7 @@ impl Selection {
             return *self;
         }
         let line_limit = layout.len().saturating_sub(1);
-        let geometry = self.focus.geometry(layout, 0.0);
+        let geometry = self.focus.layout_geometry(layout, 0.0);
         let line_index = layout
             .line_for_offset(geometry.y0 as f32)
             .map(|(ix, _)| ix)
@@ -681,9 +906,10 @@ impl Selection {
         };
         let h_pos = self
             .h_pos
-            .unwrap_or_else(|| self.focus.geometry(layout, 0.0).x0 as f32);
-        let y = line.metrics().max_coord - line.metrics().ascent * 0.5;
-        let new_focus = Cursor::from_point(layout, h_pos, y);
+            .unwrap_or_else(|| self.focus.layout_geometry(layout, 0.0).x0 as f32);
+        let metrics = &line.data.metrics;
+        let y = metrics.max_coord - metrics.ascent * 0.5;
+        let new_focus = Cursor::from_layout_point(layout, h_pos, y);
         let h_pos = Some(h_pos);
         if extend {
             Self {
@@ -791,6 +1017,30 @@ impl Selection {
         rects
     }
 
+    /// Returns the rectangles which represent the visual geometry of this
+    /// selection, like [`geometry`](Self::geometry), adjusted by `options`.
+    pub fn geometry_with_options<B: Brush>(
+        &self,
+        layout: &Layout<B>,
+        options: SelectionGeometryOptions,
+    ) -> Vec<(Rect, usize)> {
+        let mut rects: Vec<(Rect, usize)> = Vec::new();
+        self.geometry_with(layout, |rect, line_idx| {
+            if options.merge_vertical {
+                if let Some((last, _)) = rects.last_mut() {
+                    let same_columns = last.x0 == rect.x0 && last.x1 == rect.x1;
+                    // The next line is below the last one for either direction of the y axis.
+                    if same_columns && (last.y1 == rect.y0 || last.y0 == rect.y1) {
+                        *last = last.union(rect);
+                        return;
+                    }
+                }
+            }
+            rects.push((rect, line_idx));
+        });
+        rects
+    }
+
     /// Invokes `f` with the sequence of rectangles which represent the visual
     /// geometry of this selection for the given layout, and the indices of the
     /// lines to which they belong.
@@ -799,6 +1049,7 @@ impl Selection {
     /// immediately.
     pub fn geometry_with<B: Brush>(&self, layout: &Layout<B>, mut f: impl FnMut(Rect, usize)) {
         const NEWLINE_WHITESPACE_WIDTH_RATIO: f64 = 0.25;
+        let mut f = |rect, line_ix| f(layout.rect_from_layout(rect), line_ix);
         if self.is_collapsed() {
             return;
         }
@@ -817,7 +1068,7 @@ impl Selection {
             let Some(line) = layout.get(line_ix) else {
                 continue;
             };
-            let metrics = line.metrics();
+            let metrics = &line.data.metrics;
             let line_min = metrics.min_coord as f64;
             let line_max = metrics.max_coord as f64;
             // Trailing whitespace to indicate that the newline character at the
@@ -932,10 +1183,10 @@ enum AnchorBase {
 }
 
 fn cursor_rect<B: Brush>(cluster: &Cluster<'_, B>, at_end: bool, size: f32) -> Rect {
-    let line_x = (cluster.visual_offset().unwrap_or_default()
+    let line_x = (cluster.layout_visual_offset().unwrap_or_default()
         + at_end.then(|| cluster.advance()).unwrap_or_default()) as f64;
     let line = cluster.line();
-    let metrics = line.metrics();
+    let metrics = &line.data.metrics;
     Rect::new(
         line_x,
         metrics.min_coord as f64,
@@ -946,7 +1197,7 @@ fn cursor_rect<B: Brush>(cluster: &Cluster<'_, B>, at_end: bool, size: f32) -> R
 
 fn last_line_cursor_rect<B: Brush>(layout: &Layout<B>, size: f32) -> Rect {
     if let Some(line) = layout.get(layout.len().saturating_sub(1)) {
-        let metrics = line.metrics();
+        let metrics = &line.data.metrics;
         Rect::new(
             0.0,
             metrics.min_coord as f64,
@@ -971,7 +1222,7 @@ fn affinity_for_dir(is_rtl: bool, moving_right: bool) -> Affinity {
 /// Used for extending word and line selections.
 fn cursor_min_max<B: Brush>(layout: &Layout<B>, cursors: [Cursor; 4]) -> [Cursor; 2] {
     let cursor_pos = cursors
-        .map(|cursor| (cursor, cursor.geometry(layout, 0.0)))
+        .map(|cursor| (cursor, cursor.layout_geometry(layout, 0.0)))
         .map(|(cursor, rect)| (cursor, (rect.y0, rect.x0)));
     let mut min = cursor_pos[0];
     let mut max = cursor_pos[0];
@@ -1,18 +1,27 @@
 // Copyright 2021 the Parley Authors
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use crate::inline_box::InlineBox;
-use crate::layout::{ContentWidths, Glyph, LineMetrics, RunMetrics, Style};
-use crate::style::Brush;
-use crate::util::nearly_zero;
+use crate::analysis::{TextAnalysis, is_hard_break};
+use crate::context::GlyphOverride;
+use crate::inline_box::{InlineBox, LeadingBox};
+use crate::layout::{
+    Alignment, ContentWidths, Glyph, JustifyContent, LayoutWarning, LineMetrics, LinkData,
+    RunMetrics, Style, YAxis,
+};
+use crate::style::{
+    Brush, HardBreak, HyphenationLimits, SpacingRounding, TabStops, TextIndent, Visibility,
+};
+use crate::util::{Shared, nearly_zero};
 use crate::{Font, OverflowWrap};
 use core::cell::OnceCell;
 use core::ops::Range;
+use hashbrown::HashMap;
 use swash::Synthesis;
 use swash::shape::Shaper;
-use swash::text::cluster::{Boundary, ClusterInfo};
+use swash::text::cluster::{Boundary, ClusterInfo, Whitespace};
+use swash::text::{Codepoint as _, JoiningType};
 
-use alloc::vec::Vec;
+use alloc::{string::String, vec, vec::Vec};
 
 #[cfg(feature = "libm")]
 #[allow(unused_imports)]
@@ -37,6 +46,17 @@ impl ClusterData {
     pub(crate) const LIGATURE_START: u16 = 1;
     pub(crate) const LIGATURE_COMPONENT: u16 = 2;
     pub(crate) const DIVERGENT_STYLES: u16 = 4;
+    pub(crate) const IGNORABLE: u16 = 8;
+    pub(crate) const REDACTED: u16 = 16;
+    pub(crate) const HARD_BREAK: u16 = 32;
+    pub(crate) const SOFT_HYPHEN: u16 = 64;
+    pub(crate) const EMOJI_CONTINUATION: u16 = 128;
+    pub(crate) const GRAPHEME_CONTINUATION: u16 = 256;
+    pub(crate) const HYPHENATION_LIMITED: u16 = 512;
+    pub(crate) const JOINS_NEXT: u16 = 1024;
+    pub(crate) const LETTER_SPACED: u16 = 2048;
+    pub(crate) const WORD_SPACED: u16 = 4096;
+    pub(crate) const JOINS_PREVIOUS: u16 = 8192;
 
     pub(crate) fn is_ligature_start(self) -> bool {
         self.flags & Self::LIGATURE_START != 0
@@ -50,12 +70,97 @@ impl ClusterData {
         self.flags & Self::DIVERGENT_STYLES != 0
     }
 
+    pub(crate) fn is_ignorable(self) -> bool {
+        self.flags & Self::IGNORABLE != 0
+    }
+
+    pub(crate) fn is_redacted(self) -> bool {
+        self.flags & Self::REDACTED != 0
+    }
+
+    pub(crate) fn is_soft_hyphen(self) -> bool {
+        self.flags & Self::SOFT_HYPHEN != 0
+    }
+
+    /// Returns `true` if the cluster is a soft hyphen which the [`HyphenationLimits`]
+    /// don't allow to hyphenate its word.
+    ///
+    /// [`HyphenationLimits`]: crate::HyphenationLimits
+    pub(crate) fn is_hyphenation_limited(self) -> bool {
+        self.flags & Self::HYPHENATION_LIMITED != 0
+    }
+
+    /// Returns `true` if the letter of the cluster can join to the letter after it, by
+    /// its Unicode joining type.
+    pub(crate) fn joins_next(self) -> bool {
+        self.flags & Self::JOINS_NEXT != 0
+    }
+
+    /// Returns `true` if the letter of the cluster can join to the letter before it, by
+    /// its Unicode joining type.
+    pub(crate) fn joins_previous(self) -> bool {
+        self.flags & Self::JOINS_PREVIOUS != 0
+    }
+
+    /// Returns `true` if letter spacing was added to the advance of the cluster.
+    pub(crate) fn is_letter_spaced(self) -> bool {
+        self.flags & Self::LETTER_SPACED != 0
+    }
+
+    /// Returns `true` if word spacing was added to the advance of the cluster.
+    pub(crate) fn is_word_spaced(self) -> bool {
+        self.flags & Self::WORD_SPACED != 0
+    }
+
+    /// Returns `true` if the cluster continues an emoji sequence, and so the line
+    /// must not break before it.
+    pub(crate) fn is_emoji_continuation(self) -> bool {
+        self.flags & Self::EMOJI_CONTINUATION != 0
+    }
+
+    /// Returns `true` if the cluster does not start a grapheme cluster, such as a
+    /// combining mark which was shaped apart from its base.
+    pub(crate) fn is_grapheme_continuation(self) -> bool {
+        self.flags & (Self::GRAPHEME_CONTINUATION | Self::EMOJI_CONTINUATION) != 0
+    }
+
+    /// Returns `true` if the cluster is a newline or a hard break character.
+    pub(crate) fn is_hard_break(self) -> bool {
+        self.flags & Self::HARD_BREAK != 0 || self.info.whitespace() == Whitespace::Newline
+    }
+
+    pub(crate) fn glyph_count(self) -> usize {
+        if self.glyph_len == 0xFF {
+            1
+        } else {
+            self.glyph_len as usize
+        }
+    }
+
     pub(crate) fn text_range(self, run: &RunData) -> Range<usize> {
         let start = run.text_range.start + self.text_offset as usize;
         start..start + self.text_len as usize
     }
 }
 
+/// Returns the [`ClusterData::JOINS_NEXT`] and [`ClusterData::JOINS_PREVIOUS`] flags of a
+/// cluster with the given text, from the Unicode joining type of its first letter.
+///
+/// Returns `None` if the text only has transparent characters, such as combining marks,
+/// which take the joins of the cluster before them.
+pub(crate) fn joins_of(text: &str) -> Option<u16> {
+    let joining_type = text
+        .chars()
+        .map(|ch| ch.joining_type())
+        .find(|joining_type| *joining_type != JoiningType::T)?;
+    Some(match joining_type {
+        JoiningType::D | JoiningType::C => ClusterData::JOINS_NEXT | ClusterData::JOINS_PREVIOUS,
+        JoiningType::L => ClusterData::JOINS_NEXT,
+        JoiningType::R => ClusterData::JOINS_PREVIOUS,
+        _ => 0,
+    })
+}
+
 #[derive(Clone)]
 pub(crate) struct RunData {
     /// Index of the font for the run.
@@ -86,13 +191,21 @@ pub(crate) struct RunData {
     pub(crate) advance: f32,
 }
 
+/// The reason that a line ended.
 #[derive(Copy, Clone, Default, PartialEq, Debug)]
 pub enum BreakReason {
+    /// The line ends at the end of the text.
     #[default]
     None,
+    /// The line was wrapped at a soft break opportunity, such as after a space.
     Regular,
+    /// The line ends with a newline or another hard break character.
     Explicit,
+    /// The line was wrapped within a word, as there was no soft break opportunity.
     Emergency,
+    /// The line was wrapped after a soft hyphen (U+00AD), so it should be displayed
+    /// with a hyphen.
+    Hyphenated,
 }
 
 #[derive(Clone, Default)]
@@ -113,6 +226,10 @@ pub(crate) struct LineData {
     pub(crate) max_advance: f32,
     /// Number of justified clusters on the line.
     pub(crate) num_spaces: usize,
+    /// Width reserved by leading boxes at the left and right edges of the line.
+    pub(crate) inset: (f32, f32),
+    /// Alignment which was applied to the line.
+    pub(crate) alignment: Alignment,
 }
 
 impl LineData {
@@ -121,6 +238,16 @@ impl LineData {
     }
 }
 
+#[derive(Debug, Clone)]
+pub(crate) struct ParagraphData {
+    /// Range of the source text, including the trailing paragraph separator.
+    pub(crate) text_range: Range<usize>,
+    /// Resolved base bidi level of the paragraph.
+    pub(crate) base_level: u8,
+    /// Alignment of the paragraph, if it overrides that of the layout.
+    pub(crate) alignment: Option<Alignment>,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct LineItemData {
     /// Whether the item is a run or an inline box
@@ -178,6 +305,18 @@ impl LineItemData {
     }
 }
 
+/// A [`LeadingBox`] along with its placement.
+#[derive(Debug, Clone)]
+pub(crate) struct LeadingBoxData {
+    pub(crate) leading_box: LeadingBox,
+    /// Index of the paragraph that the box is attached to.
+    pub(crate) paragraph: usize,
+    /// Whether the box is at the right edge of its paragraph.
+    pub(crate) is_rtl: bool,
+    /// Top of the box, computed by line breaking.
+    pub(crate) y: f32,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum LayoutItemKind {
     TextRun,
@@ -200,11 +339,15 @@ pub(crate) struct LayoutData<B: Brush> {
     pub(crate) has_bidi: bool,
     pub(crate) base_level: u8,
     pub(crate) text_len: usize,
+    /// Identifier of the build which produced the layout, which is unique within the
+    /// program.
+    pub(crate) build_id: u64,
     pub(crate) width: f32,
     pub(crate) full_width: f32,
     pub(crate) height: f32,
     pub(crate) fonts: Vec<Font>,
     pub(crate) coords: Vec<i16>,
+    pub(crate) paragraphs: Vec<ParagraphData>,
 
     // Lazily calculated values
     content_widths: OnceCell<ContentWidths>,
@@ -212,22 +355,58 @@ pub(crate) struct LayoutData<B: Brush> {
     // Input (/ output of style resolution)
     pub(crate) styles: Vec<Style<B>>,
     pub(crate) inline_boxes: Vec<InlineBox>,
+    pub(crate) leading_boxes: Vec<LeadingBoxData>,
+    pub(crate) text_indent: TextIndent,
+    pub(crate) links: Vec<LinkData>,
+    /// Problems with fonts encountered during shaping.
+    pub(crate) warnings: Vec<LayoutWarning>,
+    /// Whether the diagnostic warnings are reported during shaping.
+    pub(crate) diagnostics: bool,
+    /// Rounding of the word and letter spacing applied by [`Self::finish`].
+    pub(crate) spacing_rounding: SpacingRounding,
+    /// Tab stops which the line breaker advances tabs to.
+    pub(crate) tab_stops: TabStops,
+    /// Limits on the soft hyphens at which the line breaker hyphenates words.
+    pub(crate) hyphenation_limits: HyphenationLimits,
+    /// Whether the positions of inline boxes are rounded to whole device pixels.
+    pub(crate) snap_inline_boxes: bool,
+    /// The text of the layout, which [`Alignment::Numeric`](crate::Alignment::Numeric)
+    /// searches for separators.
+    pub(crate) text: Shared<String>,
 
-    // Output of shaping
-    pub(crate) runs: Vec<RunData>,
-    pub(crate) items: Vec<LayoutItem>,
-    pub(crate) clusters: Vec<ClusterData>,
-    pub(crate) glyphs: Vec<Glyph>,
+    // Output of shaping, which is shared by the layouts broken from a `ShapedText`
+    pub(crate) runs: Shared<Vec<RunData>>,
+    pub(crate) items: Shared<Vec<LayoutItem>>,
+    pub(crate) clusters: Shared<Vec<ClusterData>>,
+    pub(crate) glyphs: Shared<Vec<Glyph>>,
 
     // Output of line breaking
     pub(crate) lines: Vec<LineData>,
     pub(crate) line_items: Vec<LineItemData>,
+    /// Whether the max advance given to line breaking was not positive, and so was ignored.
+    pub(crate) ignored_max_advance: bool,
+    /// The cluster, glyph and widening of each tab which line breaking expanded to its tab
+    /// stop, so that the shaped advances can be restored.
+    pub(crate) tab_expansions: Vec<(usize, Option<usize>, f32)>,
 
     // Output of alignment
     /// Whether the layout is aligned with [`crate::Alignment::Justified`].
     pub(crate) is_aligned_justified: bool,
+    /// How justified alignment distributed free space, so that it can be undone.
+    pub(crate) justify_content: JustifyContent,
+    /// The line item, cluster, glyph and change of advance of each cluster whose
+    /// spacing was rounded by alignment, so that it can be undone.
+    pub(crate) spacing_adjustments: Vec<(usize, usize, Option<usize>, f32)>,
     /// The width the layout was aligned to.
     pub(crate) alignment_width: f32,
+
+    // Coordinate space, which is kept when the layout is rebuilt
+    /// Position of the layout's origin in the caller's coordinate space.
+    pub(crate) origin: (f32, f32),
+    pub(crate) y_axis: YAxis,
+
+    #[cfg(feature = "stats")]
+    pub(crate) stats: crate::LayoutStats,
 }
 
 impl<B: Brush> Default for LayoutData<B> {
@@ -237,22 +416,42 @@ impl<B: Brush> Default for LayoutData<B> {
             has_bidi: false,
             base_level: 0,
             text_len: 0,
+            build_id: 0,
             width: 0.,
             full_width: 0.,
             content_widths: OnceCell::new(),
             height: 0.,
             fonts: Vec::new(),
             coords: Vec::new(),
+            paragraphs: Vec::new(),
             styles: Vec::new(),
             inline_boxes: Vec::new(),
-            runs: Vec::new(),
-            items: Vec::new(),
-            clusters: Vec::new(),
-            glyphs: Vec::new(),
+            leading_boxes: Vec::new(),
+            text_indent: TextIndent::default(),
+            links: Vec::new(),
+            warnings: Vec::new(),
+            diagnostics: false,
+            spacing_rounding: SpacingRounding::None,
+            tab_stops: TabStops::None,
+            hyphenation_limits: HyphenationLimits::default(),
+            snap_inline_boxes: false,
+            text: Shared::default(),
+            runs: Shared::default(),
+            items: Shared::default(),
+            clusters: Shared::default(),
+            glyphs: Shared::default(),
             lines: Vec::new(),
             line_items: Vec::new(),
+            ignored_max_advance: false,
             is_aligned_justified: false,
+            justify_content: JustifyContent::InterWord,
+            spacing_adjustments: Vec::new(),
+            tab_expansions: Vec::new(),
             alignment_width: 0.0,
+            origin: (0.0, 0.0),
+            y_axis: YAxis::Down,
+            #[cfg(feature = "stats")]
+            stats: crate::LayoutStats::default(),
         }
     }
 }
@@ -263,20 +462,39 @@ impl<B: Brush> LayoutData<B> {
         self.has_bidi = false;
         self.base_level = 0;
         self.text_len = 0;
+        self.build_id = 0;
         self.width = 0.;
         self.full_width = 0.;
         self.content_widths.take();
         self.height = 0.;
         self.fonts.clear();
         self.coords.clear();
+        self.paragraphs.clear();
         self.styles.clear();
         self.inline_boxes.clear();
+        self.leading_boxes.clear();
+        self.text_indent = TextIndent::default();
+        self.links.clear();
+        self.warnings.clear();
+        self.diagnostics = false;
+        self.spacing_rounding = SpacingRounding::None;
+        self.tab_stops = TabStops::None;
+        self.hyphenation_limits = HyphenationLimits::default();
+        self.snap_inline_boxes = false;
+        self.text.clear();
         self.runs.clear();
         self.items.clear();
         self.clusters.clear();
         self.glyphs.clear();
         self.lines.clear();
         self.line_items.clear();
+        self.ignored_max_advance = false;
+        self.spacing_adjustments.clear();
+        self.tab_expansions.clear();
+        #[cfg(feature = "stats")]
+        {
+            self.stats = crate::LayoutStats::default();
+        }
     }
 
     /// Push an inline box to the list of items
@@ -463,21 +681,371 @@ impl<B: Brush> LayoutData<B> {
         flush_run!();
     }
 
-    pub(crate) fn finish(&mut self) {
+    /// Strips the advance and glyphs from every cluster that is made up of
+    /// ignorable characters or is covered by an ignorable range.
+    ///
+    /// This happens after shaping, so the clusters still separate their neighbours
+    /// for kerning and ligatures.
+    pub(crate) fn apply_ignorables(&mut self, text: &str, chars: &[char], ranges: &[Range<usize>]) {
+        if chars.is_empty() && ranges.is_empty() {
+            return;
+        }
+        for run in &mut self.runs {
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                let range = cluster.text_range(run);
+                if range.is_empty() {
+                    continue;
+                }
+                let in_range = ranges
+                    .iter()
+                    .any(|r| r.start <= range.start && range.end <= r.end);
+                if !in_range && !text[range].chars().all(|ch| chars.contains(&ch)) {
+                    continue;
+                }
+                run.advance -= cluster.advance;
+                cluster.advance = 0.;
+                cluster.glyph_len = 0;
+                cluster.glyph_offset = 0;
+                cluster.flags |= ClusterData::IGNORABLE;
+            }
+        }
+    }
+
+    /// Marks every cluster which consists of line or paragraph separators, or of the
+    /// caller's hard break characters, as a hard break.
+    ///
+    /// Like newlines, these clusters end their line. They contribute no advance and
+    /// produce no glyphs.
+    pub(crate) fn apply_hard_breaks(&mut self, text: &str, chars: &[(char, HardBreak)]) {
+        if chars.is_empty() && !text.contains(['\u{2028}', '\u{2029}']) {
+            return;
+        }
+        for run in &mut self.runs {
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                let range = cluster.text_range(run);
+                if range.is_empty() || !text[range].chars().all(|ch| is_hard_break(ch, chars)) {
+                    continue;
+                }
+                run.advance -= cluster.advance;
+                cluster.advance = 0.;
+                cluster.glyph_len = 0;
+                cluster.glyph_offset = 0;
+                cluster.flags |= ClusterData::HARD_BREAK;
+            }
+        }
+    }
+
+    /// Replaces the glyph of each cluster that consists of a single overridden character.
+    ///
+    /// Clusters which were shaped with another font, because the font of the override
+    /// could not be loaded, are left as they are.
+    pub(crate) fn apply_glyph_overrides(
+        &mut self,
+        text: &str,
+        overrides: &HashMap<char, GlyphOverride>,
+    ) {
+        if overrides.is_empty() {
+            return;
+        }
+        for run in &mut self.runs {
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                if cluster.is_ligature_start() || cluster.is_ligature_component() {
+                    continue;
+                }
+                let mut chars = text[cluster.text_range(run)].chars();
+                let (Some(ch), None) = (chars.next(), chars.next()) else {
+                    continue;
+                };
+                let Some(glyph) = overrides
+                    .get(&ch)
+                    .filter(|glyph| self.fonts[run.font_index] == glyph.font)
+                else {
+                    continue;
+                };
+                run.advance -= cluster.advance;
+                cluster.glyph_len = 0xFF;
+                cluster.glyph_offset = glyph.glyph_id;
+                cluster.advance = glyph.advance * run.font_size;
+                run.advance += cluster.advance;
+            }
+        }
+    }
+
+    /// Marks the clusters which consist of a soft hyphen, so that lines which are
+    /// wrapped after them can be reported as hyphenated, and those at which the
+    /// hyphenation limits don't allow lines to be wrapped.
+    pub(crate) fn mark_soft_hyphens(&mut self, text: &str) {
+        if !text.contains('\u{AD}') {
+            return;
+        }
+        let limited = if self.hyphenation_limits.is_limited() {
+            self.hyphenation_limits.limited_hyphens(text)
+        } else {
+            Vec::new()
+        };
         for run in &self.runs {
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                let range = cluster.text_range(run);
+                if text.get(range.clone()) == Some("\u{AD}") {
+                    cluster.flags |= ClusterData::SOFT_HYPHEN;
+                    if limited.binary_search(&range.start).is_ok() {
+                        cluster.flags |= ClusterData::HYPHENATION_LIMITED;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Flags the clusters which continue an emoji ZWJ, flag or keycap sequence.
+    ///
+    /// Font fallback can split a sequence into several clusters when no font supports it
+    /// as a whole, but the sequence still wraps as a unit.
+    pub(crate) fn mark_emoji_continuations(&mut self, text: &str) {
+        if text.is_ascii() {
+            return;
+        }
+        for run in &self.runs {
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                let range = cluster.text_range(run);
+                if range.is_empty() {
+                    continue;
+                }
+                if continues_emoji_sequence(text, range.start) {
+                    cluster.flags |= ClusterData::EMOJI_CONTINUATION;
+                }
+            }
+        }
+    }
+
+    /// Marks the clusters which do not start a grapheme cluster of `analysis`.
+    pub(crate) fn mark_grapheme_continuations(&mut self, text: &str, analysis: &TextAnalysis) {
+        if text.is_ascii() {
+            return;
+        }
+        for run in &self.runs {
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                let range = cluster.text_range(run);
+                if range.is_empty() {
+                    continue;
+                }
+                if analysis
+                    .boundary(range.start)
+                    .is_some_and(|boundary| !boundary.grapheme)
+                {
+                    cluster.flags |= ClusterData::GRAPHEME_CONTINUATION;
+                }
+            }
+        }
+    }
+
+    /// Marks the clusters whose letters join to the letters beside them, such as in
+    /// Arabic, so that letter spacing doesn't break the joins.
+    ///
+    /// Joins are decided by the Unicode joining type of the first letter of each cluster.
+    /// Clusters of transparent characters, such as combining marks, take the joins of the
+    /// cluster before them.
+    pub(crate) fn mark_joining_clusters(&mut self, text: &str) {
+        if text.is_ascii() || self.runs.iter().all(|run| nearly_zero(run.letter_spacing)) {
+            return;
+        }
+        for run in &self.runs {
+            let mut previous = 0;
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                let joins = joins_of(&text[cluster.text_range(run)]).unwrap_or(previous);
+                cluster.flags |= joins;
+                previous = joins;
+            }
+        }
+    }
+
+    /// Records the runs from `first_run` onwards in the statistics of the layout.
+    #[cfg(feature = "stats")]
+    pub(crate) fn record_runs(
+        &mut self,
+        first_run: usize,
+        duration: core::time::Duration,
+        is_cached: bool,
+    ) {
+        let runs = &self.runs[first_run..];
+        let duration = duration / runs.len().max(1) as u32;
+        self.stats
+            .runs
+            .extend(runs.iter().map(|run| crate::RunStats {
+                text_range: run.text_range.clone(),
+                font: self.fonts[run.font_index].clone(),
+                duration,
+                is_cached,
+            }));
+    }
+
+    /// Merges each run into the previous one when they are adjacent and share a font,
+    /// font size, brush and direction.
+    pub(crate) fn merge_runs(&mut self) {
+        let mut runs: Vec<RunData> = Vec::with_capacity(self.runs.len());
+        let mut items = Vec::with_capacity(self.items.len());
+        let mut follows_run = false;
+        for item in &self.items {
+            if item.kind == LayoutItemKind::InlineBox {
+                items.push(item.clone());
+                follows_run = false;
+                continue;
+            }
+            let run = &self.runs[item.index];
+            let prev = runs
+                .last_mut()
+                .filter(|prev| follows_run && self.can_merge_runs(prev, run));
+            if let Some(prev) = prev {
+                let text_delta = run.text_range.start - prev.text_range.start;
+                let glyph_delta = run.glyph_start - prev.glyph_start;
+                for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                    cluster.text_offset += text_delta as u16;
+                    if cluster.glyph_len != 0xFF {
+                        cluster.glyph_offset += glyph_delta as u16;
+                    }
+                }
+                prev.text_range.end = run.text_range.end;
+                prev.cluster_range.end = run.cluster_range.end;
+                prev.ends_with_newline = run.ends_with_newline;
+                prev.advance += run.advance;
+                continue;
+            }
+            runs.push(run.clone());
+            items.push(LayoutItem {
+                index: runs.len() - 1,
+                ..item.clone()
+            });
+            follows_run = true;
+        }
+        self.runs = runs.into();
+        self.items = items.into();
+    }
+
+    /// Returns `true` if `run` can be merged into `prev`, the run before it.
+    fn can_merge_runs(&self, prev: &RunData, run: &RunData) -> bool {
+        const MAX_LEN: usize = u16::MAX as usize;
+        let prev_clusters = &self.clusters[prev.cluster_range.clone()];
+        let clusters = &self.clusters[run.cluster_range.clone()];
+        let brush = |cluster: Option<&ClusterData>| {
+            cluster.map(|cluster| &self.styles[cluster.style_index as usize].brush)
+        };
+        if prev.ends_with_newline
+            || prev.text_range.end != run.text_range.start
+            || prev.cluster_range.end != run.cluster_range.start
+            || brush(prev_clusters.last()) != brush(clusters.first())
+        {
+            return false;
+        }
+        let same_font = prev.font_index == run.font_index
+            && prev.font_size == run.font_size
+            && prev.synthesis.embolden() == run.synthesis.embolden()
+            && prev.synthesis.skew() == run.synthesis.skew()
+            && prev.synthesis.variations() == run.synthesis.variations()
+            && self.coords[prev.coords_range.clone()] == self.coords[run.coords_range.clone()];
+        let same_spacing =
+            prev.word_spacing == run.word_spacing && prev.letter_spacing == run.letter_spacing;
+        if !same_font || !same_spacing || prev.bidi_level != run.bidi_level {
+            return false;
+        }
+        // The offsets of the clusters into the merged run must still fit in a `u16`.
+        let glyph_delta = run.glyph_start - prev.glyph_start;
+        run.text_range.end - prev.text_range.start <= MAX_LEN
+            && clusters.iter().all(|cluster| {
+                cluster.glyph_len == 0xFF
+                    || cluster.glyph_offset as usize + cluster.glyph_len as usize + glyph_delta
+                        <= MAX_LEN
+            })
+    }
+
+    /// Strips the glyphs from every cluster that is covered by a hidden or redacted range.
+    ///
+    /// The first cluster of each redacted range takes the advance of the redaction and
+    /// the remaining clusters contribute no advance.
+    pub(crate) fn apply_visibility(&mut self, ranges: &[(Range<usize>, Visibility)]) {
+        if ranges.is_empty() {
+            return;
+        }
+        let mut redacted = vec![false; ranges.len()];
+        for run in &mut self.runs {
+            for cluster in &mut self.clusters[run.cluster_range.clone()] {
+                let range = cluster.text_range(run);
+                if range.is_empty() {
+                    continue;
+                }
+                let Some((index, (_, visibility))) = ranges
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .find(|(_, (r, _))| r.start <= range.start && range.end <= r.end)
+                else {
+                    continue;
+                };
+                let advance = match *visibility {
+                    Visibility::Visible => continue,
+                    Visibility::Hidden => None,
+                    Visibility::Redacted(width) if !redacted[index] => {
+                        redacted[index] = true;
+                        Some(width)
+                    }
+                    Visibility::Redacted(_) => None,
+                };
+                run.advance -= cluster.advance;
+                cluster.glyph_len = 0;
+                cluster.glyph_offset = 0;
+                if let Some(advance) = advance {
+                    cluster.advance = advance;
+                    cluster.flags |= ClusterData::REDACTED;
+                } else {
+                    cluster.advance = 0.;
+                    cluster.flags |= ClusterData::IGNORABLE;
+                }
+                run.advance += cluster.advance;
+            }
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        for (run_index, run) in self.runs.iter().enumerate() {
             let word = run.word_spacing;
             let letter = run.letter_spacing;
             if nearly_zero(word) && nearly_zero(letter) {
                 continue;
             }
-            let clusters = &mut self.clusters[run.cluster_range.clone()];
-            for cluster in clusters {
-                let mut spacing = letter;
+            // Whether the first cluster of the following run continues the grapheme
+            // cluster which ends this run.
+            let continued = self.runs.get(run_index + 1).is_some_and(|next| {
+                next.text_range.start == run.text_range.end
+                    && self
+                        .clusters
+                        .get(next.cluster_range.start)
+                        .is_some_and(|cluster| cluster.is_grapheme_continuation())
+            });
+            let range = run.cluster_range.clone();
+            for index in range.clone() {
+                let cluster = self.clusters[index];
+                if cluster.is_ignorable() || cluster.is_redacted() {
+                    continue;
+                }
+                // Letter spacing follows the last cluster of each grapheme cluster, so
+                // that it never separates a combining mark from its base. Nor does it
+                // separate letters which join each other, such as in Arabic.
+                let next = self.clusters[index + 1..range.end].first();
+                let ends_grapheme = match next {
+                    Some(next) => !next.is_grapheme_continuation(),
+                    None => !continued,
+                };
+                let joins_next =
+                    cluster.joins_next() && next.is_some_and(|next| next.joins_previous());
+                let mut spacing = 0.;
+                if ends_grapheme && !joins_next && !nearly_zero(letter) {
+                    spacing += letter;
+                    self.clusters[index].flags |= ClusterData::LETTER_SPACED;
+                }
                 if !nearly_zero(word) && cluster.info.whitespace().is_space_or_nbsp() {
                     spacing += word;
+                    self.clusters[index].flags |= ClusterData::WORD_SPACED;
                 }
                 if !nearly_zero(spacing) {
-                    cluster.advance += spacing;
+                    self.clusters[index].advance += spacing;
                     if cluster.glyph_len != 0xFF {
                         let start = run.glyph_start + cluster.glyph_offset as usize;
                         let end = start + cluster.glyph_len as usize;
@@ -491,6 +1059,32 @@ impl<B: Brush> LayoutData<B> {
         }
     }
 
+    /// Restores the shaped advances of the tabs which line breaking expanded to their tab
+    /// stops.
+    pub(crate) fn restore_tab_advances(&mut self) {
+        for (cluster_index, glyph_index, delta) in self.tab_expansions.drain(..) {
+            self.clusters[cluster_index].advance -= delta;
+            if let Some(glyph_index) = glyph_index {
+                self.glyphs[glyph_index].advance -= delta;
+            }
+        }
+    }
+
+    /// Returns the shaped advance of a cluster, without the expansion of a tab to its
+    /// tab stop.
+    fn shaped_advance(&self, cluster_index: usize) -> f32 {
+        let cluster = &self.clusters[cluster_index];
+        if cluster.info.whitespace() != Whitespace::Tab {
+            return cluster.advance;
+        }
+        let expansion = self
+            .tab_expansions
+            .iter()
+            .find(|(index, ..)| *index == cluster_index)
+            .map_or(0.0, |(.., delta)| *delta);
+        cluster.advance - expansion
+    }
+
     pub(crate) fn content_widths(&self) -> ContentWidths {
         *self
             .content_widths
@@ -510,6 +1104,7 @@ impl<B: Brush> LayoutData<B> {
 
         let mut running_max_width = 0.0;
         let mut prev_cluster: Option<&ClusterData> = None;
+        let mut after_limited_hyphen = false;
         let is_rtl = self.base_level & 1 == 1;
         for item in &self.items {
             match item.kind {
@@ -520,11 +1115,19 @@ impl<B: Brush> LayoutData<B> {
                     if is_rtl {
                         prev_cluster = clusters.first();
                     }
-                    for cluster in clusters {
+                    for (cluster_index, cluster) in run.cluster_range.clone().zip(clusters) {
+                        let advance = self.shaped_advance(cluster_index);
                         let boundary = cluster.info.boundary();
                         let style = &self.styles[cluster.style_index as usize];
-                        if matches!(boundary, Boundary::Line | Boundary::Mandatory)
-                            || style.overflow_wrap == OverflowWrap::Anywhere
+                        // Like the line breaker, never break within a ligature or an emoji
+                        // sequence, or after a soft hyphen which the hyphenation limits disallow.
+                        let is_line_boundary = boundary == Boundary::Line && !after_limited_hyphen;
+                        after_limited_hyphen = cluster.is_hyphenation_limited();
+                        if !cluster.is_ligature_component()
+                            && !cluster.is_emoji_continuation()
+                            && (is_line_boundary
+                                || boundary == Boundary::Mandatory
+                                || style.overflow_wrap == OverflowWrap::Anywhere)
                         {
                             let trailing_whitespace = whitespace_advance(prev_cluster);
                             min_width = min_width.max(running_min_width - trailing_whitespace);
@@ -533,8 +1136,8 @@ impl<B: Brush> LayoutData<B> {
                                 running_max_width = 0.0;
                             }
                         }
-                        running_min_width += cluster.advance;
-                        running_max_width += cluster.advance;
+                        running_min_width += advance;
+                        running_max_width += advance;
                         if !is_rtl {
                             prev_cluster = Some(cluster);
                         }
@@ -547,6 +1150,7 @@ impl<B: Brush> LayoutData<B> {
                     min_width = min_width.max(ibox.width);
                     running_max_width += ibox.width;
                     prev_cluster = None;
+                    after_limited_hyphen = false;
                 }
             }
             let trailing_whitespace = whitespace_advance(prev_cluster);
@@ -559,3 +1163,26 @@ impl<B: Brush> LayoutData<B> {
         }
     }
 }
+
+/// Returns `true` if the character at `index` continues the emoji sequence before it, such
+/// as the character following a zero width joiner or the second regional indicator of a flag.
+fn continues_emoji_sequence(text: &str, index: usize) -> bool {
+    let is_regional_indicator = |ch: char| matches!(ch, '\u{1F1E6}'..='\u{1F1FF}');
+    let Some(ch) = text[index..].chars().next() else {
+        return false;
+    };
+    let mut before = text[..index].chars().rev();
+    match ch {
+        // Zero width joiner, emoji presentation selector, keycap, skin tone modifiers and tags.
+        '\u{200D}'
+        | '\u{FE0F}'
+        | '\u{20E3}'
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        | '\u{E0020}'..='\u{E007F}' => true,
+        _ if before.clone().next() == Some('\u{200D}') => true,
+        _ if is_regional_indicator(ch) => {
+            before.take_while(|ch| is_regional_indicator(*ch)).count() % 2 == 1
+        }
+        _ => false,
+    }
+}
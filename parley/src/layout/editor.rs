@@ -7,7 +7,7 @@ use crate::{
     FontContext, LayoutContext, Rect, StyleProperty, StyleSet,
     layout::{
         Affinity, Alignment, AlignmentOptions, Layout,
-        cursor::{Cursor, Selection},
+        cursor::{CaretMovement, Cursor, Selection},
     },
     resolve::ResolvedStyle,
     style::Brush,
@@ -91,6 +91,30 @@ impl<'source> IntoIterator for SplitString<'source> {
     }
 }
 
+/// The state of a [`PlainEditor`] which is independent of its styles and layout.
+///
+/// Obtained from [`PlainEditor::snapshot`], and applied with [`PlainEditor::restore`]. This
+/// can be used to implement undo and redo, or to persist the editor across sessions when
+/// the `serde` feature is enabled.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EditorState {
+    /// The text buffer, including the IME preedit text if any.
+    pub text: String,
+    /// Byte index of the anchor of the selection.
+    pub anchor: usize,
+    /// Affinity of the anchor of the selection.
+    pub anchor_affinity: Affinity,
+    /// Byte index of the focus of the selection.
+    pub focus: usize,
+    /// Affinity of the focus of the selection.
+    pub focus_affinity: Affinity,
+    /// Byte offsets of the IME preedit text in `text`, if composing.
+    pub compose: Option<Range<usize>>,
+    /// Whether the cursor is shown.
+    pub show_cursor: bool,
+}
+
 /// Basic plain text editor with a single style applied to the entire text.
 ///
 /// Internally, this is a wrapper around a string buffer and its corresponding [`Layout`],
@@ -109,10 +133,10 @@ where
     selection: Selection,
     /// Byte offsets of IME composing preedit text in the text buffer.
     /// `None` if the IME is not currently composing.
-    compose: Option<Range<usize>>,
+    pub(crate) compose: Option<Range<usize>>,
     /// Whether the cursor should be shown. The IME can request to hide the cursor.
     show_cursor: bool,
-    width: Option<f32>,
+    pub(crate) width: Option<f32>,
     scale: f32,
     // Simple tracking of when the layout needs to be updated
     // before it can be used for `Selection` calculations or
@@ -120,11 +144,12 @@ where
     // Not all operations on `PlainEditor` need to operate on a
     // clean layout, and not all operations trigger a layout.
     layout_dirty: bool,
-    // TODO: We could avoid redoing the full text layout if only
-    // linebreaking or alignment were changed.
-    // linebreak_dirty: bool,
-    // alignment_dirty: bool,
+    // Changes to the width or alignment only need the existing
+    // shaped text to be broken into lines or aligned again.
+    linebreak_dirty: bool,
+    alignment_dirty: bool,
     alignment: Alignment,
+    caret_movement: CaretMovement,
     generation: Generation,
 }
 
@@ -146,7 +171,10 @@ where
             width: None,
             scale: 1.0,
             layout_dirty: true,
+            linebreak_dirty: false,
+            alignment_dirty: false,
             alignment: Alignment::Start,
+            caret_movement: CaretMovement::default(),
             // We don't use the `default` value to start with, as our consumers
             // will choose to use that as their initial value, but will probably need
             // to redraw if they haven't already.
@@ -441,28 +469,30 @@ where
             .set_selection(self.editor.selection.next_line(&self.editor.layout, false));
     }
 
-    /// Move to the next cluster left in visual order.
+    /// Move to the next cluster left, in visual or logical order depending on the
+    /// [caret movement](PlainEditor::set_caret_movement).
     pub fn move_left(&mut self) {
         assert!(!self.editor.is_composing());
 
         self.refresh_layout();
-        self.editor.set_selection(
-            self.editor
-                .selection
-                .previous_visual(&self.editor.layout, false),
-        );
+        self.editor.set_selection(self.editor.selection.move_left(
+            &self.editor.layout,
+            self.editor.caret_movement,
+            false,
+        ));
     }
 
-    /// Move to the next cluster right in visual order.
+    /// Move to the next cluster right, in visual or logical order depending on the
+    /// [caret movement](PlainEditor::set_caret_movement).
     pub fn move_right(&mut self) {
         assert!(!self.editor.is_composing());
 
         self.refresh_layout();
-        self.editor.set_selection(
-            self.editor
-                .selection
-                .next_visual(&self.editor.layout, false),
-        );
+        self.editor.set_selection(self.editor.selection.move_right(
+            &self.editor.layout,
+            self.editor.caret_movement,
+            false,
+        ));
     }
 
     /// Move to the next word boundary left.
@@ -570,25 +600,30 @@ where
             .set_selection(self.editor.selection.next_line(&self.editor.layout, true));
     }
 
-    /// Move the selection focus point to the next cluster left in visual order.
+    /// Move the selection focus point to the next cluster left, in visual or logical
+    /// order depending on the [caret movement](PlainEditor::set_caret_movement).
     pub fn select_left(&mut self) {
         assert!(!self.editor.is_composing());
 
         self.refresh_layout();
-        self.editor.set_selection(
-            self.editor
-                .selection
-                .previous_visual(&self.editor.layout, true),
-        );
+        self.editor.set_selection(self.editor.selection.move_left(
+            &self.editor.layout,
+            self.editor.caret_movement,
+            true,
+        ));
     }
 
-    /// Move the selection focus point to the next cluster right in visual order.
+    /// Move the selection focus point to the next cluster right, in visual or logical
+    /// order depending on the [caret movement](PlainEditor::set_caret_movement).
     pub fn select_right(&mut self) {
         assert!(!self.editor.is_composing());
 
         self.refresh_layout();
-        self.editor
-            .set_selection(self.editor.selection.next_visual(&self.editor.layout, true));
+        self.editor.set_selection(self.editor.selection.move_right(
+            &self.editor.layout,
+            self.editor.caret_movement,
+            true,
+        ));
     }
 
     /// Move the selection focus point to the next word boundary left.
@@ -836,9 +871,10 @@ where
         // Using 0.6 as an estimate of the average advance
         let inflate = 3. * 0.6 * font_size as f64;
         let editor_width = self.width.map(f64::from).unwrap_or(f64::INFINITY);
+        let origin_x = self.layout.origin().0 as f64;
         Rect {
-            x0: (area.x0 - inflate).max(0.),
-            x1: (area.x1 + inflate).min(editor_width),
+            x0: (area.x0 - inflate).max(origin_x),
+            x1: (area.x1 + inflate).min(origin_x + editor_width),
             y0: area.y0,
             y1: area.y1,
         }
@@ -886,16 +922,82 @@ where
         self.layout_dirty = true;
     }
 
+    /// Capture the text, selection and IME state of the editor.
+    ///
+    /// The styles, width and alignment are not included.
+    pub fn snapshot(&self) -> EditorState {
+        EditorState {
+            text: self.buffer.clone(),
+            anchor: self.selection.anchor().index(),
+            anchor_affinity: self.selection.anchor().affinity(),
+            focus: self.selection.focus().index(),
+            focus_affinity: self.selection.focus().affinity(),
+            compose: self.compose.clone(),
+            show_cursor: self.show_cursor,
+        }
+    }
+
+    /// Replace the text, selection and IME state of the editor with a snapshot
+    /// taken by [`snapshot`](Self::snapshot).
+    ///
+    /// Offsets past the end of the text are clamped to its end, and offsets within a
+    /// character are moved back to its start. An invalid preedit range is dropped. The
+    /// selection is snapped to cluster boundaries when the layout is next updated.
+    pub fn restore(&mut self, state: EditorState) {
+        let text = state.text;
+        let clamp = |mut index: usize| {
+            index = index.min(text.len());
+            while !text.is_char_boundary(index) {
+                index -= 1;
+            }
+            index
+        };
+        self.selection = Selection::new(
+            Cursor::new(clamp(state.anchor), state.anchor_affinity),
+            Cursor::new(clamp(state.focus), state.focus_affinity),
+        );
+        self.compose = state.compose.filter(|compose| {
+            compose.start <= compose.end
+                && text.is_char_boundary(compose.start)
+                && text.is_char_boundary(compose.end)
+        });
+        self.buffer = text;
+        self.show_cursor = state.show_cursor;
+        self.layout_dirty = true;
+        self.generation.nudge();
+    }
+
     /// Set the width of the layout.
+    ///
+    /// This only breaks the existing layout into lines again, without shaping the text.
     pub fn set_width(&mut self, width: Option<f32>) {
-        self.width = width;
-        self.layout_dirty = true;
+        if width != self.width {
+            self.width = width;
+            self.linebreak_dirty = true;
+        }
     }
 
     /// Set the alignment of the layout.
+    ///
+    /// This only aligns the existing lines again, without shaping the text.
     pub fn set_alignment(&mut self, alignment: Alignment) {
-        self.alignment = alignment;
-        self.layout_dirty = true;
+        if alignment != self.alignment {
+            self.alignment = alignment;
+            self.alignment_dirty = true;
+        }
+    }
+
+    /// Set the convention for moving the caret left and right.
+    ///
+    /// This is used by [`PlainEditorDriver::move_left`], [`PlainEditorDriver::move_right`]
+    /// and the corresponding selection methods.
+    pub fn set_caret_movement(&mut self, movement: CaretMovement) {
+        self.caret_movement = movement;
+    }
+
+    /// Get the convention for moving the caret left and right.
+    pub fn caret_movement(&self) -> CaretMovement {
+        self.caret_movement
     }
 
     /// Set the scale for the layout.
@@ -938,7 +1040,7 @@ where
     ///
     /// The [`layout`](Self::layout) method should generally be preferred.
     pub fn try_layout(&self) -> Option<&Layout<T>> {
-        if self.layout_dirty {
+        if self.is_dirty() {
             None
         } else {
             Some(&self.layout)
@@ -962,7 +1064,7 @@ where
         x_offset: f64,
         y_offset: f64,
     ) -> Option<()> {
-        if self.layout_dirty {
+        if self.is_dirty() {
             return None;
         }
         self.accessibility_unchecked(update, node, next_node_id, x_offset, y_offset);
@@ -977,10 +1079,25 @@ where
     pub fn refresh_layout(&mut self, font_cx: &mut FontContext, layout_cx: &mut LayoutContext<T>) {
         if self.layout_dirty {
             self.update_layout(font_cx, layout_cx);
+        } else if self.linebreak_dirty || self.alignment_dirty {
+            if self.linebreak_dirty {
+                self.layout.break_all_lines(self.width);
+            }
+            self.layout
+                .align(self.width, self.alignment, AlignmentOptions::default());
+            self.selection = self.selection.refresh(&self.layout);
+            self.linebreak_dirty = false;
+            self.alignment_dirty = false;
+            self.generation.nudge();
         }
     }
 
     // --- MARK: Internal Helpers ---
+    /// Whether the layout needs to be updated before it can be used.
+    fn is_dirty(&self) -> bool {
+        self.layout_dirty || self.linebreak_dirty || self.alignment_dirty
+    }
+
     /// Make a cursor at a given byte index.
     fn cursor_at(&self, index: usize) -> Cursor {
         // TODO: Do we need to be non-dirty?
@@ -1070,6 +1187,8 @@ where
             .align(self.width, self.alignment, AlignmentOptions::default());
         self.selection = self.selection.refresh(&self.layout);
         self.layout_dirty = false;
+        self.linebreak_dirty = false;
+        self.alignment_dirty = false;
         self.generation.nudge();
     }
 
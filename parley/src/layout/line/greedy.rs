@@ -15,7 +15,7 @@ use crate::layout::{
     Boundary, BreakReason, Layout, LayoutData, LayoutItem, LayoutItemKind, LineData, LineItemData,
     LineMetrics, Run,
 };
-use crate::style::Brush;
+use crate::style::{Brush, TabStops};
 
 use core::ops::Range;
 
@@ -65,6 +65,11 @@ struct BreakerState {
     line: LineState,
     prev_boundary: Option<PrevBoundaryState>,
     emergency_boundary: Option<PrevBoundaryState>,
+
+    /// Top of the current line.
+    y: f32,
+    /// Width reserved by leading boxes at the left and right edges of the current line.
+    inset: (f32, f32),
 }
 
 impl BreakerState {
@@ -109,6 +114,16 @@ impl BreakerState {
     }
 }
 
+/// How lines are balanced when breaking to a target line count.
+///
+/// See [`BreakLines::target_lines`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Balance {
+    /// The lines are made as close to the same width as possible, by breaking them at the
+    /// narrowest advance which fits the text into the target number of lines.
+    Even,
+}
+
 /// Line breaking support for a paragraph.
 pub struct BreakLines<'a, B: Brush> {
     layout: &'a mut Layout<B>,
@@ -116,12 +131,23 @@ pub struct BreakLines<'a, B: Brush> {
     state: BreakerState,
     prev_state: Option<BreakerState>,
     done: bool,
+    /// The narrowest advance that lines are broken at, or zero for no minimum.
+    min_wrap_width: f32,
+    /// The number of lines that [`Self::break_remaining`] balances the text across.
+    target_lines: Option<(usize, Balance)>,
+    /// The sorted byte offsets that lines may be broken before, replacing the line
+    /// break opportunities of the text, if set by the caller.
+    allowed_breaks: Option<&'a [usize]>,
+    #[cfg(feature = "stats")]
+    start: std::time::Instant,
 }
 
 impl<'a, B: Brush> BreakLines<'a, B> {
     pub(crate) fn new(layout: &'a mut Layout<B>) -> Self {
+        layout.data.restore_tab_advances();
         layout.data.width = 0.;
         layout.data.height = 0.;
+        layout.data.ignored_max_advance = false;
         let mut lines = LineLayout::default();
         lines.swap(&mut layout.data);
         lines.lines.clear();
@@ -132,11 +158,57 @@ impl<'a, B: Brush> BreakLines<'a, B> {
             state: BreakerState::default(),
             prev_state: None,
             done: false,
+            min_wrap_width: 0.,
+            target_lines: None,
+            allowed_breaks: None,
+            #[cfg(feature = "stats")]
+            start: std::time::Instant::now(),
         }
     }
 
+    /// Sets the narrowest advance that lines are broken at, overriding any smaller
+    /// max advance.
+    ///
+    /// By default, there is no minimum. A container which is transiently too narrow for
+    /// any content can pass a width such as [`Layout::min_content_width`], so that it
+    /// doesn't produce a line per cluster.
+    pub fn min_wrap_width(mut self, width: f32) -> Self {
+        self.min_wrap_width = width;
+        self
+    }
+
+    /// Sets the number of lines that [`Self::break_remaining`] distributes the text
+    /// across, balanced according to `balance`.
+    ///
+    /// Rather than filling the earlier lines greedily, the lines are broken at a narrower
+    /// advance so that the text fills `count` lines as evenly as possible, such as for a
+    /// title on a button. Text with too few break opportunities uses fewer lines. Text
+    /// which needs more than `count` lines at the max advance is broken greedily.
+    pub fn target_lines(mut self, count: usize, balance: Balance) -> Self {
+        self.target_lines = Some((count, balance));
+        self
+    }
+
+    /// Restricts line breaks to before the characters at the byte offsets in `breaks`,
+    /// which must be sorted, such as break points computed from a syntax tree.
+    ///
+    /// The line break opportunities of the text are ignored, so lines are only broken
+    /// at these offsets, at hard breaks, and at emergency break opportunities if the
+    /// style's [`OverflowWrap`] allows them.
+    pub fn with_allowed_breaks(mut self, breaks: &'a [usize]) -> Self {
+        self.allowed_breaks = Some(breaks);
+        self
+    }
+
     /// Reset state when a line has been committed
     fn start_new_line(&mut self) -> Option<(f32, f32)> {
+        if let Some(line) = self.lines.lines.last_mut() {
+            line.inset = self.state.inset;
+            self.state.y += self.lines.line_items[line.item_range.clone()]
+                .iter()
+                .map(|item| item.compute_line_height(&self.layout.data))
+                .fold(0., f32::max);
+        }
         self.state.items = self.lines.line_items.len();
         self.state.lines = self.lines.lines.len();
         self.state.line.x = 0.;
@@ -150,6 +222,65 @@ impl<'a, B: Brush> BreakLines<'a, B> {
         Some((line.metrics.advance, line.size()))
     }
 
+    /// Places the leading boxes of a paragraph which starts on the current line, and
+    /// returns the width reserved by leading boxes and the text indent at the left and
+    /// right edges of the line.
+    fn leading_box_inset(&mut self) -> (f32, f32) {
+        let data = &mut self.layout.data;
+        if data.leading_boxes.is_empty() && data.text_indent.amount == 0. {
+            return (0., 0.);
+        }
+        let line_start = self
+            .lines
+            .lines
+            .last()
+            .and_then(|line| {
+                self.lines.line_items[line.item_range.clone()]
+                    .iter()
+                    .filter(|item| item.is_text_run())
+                    .map(|item| item.text_range.end)
+                    .max()
+            })
+            .unwrap_or(0);
+        let paragraph = data
+            .paragraphs
+            .partition_point(|paragraph| paragraph.text_range.end <= line_start);
+        let is_first_line = data
+            .paragraphs
+            .get(paragraph)
+            .is_some_and(|paragraph| paragraph.text_range.start == line_start);
+        if is_first_line {
+            for leading_box in &mut data.leading_boxes {
+                if leading_box.paragraph == paragraph {
+                    leading_box.y = self.state.y;
+                }
+            }
+        }
+
+        let mut inset = (0_f32, 0_f32);
+        for leading_box in &data.leading_boxes {
+            if leading_box.paragraph > paragraph
+                || self.state.y >= leading_box.y + leading_box.leading_box.height
+            {
+                continue;
+            }
+            let side = if leading_box.is_rtl {
+                &mut inset.1
+            } else {
+                &mut inset.0
+            };
+            *side = side.max(leading_box.leading_box.width);
+        }
+
+        // The indent is applied at the start edge, after any leading box.
+        if is_first_line && (paragraph == 0 || data.text_indent.each_paragraph) {
+            let is_rtl = data.paragraphs[paragraph].base_level & 1 == 1;
+            let side = if is_rtl { &mut inset.1 } else { &mut inset.0 };
+            *side += data.text_indent.amount;
+        }
+        inset
+    }
+
     /// Returns true if all the text has been placed into lines.
     pub fn is_done(&self) -> bool {
         self.done
@@ -164,6 +295,15 @@ impl<'a, B: Brush> BreakLines<'a, B> {
         }
         self.prev_state = Some(self.state.clone());
 
+        // An advance which is not positive can't fit any content, so break the lines as if
+        // it were unbounded rather than producing a line per cluster.
+        let max_advance = if max_advance > 0. {
+            max_advance
+        } else {
+            self.layout.data.ignored_max_advance = true;
+            f32::MAX
+        };
+
         // HACK: ignore max_advance for empty layouts
         // Prevents crash when width is too small (https://github.com/linebender/parley/issues/186)
         let max_advance =
@@ -173,6 +313,11 @@ impl<'a, B: Brush> BreakLines<'a, B> {
                 max_advance
             };
 
+        // Leading boxes and the text indent reduce the space available to the line.
+        self.state.inset = self.leading_box_inset();
+        let max_advance =
+            (max_advance - self.state.inset.0 - self.state.inset.1).max(self.min_wrap_width);
+
         // This macro simply calls the `commit_line` with the provided arguments and some parts of self.
         // It exists solely to cut down on the boilerplate for accessing the self variables while
         // keeping the borrow checker happy
@@ -193,6 +338,27 @@ impl<'a, B: Brush> BreakLines<'a, B> {
         // println!("\nBREAK NEXT");
         // dbg!(&self.state.line.items);
 
+        let tab_stops = self.layout.data.tab_stops;
+        // Soft hyphens are not break opportunities once the lines before this one reach the
+        // limit on consecutive hyphenated lines.
+        let hyphens_exhausted = self
+            .layout
+            .data
+            .hyphenation_limits
+            .max_consecutive_hyphenated_lines
+            .is_some_and(|max| {
+                self.lines
+                    .lines
+                    .iter()
+                    .rev()
+                    .take_while(|line| line.break_reason == BreakReason::Hyphenated)
+                    .count()
+                    >= max
+            });
+        let allowed_breaks = self.allowed_breaks;
+        let is_allowed_break =
+            |index: usize| allowed_breaks.is_none_or(|breaks| breaks.binary_search(&index).is_ok());
+
         // Iterate over remaining runs in the Layout
         let item_count = self.layout.data.items.len();
         while self.state.item_idx < item_count {
@@ -222,7 +388,9 @@ impl<'a, B: Brush> BreakLines<'a, B> {
                         self.state.append_inline_box_to_line(next_x);
 
                         // We can always line break after an inline box
-                        self.state.mark_line_break_opportunity();
+                        if is_allowed_break(inline_box.index) {
+                            self.state.mark_line_break_opportunity();
+                        }
                     } else {
                         // If we're at the start of the line, this box will never fit, so consume it and accept the overflow.
                         if self.state.line.x == 0.0 {
@@ -255,32 +423,49 @@ impl<'a, B: Brush> BreakLines<'a, B> {
                         let cluster = run.get(self.state.cluster_idx - cluster_start).unwrap();
 
                         // Retrieve metadata about the cluster
-                        let is_ligature_continuation = cluster.is_ligature_continuation();
+                        // Emoji sequences are never broken, even when fallback splits them.
+                        let is_continuation = cluster.is_ligature_continuation()
+                            || cluster.data.is_emoji_continuation();
                         let whitespace = cluster.info().whitespace();
-                        let is_newline = whitespace == Whitespace::Newline;
+                        let is_newline = cluster.data.is_hard_break();
                         let is_space = whitespace.is_space_or_nbsp();
-                        let boundary = cluster.info().boundary();
+                        let text_range = cluster.text_range();
+                        let is_line_boundary = match allowed_breaks {
+                            Some(breaks) => breaks.binary_search(&text_range.start).is_ok(),
+                            None => cluster.info().boundary() == Boundary::Line,
+                        };
                         let style = &self.layout.data.styles[cluster.data.style_index as usize];
 
-                        if boundary == Boundary::Line {
-                            // We do not currently handle breaking within a ligature, so we ignore boundaries in such a position.
-                            //
-                            // We also don't record boundaries when the advance is 0. As we do not want overflowing content to cause extra consecutive
-                            // line breaks. We should accept the overflowing fragment in that scenario.
-                            if !is_ligature_continuation && self.state.line.x != 0.0 {
-                                self.state.mark_line_break_opportunity();
-                                // break_opportunity = true;
-                            }
-                        } else if is_newline {
+                        if is_newline {
                             self.state.append_cluster_to_line(self.state.line.x);
                             if try_commit_line!(BreakReason::Explicit) {
                                 // TODO: can this be hoisted out of the conditional?
                                 self.state.cluster_idx += 1;
                                 return self.start_new_line();
                             }
+                        } else if is_line_boundary {
+                            // We do not currently handle breaking within a ligature or an emoji sequence, so we ignore boundaries in such a position.
+                            //
+                            // We also don't record boundaries when the advance is 0. As we do not want overflowing content to cause extra consecutive
+                            // line breaks. We should accept the overflowing fragment in that scenario.
+                            //
+                            // Boundaries after soft hyphens that the hyphenation limits disallow are ignored too.
+                            let is_limited_hyphen = self
+                                .state
+                                .cluster_idx
+                                .checked_sub(1)
+                                .map(|index| self.layout.data.clusters[index])
+                                .is_some_and(|prev| {
+                                    prev.is_hyphenation_limited()
+                                        || (hyphens_exhausted && prev.is_soft_hyphen())
+                                });
+                            if !is_continuation && self.state.line.x != 0.0 && !is_limited_hyphen {
+                                self.state.mark_line_break_opportunity();
+                                // break_opportunity = true;
+                            }
                         } else if
                         // This text can contribute "emergency" line breaks.
-                        style.overflow_wrap != OverflowWrap::Normal && !is_ligature_continuation
+                        style.overflow_wrap != OverflowWrap::Normal && !is_continuation
                         // If we're at the start of the line, this particular cluster will never fit, so it's not a valid emergency break opportunity.
                         && self.state.line.x != 0.0
                         {
@@ -301,6 +486,13 @@ impl<'a, B: Brush> BreakLines<'a, B> {
                             }
                         }
 
+                        // Tabs reach the next tab stop, and are widened to match in `finish`.
+                        if whitespace == Whitespace::Tab {
+                            if let Some(tab_advance) = tab_stops.advance(self.state.line.x) {
+                                advance = tab_advance;
+                            }
+                        }
+
                         // Compute the x position of the content being currently processed
                         let next_x = self.state.line.x + advance;
 
@@ -321,7 +513,7 @@ impl<'a, B: Brush> BreakLines<'a, B> {
                         // Else we line break:
                         else {
                             // Handle case where cluster is space character. Hang overflowing whitespace.
-                            if is_space {
+                            if is_space && is_allowed_break(text_range.end) {
                                 self.state.append_cluster_to_line(next_x);
                                 if try_commit_line!(BreakReason::Regular) {
                                     // TODO: can this be hoisted out of the conditional?
@@ -413,10 +605,64 @@ impl<'a, B: Brush> BreakLines<'a, B> {
 
         // println!("\nBREAK ALL");
 
+        let max_advance = match self.target_lines {
+            Some((count, Balance::Even)) if !self.done => self.balanced_advance(max_advance, count),
+            _ => max_advance,
+        };
         while self.break_next(max_advance).is_some() {}
         self.finish();
     }
 
+    /// Returns the narrowest advance, up to `max_advance`, which breaks the remaining
+    /// text into at most `count` lines, or `max_advance` if there is none.
+    fn balanced_advance(&mut self, max_advance: f32, count: usize) -> f32 {
+        let (lines, widest) = self.measure_remaining(max_advance);
+        if lines > count {
+            return max_advance;
+        }
+        let mut low = self.min_wrap_width;
+        let mut high = max_advance.min(widest);
+        if high <= low || self.measure_remaining(high).0 > count {
+            return max_advance;
+        }
+        if self.measure_remaining(low).0 <= count {
+            return low;
+        }
+        // Bisect the advance, keeping `high` as one which fits the text into `count` lines.
+        while high - low > 0.5 {
+            let mid = (low + high) * 0.5;
+            if self.measure_remaining(mid).0 <= count {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        high
+    }
+
+    /// Breaks the remaining lines at `max_advance` and reverts them, returning the number
+    /// of lines and the widest line, including its insets.
+    fn measure_remaining(&mut self, max_advance: f32) -> (usize, f32) {
+        let state = self.state.clone();
+        let prev_state = self.prev_state.take();
+        let ignored_max_advance = self.layout.data.ignored_max_advance;
+        while self.break_next(max_advance).is_some() {}
+        let lines = &self.lines.lines[state.lines..];
+        let widest = lines
+            .iter()
+            .map(|line| line.metrics.advance + line.inset.0 + line.inset.1)
+            .fold(0., f32::max);
+        let count = lines.len();
+
+        self.lines.lines.truncate(state.lines);
+        self.lines.line_items.truncate(state.items);
+        self.state = state;
+        self.prev_state = prev_state;
+        self.done = false;
+        self.layout.data.ignored_max_advance = ignored_max_advance;
+        (count, widest)
+    }
+
     /// Consumes the line breaker and finalizes all line computations.
     pub fn finish(mut self) {
         // For each run (item which is a text run):
@@ -457,11 +703,16 @@ impl<'a, B: Brush> BreakLines<'a, B> {
         let mut y = 0.;
         let mut prev_line_metrics = None;
         for line in &mut self.lines.lines {
+            expand_tabs(
+                &mut self.layout.data,
+                &self.lines.line_items[line.item_range.clone()],
+            );
+
             // Reset metrics for line
             line.metrics.ascent = 0.;
             line.metrics.descent = 0.;
             line.metrics.leading = 0.;
-            line.metrics.offset = 0.;
+            line.metrics.offset = line.inset.0;
             line.text_range.start = usize::MAX;
 
             if line.item_range.is_empty() {
@@ -631,15 +882,26 @@ impl<B: Brush> Drop for BreakLines<'_, B> {
         let mut full_width = 0_f32;
         let mut height = 0_f32;
         for line in &self.lines.lines {
-            width = width.max(line.metrics.advance - line.metrics.trailing_whitespace);
-            full_width = full_width.max(line.metrics.advance);
+            let inset = line.inset.0 + line.inset.1;
+            width = width.max(line.metrics.advance - line.metrics.trailing_whitespace + inset);
+            full_width = full_width.max(line.metrics.advance + inset);
             height = height.max(line.metrics.max_coord);
         }
+        for leading_box in &self.layout.data.leading_boxes {
+            width = width.max(leading_box.leading_box.width);
+            height = height.max(leading_box.y + leading_box.leading_box.height);
+        }
 
         // Save the computed widths/height to the layout
         self.layout.data.width = width;
         self.layout.data.full_width = full_width;
         self.layout.data.height = height;
+        #[cfg(feature = "stats")]
+        {
+            let stats = &mut self.layout.data.stats;
+            stats.line_breaking = self.start.elapsed();
+            stats.line_count = self.lines.lines.len();
+        }
 
         // for (i, line) in self.lines.lines.iter().enumerate() {
         //     println!("LINE {i}");
@@ -817,6 +1079,20 @@ fn try_commit_line<B: Brush>(
     //     return false;
     // }
 
+    // A soft break directly after a soft hyphen hyphenates the word.
+    let break_reason = if break_reason == BreakReason::Regular
+        && state
+            .clusters
+            .end
+            .checked_sub(1)
+            .and_then(|index| layout.data.clusters.get(index))
+            .is_some_and(|cluster| cluster.is_soft_hyphen())
+    {
+        BreakReason::Hyphenated
+    } else {
+        break_reason
+    };
+
     // Q: why this special case?
     let mut num_spaces = state.num_spaces;
     if break_reason == BreakReason::Regular {
@@ -856,6 +1132,44 @@ fn try_commit_line<B: Brush>(
     true
 }
 
+/// Sets the advance of each tab in the logical `items` of a line to reach the next tab
+/// stop, as measured when breaking the line.
+///
+/// Each expansion is recorded, so that [`LayoutData::restore_tab_advances`] can undo it.
+fn expand_tabs<B: Brush>(data: &mut LayoutData<B>, items: &[LineItemData]) {
+    if data.tab_stops == TabStops::None {
+        return;
+    }
+    let mut x = 0.;
+    for item in items {
+        if item.kind == LayoutItemKind::InlineBox {
+            x += data.inline_boxes[item.index].width;
+            continue;
+        }
+        let glyph_start = data.runs[item.index].glyph_start;
+        for cluster_index in item.cluster_range.clone() {
+            let cluster = &mut data.clusters[cluster_index];
+            if cluster.info.whitespace() == Whitespace::Tab {
+                if let Some(advance) = data.tab_stops.advance(x) {
+                    let delta = advance - cluster.advance;
+                    let glyph_index =
+                        (cluster.glyph_len != 0xFF && cluster.glyph_len != 0).then(|| {
+                            glyph_start + cluster.glyph_offset as usize + cluster.glyph_len as usize
+                                - 1
+                        });
+                    cluster.advance = advance;
+                    if let Some(glyph_index) = glyph_index {
+                        data.glyphs[glyph_index].advance += delta;
+                    }
+                    data.tab_expansions
+                        .push((cluster_index, glyph_index, delta));
+                }
+            }
+            x += data.clusters[cluster_index].advance;
+        }
+    }
+}
+
 /// Reorder items within line according to the bidi levels of the items
 fn reorder_line_items(runs: &mut [LineItemData]) {
     let run_count = runs.len();
@@ -1,22 +1,113 @@
 // Copyright 2021 the Parley Authors
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use crate::InlineBox;
+use alloc::vec::Vec;
+#[cfg(feature = "libm")]
+#[allow(unused_imports)]
+use core_maths::CoreFloat;
 
-use super::{BreakReason, Brush, Glyph, LayoutItemKind, Line, Range, Run, Style};
+use crate::{InlineBox, Rect};
+
+use super::{Alignment, BreakReason, Brush, Glyph, LayoutItemKind, Line, Range, Run, Style};
 
 pub(crate) mod greedy;
 
 impl<'a, B: Brush> Line<'a, B> {
     /// Returns the metrics for the line.
-    pub fn metrics(&self) -> &LineMetrics {
-        &self.data.metrics
+    ///
+    /// Positions are in the coordinate space of the layout, see [`Layout::set_origin`]
+    /// and [`Layout::set_y_axis`].
+    ///
+    /// [`Layout::set_origin`]: crate::Layout::set_origin
+    /// [`Layout::set_y_axis`]: crate::Layout::set_y_axis
+    pub fn metrics(&self) -> LineMetrics {
+        let layout = self.layout;
+        let metrics = &self.data.metrics;
+        let min_coord = layout.y_from_layout(metrics.min_coord);
+        let max_coord = layout.y_from_layout(metrics.max_coord);
+        LineMetrics {
+            baseline: layout.y_from_layout(metrics.baseline),
+            offset: layout.x_from_layout(metrics.offset),
+            min_coord: min_coord.min(max_coord),
+            max_coord: min_coord.max(max_coord),
+            ..*metrics
+        }
+    }
+
+    /// Returns the index of the line in the layout.
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    /// Returns the vertical extent of the line, from its minimum to its maximum
+    /// coordinate.
+    ///
+    /// This is the same as the `min_coord` and `max_coord` of [`Line::metrics`], and
+    /// can be used to position a scrollbar without iterating over the lines.
+    pub fn y_range(&self) -> Range<f32> {
+        let metrics = self.metrics();
+        metrics.min_coord..metrics.max_coord
+    }
+
+    /// Returns the advance from the start of the line to its first `separator`, or to
+    /// the end of its last digit if it has no separator.
+    ///
+    /// This doesn't include the alignment offset of the line. The largest advance of
+    /// the cells in a column is the position to use for [`Alignment::Numeric`].
+    ///
+    /// [`Alignment::Numeric`]: crate::Alignment::Numeric
+    pub fn separator_advance(&self, separator: char) -> Option<f32> {
+        super::alignment::separator_advance(&self.layout.data, self.data, separator)
+    }
+
+    /// Returns how far the line extends past a container of `container_width`, or the
+    /// negated space left over if it fits.
+    ///
+    /// Trailing whitespace is not counted, as it hangs past the end of the line, but
+    /// the width reserved by leading boxes and the text indent is. The largest amount of
+    /// the lines is how much the text would need to shrink to fit the container.
+    pub fn overflow_amount(&self, container_width: f32) -> f32 {
+        let metrics = &self.data.metrics;
+        let (left, right) = self.data.inset;
+        metrics.advance - metrics.trailing_whitespace + left + right - container_width
+    }
+
+    /// Returns the width of the visible content of the line.
+    ///
+    /// Unlike [`LineMetrics::advance`], this excludes trailing whitespace, which hangs past
+    /// the end of the line, and unlike [`overflow_amount`](Self::overflow_amount) it excludes
+    /// the width reserved by leading boxes and the text indent.
+    pub fn content_width(&self) -> f32 {
+        let metrics = &self.data.metrics;
+        metrics.advance - metrics.trailing_whitespace
+    }
+
+    /// Returns the alignment which was applied to the line by [`Layout::align`], which is
+    /// that of its paragraph if it was overridden.
+    ///
+    /// See [`RangedBuilder::set_paragraph_alignment`].
+    ///
+    /// [`Layout::align`]: crate::Layout::align
+    /// [`RangedBuilder::set_paragraph_alignment`]: crate::RangedBuilder::set_paragraph_alignment
+    pub fn alignment(&self) -> Alignment {
+        self.data.alignment
     }
 
+    /// Returns the reason that the line ended.
     pub fn break_reason(&self) -> BreakReason {
         self.data.break_reason
     }
 
+    /// Returns `true` if the line was wrapped at a soft hyphen, so the hyphen displayed
+    /// at its end is inserted rather than part of the text.
+    ///
+    /// A line which ends with a hyphen-minus in the text returns `false`, so copied text
+    /// can omit inserted hyphens. This is the same as a [`BreakReason::Hyphenated`]
+    /// break reason.
+    pub fn ends_with_inserted_hyphen(&self) -> bool {
+        self.data.break_reason == BreakReason::Hyphenated
+    }
+
     /// Returns the range of text for the line.
     pub fn text_range(&self) -> Range<usize> {
         self.data.text_range.clone()
@@ -90,6 +181,56 @@ impl<'a, B: Brush> Line<'a, B> {
             offset: 0.,
         }
     }
+
+    /// Returns the areas covered by redacted text on the line, in visual order.
+    ///
+    /// See [`Visibility::Redacted`](crate::Visibility::Redacted).
+    pub fn redactions(&self) -> impl Iterator<Item = Rect> + 'a + Clone {
+        let layout = self.layout;
+        let metrics = &self.data.metrics;
+        let mut rects = Vec::new();
+        let mut x = metrics.offset as f64;
+        for item in self.items_nonpositioned() {
+            match item {
+                LineItem::Run(run) => {
+                    for cluster in run.visual_clusters() {
+                        let advance = cluster.advance() as f64;
+                        if cluster.data.is_redacted() {
+                            rects.push(layout.rect_from_layout(Rect::new(
+                                x,
+                                metrics.min_coord as f64,
+                                x + advance,
+                                metrics.max_coord as f64,
+                            )));
+                        }
+                        x += advance;
+                    }
+                }
+                LineItem::InlineBox(inline_box) => x += inline_box.width as f64,
+            }
+        }
+        rects.into_iter()
+    }
+
+    /// Returns the distinct brushes of the glyphs on the line, in visual order of
+    /// their first appearance.
+    ///
+    /// This is useful for renderers which group the glyphs they draw by paint.
+    pub fn brushes(&self) -> impl Iterator<Item = &'a B> + 'a + Clone {
+        let layout = self.layout;
+        let mut brushes: Vec<&'a B> = Vec::new();
+        for run in self.runs() {
+            for cluster in run.visual_clusters() {
+                for glyph in cluster.glyphs() {
+                    let brush = &layout.data.styles[glyph.style_index()].brush;
+                    if !brushes.contains(&brush) {
+                        brushes.push(brush);
+                    }
+                }
+            }
+        }
+        brushes.into_iter()
+    }
 }
 
 /// Metrics information for a line.
@@ -115,12 +256,14 @@ pub struct LineMetrics {
     /// Minimum coordinate in the direction orthogonal to line
     /// direction.
     ///
-    /// For horizontal text, this would be the top of the line.
+    /// For horizontal text, this would be the top of the line, or the bottom
+    /// with [`YAxis::Up`](crate::YAxis::Up).
     pub min_coord: f32,
     /// Maximum coordinate in the direction orthogonal to line
     /// direction.
     ///
-    /// For horizontal text, this would be the bottom of the line.
+    /// For horizontal text, this would be the bottom of the line, or the top
+    /// with [`YAxis::Up`](crate::YAxis::Up).
     pub max_coord: f32,
 }
 
@@ -132,7 +275,7 @@ impl LineMetrics {
 }
 
 /// A line item and its corresponding data (a run or inline box). Unlike a
-/// [`PositionedLayoutItem`], runs are not guaranteed to be split by style.
+/// [`PositionedLayoutItem`], runs are not split into glyph runs.
 pub(crate) enum LineItem<'a, B: Brush> {
     Run(Run<'a, B>),
     InlineBox(&'a InlineBox),
@@ -158,13 +301,18 @@ pub enum PositionedLayoutItem<'a, B: Brush> {
 #[derive(Debug, Clone)]
 pub struct PositionedInlineBox {
     pub x: f32,
+    /// The minimum y coordinate of the box, which is its top edge unless the layout
+    /// uses [`YAxis::Up`](crate::YAxis::Up).
     pub y: f32,
     pub width: f32,
     pub height: f32,
     pub id: u64,
 }
 
-/// Sequence of fully positioned glyphs with the same style.
+/// Sequence of fully positioned glyphs from the same run.
+///
+/// Styles which only differ in their brushes and decorations do not affect shaping, so
+/// they don't split glyph runs. Use [`GlyphRun::style_spans`] to draw them.
 #[derive(Clone)]
 pub struct GlyphRun<'a, B: Brush> {
     run: Run<'a, B>,
@@ -182,19 +330,22 @@ impl<'a, B: Brush> GlyphRun<'a, B> {
         &self.run
     }
 
-    /// Returns the associated style.
+    /// Returns the style of the first glyph in the run.
+    ///
+    /// The brushes and decorations of the remaining glyphs may differ, see
+    /// [`GlyphRun::style_spans`].
     pub fn style(&self) -> &Style<B> {
         self.style
     }
 
     /// Returns the offset to the baseline.
     pub fn baseline(&self) -> f32 {
-        self.baseline
+        self.run.layout.y_from_layout(self.baseline)
     }
 
     /// Returns the offset to the first glyph along the baseline.
     pub fn offset(&self) -> f32 {
-        self.offset
+        self.run.layout.x_from_layout(self.offset)
     }
 
     /// Returns the total advance of the run.
@@ -204,26 +355,139 @@ impl<'a, B: Brush> GlyphRun<'a, B> {
 
     /// Returns an iterator over the glyphs in the run.
     pub fn glyphs(&'a self) -> impl Iterator<Item = Glyph> + 'a + Clone {
-        self.run
-            .visual_clusters()
-            .flat_map(|cluster| cluster.glyphs())
+        glyph_slots(&self.run)
             .skip(self.glyph_start)
             .take(self.glyph_count)
+            .filter_map(|slot| match slot {
+                GlyphSlot::Glyph(glyph) => Some(glyph),
+                GlyphSlot::Space(_) => None,
+            })
+    }
+
+    /// Returns an iterator over the ranges of glyphs in the run which share a style,
+    /// in visual order.
+    ///
+    /// Renderers should use the brush and decorations of each span, rather than those
+    /// of [`GlyphRun::style`].
+    pub fn style_spans(&self) -> impl Iterator<Item = StyleSpan<'a, B>> + 'a + Clone {
+        let layout = self.run.layout;
+        let mut spans: Vec<StyleSpan<'a, B>> = Vec::new();
+        let mut offset = self.offset;
+        let slots = glyph_slots(&self.run)
+            .skip(self.glyph_start)
+            .take(self.glyph_count)
+            .filter_map(|slot| match slot {
+                GlyphSlot::Glyph(glyph) => Some(glyph),
+                GlyphSlot::Space(_) => None,
+            });
+        for (index, glyph) in slots.enumerate() {
+            let style = &layout.data.styles[glyph.style_index()];
+            match spans.last_mut() {
+                Some(span) if core::ptr::eq(span.style, style) => {
+                    span.glyph_range.end = index + 1;
+                    span.advance += glyph.advance;
+                }
+                _ => spans.push(StyleSpan {
+                    style,
+                    glyph_range: index..index + 1,
+                    offset: layout.x_from_layout(offset),
+                    advance: glyph.advance,
+                }),
+            }
+            offset += glyph.advance;
+        }
+        spans.into_iter()
+    }
+
+    /// Returns the rectangle covered by the underline of `span`, a style span of this run,
+    /// or `None` if the span is not underlined.
+    ///
+    /// With a `pixel_scale`, the number of device pixels per unit, the underline is snapped
+    /// to the device pixel grid so that it covers whole pixel rows: its edge nearest the
+    /// baseline is rounded and its thickness is rounded to at least one pixel. The underline
+    /// is then moved towards the baseline if needed to stay within the descender.
+    pub fn underline_rect(
+        &self,
+        span: &StyleSpan<'_, B>,
+        pixel_scale: Option<f32>,
+    ) -> Option<Rect> {
+        let underline = span.style.underline.as_ref()?;
+        let layout = self.run.layout;
+        let metrics = self.run.metrics();
+        let offset = underline.offset.unwrap_or(metrics.underline_offset);
+        let size = underline.size.unwrap_or(metrics.underline_size);
+        let baseline = layout.y_from_layout(self.baseline);
+        let near = layout.y_from_layout(self.baseline - offset);
+        let far = layout.y_from_layout(self.baseline - offset + size);
+        let limit = layout.y_from_layout(self.baseline + metrics.descent);
+        // The sign of the direction away from the baseline, which depends on the y-axis.
+        let down = if limit >= baseline { 1. } else { -1. };
+        let (near, far) = match pixel_scale {
+            Some(scale) if scale > 0. => {
+                let thickness = (size * scale).round().max(1.) / scale;
+                let near = (near * scale).round() / scale;
+                let mut far = near + down * thickness;
+                let limit = (limit * down * scale).floor() * down / scale;
+                let overflow = (far - limit) * down;
+                if overflow > 0. {
+                    far -= overflow * down;
+                }
+                (far - down * thickness, far)
+            }
+            _ => (near, far),
+        };
+        Some(Rect::new(
+            span.offset as f64,
+            near.min(far) as f64,
+            (span.offset + span.advance) as f64,
+            near.max(far) as f64,
+        ))
     }
 
     /// Returns an iterator over the fully positioned glyphs in the run.
     pub fn positioned_glyphs(&'a self) -> impl Iterator<Item = Glyph> + 'a + Clone {
+        let layout = self.run.layout;
         let mut offset = self.offset;
         let baseline = self.baseline;
         self.glyphs().map(move |mut g| {
-            g.x += offset;
-            g.y += baseline;
+            g.x = layout.x_from_layout(g.x + offset);
+            g.y = layout.y_from_layout(g.y + baseline);
             offset += g.advance;
             g
         })
     }
 }
 
+/// A range of glyphs within a [`GlyphRun`] which share a style.
+#[derive(Clone, Debug)]
+pub struct StyleSpan<'a, B: Brush> {
+    /// The style of the glyphs.
+    pub style: &'a Style<B>,
+    /// Range of the glyphs, as indices into [`GlyphRun::glyphs`].
+    pub glyph_range: Range<usize>,
+    /// Offset to the first glyph along the baseline.
+    pub offset: f32,
+    /// Total advance of the glyphs.
+    pub advance: f32,
+}
+
+/// A glyph, or the advance of a cluster which has no glyphs, such as a redaction.
+#[derive(Copy, Clone)]
+enum GlyphSlot {
+    Glyph(Glyph),
+    Space(f32),
+}
+
+/// Returns the glyphs of the run in visual order, with a [`GlyphSlot::Space`] for each
+/// cluster that has an advance but no glyphs.
+fn glyph_slots<'a, B: Brush>(run: &'a Run<'a, B>) -> impl Iterator<Item = GlyphSlot> + 'a + Clone {
+    run.visual_clusters().flat_map(|cluster| {
+        let space = (cluster.data.glyph_len == 0 && cluster.data.advance != 0.)
+            .then_some(GlyphSlot::Space(cluster.data.advance));
+        cluster.glyphs().map(GlyphSlot::Glyph).chain(space)
+    })
+}
+
 #[derive(Clone)]
 struct GlyphRunIter<'a, B: Brush> {
     line: Line<'a, B>,
@@ -245,42 +509,60 @@ impl<'a, B: Brush> Iterator for GlyphRunIter<'a, B> {
                     self.item_index += 1;
                     self.glyph_start = 0;
                     self.offset += inline_box.width;
+                    let layout = self.line.layout;
+                    let baseline = self.line.data.metrics.baseline;
+                    let top = layout.y_from_layout(baseline - inline_box.height);
+                    let bottom = layout.y_from_layout(baseline);
+                    let (mut x, mut y) = (layout.x_from_layout(x), top.min(bottom));
+                    if layout.data.snap_inline_boxes {
+                        x = x.round();
+                        y = y.round();
+                    }
                     return Some(PositionedLayoutItem::InlineBox(PositionedInlineBox {
                         x,
-                        y: self.line.data.metrics.baseline - inline_box.height,
+                        y,
                         width: inline_box.width,
                         height: inline_box.height,
                         id: inline_box.id,
                     }));
                 }
                 LineItem::Run(run) => {
-                    let mut iter = run
-                        .visual_clusters()
-                        .flat_map(|c| c.glyphs())
-                        .skip(self.glyph_start);
-
-                    if let Some(first) = iter.next() {
-                        let mut advance = first.advance;
-                        let style_index = first.style_index();
-                        let mut glyph_count = 1;
-                        for glyph in iter.take_while(|g| g.style_index() == style_index) {
-                            glyph_count += 1;
-                            advance += glyph.advance;
+                    let mut iter = glyph_slots(&run).skip(self.glyph_start);
+
+                    match iter.next() {
+                        Some(GlyphSlot::Space(advance)) => {
+                            self.glyph_start += 1;
+                            self.offset += advance;
+                            continue;
+                        }
+                        Some(GlyphSlot::Glyph(first)) => {
+                            let mut advance = first.advance;
+                            let mut glyph_count = 1;
+                            for slot in iter {
+                                match slot {
+                                    GlyphSlot::Glyph(glyph) => {
+                                        glyph_count += 1;
+                                        advance += glyph.advance;
+                                    }
+                                    GlyphSlot::Space(_) => break,
+                                }
+                            }
+                            let style = run.layout.data.styles.get(first.style_index())?;
+                            let glyph_start = self.glyph_start;
+                            self.glyph_start += glyph_count;
+                            let offset = self.offset;
+                            self.offset += advance;
+                            return Some(PositionedLayoutItem::GlyphRun(GlyphRun {
+                                run,
+                                style,
+                                glyph_start,
+                                glyph_count,
+                                offset: offset + self.line.data.metrics.offset,
+                                baseline: self.line.data.metrics.baseline,
+                                advance,
+                            }));
                         }
-                        let style = run.layout.data.styles.get(style_index)?;
-                        let glyph_start = self.glyph_start;
-                        self.glyph_start += glyph_count;
-                        let offset = self.offset;
-                        self.offset += advance;
-                        return Some(PositionedLayoutItem::GlyphRun(GlyphRun {
-                            run,
-                            style,
-                            glyph_start,
-                            glyph_count,
-                            offset: offset + self.line.data.metrics.offset,
-                            baseline: self.line.data.metrics.baseline,
-                            advance,
-                        }));
+                        None => {}
                     }
                     self.item_index += 1;
                     self.glyph_start = 0;
@@ -7,6 +7,7 @@ mod alignment;
 mod cluster;
 mod line;
 mod run;
+mod shaped;
 
 pub(crate) mod data;
 
@@ -16,27 +17,34 @@ pub mod editor;
 use self::alignment::align;
 
 use super::style::Brush;
-use crate::{Font, InlineBox, OverflowWrap};
+use crate::{Font, InlineBox, OverflowWrap, Rect, TextDirection};
 #[cfg(feature = "accesskit")]
-use accesskit::{Node, NodeId, Role, TextDirection, TreeUpdate};
+use accesskit::{Node, NodeId, Role, TreeUpdate};
 use alignment::unjustify;
-#[cfg(feature = "accesskit")]
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use core::{cmp::Ordering, ops::Range};
+#[cfg(feature = "libm")]
+#[allow(unused_imports)]
+use core_maths::CoreFloat;
 use data::{ClusterData, LayoutData, LayoutItem, LayoutItemKind, LineData, LineItemData, RunData};
 #[cfg(feature = "accesskit")]
 use hashbrown::{HashMap, HashSet};
+use peniko::kurbo::Point;
 use swash::text::cluster::{Boundary, ClusterInfo};
 use swash::{GlyphId, NormalizedCoord, Synthesis};
 
-pub use alignment::AlignmentOptions;
-pub use cluster::{Affinity, ClusterPath, ClusterSide};
-pub use cursor::{Cursor, Selection};
+pub use alignment::{AlignmentOptions, JustifyContent};
+pub use cluster::{Affinity, ClusterPath, ClusterSide, ExtraSpacing};
+pub use cursor::{CaretMovement, Cursor, Selection, SelectionGeometryOptions};
 pub use data::BreakReason;
 pub(crate) use line::LineItem;
-pub use line::greedy::BreakLines;
-pub use line::{GlyphRun, LineMetrics, PositionedInlineBox, PositionedLayoutItem};
+pub use line::greedy::{Balance, BreakLines};
+pub use line::{GlyphRun, LineMetrics, PositionedInlineBox, PositionedLayoutItem, StyleSpan};
 pub use run::RunMetrics;
+pub use shaped::ShapedText;
+
+/// The character which represents an inline box in accessible text.
+pub(crate) const OBJECT_REPLACEMENT_CHARACTER: char = '\u{FFFC}';
 
 /// Alignment of a layout.
 #[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
@@ -60,7 +68,34 @@ pub enum Alignment {
     /// [`Alignment::End`] instead.
     Right,
     /// Justify each line by spacing out content, except for the last line.
+    ///
+    /// Spaces are only ever widened: a line which is already as wide as the alignment
+    /// width, or overflows it, is left as it is. So there is no minimum space width to
+    /// configure, and narrow columns are justified by widening rather than by shrinking.
     Justified,
+    /// Align each line on a separator, such as the decimal point of a number.
+    ///
+    /// The first `separator` in each line is placed at
+    /// [`AlignmentOptions::numeric_position`]. Lines without a separator place the end of
+    /// their last digit there instead, and lines without digits are [`Alignment::End`]
+    /// aligned.
+    ///
+    /// To align a column of numbers, use [`Line::separator_advance`] to find the
+    /// position which fits every cell.
+    Numeric {
+        /// The decimal separator, such as `'.'` or `','`.
+        separator: char,
+    },
+}
+
+/// The direction in which y coordinates increase.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum YAxis {
+    /// Y coordinates increase downwards, so the first line has the smallest coordinates.
+    #[default]
+    Down,
+    /// Y coordinates increase upwards, so the first line has the largest coordinates.
+    Up,
 }
 
 /// Text layout.
@@ -96,6 +131,25 @@ impl<B: Brush> Layout<B> {
         self.data.full_width
     }
 
+    /// Returns `true` if the max advance given to the last line breaking was zero,
+    /// negative or NaN.
+    ///
+    /// Such an advance is ignored, and the lines are broken as if it were unbounded, so
+    /// the text overflows the container rather than being broken into a line per cluster.
+    pub fn ignored_max_advance(&self) -> bool {
+        self.data.ignored_max_advance
+    }
+
+    /// Returns the statistics of building the layout, and of the most recent line
+    /// breaking and alignment.
+    ///
+    /// Line breaking is timed from [`Layout::break_lines`] until the line breaker is
+    /// dropped.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &crate::LayoutStats {
+        &self.data.stats
+    }
+
     /// Returns the lower and upper bounds on the width of the layout.
     pub fn content_widths(&self) -> ContentWidths {
         self.data.content_widths()
@@ -142,10 +196,300 @@ impl<B: Brush> Layout<B> {
         self.data.base_level & 1 != 0
     }
 
+    /// Returns the resolved base direction of the first paragraph in the layout.
+    pub fn base_direction(&self) -> TextDirection {
+        TextDirection::from_bidi_level(self.data.base_level)
+    }
+
+    /// Returns an iterator over the resolved base directions of each paragraph
+    /// in the layout.
+    ///
+    /// Paragraphs are separated by hard breaks.
+    pub fn paragraph_directions(&self) -> impl Iterator<Item = TextDirection> + '_ + Clone {
+        self.data
+            .paragraphs
+            .iter()
+            .map(|paragraph| TextDirection::from_bidi_level(paragraph.base_level))
+    }
+
+    /// Returns an iterator over the text range and resolved style of each paragraph
+    /// in the layout.
+    ///
+    /// Paragraphs are separated by hard breaks, and their ranges include the break.
+    pub fn paragraph_styles(
+        &self,
+    ) -> impl Iterator<Item = (Range<usize>, ParagraphStyle)> + '_ + Clone {
+        let indent = self.data.text_indent;
+        self.data
+            .paragraphs
+            .iter()
+            .enumerate()
+            .map(move |(index, paragraph)| {
+                let style = ParagraphStyle {
+                    direction: TextDirection::from_bidi_level(paragraph.base_level),
+                    alignment: paragraph.alignment,
+                    indent: if index == 0 || indent.each_paragraph {
+                        indent.amount
+                    } else {
+                        0.0
+                    },
+                };
+                (paragraph.text_range.clone(), style)
+            })
+    }
+
     pub fn inline_boxes(&self) -> &[InlineBox] {
         &self.data.inline_boxes
     }
 
+    /// Returns an iterator over the identifier and area of each [`LeadingBox`].
+    ///
+    /// Boxes are placed by line breaking. The boxes of right-to-left paragraphs are
+    /// placed at the right edge of the width that the layout was aligned to, or of the
+    /// layout itself if it is wider.
+    ///
+    /// [`LeadingBox`]: crate::LeadingBox
+    pub fn leading_boxes(&self) -> impl Iterator<Item = (u64, Rect)> + '_ + Clone {
+        let right = self.data.alignment_width.max(self.data.width) as f64;
+        self.data.leading_boxes.iter().map(move |data| {
+            let leading_box = &data.leading_box;
+            let width = leading_box.width as f64;
+            let x = if data.is_rtl { right - width } else { 0. };
+            let y = data.y as f64;
+            let rect = Rect::new(x, y, x + width, y + leading_box.height as f64);
+            (leading_box.id, self.rect_from_layout(rect))
+        })
+    }
+
+    /// Returns the links in the layout, in the order that they were pushed.
+    pub fn links(&self) -> &[LinkData] {
+        &self.data.links
+    }
+
+    /// Returns the problems with fonts which were encountered while building the layout,
+    /// in text order.
+    ///
+    /// Text which was affected by a warning has still been laid out, using another font
+    /// where possible.
+    pub fn warnings(&self) -> &[LayoutWarning] {
+        &self.data.warnings
+    }
+
+    /// Returns the accessible text of the layout, where each inline box is represented
+    /// by a U+FFFC OBJECT REPLACEMENT CHARACTER inserted at its index.
+    ///
+    /// This is the text exposed to accessibility, and the text which UTF-16 offsets
+    /// from [`accessible_utf16_offset`](Self::accessible_utf16_offset) refer to.
+    /// `text` must be the text the layout was built from.
+    pub fn accessible_text(&self, text: &str) -> String {
+        let mut accessible_text = String::with_capacity(
+            text.len() + self.data.inline_boxes.len() * OBJECT_REPLACEMENT_CHARACTER.len_utf8(),
+        );
+        let mut start = 0;
+        for inline_box in &self.data.inline_boxes {
+            accessible_text.push_str(&text[start..inline_box.index]);
+            accessible_text.push(OBJECT_REPLACEMENT_CHARACTER);
+            start = inline_box.index;
+        }
+        accessible_text.push_str(&text[start..]);
+        accessible_text
+    }
+
+    /// Returns `text[range]` with each tab replaced by the number of spaces closest to its
+    /// width in the layout, such as for copying to plain-text contexts.
+    ///
+    /// This keeps text aligned to [tab stops](crate::TabStops) aligned when it is shown
+    /// with spaces instead. Every tab is replaced by at least one space. `text` must be the
+    /// text the layout was built from, and lines must have been broken.
+    pub fn text_with_expanded_tabs(&self, text: &str, range: Range<usize>) -> String {
+        let text = &text[range.clone()];
+        let mut expanded = String::with_capacity(text.len());
+        let mut start = 0;
+        for (index, _) in text.match_indices('\t') {
+            expanded.push_str(&text[start..index]);
+            let spaces = Cluster::from_byte_index(self, range.start + index)
+                .map(|cluster| {
+                    let space_advance = cluster.run().space_advance();
+                    if space_advance > 0. {
+                        (cluster.advance() / space_advance).round() as usize
+                    } else {
+                        1
+                    }
+                })
+                .unwrap_or(1)
+                .max(1);
+            expanded.extend(core::iter::repeat_n(' ', spaces));
+            start = index + 1;
+        }
+        expanded.push_str(&text[start..]);
+        expanded
+    }
+
+    /// Returns the offset in UTF-16 code units into the
+    /// [accessible text](Self::accessible_text) of a byte index into `text`.
+    ///
+    /// An inline box takes up no text, so a byte index at the index of a box is placed
+    /// after the box's replacement character, matching where a downstream caret is drawn.
+    pub fn accessible_utf16_offset(&self, text: &str, index: usize) -> usize {
+        let boxes = self
+            .data
+            .inline_boxes
+            .partition_point(|inline_box| inline_box.index <= index);
+        text[..index].encode_utf16().count() + boxes
+    }
+
+    /// Returns the byte index into `text` of an offset in UTF-16 code units into the
+    /// [accessible text](Self::accessible_text).
+    ///
+    /// This is the inverse of [`accessible_utf16_offset`](Self::accessible_utf16_offset).
+    /// Offsets on either side of a box's replacement character map to the index of the
+    /// box. Offsets inside a surrogate pair are moved back to the start of the character,
+    /// and offsets past the end of the text map to its end.
+    pub fn byte_index_from_accessible_utf16(&self, text: &str, offset: usize) -> usize {
+        let mut remaining = offset;
+        let mut inline_boxes = self.data.inline_boxes.iter().peekable();
+        for (index, ch) in text.char_indices() {
+            while inline_boxes
+                .next_if(|inline_box| inline_box.index <= index)
+                .is_some()
+            {
+                if remaining == 0 {
+                    return index;
+                }
+                remaining -= 1;
+            }
+            if remaining < ch.len_utf16() {
+                return index;
+            }
+            remaining -= ch.len_utf16();
+        }
+        text.len()
+    }
+
+    /// Returns the link at the given point, if any.
+    ///
+    /// Where links overlap at the point, the one pushed first is returned.
+    pub fn link_at_point(&self, x: f32, y: f32) -> Option<&LinkData> {
+        let (cluster, _) = Cluster::from_point(self, x, y)?;
+        let index = cluster.text_range().start;
+        let point = Point::new(x as f64, y as f64);
+        self.data.links.iter().find(|link| {
+            link.text_range.contains(&index)
+                && self
+                    .link_rects(link)
+                    .iter()
+                    .any(|rect| rect.contains(point))
+        })
+    }
+
+    /// Returns the areas covered by `link`, with one rectangle for each line fragment
+    /// when the link wraps.
+    pub fn link_rects(&self, link: &LinkData) -> Vec<Rect> {
+        let start = Cursor::from_byte_index(self, link.text_range.start, Affinity::Downstream);
+        let end = Cursor::from_byte_index(self, link.text_range.end, Affinity::Upstream);
+        Selection::new(start, end)
+            .geometry(self)
+            .into_iter()
+            .map(|(rect, _)| rect)
+            .collect()
+    }
+
+    /// Returns the horizontal extent of the text in `range` on each line it covers, as
+    /// the index of the line, the x coordinate of the fragment and its width.
+    ///
+    /// Unlike the geometry of a [`Selection`], the fragments are exactly as wide as the
+    /// laid out text, without the width shown for a selected newline, and have no
+    /// height. Bidi text can have several fragments on a line, in visual order.
+    pub fn substring_geometry(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (usize, f32, f32)> {
+        let mut fragments = Vec::new();
+        for (line_index, line) in self.lines().enumerate() {
+            let line_range = line.text_range();
+            if line_range.start >= range.end || range.start >= line_range.end {
+                continue;
+            }
+            let mut x = self.data.origin.0 + line.data.metrics.offset;
+            let mut fragment: Option<(f32, f32)> = None;
+            for item in line.items_nonpositioned() {
+                match item {
+                    LineItem::Run(run) => {
+                        for cluster in run.visual_clusters() {
+                            let advance = cluster.advance();
+                            if range.contains(&cluster.text_range().start)
+                                && !cluster.is_hard_line_break()
+                            {
+                                let (start, width) = fragment.get_or_insert((x, 0.));
+                                *width = x + advance - *start;
+                            } else if let Some((start, width)) = fragment.take() {
+                                fragments.push((line_index, start, width));
+                            }
+                            x += advance;
+                        }
+                    }
+                    LineItem::InlineBox(inline_box) => {
+                        // Boxes are not part of the text, so they end a fragment.
+                        if let Some((start, width)) = fragment.take() {
+                            fragments.push((line_index, start, width));
+                        }
+                        x += inline_box.width;
+                    }
+                }
+            }
+            if let Some((start, width)) = fragment {
+                fragments.push((line_index, start, width));
+            }
+        }
+        fragments.into_iter()
+    }
+
+    /// Returns the text range and area of the grapheme cluster nearest to the given point,
+    /// if it is within `slop` of the point.
+    ///
+    /// This is intended for touch gestures, where the point is imprecise, such as for
+    /// placing a magnifier or selection handle. Hard line breaks are never returned.
+    pub fn grapheme_at_point(&self, x: f32, y: f32, slop: f32) -> Option<(Range<usize>, Rect)> {
+        let (x, y) = self.point_to_layout(x, y);
+        let (x, y, slop) = (x as f64, y as f64, slop as f64);
+        let mut nearest: Option<(f64, Range<usize>, Rect)> = None;
+        for line in self.lines() {
+            let metrics = &line.data.metrics;
+            let (min_coord, max_coord) = (metrics.min_coord as f64, metrics.max_coord as f64);
+            if y + slop < min_coord || y - slop > max_coord {
+                continue;
+            }
+            let mut offset = metrics.offset as f64;
+            for item in line.items_nonpositioned() {
+                match item {
+                    LineItem::Run(run) => {
+                        for cluster in run.visual_clusters() {
+                            let advance = cluster.advance() as f64;
+                            let rect = Rect::new(offset, min_coord, offset + advance, max_coord);
+                            offset += advance;
+                            if cluster.is_hard_line_break() {
+                                continue;
+                            }
+                            // Squared distance from the point to the nearest edge of the cluster.
+                            let dx = (rect.x0 - x).max(x - rect.x1).max(0.);
+                            let dy = (rect.y0 - y).max(y - rect.y1).max(0.);
+                            let distance = dx * dx + dy * dy;
+                            if distance <= slop * slop
+                                && nearest
+                                    .as_ref()
+                                    .is_none_or(|(nearest, ..)| distance < *nearest)
+                            {
+                                nearest = Some((distance, cluster.text_range(), rect));
+                            }
+                        }
+                    }
+                    LineItem::InlineBox(inline_box) => offset += inline_box.width as f64,
+                }
+            }
+        }
+        nearest.map(|(_, text_range, rect)| (text_range, self.rect_from_layout(rect)))
+    }
+
     pub fn inline_boxes_mut(&mut self) -> &mut [InlineBox] {
         &mut self.data.inline_boxes
     }
@@ -163,6 +507,104 @@ impl<B: Brush> Layout<B> {
             })
     }
 
+    /// Returns an iterator over the lines which intersect the vertical range from `y0`
+    /// to `y1`, along with their indices.
+    ///
+    /// This is useful for rendering only the visible lines of a large layout. The lines
+    /// are found by binary search, so this is `O(log n)` in the number of lines plus the
+    /// number of lines returned.
+    pub fn lines_in_range(
+        &self,
+        y0: f32,
+        y1: f32,
+    ) -> impl Iterator<Item = (usize, Line<'_, B>)> + '_ + Clone {
+        let (_, y0) = self.point_to_layout(0.0, y0);
+        let (_, y1) = self.point_to_layout(0.0, y1);
+        let (y0, y1) = (y0.min(y1), y0.max(y1));
+        let lines = &self.data.lines;
+        let start = lines.partition_point(|line| line.metrics.max_coord <= y0);
+        let end = lines.partition_point(|line| line.metrics.min_coord < y1);
+        (start..end.max(start)).map(move |index| {
+            (
+                index,
+                Line {
+                    index: index as u32,
+                    layout: self,
+                    data: &lines[index],
+                },
+            )
+        })
+    }
+
+    /// Returns an iterator over the lines with indices in `range`.
+    ///
+    /// Lines outside of the layout are skipped. Each line is the same as the one
+    /// returned by [`Layout::lines`], and positions are still relative to the whole
+    /// layout.
+    pub fn slice_lines(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = Line<'_, B>> + '_ + Clone {
+        let lines = &self.data.lines;
+        let end = range.end.min(lines.len());
+        let start = range.start.min(end);
+        lines[start..end]
+            .iter()
+            .enumerate()
+            .map(move |(offset, data)| Line {
+                index: (start + offset) as u32,
+                layout: self,
+                data,
+            })
+    }
+
+    /// Returns an iterator over the index and top of each line, from the last line to
+    /// the first, with the bottom of the last line at `bottom_y`.
+    ///
+    /// This is useful for views which are anchored at the bottom, such as a chat log,
+    /// where the newest line is drawn at a fixed position and older lines above it.
+    /// `bottom_y` and the tops are in the coordinate space set by the origin and
+    /// [`YAxis`] of the layout, like its other positions.
+    pub fn lines_bottom_up(&self, bottom_y: f32) -> impl Iterator<Item = (usize, f32)> + '_ {
+        let (_, bottom_y) = self.point_to_layout(0.0, bottom_y);
+        let lines = &self.data.lines;
+        let bottom = lines.last().map_or(0., |line| line.metrics.max_coord);
+        lines.iter().enumerate().rev().map(move |(index, line)| {
+            let top = bottom_y - (bottom - line.metrics.min_coord);
+            (index, self.y_from_layout(top))
+        })
+    }
+
+    /// Returns the index of the line which contains the vertical position `y`, or
+    /// `None` if it is above the first line or below the last one.
+    ///
+    /// Line positions are a running total of the line heights which is computed by
+    /// line breaking, so this is a binary search and is `O(log n)` in the number of
+    /// lines.
+    pub fn line_for_y(&self, y: f32) -> Option<usize> {
+        let (_, y) = self.point_to_layout(0.0, y);
+        let lines = &self.data.lines;
+        let index = lines.partition_point(|line| line.metrics.max_coord <= y);
+        let line = lines.get(index)?;
+        (line.metrics.min_coord <= y).then_some(index)
+    }
+
+    /// Returns the distance from the baseline of the line at `line_index` to the
+    /// baseline of the next line.
+    ///
+    /// This differs from the line height when the lines have different ascents and
+    /// descents, or leading which is split unevenly above and below them. The last
+    /// line has no next line, so its line height is returned instead, and `0.0` is
+    /// returned for an index past the end.
+    pub fn line_spacing(&self, line_index: usize) -> f32 {
+        let lines = &self.data.lines;
+        match (lines.get(line_index), lines.get(line_index + 1)) {
+            (Some(line), Some(next)) => (next.metrics.baseline - line.metrics.baseline).abs(),
+            (Some(line), None) => line.metrics.line_height,
+            _ => 0.0,
+        }
+    }
+
     /// Returns line breaker to compute lines for the layout.
     pub fn break_lines(&mut self) -> BreakLines<'_, B> {
         unjustify(&mut self.data);
@@ -187,8 +629,79 @@ impl<B: Brush> Layout<B> {
         alignment: Alignment,
         options: AlignmentOptions,
     ) {
+        #[cfg(feature = "stats")]
+        let start = std::time::Instant::now();
         unjustify(&mut self.data);
         align(&mut self.data, container_width, alignment, options);
+        #[cfg(feature = "stats")]
+        {
+            self.data.stats.alignment = start.elapsed();
+        }
+    }
+
+    /// Sets the position of the layout's top-left corner in the coordinate space used
+    /// for every position that the layout reports or accepts.
+    ///
+    /// This includes glyph and inline box positions, line metrics, hit testing and
+    /// cursor and selection geometry. The origin is kept when the layout is rebuilt.
+    pub fn set_origin(&mut self, x: f32, y: f32) {
+        self.data.origin = (x, y);
+    }
+
+    /// Returns the position of the layout's top-left corner.
+    ///
+    /// See [`Layout::set_origin`].
+    pub fn origin(&self) -> (f32, f32) {
+        self.data.origin
+    }
+
+    /// Sets the direction in which y coordinates increase for every position that the
+    /// layout reports or accepts.
+    ///
+    /// With [`YAxis::Up`], the origin is still the top-left corner of the layout, so
+    /// the content extends below it to smaller coordinates. The axis is kept when the
+    /// layout is rebuilt.
+    pub fn set_y_axis(&mut self, y_axis: YAxis) {
+        self.data.y_axis = y_axis;
+    }
+
+    /// Returns the direction in which y coordinates increase.
+    pub fn y_axis(&self) -> YAxis {
+        self.data.y_axis
+    }
+
+    /// Converts a point from the caller's coordinate space into layout space, where
+    /// the origin is at zero and y increases downwards.
+    pub(crate) fn point_to_layout(&self, x: f32, y: f32) -> (f32, f32) {
+        let (origin_x, origin_y) = self.data.origin;
+        match self.data.y_axis {
+            YAxis::Down => (x - origin_x, y - origin_y),
+            YAxis::Up => (x - origin_x, origin_y - y),
+        }
+    }
+
+    /// Converts an x coordinate from layout space into the caller's coordinate space.
+    pub(crate) fn x_from_layout(&self, x: f32) -> f32 {
+        x + self.data.origin.0
+    }
+
+    /// Converts a y coordinate from layout space into the caller's coordinate space.
+    pub(crate) fn y_from_layout(&self, y: f32) -> f32 {
+        match self.data.y_axis {
+            YAxis::Down => self.data.origin.1 + y,
+            YAxis::Up => self.data.origin.1 - y,
+        }
+    }
+
+    /// Converts a rectangle from layout space into the caller's coordinate space.
+    pub(crate) fn rect_from_layout(&self, rect: Rect) -> Rect {
+        let origin_x = self.data.origin.0 as f64;
+        let origin_y = self.data.origin.1 as f64;
+        let (y0, y1) = match self.data.y_axis {
+            YAxis::Down => (origin_y + rect.y0, origin_y + rect.y1),
+            YAxis::Up => (origin_y - rect.y1, origin_y - rect.y0),
+        };
+        Rect::new(origin_x + rect.x0, y0, origin_x + rect.x1, y1)
     }
 
     /// Returns the index and `Line` object for the line containing the
@@ -298,12 +811,162 @@ pub struct Style<B: Brush> {
     pub underline: Option<Decoration<B>>,
     /// Strikethrough decoration.
     pub strikethrough: Option<Decoration<B>>,
-    /// Absolute line height in layout units (style line height * font size)
+    /// Absolute line height in layout units (style line height * font size), raised to
+    /// the minimum line height of the style
     pub(crate) line_height: f32,
     /// Per-cluster overflow-wrap setting
     pub(crate) overflow_wrap: OverflowWrap,
 }
 
+/// A range of text which was tagged as a link with
+/// [`RangedBuilder::push_link`](crate::RangedBuilder::push_link) or
+/// [`TreeBuilder::push_link`](crate::TreeBuilder::push_link).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LinkData {
+    /// User-specified identifier for the link.
+    pub id: u64,
+    /// The range of text covered by the link.
+    pub text_range: Range<usize>,
+}
+
+/// A problem with the fonts which was encountered while building a layout.
+///
+/// See [`Layout::warnings`].
+#[derive(Clone, PartialEq, Debug)]
+pub enum LayoutWarning {
+    /// A font could not be loaded, or shaping the text with it produced no glyphs, so the
+    /// text was shaped with the next font in the stack or fallback chain.
+    FontLoadFailed {
+        /// The font which failed to load.
+        font: Font,
+        /// The range of text which would have used the font.
+        text_range: Range<usize>,
+    },
+    /// No font could be loaded for the text.
+    ///
+    /// The text is shaped with the notdef glyph of a font used elsewhere in the layout, or
+    /// of the first font of the generic families which can be loaded. It is only omitted
+    /// if no such font can be loaded either.
+    NoUsableFont {
+        /// The range of text which has no font.
+        text_range: Range<usize>,
+    },
+    /// No font in the font stack supports the text, so it was shaped with a font from the
+    /// fallback chain for its script and locale.
+    ///
+    /// This is only reported when enabled with
+    /// [`LayoutContext::set_diagnostics`](crate::LayoutContext::set_diagnostics).
+    FallbackFont {
+        /// The fallback font.
+        font: Font,
+        /// The range of text which used the font.
+        text_range: Range<usize>,
+    },
+    /// No font supports all of the characters of the text, so some are shown as notdef
+    /// glyphs.
+    ///
+    /// This is only reported when enabled with
+    /// [`LayoutContext::set_diagnostics`](crate::LayoutContext::set_diagnostics).
+    MissingGlyphs {
+        /// The font which the text was shaped with.
+        font: Font,
+        /// The range of text with missing glyphs.
+        text_range: Range<usize>,
+    },
+    /// The font stack of the text is empty or none of its families are available, so it
+    /// was shaped with the sans-serif generic family, or with the last-resort font if
+    /// that is not available either.
+    EmptyFontStack {
+        /// The range of text with the empty font stack.
+        text_range: Range<usize>,
+    },
+}
+
+impl LayoutWarning {
+    /// Returns the range of text which the warning applies to.
+    pub fn text_range(&self) -> Range<usize> {
+        match self {
+            Self::FontLoadFailed { text_range, .. }
+            | Self::NoUsableFont { text_range }
+            | Self::EmptyFontStack { text_range }
+            | Self::FallbackFont { text_range, .. }
+            | Self::MissingGlyphs { text_range, .. } => text_range.clone(),
+        }
+    }
+
+    /// Returns the range of text which the warning applies to, for rebasing it.
+    pub(crate) fn text_range_mut(&mut self) -> &mut Range<usize> {
+        match self {
+            Self::FontLoadFailed { text_range, .. }
+            | Self::NoUsableFont { text_range }
+            | Self::EmptyFontStack { text_range }
+            | Self::FallbackFont { text_range, .. }
+            | Self::MissingGlyphs { text_range, .. } => text_range,
+        }
+    }
+
+    /// Appends `warning` to `warnings`, merging it into an earlier warning if they are the
+    /// same problem for adjacent text.
+    pub(crate) fn push(warnings: &mut Vec<Self>, warning: Self) {
+        let start = warning.text_range().start;
+        let merged = warnings
+            .iter_mut()
+            .rev()
+            .take_while(|last| last.text_range().end == start)
+            .any(|last| last.merge(&warning));
+        if !merged {
+            warnings.push(warning);
+        }
+    }
+
+    /// Extends `self` to cover `other` if they are the same problem and their ranges are
+    /// adjacent, returning `true` if so.
+    fn merge(&mut self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::FontLoadFailed { font, text_range },
+                Self::FontLoadFailed {
+                    font: other_font,
+                    text_range: other_range,
+                },
+            )
+            | (
+                Self::FallbackFont { font, text_range },
+                Self::FallbackFont {
+                    font: other_font,
+                    text_range: other_range,
+                },
+            )
+            | (
+                Self::MissingGlyphs { font, text_range },
+                Self::MissingGlyphs {
+                    font: other_font,
+                    text_range: other_range,
+                },
+            ) if font == other_font && text_range.end == other_range.start => {
+                text_range.end = other_range.end;
+                true
+            }
+            (
+                Self::NoUsableFont { text_range },
+                Self::NoUsableFont {
+                    text_range: other_range,
+                },
+            )
+            | (
+                Self::EmptyFontStack { text_range },
+                Self::EmptyFontStack {
+                    text_range: other_range,
+                },
+            ) if text_range.end == other_range.start => {
+                text_range.end = other_range.end;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
 /// Underline or strikethrough decoration.
 #[derive(Clone, Debug)]
 pub struct Decoration<B: Brush> {
@@ -344,33 +1007,41 @@ impl LayoutAccessibility {
     ) {
         // Build a set of node IDs for the runs encountered in this pass.
         let mut ids = HashSet::<NodeId>::new();
-        // Reuse scratch space for storing a sorted list of runs.
-        let mut runs = Vec::new();
+        // Reuse scratch space for storing a sorted list of runs and inline boxes.
+        let mut items = Vec::new();
 
         for (line_index, line) in layout.lines().enumerate() {
-            let metrics = line.metrics();
+            let metrics = &line.data.metrics;
             // Defer adding each run node until we reach either the next run
             // or the end of the line. That way, we can set relations between
             // runs in a line and do anything special that might be required
             // for the last run in a line.
             let mut last_node: Option<(NodeId, Node)> = None;
 
-            // Iterate over the runs from left to right, computing their offsets,
-            // then sort them into text order.
-            runs.clear();
-            runs.reserve(line.len());
+            // Iterate over the runs and inline boxes from left to right, computing
+            // their offsets, then sort them into text order. Each inline box is
+            // exposed as a U+FFFC OBJECT REPLACEMENT CHARACTER which comes before
+            // the text at its index, matching `Layout::accessible_text`.
+            items.clear();
+            items.reserve(line.len());
             {
-                let mut run_offset = metrics.offset;
-                for run in line.runs() {
-                    let advance = run.advance();
-                    runs.push((run, run_offset));
-                    run_offset += advance;
+                let mut item_offset = metrics.offset;
+                for (item_index, item) in line.items_nonpositioned().enumerate() {
+                    let advance = match &item {
+                        LineItem::Run(run) => run.advance(),
+                        LineItem::InlineBox(inline_box) => inline_box.width,
+                    };
+                    items.push((item_index, item, item_offset));
+                    item_offset += advance;
                 }
             }
-            runs.sort_by_key(|(r, _)| r.text_range().start);
+            items.sort_by_key(|(_, item, _)| match item {
+                LineItem::Run(run) => (run.text_range().start, 1),
+                LineItem::InlineBox(inline_box) => (inline_box.index, 0),
+            });
 
-            for (run, run_offset) in runs.drain(..) {
-                let run_path = (line_index, run.index());
+            for (item_index, item, item_offset) in items.drain(..) {
+                let run_path = (line_index, item_index);
                 // If we encountered this same run path in the previous
                 // accessibility pass, reuse the same AccessKit ID. Otherwise,
                 // allocate a new one. This enables stable node IDs when merely
@@ -395,16 +1066,34 @@ impl LayoutAccessibility {
                     parent_node.push_child(last_id);
                 }
 
+                let run = match item {
+                    LineItem::Run(run) => run,
+                    LineItem::InlineBox(inline_box) => {
+                        node.set_bounds(accesskit::Rect {
+                            x0: x_offset + item_offset as f64,
+                            y0: y_offset + metrics.min_coord as f64,
+                            x1: x_offset + (item_offset + inline_box.width) as f64,
+                            y1: y_offset + metrics.max_coord as f64,
+                        });
+                        node.set_value(String::from(OBJECT_REPLACEMENT_CHARACTER));
+                        node.set_character_lengths([OBJECT_REPLACEMENT_CHARACTER.len_utf8() as u8]);
+                        node.set_character_positions([0.0_f32]);
+                        node.set_character_widths([inline_box.width]);
+                        node.set_word_lengths([1_u8]);
+                        last_node = Some((id, node));
+                        continue;
+                    }
+                };
                 node.set_bounds(accesskit::Rect {
-                    x0: x_offset + run_offset as f64,
+                    x0: x_offset + item_offset as f64,
                     y0: y_offset + metrics.min_coord as f64,
-                    x1: x_offset + (run_offset + run.advance()) as f64,
+                    x1: x_offset + (item_offset + run.advance()) as f64,
                     y1: y_offset + metrics.max_coord as f64,
                 });
                 node.set_text_direction(if run.is_rtl() {
-                    TextDirection::RightToLeft
+                    accesskit::TextDirection::RightToLeft
                 } else {
-                    TextDirection::LeftToRight
+                    accesskit::TextDirection::LeftToRight
                 });
 
                 let run_text = &text[run.text_range()];
@@ -475,3 +1164,15 @@ pub struct ContentWidths {
     /// opportunities are taken.
     pub max: f32,
 }
+
+/// The resolved style of a paragraph, from [`Layout::paragraph_styles`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ParagraphStyle {
+    /// The resolved base direction of the paragraph.
+    pub direction: TextDirection,
+    /// The alignment of the paragraph, if it overrides the alignment passed to
+    /// [`Layout::align`].
+    pub alignment: Option<Alignment>,
+    /// The indentation of the first line of the paragraph.
+    pub indent: f32,
+}
@@ -2,9 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use super::{
-    Brush, Cluster, ClusterPath, Font, Layout, LineItemData, NormalizedCoord, Range, Run, RunData,
-    Synthesis,
+    Brush, Cluster, ClusterPath, Font, Glyph, GlyphRun, Layout, LineItemData, NormalizedCoord,
+    PositionedLayoutItem, Range, Run, RunData, Synthesis,
 };
+use crate::{FontAttributes, FontStyle, FontWeight, FontWidth};
+use swash::{FontRef, tag_from_bytes};
 
 impl<'a, B: Brush> Run<'a, B> {
     pub(crate) fn new(
@@ -43,6 +45,66 @@ impl<'a, B: Brush> Run<'a, B> {
         self.data.synthesis
     }
 
+    /// Returns the attributes of the font face which was selected for the run.
+    ///
+    /// These can differ from the requested style when the font stack has no exact match,
+    /// such as a bold weight of 600 for a requested 700. For variable fonts, the weight,
+    /// width and slant which font selection set on the variation axes replace those of
+    /// the default instance. Synthesized bold and italic are not included, see
+    /// [`Self::synthesis`].
+    pub fn selected_attributes(&self) -> FontAttributes {
+        let font = self.font();
+        let mut attributes =
+            FontAttributes::from_font_data(font.data.as_ref(), font.index).unwrap_or_default();
+        for setting in self.data.synthesis.variations() {
+            if setting.tag == tag_from_bytes(b"wght") {
+                attributes.weight = FontWeight::new(setting.value);
+            } else if setting.tag == tag_from_bytes(b"wdth") {
+                attributes.width = FontWidth::from_percentage(setting.value);
+            } else if setting.tag == tag_from_bytes(b"slnt") {
+                attributes.style = FontStyle::Oblique(Some(setting.value));
+            } else if setting.tag == tag_from_bytes(b"ital") && setting.value >= 1. {
+                attributes.style = FontStyle::Italic;
+            }
+        }
+        attributes
+    }
+
+    /// Returns `true` if the font of the run has color glyphs, from a `COLR`, `CBDT` or
+    /// `sbix` table, so that the run can be drawn by a color glyph pipeline rather than
+    /// as monochrome outlines.
+    ///
+    /// This only checks for the tables, so glyphs of the run which the tables don't
+    /// cover are drawn from the outlines of the font as usual.
+    pub fn is_color(&self) -> bool {
+        let font = self.font();
+        FontRef::from_index(font.data.as_ref(), font.index as usize).is_some_and(|font| {
+            [b"COLR", b"CBDT", b"sbix"]
+                .into_iter()
+                .any(|tag| font.table(tag_from_bytes(tag)).is_some())
+        })
+    }
+
+    /// Returns the nominal advance of a space (U+0020) in the font and size of the run,
+    /// at its variation coordinates.
+    ///
+    /// This is useful for measuring in spaces, such as for grids and tab sizes, without
+    /// shaping a probe space. It excludes letter and word spacing. Returns `0.0` if the
+    /// font has no space glyph.
+    pub fn space_advance(&self) -> f32 {
+        let font = self.font();
+        let Some(font) = FontRef::from_index(font.data.as_ref(), font.index as usize) else {
+            return 0.0;
+        };
+        let glyph_id = font.charmap().map(' ');
+        if glyph_id == 0 {
+            return 0.0;
+        }
+        font.glyph_metrics(self.normalized_coords())
+            .scale(self.data.font_size)
+            .advance_width(glyph_id)
+    }
+
     /// Returns the normalized variation coordinates for the font associated
     /// with the run.
     pub fn normalized_coords(&self) -> &[NormalizedCoord] {
@@ -78,6 +140,14 @@ impl<'a, B: Brush> Run<'a, B> {
         self.data.bidi_level & 1 != 0
     }
 
+    /// Returns the resolved bidi embedding level of the run.
+    ///
+    /// Even levels are left-to-right and odd levels are right-to-left. Runs inside
+    /// isolates and embeddings have higher levels than the text around them.
+    pub fn bidi_level(&self) -> u8 {
+        self.data.bidi_level
+    }
+
     /// Returns the cluster range for the run.
     pub fn cluster_range(&self) -> Range<usize> {
         self.line_data
@@ -162,6 +232,70 @@ impl<'a, B: Brush> Run<'a, B> {
             rev: self.is_rtl(),
         }
     }
+
+    /// Returns the number of glyphs in the run.
+    pub fn glyph_count(&self) -> usize {
+        self.glyph_runs()
+            .map(|glyph_run| glyph_run.glyphs().count())
+            .sum()
+    }
+
+    /// Returns the glyph at the specified index in visual order, fully positioned like
+    /// the glyphs of [`GlyphRun::positioned_glyphs`].
+    ///
+    /// The glyphs are indexed in the order of the glyph runs of the line which draw this
+    /// run. This walks the items of the line, so iterating over the glyph runs is faster
+    /// for visiting every glyph.
+    pub fn glyph(&self, index: usize) -> Option<Glyph> {
+        let mut index = index;
+        for glyph_run in self.glyph_runs() {
+            let count = glyph_run.glyphs().count();
+            if index < count {
+                return glyph_run.positioned_glyphs().nth(index);
+            }
+            index -= count;
+        }
+        None
+    }
+
+    /// Returns the source text range of the cluster which produced the glyph at
+    /// `glyph_index`, the inverse of [`Cluster::glyph_range`].
+    ///
+    /// Glyphs are indexed in logical order, as for [`Cluster::glyph_range`]. The range of
+    /// a ligature covers all the clusters it joins. Returns `None` if the run has fewer
+    /// glyphs.
+    pub fn cluster_for_glyph(&self, glyph_index: usize) -> Option<Range<usize>> {
+        let clusters = &self.layout.data.clusters[self.cluster_range()];
+        let mut glyph_end = 0;
+        for (index, cluster) in clusters.iter().enumerate() {
+            glyph_end += cluster.glyph_count();
+            if glyph_index < glyph_end {
+                let continuations = clusters[index + 1..]
+                    .iter()
+                    .take_while(|cluster| cluster.is_ligature_component())
+                    .count();
+                let first = cluster.text_range(self.data);
+                let last = clusters[index + continuations].text_range(self.data);
+                return Some(first.start.min(last.start)..first.end.max(last.end));
+            }
+        }
+        None
+    }
+
+    /// Returns the glyph runs of the line which draw this run.
+    fn glyph_runs(&self) -> impl Iterator<Item = GlyphRun<'a, B>> + 'a {
+        let index = self.index;
+        self.line_data
+            .and_then(|_| self.layout.get(self.line_index as usize))
+            .into_iter()
+            .flat_map(|line| line.items())
+            .filter_map(move |item| match item {
+                PositionedLayoutItem::GlyphRun(glyph_run) if glyph_run.run().index == index => {
+                    Some(glyph_run)
+                }
+                _ => None,
+            })
+    }
 }
 
 struct Clusters<'a, B: Brush> {
@@ -0,0 +1,75 @@
+// Copyright 2025 the Parley Authors
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Shaped text which can be broken into lines at several widths.
+
+use super::{Alignment, AlignmentOptions, ContentWidths, Layout};
+use crate::style::Brush;
+
+/// Text which has been shaped but not broken into lines.
+///
+/// This is useful for measuring text at several candidate widths, such as during
+/// constraint negotiation, as the text is only shaped once. It is created with
+/// [`LayoutContext::shape`](crate::LayoutContext::shape) or
+/// [`RangedBuilder::shape`](crate::RangedBuilder::shape).
+///
+/// The layouts broken from the text share its runs, clusters and glyphs, and only
+/// store their own lines. A layout copies the shaped text if it has to modify it,
+/// such as for [`Alignment::Justified`] or tab stops.
+#[derive(Clone)]
+pub struct ShapedText<B: Brush> {
+    layout: Layout<B>,
+}
+
+impl<B: Brush> ShapedText<B> {
+    /// Creates shaped text from a layout which has not been broken into lines.
+    pub(crate) fn new(layout: Layout<B>) -> Self {
+        Self { layout }
+    }
+
+    /// Returns the layout of the text, without breaking it into lines.
+    pub(crate) fn into_layout(self) -> Layout<B> {
+        self.layout
+    }
+
+    /// Returns a layout of the text, broken into lines of at most `max_advance` but not
+    /// yet aligned.
+    ///
+    /// This can be called repeatedly with different widths without shaping the text again,
+    /// such as while a container is resized. The layout can then be aligned with
+    /// [`Layout::align`] and any [`AlignmentOptions`].
+    pub fn break_lines(&self, max_advance: Option<f32>) -> Layout<B> {
+        // Computed before cloning, so that it is shared by every layout broken from the text.
+        self.layout.data.content_widths();
+        let mut layout = self.layout.clone();
+        layout.break_all_lines(max_advance);
+        layout
+    }
+
+    /// Returns a layout of the text, broken into lines of at most `max_advance` and
+    /// aligned with `alignment`.
+    ///
+    /// This can be called repeatedly with different widths without shaping the text again.
+    pub fn break_and_align(&self, max_advance: Option<f32>, alignment: Alignment) -> Layout<B> {
+        let mut layout = self.break_lines(max_advance);
+        layout.align(max_advance, alignment, AlignmentOptions::default());
+        layout
+    }
+
+    /// Returns the lower and upper bounds on the width of the text.
+    pub fn content_widths(&self) -> ContentWidths {
+        self.layout.content_widths()
+    }
+
+    /// Returns the minimum content width of the text. This is the width of the text if _all_
+    /// soft line-breaking opportunities are taken.
+    pub fn min_content_width(&self) -> f32 {
+        self.layout.min_content_width()
+    }
+
+    /// Returns the maximum content width of the text. This is the width of the text if _no_
+    /// soft line-breaking opportunities are taken.
+    pub fn max_content_width(&self) -> f32 {
+        self.layout.max_content_width()
+    }
+}
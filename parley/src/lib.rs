@@ -103,33 +103,46 @@ extern crate alloc;
 pub use fontique;
 pub use swash;
 
+mod analysis;
 mod bidi;
 mod builder;
 mod context;
 mod font;
 mod inline_box;
+mod label;
+mod paragraph_cache;
 mod resolve;
 mod shape;
+#[cfg(feature = "stats")]
+mod stats;
 mod swash_convert;
+mod transform;
 mod util;
 
 pub mod layout;
 pub mod style;
 
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 #[cfg(test)]
 mod tests;
 
 pub use peniko::Font;
 pub use peniko::kurbo::Rect;
 
+pub use analysis::{TextAnalysis, TextBoundary};
 pub use builder::{RangedBuilder, TreeBuilder};
-pub use context::LayoutContext;
-pub use font::FontContext;
-pub use inline_box::InlineBox;
+pub use context::{GlyphOverride, LayoutContext};
+pub use font::{FontContext, FontError};
+pub use inline_box::{InlineBox, LeadingBox};
+pub use label::{layout_label, layout_truncated_label};
 #[doc(inline)]
 pub use layout::Layout;
+#[cfg(feature = "stats")]
+pub use stats::{LayoutStats, RunStats};
 
-pub use layout::editor::{PlainEditor, PlainEditorDriver};
+pub use layout::editor::{EditorState, PlainEditor, PlainEditorDriver};
 
 pub use layout::*;
 pub use style::*;
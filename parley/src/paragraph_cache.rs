@@ -0,0 +1,346 @@
+// Copyright 2025 the Parley Authors
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Cache of shaped paragraphs.
+
+use alloc::{string::String, vec::Vec};
+use core::hash::{BuildHasher, Hash, Hasher};
+use core::ops::Range;
+
+use fontique::FamilyId;
+use hashbrown::{DefaultHashBuilder, HashMap};
+use swash::text::Language;
+
+use crate::Font;
+use crate::LayoutWarning;
+use crate::layout::Glyph;
+use crate::layout::data::{ClusterData, LayoutData, LayoutItem, LayoutItemKind, RunData};
+use crate::resolve::{RangedStyle, ResolveContext};
+use crate::style::{
+    Brush, FontFeature, FontStyle, FontVariation, FontWeight, FontWidth, Hyphens, TextDirection,
+    TextTransform, WordBreakStrength,
+};
+
+/// The subset of a resolved style which affects shaping, along with the range of the
+/// paragraph that it covers.
+#[derive(Clone, PartialEq)]
+struct ShapingStyle {
+    /// Range relative to the start of the paragraph.
+    range: Range<usize>,
+    families: Vec<FamilyId>,
+    font_size: f32,
+    font_width: FontWidth,
+    font_style: FontStyle,
+    font_weight: FontWeight,
+    variations: Vec<FontVariation>,
+    features: Vec<FontFeature>,
+    locale: Option<Language>,
+    word_spacing: f32,
+    letter_spacing: f32,
+    word_break: WordBreakStrength,
+    hyphens: Hyphens,
+    text_transform: TextTransform,
+}
+
+/// Everything that determines the shaping of a paragraph.
+#[derive(Clone, PartialEq)]
+struct ParagraphKey {
+    text: String,
+    is_first: bool,
+    base_direction: Option<TextDirection>,
+    styles: Vec<ShapingStyle>,
+}
+
+/// Shaping output for a single paragraph.
+///
+/// Text ranges, cluster, glyph and coordinate indices are relative to the paragraph,
+/// style indices are relative to the first style covering the paragraph, and font
+/// indices refer to `fonts`.
+#[derive(Clone)]
+struct ShapedParagraph {
+    key: ParagraphKey,
+    fonts: Vec<Font>,
+    coords: Vec<i16>,
+    runs: Vec<RunData>,
+    clusters: Vec<ClusterData>,
+    glyphs: Vec<Glyph>,
+    /// Warnings reported while shaping the paragraph, which are reported again
+    /// whenever it is reused.
+    warnings: Vec<LayoutWarning>,
+    last_used: u64,
+}
+
+/// Handle for a paragraph lookup, returned by [`ParagraphCache::lookup`].
+pub(crate) struct ParagraphLookup {
+    hash: u64,
+    key: ParagraphKey,
+    style_base: usize,
+}
+
+/// Position of the shaping output in the layout before a paragraph was shaped.
+pub(crate) struct ShapedMark {
+    runs: usize,
+    clusters: usize,
+    glyphs: usize,
+    coords: usize,
+}
+
+impl ShapedMark {
+    pub(crate) fn new<B: Brush>(layout: &LayoutData<B>) -> Self {
+        Self {
+            runs: layout.runs.len(),
+            clusters: layout.clusters.len(),
+            glyphs: layout.glyphs.len(),
+            coords: layout.coords.len(),
+        }
+    }
+}
+
+/// Size-limited cache of shaped paragraphs, keyed by their content and styles.
+#[derive(Clone)]
+pub(crate) struct ParagraphCache {
+    capacity: usize,
+    /// Generation of the font collection that the entries were shaped with.
+    generation: Option<u64>,
+    clock: u64,
+    hasher: DefaultHashBuilder,
+    entries: HashMap<u64, ShapedParagraph>,
+}
+
+impl ParagraphCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            generation: None,
+            clock: 0,
+            hasher: DefaultHashBuilder::default(),
+            entries: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Prepares the cache for a build, discarding all entries if the font collection
+    /// has changed since they were shaped.
+    pub(crate) fn begin(&mut self, generation: u64) {
+        if self.generation != Some(generation) {
+            self.entries.clear();
+            self.generation = Some(generation);
+        }
+        self.clock += 1;
+    }
+
+    /// Computes the cache key for the paragraph at `range`.
+    pub(crate) fn lookup<B: Brush>(
+        &self,
+        rcx: &ResolveContext,
+        styles: &[RangedStyle<B>],
+        text: &str,
+        range: Range<usize>,
+        base_direction: Option<TextDirection>,
+    ) -> ParagraphLookup {
+        let style_base = styles
+            .iter()
+            .position(|style| style.range.end > range.start)
+            .unwrap_or(0);
+        let key = ParagraphKey {
+            text: text[range.clone()].into(),
+            is_first: range.start == 0,
+            base_direction,
+            styles: styles[style_base..]
+                .iter()
+                .take_while(|style| style.range.start < range.end)
+                .map(|ranged| {
+                    let style = &ranged.style;
+                    ShapingStyle {
+                        range: ranged.range.start.max(range.start) - range.start
+                            ..ranged.range.end.min(range.end) - range.start,
+                        families: rcx.stack(style.font_stack).unwrap_or(&[]).into(),
+                        font_size: style.font_size,
+                        font_width: style.font_width,
+                        font_style: style.font_style,
+                        font_weight: style.font_weight,
+                        variations: rcx.variations(style.font_variations).unwrap_or(&[]).into(),
+                        features: rcx.features(style.font_features).unwrap_or(&[]).into(),
+                        locale: style.locale,
+                        word_spacing: style.word_spacing,
+                        letter_spacing: style.letter_spacing,
+                        word_break: style.word_break,
+                        hyphens: style.hyphens,
+                        text_transform: style.text_transform,
+                    }
+                })
+                .collect(),
+        };
+        let mut hasher = self.hasher.build_hasher();
+        key.text.hash(&mut hasher);
+        key.is_first.hash(&mut hasher);
+        key.base_direction
+            .map(TextDirection::bidi_level)
+            .hash(&mut hasher);
+        for style in &key.styles {
+            style.range.hash(&mut hasher);
+            style.families.hash(&mut hasher);
+            style.font_size.to_bits().hash(&mut hasher);
+        }
+        ParagraphLookup {
+            hash: hasher.finish(),
+            key,
+            style_base,
+        }
+    }
+
+    /// Appends the cached shaping output for a paragraph to the layout.
+    ///
+    /// Returns `false` if the paragraph is not in the cache.
+    pub(crate) fn splice<B: Brush>(
+        &mut self,
+        lookup: &ParagraphLookup,
+        text_start: usize,
+        layout: &mut LayoutData<B>,
+    ) -> bool {
+        let Some(entry) = self.entries.get_mut(&lookup.hash) else {
+            return false;
+        };
+        if entry.key != lookup.key {
+            return false;
+        }
+        entry.last_used = self.clock;
+
+        let style_base = lookup.style_base as u16;
+        let mark = ShapedMark::new(layout);
+        let font_indices: Vec<usize> = entry
+            .fonts
+            .iter()
+            .map(|font| {
+                layout
+                    .fonts
+                    .iter()
+                    .position(|f| f == font)
+                    .unwrap_or_else(|| {
+                        layout.fonts.push(font.clone());
+                        layout.fonts.len() - 1
+                    })
+            })
+            .collect();
+        layout.coords.extend_from_slice(&entry.coords);
+        layout
+            .clusters
+            .extend(entry.clusters.iter().map(|cluster| ClusterData {
+                style_index: cluster.style_index + style_base,
+                ..*cluster
+            }));
+        layout.glyphs.extend(entry.glyphs.iter().map(|glyph| Glyph {
+            style_index: glyph.style_index + style_base,
+            ..*glyph
+        }));
+        for run in &entry.runs {
+            let mut run = run.clone();
+            run.font_index = font_indices[run.font_index];
+            run.coords_range = offset_range(&run.coords_range, mark.coords);
+            run.text_range = offset_range(&run.text_range, text_start);
+            run.cluster_range = offset_range(&run.cluster_range, mark.clusters);
+            run.glyph_start += mark.glyphs;
+            layout.items.push(LayoutItem {
+                kind: LayoutItemKind::TextRun,
+                index: layout.runs.len(),
+                bidi_level: run.bidi_level,
+            });
+            layout.runs.push(run);
+        }
+        for warning in &entry.warnings {
+            let mut warning = warning.clone();
+            let range = warning.text_range_mut();
+            *range = offset_range(range, text_start);
+            LayoutWarning::push(&mut layout.warnings, warning);
+        }
+        true
+    }
+
+    /// Stores the shaping output which was appended to the layout since `mark`, along
+    /// with the `warnings` reported while shaping it.
+    pub(crate) fn insert<B: Brush>(
+        &mut self,
+        lookup: ParagraphLookup,
+        text_start: usize,
+        mark: &ShapedMark,
+        warnings: &[LayoutWarning],
+        layout: &LayoutData<B>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&lookup.hash) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(hash, _)| *hash);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        let style_base = lookup.style_base as u16;
+        let mut fonts: Vec<Font> = Vec::new();
+        let runs = layout.runs[mark.runs..]
+            .iter()
+            .map(|run| {
+                let font = &layout.fonts[run.font_index];
+                let font_index = fonts.iter().position(|f| f == font).unwrap_or_else(|| {
+                    fonts.push(font.clone());
+                    fonts.len() - 1
+                });
+                RunData {
+                    font_index,
+                    coords_range: unoffset_range(&run.coords_range, mark.coords),
+                    text_range: unoffset_range(&run.text_range, text_start),
+                    cluster_range: unoffset_range(&run.cluster_range, mark.clusters),
+                    glyph_start: run.glyph_start - mark.glyphs,
+                    ..run.clone()
+                }
+            })
+            .collect();
+        let entry = ShapedParagraph {
+            key: lookup.key,
+            fonts,
+            coords: layout.coords[mark.coords..].into(),
+            runs,
+            clusters: layout.clusters[mark.clusters..]
+                .iter()
+                .map(|cluster| ClusterData {
+                    style_index: cluster.style_index - style_base,
+                    ..*cluster
+                })
+                .collect(),
+            glyphs: layout.glyphs[mark.glyphs..]
+                .iter()
+                .map(|glyph| Glyph {
+                    style_index: glyph.style_index - style_base,
+                    ..*glyph
+                })
+                .collect(),
+            warnings: warnings
+                .iter()
+                .map(|warning| {
+                    let mut warning = warning.clone();
+                    let range = warning.text_range_mut();
+                    *range = unoffset_range(range, text_start);
+                    warning
+                })
+                .collect(),
+            last_used: self.clock,
+        };
+        self.entries.insert(lookup.hash, entry);
+    }
+}
+
+fn offset_range(range: &Range<usize>, offset: usize) -> Range<usize> {
+    range.start + offset..range.end + offset
+}
+
+fn unoffset_range(range: &Range<usize>, offset: usize) -> Range<usize> {
+    range.start - offset..range.end - offset
+}
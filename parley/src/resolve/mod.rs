@@ -17,10 +17,10 @@ use super::style::{
 use crate::font::FontContext;
 use crate::style::TextStyle;
 use crate::util::nearly_eq;
-use crate::{OverflowWrap, WordBreakStrength, layout};
+use crate::{Hyphens, OverflowWrap, TextTransform, WordBreakStrength, layout};
 use core::borrow::Borrow;
 use core::ops::Range;
-use fontique::FamilyId;
+use fontique::{FamilyId, GenericFamily};
 use swash::text::Language;
 
 /// Style with an associated range.
@@ -119,7 +119,10 @@ pub(crate) struct ResolveContext {
     families: Cache<FamilyId>,
     variations: Cache<FontVariation>,
     features: Cache<FontFeature>,
+    /// Font stacks which contain a generic family with no registered families.
+    missing_generics: Vec<(Resolved<FamilyId>, GenericFamily)>,
     tmp_families: Vec<FamilyId>,
+    tmp_missing_generics: Vec<GenericFamily>,
     tmp_variations: Vec<FontVariation>,
     tmp_features: Vec<FontFeature>,
 }
@@ -153,10 +156,13 @@ impl ResolveContext {
             StyleProperty::StrikethroughSize(value) => StrikethroughSize(value.map(|x| x * scale)),
             StyleProperty::StrikethroughBrush(value) => StrikethroughBrush(value.clone()),
             StyleProperty::LineHeight(value) => LineHeight(*value),
+            StyleProperty::MinLineHeight(value) => MinLineHeight(*value * scale),
             StyleProperty::WordSpacing(value) => WordSpacing(*value * scale),
             StyleProperty::LetterSpacing(value) => LetterSpacing(*value * scale),
             StyleProperty::WordBreak(value) => WordBreak(*value),
             StyleProperty::OverflowWrap(value) => OverflowWrap(*value),
+            StyleProperty::TextTransform(value) => TextTransform(*value),
+            StyleProperty::Hyphens(value) => Hyphens(*value),
         }
     }
 
@@ -189,10 +195,13 @@ impl ResolveContext {
                 brush: raw_style.strikethrough_brush.clone(),
             },
             line_height: raw_style.line_height,
+            min_line_height: raw_style.min_line_height * scale,
             word_spacing: raw_style.word_spacing * scale,
             letter_spacing: raw_style.letter_spacing * scale,
             word_break: raw_style.word_break,
             overflow_wrap: raw_style.overflow_wrap,
+            text_transform: raw_style.text_transform,
+            hyphens: raw_style.hyphens,
         }
     }
 
@@ -212,10 +221,7 @@ impl ResolveContext {
                                 self.tmp_families.push(family.id());
                             }
                         }
-                        FontFamily::Generic(family) => {
-                            self.tmp_families
-                                .extend(fcx.collection.generic_families(family));
-                        }
+                        FontFamily::Generic(family) => self.resolve_generic(fcx, family),
                     }
                 }
             }
@@ -225,10 +231,7 @@ impl ResolveContext {
                         self.tmp_families.push(family.id());
                     }
                 }
-                FontFamily::Generic(family) => {
-                    self.tmp_families
-                        .extend(fcx.collection.generic_families(*family));
-                }
+                FontFamily::Generic(family) => self.resolve_generic(fcx, *family),
             },
             FontStack::List(families) => {
                 let families: &[FontFamily<'_>] = families.borrow();
@@ -239,19 +242,39 @@ impl ResolveContext {
                                 self.tmp_families.push(family.id());
                             }
                         }
-                        FontFamily::Generic(family) => {
-                            self.tmp_families
-                                .extend(fcx.collection.generic_families(*family));
-                        }
+                        FontFamily::Generic(family) => self.resolve_generic(fcx, *family),
                     }
                 }
             }
         }
         let resolved = self.families.insert(&self.tmp_families);
         self.tmp_families.clear();
+        for generic in self.tmp_missing_generics.drain(..) {
+            if !self.missing_generics.contains(&(resolved, generic)) {
+                self.missing_generics.push((resolved, generic));
+            }
+        }
         resolved
     }
 
+    /// Appends the families for a generic family to the font stack being resolved.
+    fn resolve_generic(&mut self, fcx: &mut FontContext, family: GenericFamily) {
+        let len = self.tmp_families.len();
+        self.tmp_families
+            .extend(fcx.collection.generic_families(family));
+        if self.tmp_families.len() == len {
+            self.tmp_missing_generics.push(family);
+        }
+    }
+
+    /// Returns a generic family in the font stack for which no families are registered.
+    pub(crate) fn missing_generic(&self, stack: Resolved<FamilyId>) -> Option<GenericFamily> {
+        self.missing_generics
+            .iter()
+            .find(|(resolved, _)| *resolved == stack)
+            .map(|(_, generic)| *generic)
+    }
+
     /// Resolves font variation settings.
     pub(crate) fn resolve_variations(
         &mut self,
@@ -322,6 +345,7 @@ impl ResolveContext {
     /// Clears the resources in the context.
     pub(crate) fn clear(&mut self) {
         self.families.clear();
+        self.missing_generics.clear();
         self.variations.clear();
         self.features.clear();
     }
@@ -366,6 +390,8 @@ pub(crate) enum ResolvedProperty<B: Brush> {
     StrikethroughBrush(Option<B>),
     /// Line height multiplier.
     LineHeight(f32),
+    /// Minimum line height.
+    MinLineHeight(f32),
     /// Extra spacing between words.
     WordSpacing(f32),
     /// Extra spacing between letters.
@@ -374,6 +400,10 @@ pub(crate) enum ResolvedProperty<B: Brush> {
     WordBreak(WordBreakStrength),
     /// Control over "emergency" line-breaking.
     OverflowWrap(OverflowWrap),
+    /// Change of case for display.
+    TextTransform(TextTransform),
+    /// Control over hyphenation at soft hyphens.
+    Hyphens(Hyphens),
 }
 
 /// Flattened group of style properties.
@@ -403,6 +433,8 @@ pub(crate) struct ResolvedStyle<B: Brush> {
     pub(crate) strikethrough: ResolvedDecoration<B>,
     /// Line height multiplier.
     pub(crate) line_height: f32,
+    /// Minimum line height.
+    pub(crate) min_line_height: f32,
     /// Extra spacing between words.
     pub(crate) word_spacing: f32,
     /// Extra spacing between letters.
@@ -411,6 +443,10 @@ pub(crate) struct ResolvedStyle<B: Brush> {
     pub(crate) word_break: WordBreakStrength,
     /// Control over "emergency" line-breaking.
     pub(crate) overflow_wrap: OverflowWrap,
+    /// Change of case for display.
+    pub(crate) text_transform: TextTransform,
+    /// Control over hyphenation at soft hyphens.
+    pub(crate) hyphens: Hyphens,
 }
 
 impl<B: Brush> Default for ResolvedStyle<B> {
@@ -428,10 +464,13 @@ impl<B: Brush> Default for ResolvedStyle<B> {
             underline: Default::default(),
             strikethrough: Default::default(),
             line_height: 1.,
+            min_line_height: 0.,
             word_spacing: 0.,
             letter_spacing: 0.,
             word_break: Default::default(),
             overflow_wrap: Default::default(),
+            text_transform: Default::default(),
+            hyphens: Default::default(),
         }
     }
 }
@@ -459,10 +498,13 @@ impl<B: Brush> ResolvedStyle<B> {
             StrikethroughSize(value) => self.strikethrough.size = value,
             StrikethroughBrush(value) => self.strikethrough.brush = value,
             LineHeight(value) => self.line_height = value,
+            MinLineHeight(value) => self.min_line_height = value,
             WordSpacing(value) => self.word_spacing = value,
             LetterSpacing(value) => self.letter_spacing = value,
             WordBreak(value) => self.word_break = value,
             OverflowWrap(value) => self.overflow_wrap = value,
+            TextTransform(value) => self.text_transform = value,
+            Hyphens(value) => self.hyphens = value,
         }
     }
 
@@ -487,10 +529,13 @@ impl<B: Brush> ResolvedStyle<B> {
             StrikethroughSize(value) => self.strikethrough.size == *value,
             StrikethroughBrush(value) => self.strikethrough.brush == *value,
             LineHeight(value) => nearly_eq(self.line_height, *value),
+            MinLineHeight(value) => nearly_eq(self.min_line_height, *value),
             WordSpacing(value) => nearly_eq(self.word_spacing, *value),
             LetterSpacing(value) => nearly_eq(self.letter_spacing, *value),
             WordBreak(value) => self.word_break == *value,
             OverflowWrap(value) => self.overflow_wrap == *value,
+            TextTransform(value) => self.text_transform == *value,
+            Hyphens(value) => self.hyphens == *value,
         }
     }
 
@@ -499,7 +544,7 @@ impl<B: Brush> ResolvedStyle<B> {
             brush: self.brush.clone(),
             underline: self.underline.as_layout_decoration(&self.brush),
             strikethrough: self.strikethrough.as_layout_decoration(&self.brush),
-            line_height: self.line_height * self.font_size,
+            line_height: (self.line_height * self.font_size).max(self.min_line_height),
             overflow_wrap: self.overflow_wrap,
         }
     }
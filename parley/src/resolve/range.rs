@@ -13,6 +13,7 @@ use core::ops::{Bound, Range, RangeBounds};
 #[derive(Clone)]
 pub(crate) struct RangedStyleBuilder<B: Brush> {
     properties: Vec<RangedProperty<B>>,
+    spans: Vec<RangedStyle<B>>,
     default_style: ResolvedStyle<B>,
     len: usize,
 }
@@ -21,6 +22,7 @@ impl<B: Brush> Default for RangedStyleBuilder<B> {
     fn default() -> Self {
         Self {
             properties: vec![],
+            spans: vec![],
             default_style: Default::default(),
             len: !0,
         }
@@ -32,10 +34,21 @@ impl<B: Brush> RangedStyleBuilder<B> {
     /// specified length.
     pub(crate) fn begin(&mut self, len: usize) {
         self.properties.clear();
+        self.spans.clear();
         self.default_style = ResolvedStyle::default();
         self.len = len;
     }
 
+    /// Returns the length of the text that the builder was prepared for.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the style of text which is not covered by a ranged property.
+    pub(crate) fn default_style(&self) -> &ResolvedStyle<B> {
+        &self.default_style
+    }
+
     /// Pushes a property that covers the full range of text.
     pub(crate) fn push_default(&mut self, property: ResolvedProperty<B>) {
         assert!(self.len != !0);
@@ -49,17 +62,49 @@ impl<B: Brush> RangedStyleBuilder<B> {
         self.properties.push(RangedProperty { property, range });
     }
 
+    /// Pushes a complete style for each of a sequence of sorted, non-overlapping
+    /// ranges, which the sequence of ranged styles starts from.
+    ///
+    /// Text which is not covered by a span has the default style. Ranges are clamped to
+    /// the text, and the part of a span which overlaps the spans before it is dropped.
+    pub(crate) fn push_spans(&mut self, spans: impl IntoIterator<Item = RangedStyle<B>>) {
+        assert!(self.len != !0);
+        for mut span in spans {
+            let start = self.spans.last().map_or(0, |prev| prev.range.end);
+            let range = resolve_range(span.range, self.len);
+            span.range = range.start.max(start)..range.end;
+            if !span.range.is_empty() {
+                self.spans.push(span);
+            }
+        }
+    }
+
     /// Computes the sequence of ranged styles.
     pub(crate) fn finish(&mut self, styles: &mut Vec<RangedStyle<B>>) {
         if self.len == !0 {
             self.properties.clear();
+            self.spans.clear();
             self.default_style = ResolvedStyle::default();
             return;
         }
-        styles.push(RangedStyle {
-            style: self.default_style.clone(),
-            range: 0..self.len,
-        });
+        // The spans are already sorted, so they only need the gaps between them filled.
+        let mut end = 0;
+        for span in self.spans.drain(..) {
+            if span.range.start > end {
+                styles.push(RangedStyle {
+                    style: self.default_style.clone(),
+                    range: end..span.range.start,
+                });
+            }
+            end = span.range.end;
+            styles.push(span);
+        }
+        if end < self.len || self.len == 0 {
+            styles.push(RangedStyle {
+                style: self.default_style.clone(),
+                range: end..self.len,
+            });
+        }
         for prop in &self.properties {
             if prop.range.start > prop.range.end {
                 continue;
@@ -107,6 +152,12 @@ impl<B: Brush> RangedStyleBuilder<B> {
                 }
             }
         }
+        // Spans are used as they are, so only the spans split by properties are merged.
+        if self.properties.is_empty() {
+            self.default_style = ResolvedStyle::default();
+            self.len = !0;
+            return;
+        }
         let mut prev_index = 0;
         let mut merged_count = 0;
         for i in 1..styles.len() {
@@ -170,7 +221,7 @@ fn split_range<B: Brush>(prop: &RangedProperty<B>, spans: &[RangedStyle<B>]) ->
 }
 
 /// Resolves a `RangeBounds` into a range in the range 0..len.
-fn resolve_range(range: impl RangeBounds<usize>, len: usize) -> Range<usize> {
+pub(crate) fn resolve_range(range: impl RangeBounds<usize>, len: usize) -> Range<usize> {
     let start = match range.start_bound() {
         Bound::Unbounded => 0,
         Bound::Included(n) => *n,
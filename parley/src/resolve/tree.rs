@@ -5,7 +5,7 @@
 use alloc::borrow::Cow;
 use alloc::{string::String, vec::Vec};
 
-use crate::style::WhiteSpaceCollapse;
+use crate::style::{BoundarySpace, WhiteSpaceCollapse};
 
 use super::{Brush, RangedStyle, ResolvedProperty, ResolvedStyle};
 
@@ -15,11 +15,16 @@ struct StyleTreeNode<B: Brush> {
     style: ResolvedStyle<B>,
 }
 
+/// What precedes the next character for the purposes of white space collapsing.
 #[derive(Clone, Copy, PartialEq)]
-pub(crate) enum ItemKind {
-    None,
-    InlineBox,
-    TextRun,
+enum WhiteSpaceState {
+    /// The start of the text, or of a line after a preserved line break. Collapsible
+    /// white space is removed.
+    LineStart,
+    /// A collapsible space. Further collapsible white space is removed.
+    Space,
+    /// Any other character or an inline box.
+    Other,
 }
 
 /// Builder for constructing a tree of styles
@@ -28,11 +33,11 @@ pub(crate) struct TreeStyleBuilder<B: Brush> {
     tree: Vec<StyleTreeNode<B>>,
     flatted_styles: Vec<RangedStyle<B>>,
     white_space_collapse: WhiteSpaceCollapse,
+    boundary_space: BoundarySpace,
     text: String,
     uncommitted_text: String,
     current_span: usize,
-    is_span_first: bool,
-    last_item_kind: ItemKind,
+    white_space_state: WhiteSpaceState,
 }
 
 impl<B: Brush> TreeStyleBuilder<B> {
@@ -47,11 +52,11 @@ impl<B: Brush> Default for TreeStyleBuilder<B> {
             tree: Vec::new(),
             flatted_styles: Vec::new(),
             white_space_collapse: WhiteSpaceCollapse::Preserve,
+            boundary_space: BoundarySpace::default(),
             text: String::new(),
             uncommitted_text: String::new(),
             current_span: usize::MAX,
-            is_span_first: false,
-            last_item_kind: ItemKind::None,
+            white_space_state: WhiteSpaceState::LineStart,
         }
     }
 }
@@ -62,6 +67,7 @@ impl<B: Brush> TreeStyleBuilder<B> {
         self.tree.clear();
         self.flatted_styles.clear();
         self.white_space_collapse = WhiteSpaceCollapse::Preserve;
+        self.boundary_space = BoundarySpace::default();
         self.text.clear();
         self.uncommitted_text.clear();
 
@@ -70,62 +76,61 @@ impl<B: Brush> TreeStyleBuilder<B> {
             style: root_style,
         });
         self.current_span = 0;
-        self.is_span_first = true;
+        self.white_space_state = WhiteSpaceState::LineStart;
     }
 
-    pub(crate) fn set_white_space_mode(&mut self, white_space_collapse: WhiteSpaceCollapse) {
-        self.white_space_collapse = white_space_collapse;
+    /// Returns the style of the root of the tree.
+    pub(crate) fn root_style(&self) -> &ResolvedStyle<B> {
+        &self.tree[0].style
     }
 
-    pub(crate) fn set_is_span_first(&mut self, is_span_first: bool) {
-        self.is_span_first = is_span_first;
+    pub(crate) fn set_white_space_mode(&mut self, white_space_collapse: WhiteSpaceCollapse) {
+        self.white_space_collapse = white_space_collapse;
     }
 
-    pub(crate) fn set_last_item_kind(&mut self, item_kind: ItemKind) {
-        self.last_item_kind = item_kind;
+    pub(crate) fn set_boundary_space(&mut self, boundary_space: BoundarySpace) {
+        self.boundary_space = boundary_space;
     }
 
-    pub(crate) fn push_uncommitted_text(&mut self, is_span_last: bool) {
+    /// Commits the pending text, collapsing white space with respect to everything
+    /// committed before it, exactly as if all of the text had been pushed at once.
+    fn push_uncommitted_text(&mut self) {
         let span_text: Cow<'_, str> = match self.white_space_collapse {
-            WhiteSpaceCollapse::Preserve => Cow::from(&self.uncommitted_text),
-            WhiteSpaceCollapse::Collapse => {
-                let mut span_text = self.uncommitted_text.as_str();
-
-                if self.is_span_first
-                    || (self.last_item_kind == ItemKind::TextRun
-                        && self
-                            .text
-                            .chars()
-                            .last()
-                            .is_some_and(|c| c.is_ascii_whitespace()))
-                {
-                    span_text = span_text.trim_start();
+            WhiteSpaceCollapse::Preserve => {
+                if self.uncommitted_text.starts_with(is_line_break) {
+                    self.trim_collapsible_space();
                 }
-                if is_span_last {
-                    span_text = span_text.trim_end();
+                if let Some(last) = self.uncommitted_text.chars().last() {
+                    self.white_space_state = if is_line_break(last) {
+                        WhiteSpaceState::LineStart
+                    } else {
+                        WhiteSpaceState::Other
+                    };
                 }
-
-                // Collapse spaces
-                let mut last_char_whitespace = false;
-                let span_text: String = span_text
-                    .chars()
-                    .filter_map(|c: char| {
-                        let this_char_whitespace = c.is_ascii_whitespace();
-                        let prev_char_whitespace = last_char_whitespace;
-                        last_char_whitespace = this_char_whitespace;
-
-                        if this_char_whitespace {
-                            if prev_char_whitespace {
-                                None
-                            } else {
-                                Some(' ')
-                            }
-                        } else {
-                            Some(c)
+                Cow::from(&self.uncommitted_text)
+            }
+            WhiteSpaceCollapse::Collapse => {
+                let mut span_text = String::with_capacity(self.uncommitted_text.len());
+                for c in self.uncommitted_text.chars() {
+                    if c.is_ascii_whitespace() {
+                        if self.white_space_state == WhiteSpaceState::Other {
+                            span_text.push(' ');
+                            self.white_space_state = WhiteSpaceState::Space;
+                        } else if self.white_space_state == WhiteSpaceState::Space
+                            && span_text.is_empty()
+                            && self.boundary_space == BoundarySpace::Following
+                        {
+                            // The space was committed with an earlier span, so move it
+                            // into this one.
+                            self.trim_collapsible_space();
+                            span_text.push(' ');
+                            self.white_space_state = WhiteSpaceState::Space;
                         }
-                    })
-                    .collect();
-
+                    } else {
+                        span_text.push(c);
+                        self.white_space_state = WhiteSpaceState::Other;
+                    }
+                }
                 Cow::from(span_text)
             }
         };
@@ -142,8 +147,39 @@ impl<B: Brush> TreeStyleBuilder<B> {
         self.flatted_styles.push(RangedStyle { style, range });
         self.text.push_str(span_text);
         self.uncommitted_text.clear();
-        self.is_span_first = false;
-        self.last_item_kind = ItemKind::TextRun;
+    }
+
+    /// Removes a collapsible space from the end of the text, as it ends a line.
+    fn trim_collapsible_space(&mut self) {
+        if self.white_space_state != WhiteSpaceState::Space {
+            return;
+        }
+        // The space was emitted by the last text to be committed, so it is always
+        // covered by the last style.
+        self.text.pop();
+        if let Some(last) = self.flatted_styles.last_mut() {
+            last.range.end = self.text.len();
+            if last.range.is_empty() {
+                self.flatted_styles.pop();
+            }
+        }
+        self.white_space_state = WhiteSpaceState::LineStart;
+    }
+
+    /// Commits the pending text and returns the text position of an inline box
+    /// pushed after it.
+    ///
+    /// For white space collapsing, an inline box acts like a non-whitespace character.
+    pub(crate) fn push_inline_box(&mut self) -> usize {
+        self.push_uncommitted_text();
+        self.white_space_state = WhiteSpaceState::Other;
+        self.text.len()
+    }
+
+    /// Commits the pending text and returns the length of the text.
+    pub(crate) fn commit_text(&mut self) -> usize {
+        self.push_uncommitted_text();
+        self.text.len()
     }
 
     pub(crate) fn current_text_len(&self) -> usize {
@@ -151,14 +187,13 @@ impl<B: Brush> TreeStyleBuilder<B> {
     }
 
     pub(crate) fn push_style_span(&mut self, style: ResolvedStyle<B>) {
-        self.push_uncommitted_text(false);
+        self.push_uncommitted_text();
 
         self.tree.push(StyleTreeNode {
             parent: Some(self.current_span),
             style,
         });
         self.current_span = self.tree.len() - 1;
-        self.is_span_first = true;
     }
 
     pub(crate) fn push_style_modification_span(
@@ -173,7 +208,7 @@ impl<B: Brush> TreeStyleBuilder<B> {
     }
 
     pub(crate) fn pop_style_span(&mut self) {
-        self.push_uncommitted_text(true);
+        self.push_uncommitted_text();
 
         self.current_span = self.tree[self.current_span]
             .parent
@@ -193,7 +228,8 @@ impl<B: Brush> TreeStyleBuilder<B> {
             self.pop_style_span();
         }
 
-        self.push_uncommitted_text(true);
+        self.push_uncommitted_text();
+        self.trim_collapsible_space();
 
         styles.clear();
         styles.extend_from_slice(&self.flatted_styles);
@@ -201,3 +237,7 @@ impl<B: Brush> TreeStyleBuilder<B> {
         core::mem::take(&mut self.text)
     }
 }
+
+fn is_line_break(c: char) -> bool {
+    matches!(c, '\n' | '\r')
+}
@@ -1,19 +1,23 @@
 // Copyright 2021 the Parley Authors
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use super::layout::Layout;
+use super::context::GlyphOverride;
+use super::layout::{Layout, LayoutWarning};
 use super::resolve::{RangedStyle, ResolveContext, Resolved};
-use super::style::{Brush, FontFeature, FontVariation};
+use super::style::{Brush, FontFeature, FontVariation, TextTransform};
+use super::transform::push_tokens;
 use crate::Font;
 use crate::util::nearly_eq;
 use fontique::QueryFamily;
-use fontique::{self, Query, QueryFont};
+use fontique::{self, Blob, Query};
 use swash::shape::{Direction, ShapeContext, partition};
-use swash::text::cluster::{CharCluster, CharInfo, Token};
+use swash::text::cluster::{CharCluster, CharInfo, Status as MapStatus};
 use swash::text::{Language, Script};
 use swash::{FontRef, Synthesis};
 
 use alloc::vec::Vec;
+use core::ops::Range;
+use hashbrown::HashMap;
 
 use crate::inline_box::InlineBox;
 
@@ -27,6 +31,7 @@ struct Item {
     features: Resolved<FontFeature>,
     word_spacing: f32,
     letter_spacing: f32,
+    text_transform: TextTransform,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -37,6 +42,8 @@ pub(crate) fn shape_text<'a, B: Brush>(
     inline_boxes: &[InlineBox],
     infos: &[(CharInfo, u16)],
     levels: &[u8],
+    glyph_overrides: &'a HashMap<char, GlyphOverride>,
+    fallback_allowlist: Option<&'a [Font]>,
     scx: &mut ShapeContext,
     mut text: &str,
     layout: &mut Layout<B>,
@@ -46,25 +53,68 @@ pub(crate) fn shape_text<'a, B: Brush>(
     if text.is_empty() && inline_boxes.is_empty() {
         text = " ";
     }
+    shape_range(
+        rcx,
+        &mut fq,
+        styles,
+        inline_boxes,
+        0,
+        infos,
+        levels,
+        glyph_overrides,
+        fallback_allowlist,
+        scx,
+        text,
+        0..text.len(),
+        0,
+        layout,
+    );
+}
+
+/// Shapes the given byte range of `text`, which starts at the character index `char_start`.
+///
+/// The inline boxes must be those positioned within the range, along with the index of the
+/// first of them in the full list of inline boxes. Boxes positioned at or beyond the end of
+/// the range are pushed after the text.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn shape_range<'a, B: Brush>(
+    rcx: &'a ResolveContext,
+    fq: &mut Query<'a>,
+    styles: &'a [RangedStyle<B>],
+    inline_boxes: &[InlineBox],
+    first_box_index: usize,
+    infos: &[(CharInfo, u16)],
+    levels: &[u8],
+    glyph_overrides: &'a HashMap<char, GlyphOverride>,
+    fallback_allowlist: Option<&'a [Font]>,
+    scx: &mut ShapeContext,
+    text: &str,
+    range: Range<usize>,
+    char_start: usize,
+    layout: &mut Layout<B>,
+) {
     // Do nothing if there is no text or styles (there should always be a default style)
-    if text.is_empty() || styles.is_empty() {
+    if range.is_empty() || styles.is_empty() {
         // Process any remaining inline boxes whose index is greater than the length of the text
         for box_idx in 0..inline_boxes.len() {
             // Push the box to the list of items
-            layout.data.push_inline_box(box_idx);
+            layout.data.push_inline_box(first_box_index + box_idx);
         }
         return;
     }
 
     // Setup mutable state for iteration
-    let mut style = &styles[0].style;
+    let infos_in_range = &infos[char_start..];
+    let first_style_index = infos_in_range[0].1;
+    let mut style = &styles[first_style_index as usize].style;
     let mut item = Item {
-        style_index: 0,
+        style_index: first_style_index,
         size: style.font_size,
-        level: levels.first().copied().unwrap_or(0),
-        script: infos
-            .iter()
-            .map(|x| x.0.script())
+        level: levels.get(char_start).copied().unwrap_or(0),
+        script: text[range.clone()]
+            .chars()
+            .zip(infos_in_range)
+            .map(|(_, x)| x.0.script())
             .find(|&script| real_script(script))
             .unwrap_or(Script::Latin),
         locale: style.locale,
@@ -72,9 +122,12 @@ pub(crate) fn shape_text<'a, B: Brush>(
         features: style.font_features,
         word_spacing: style.word_spacing,
         letter_spacing: style.letter_spacing,
+        text_transform: style.text_transform,
     };
-    let mut char_range = 0..0;
-    let mut text_range = 0..0;
+    let mut char_range = char_start..char_start;
+    let mut text_range = range.start..range.start;
+    let mut tokens = Vec::new();
+    let mut font_checks = HashMap::new();
 
     let mut inline_box_iter = inline_boxes.iter().enumerate();
     let mut current_box = inline_box_iter.next();
@@ -83,46 +136,58 @@ pub(crate) fn shape_text<'a, B: Brush>(
     // Define macro to shape
     macro_rules! shape_item {
         () => {
-            let item_text = &text[text_range.clone()];
             let item_infos = &infos[char_range.start..];
             let first_style_index = item_infos[0].1;
-            let mut fs = FontSelector::new(
-                &mut fq,
-                rcx,
-                styles,
-                first_style_index,
-                item.script,
-                item.locale,
-            );
-            let options = partition::SimpleShapeOptions {
-                size: item.size,
-                script: item.script,
-                language: item.locale,
-                direction: if item.level & 1 != 0 {
-                    Direction::RightToLeft
-                } else {
-                    Direction::LeftToRight
-                },
-                variations: rcx.variations(item.variations).unwrap_or(&[]),
-                features: rcx.features(item.features).unwrap_or(&[]),
-                insert_dotted_circles: false,
-            };
-            partition::shape(
-                scx,
-                &mut fs,
-                &options,
-                item_text.char_indices().zip(item_infos).map(
-                    |((offset, ch), (info, style_index))| Token {
-                        ch,
-                        offset: (text_range.start + offset) as u32,
-                        len: ch.len_utf8() as u8,
-                        info: *info,
-                        data: *style_index as _,
+            // A font which shapes letters or digits without any glyphs is rejected, and
+            // the item is shaped again without it.
+            loop {
+                let runs_start = layout.data.runs.len();
+                let items_start = layout.data.items.len();
+                let clusters_start = layout.data.clusters.len();
+                let glyphs_start = layout.data.glyphs.len();
+                let coords_start = layout.data.coords.len();
+                let fonts_start = layout.data.fonts.len();
+                let mut fs = FontSelector::new(
+                    &mut *fq,
+                    rcx,
+                    styles,
+                    first_style_index,
+                    item.script,
+                    item.locale,
+                    glyph_overrides,
+                    fallback_allowlist,
+                    &mut font_checks,
+                    layout.data.fonts.last().cloned(),
+                    layout.data.diagnostics,
+                );
+                let options = partition::SimpleShapeOptions {
+                    size: item.size,
+                    script: item.script,
+                    language: item.locale,
+                    direction: if item.level & 1 != 0 {
+                        Direction::RightToLeft
+                    } else {
+                        Direction::LeftToRight
                     },
-                ),
-                |font, shaper| {
+                    variations: rcx.variations(item.variations).unwrap_or(&[]),
+                    features: rcx.features(item.features).unwrap_or(&[]),
+                    insert_dotted_circles: false,
+                };
+                push_tokens(
+                    text,
+                    text_range.clone(),
+                    item_infos,
+                    item.text_transform,
+                    item.locale,
+                    &mut tokens,
+                );
+                #[cfg(feature = "stats")]
+                let start = std::time::Instant::now();
+                partition::shape(scx, &mut fs, &options, tokens.drain(..), |font, shaper| {
+                    #[cfg(feature = "stats")]
+                    let (start, first_run) = (std::time::Instant::now(), layout.data.runs.len());
                     layout.data.push_run(
-                        Font::new(font.font.blob.clone(), font.font.index),
+                        font.font.clone(),
                         item.size,
                         font.synthesis,
                         shaper,
@@ -130,15 +195,63 @@ pub(crate) fn shape_text<'a, B: Brush>(
                         item.word_spacing,
                         item.letter_spacing,
                     );
-                },
-            );
+                    #[cfg(feature = "stats")]
+                    layout.data.record_runs(first_run, start.elapsed(), false);
+                });
+                #[cfg(feature = "stats")]
+                {
+                    let stats = &mut layout.data.stats;
+                    stats.font_selection += fs.duration;
+                    stats.shaping += start.elapsed().saturating_sub(fs.duration);
+                }
+                let mut warnings = core::mem::take(&mut fs.warnings);
+                let data = &layout.data;
+                let mut rejected = false;
+                for run in &data.runs[runs_start..] {
+                    let has_glyphs = data.clusters[run.cluster_range.clone()]
+                        .iter()
+                        .any(|cluster| cluster.glyph_len != 0);
+                    if has_glyphs || !text[run.text_range.clone()].contains(char::is_alphanumeric) {
+                        continue;
+                    }
+                    let font = &data.fonts[run.font_index];
+                    font_checks.insert((font.data.id(), font.index), false);
+                    LayoutWarning::push(
+                        &mut warnings,
+                        LayoutWarning::FontLoadFailed {
+                            font: font.clone(),
+                            text_range: run.text_range.clone(),
+                        },
+                    );
+                    rejected = true;
+                }
+                if rejected {
+                    let data = &mut layout.data;
+                    data.runs.truncate(runs_start);
+                    data.items.truncate(items_start);
+                    data.clusters.truncate(clusters_start);
+                    data.glyphs.truncate(glyphs_start);
+                    data.coords.truncate(coords_start);
+                    data.fonts.truncate(fonts_start);
+                }
+                for warning in warnings {
+                    LayoutWarning::push(&mut layout.data.warnings, warning);
+                }
+                if !rejected {
+                    break;
+                }
+            }
         };
     }
 
     // Iterate over characters in the text
-    for ((char_index, (byte_index, ch)), (info, style_index)) in
-        text.char_indices().enumerate().zip(infos)
+    for ((char_offset, (byte_offset, ch)), (info, style_index)) in text[range.clone()]
+        .char_indices()
+        .enumerate()
+        .zip(infos_in_range)
     {
+        let char_index = char_start + char_offset;
+        let byte_index = range.start + byte_offset;
         let mut break_run = false;
         let mut script = info.script();
         if !real_script(script) {
@@ -154,6 +267,7 @@ pub(crate) fn shape_text<'a, B: Brush>(
                 || style.font_features != item.features
                 || !nearly_eq(style.letter_spacing, item.letter_spacing)
                 || !nearly_eq(style.word_spacing, item.word_spacing)
+                || style.text_transform != item.text_transform
             {
                 break_run = true;
             }
@@ -173,7 +287,7 @@ pub(crate) fn shape_text<'a, B: Brush>(
 
             if inline_box.index == byte_index {
                 break_run = true;
-                deferred_boxes.push(box_idx);
+                deferred_boxes.push(first_box_index + box_idx);
                 // Update the current box to the next box
                 current_box = inline_box_iter.next();
             } else {
@@ -189,6 +303,7 @@ pub(crate) fn shape_text<'a, B: Brush>(
             item.locale = style.locale;
             item.variations = style.font_variations;
             item.features = style.font_features;
+            item.text_transform = style.text_transform;
             text_range.start = text_range.end;
             char_range.start = char_range.end;
         }
@@ -208,14 +323,14 @@ pub(crate) fn shape_text<'a, B: Brush>(
 
     // Process any remaining inline boxes whose index is greater than the length of the text
     if let Some((box_idx, _inline_box)) = current_box {
-        layout.data.push_inline_box(box_idx);
+        layout.data.push_inline_box(first_box_index + box_idx);
     }
     for (box_idx, _inline_box) in inline_box_iter {
-        layout.data.push_inline_box(box_idx);
+        layout.data.push_inline_box(first_box_index + box_idx);
     }
 }
 
-fn real_script(script: Script) -> bool {
+pub(crate) fn real_script(script: Script) -> bool {
     script != Script::Common && script != Script::Unknown && script != Script::Inherited
 }
 
@@ -228,9 +343,24 @@ struct FontSelector<'a, 'b, B: Brush> {
     attrs: fontique::Attributes,
     variations: &'a [FontVariation],
     features: &'a [FontFeature],
+    glyph_overrides: &'a HashMap<char, GlyphOverride>,
+    /// The only fonts which are used as fallbacks, if restricted.
+    fallback_allowlist: Option<&'a [Font]>,
+    /// Whether each font, by the identifier of its data and its index, can be used for
+    /// shaping.
+    font_checks: &'b mut HashMap<(u64, u32), bool>,
+    /// Font used for clusters which no font can be loaded for.
+    last_resort: Option<Font>,
+    warnings: Vec<LayoutWarning>,
+    /// Whether fallback fonts and missing glyphs are reported as warnings.
+    diagnostics: bool,
+    /// Time spent selecting fonts.
+    #[cfg(feature = "stats")]
+    duration: core::time::Duration,
 }
 
 impl<'a, 'b, B: Brush> FontSelector<'a, 'b, B> {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         query: &'b mut Query<'a>,
         rcx: &'a ResolveContext,
@@ -238,6 +368,11 @@ impl<'a, 'b, B: Brush> FontSelector<'a, 'b, B> {
         style_index: u16,
         script: Script,
         locale: Option<Language>,
+        glyph_overrides: &'a HashMap<char, GlyphOverride>,
+        fallback_allowlist: Option<&'a [Font]>,
+        font_checks: &'b mut HashMap<(u64, u32), bool>,
+        last_resort: Option<Font>,
+        diagnostics: bool,
     ) -> Self {
         let style = &styles[style_index as usize].style;
         let fonts_id = style.font_stack.id();
@@ -249,10 +384,13 @@ impl<'a, 'b, B: Brush> FontSelector<'a, 'b, B> {
         };
         let variations = rcx.variations(style.font_variations).unwrap_or(&[]);
         let features = rcx.features(style.font_features).unwrap_or(&[]);
-        query.set_families(fonts.iter().copied());
+        set_stack_families(query, fonts);
         let fb_script = crate::swash_convert::script_to_fontique(script);
         let fb_language = locale.and_then(crate::swash_convert::locale_to_fontique);
-        query.set_fallbacks(fontique::FallbackKey::new(fb_script, fb_language.as_ref()));
+        // Fallback is restricted to the allowlist, which is searched after the query.
+        if fallback_allowlist.is_none() {
+            query.set_fallbacks(fontique::FallbackKey::new(fb_script, fb_language.as_ref()));
+        }
         query.set_attributes(attrs);
         Self {
             query,
@@ -263,14 +401,37 @@ impl<'a, 'b, B: Brush> FontSelector<'a, 'b, B> {
             attrs,
             variations,
             features,
+            glyph_overrides,
+            fallback_allowlist,
+            font_checks,
+            last_resort,
+            warnings: Vec::new(),
+            diagnostics,
+            #[cfg(feature = "stats")]
+            duration: core::time::Duration::ZERO,
         }
     }
-}
 
-impl<B: Brush> partition::Selector for FontSelector<'_, '_, B> {
-    type SelectedFont = SelectedFont;
-
-    fn select_font(&mut self, cluster: &mut CharCluster) -> Option<Self::SelectedFont> {
+    fn select(&mut self, cluster: &mut CharCluster) -> Option<SelectedFont> {
+        let text_range = cluster.range().to_range();
+        // Overridden characters use the font of their glyph, which is substituted after shaping.
+        if let [ch] = cluster.chars() {
+            if let Some(glyph) = self.glyph_overrides.get(&ch.ch) {
+                if is_usable(self.font_checks, &glyph.font.data, glyph.font.index) {
+                    return Some(SelectedFont {
+                        font: glyph.font.clone(),
+                        synthesis: Synthesis::default(),
+                    });
+                }
+                LayoutWarning::push(
+                    &mut self.warnings,
+                    LayoutWarning::FontLoadFailed {
+                        font: glyph.font.clone(),
+                        text_range: text_range.clone(),
+                    },
+                );
+            }
+        }
         let style_index = cluster.user_data() as u16;
         let is_emoji = cluster.info().is_emoji();
         if style_index != self.style_index || is_emoji || self.fonts_id.is_none() {
@@ -294,7 +455,7 @@ impl<B: Brush> partition::Selector for FontSelector<'_, '_, B> {
                 self.fonts_id = None;
             } else if self.fonts_id != Some(fonts_id) {
                 let fonts = self.rcx.stack(style.font_stack).unwrap_or(&[]);
-                self.query.set_families(fonts.iter().copied());
+                set_stack_families(self.query, fonts);
                 self.fonts_id = Some(fonts_id);
             }
             if self.attrs != attrs {
@@ -306,69 +467,244 @@ impl<B: Brush> partition::Selector for FontSelector<'_, '_, B> {
             self.features = features;
         }
         let mut selected_font = None;
+        let mut selected_family = None;
+        let mut is_complete = false;
+        let fallback_allowlist = self.fallback_allowlist;
+        let requested = self
+            .rcx
+            .stack(self.styles[self.style_index as usize].style.font_stack)
+            .unwrap_or(&[]);
+        if requested.is_empty() {
+            LayoutWarning::push(
+                &mut self.warnings,
+                LayoutWarning::EmptyFontStack {
+                    text_range: text_range.clone(),
+                },
+            );
+        }
         self.query.matches_with(|font| {
-            if let Ok(font_ref) = skrifa::FontRef::from_index(font.blob.as_ref(), font.index) {
-                use crate::swash_convert::synthesis_to_swash;
-                use skrifa::MetadataProvider;
-                use swash::text::cluster::Status as MapStatus;
-                let charmap = font_ref.charmap();
-                match cluster.map(|ch| {
-                    charmap
-                        .map(ch)
-                        .map(|g| {
-                            g.to_u32()
-                                .try_into()
-                                .expect("Swash requires u16 glyph, so we hope that the glyph fits")
-                        })
-                        .unwrap_or_default()
-                }) {
-                    MapStatus::Complete => {
-                        selected_font = Some(SelectedFont {
-                            font: font.clone(),
-                            synthesis: synthesis_to_swash(font.synthesis),
-                        });
-                        return fontique::QueryStatus::Stop;
-                    }
-                    MapStatus::Keep => {
+            use crate::swash_convert::synthesis_to_swash;
+
+            let font_ref = skrifa::FontRef::from_index(font.blob.as_ref(), font.index)
+                .ok()
+                .filter(|_| is_usable(self.font_checks, &font.blob, font.index));
+            let Some(font_ref) = font_ref else {
+                LayoutWarning::push(
+                    &mut self.warnings,
+                    LayoutWarning::FontLoadFailed {
+                        font: Font::new(font.blob.clone(), font.index),
+                        text_range: text_range.clone(),
+                    },
+                );
+                return fontique::QueryStatus::Continue;
+            };
+            match map_cluster(cluster, &font_ref) {
+                MapStatus::Complete => {
+                    selected_font = Some(SelectedFont {
+                        font: Font::new(font.blob.clone(), font.index),
+                        synthesis: synthesis_to_swash(font.synthesis),
+                    });
+                    selected_family = Some(font.family.0);
+                    is_complete = true;
+                    return fontique::QueryStatus::Stop;
+                }
+                MapStatus::Keep => {
+                    selected_font = Some(SelectedFont {
+                        font: Font::new(font.blob.clone(), font.index),
+                        synthesis: synthesis_to_swash(font.synthesis),
+                    });
+                    selected_family = Some(font.family.0);
+                }
+                MapStatus::Discard => {
+                    if selected_font.is_none() {
                         selected_font = Some(SelectedFont {
-                            font: font.clone(),
+                            font: Font::new(font.blob.clone(), font.index),
                             synthesis: synthesis_to_swash(font.synthesis),
                         });
-                    }
-                    MapStatus::Discard => {
-                        if selected_font.is_none() {
-                            selected_font = Some(SelectedFont {
-                                font: font.clone(),
-                                synthesis: synthesis_to_swash(font.synthesis),
-                            });
-                        }
+                        selected_family = Some(font.family.0);
                     }
                 }
             }
             fontique::QueryStatus::Continue
         });
-        selected_font
+        let mut from_allowlist = false;
+        for font in fallback_allowlist.unwrap_or(&[]) {
+            if is_complete {
+                break;
+            }
+            if !is_usable(self.font_checks, &font.data, font.index) {
+                continue;
+            }
+            let Ok(font_ref) = skrifa::FontRef::from_index(font.data.as_ref(), font.index) else {
+                continue;
+            };
+            // As in the query, the cluster keeps the best mapping so far.
+            let status = map_cluster(cluster, &font_ref);
+            is_complete = matches!(status, MapStatus::Complete);
+            if !matches!(status, MapStatus::Discard) || selected_font.is_none() {
+                selected_font = Some(SelectedFont {
+                    font: font.clone(),
+                    synthesis: Synthesis::default(),
+                });
+                from_allowlist = true;
+            }
+        }
+        if let Some(font) = &selected_font {
+            if self.diagnostics {
+                if from_allowlist
+                    || selected_family.is_some_and(|family| !requested.contains(&family))
+                {
+                    LayoutWarning::push(
+                        &mut self.warnings,
+                        LayoutWarning::FallbackFont {
+                            font: font.font.clone(),
+                            text_range: text_range.clone(),
+                        },
+                    );
+                }
+                if !is_complete {
+                    LayoutWarning::push(
+                        &mut self.warnings,
+                        LayoutWarning::MissingGlyphs {
+                            font: font.font.clone(),
+                            text_range: text_range.clone(),
+                        },
+                    );
+                }
+            }
+            self.last_resort = Some(font.font.clone());
+            return selected_font;
+        }
+
+        // Rather than dropping the text, show notdef glyphs from a font which did load.
+        LayoutWarning::push(
+            &mut self.warnings,
+            LayoutWarning::NoUsableFont { text_range },
+        );
+        let font = self.last_resort_font()?;
+        cluster.map(|_| 0);
+        self.last_resort = Some(font.clone());
+        Some(SelectedFont {
+            font,
+            synthesis: Synthesis::default(),
+        })
+    }
+
+    /// Returns a usable font for text which no font in the stack or fallback chain can
+    /// be used for.
+    ///
+    /// This is the font used most recently, or the first usable font of the generic
+    /// families if no font has been used yet in the layout.
+    fn last_resort_font(&mut self) -> Option<Font> {
+        if let Some(font) = self
+            .last_resort
+            .as_ref()
+            .filter(|font| is_usable(self.font_checks, &font.data, font.index))
+        {
+            return Some(font.clone());
+        }
+        let generics = fontique::GenericFamily::all().iter().copied();
+        self.query.set_families(generics.map(QueryFamily::Generic));
+        // The families of the style are set again for the next cluster.
+        self.fonts_id = None;
+        let mut last_resort = None;
+        let font_checks = &mut *self.font_checks;
+        self.query.matches_with(|font| {
+            if !is_usable(font_checks, &font.blob, font.index) {
+                return fontique::QueryStatus::Continue;
+            }
+            last_resort = Some(Font::new(font.blob.clone(), font.index));
+            fontique::QueryStatus::Stop
+        });
+        last_resort
+    }
+}
+
+/// Returns whether both font parsers, which are each used for shaping, can load the font
+/// and the tables that shaping requires.
+///
+/// The result is cached in `checks`, so that each font is only parsed once.
+fn is_usable(checks: &mut HashMap<(u64, u32), bool>, data: &Blob<u8>, index: u32) -> bool {
+    use skrifa::raw::TableProvider;
+
+    *checks.entry((data.id(), index)).or_insert_with(|| {
+        let Ok(font) = skrifa::FontRef::from_index(data.as_ref(), index) else {
+            return false;
+        };
+        FontRef::from_index(data.as_ref(), index as _).is_some()
+            && font.head().is_ok()
+            && font.maxp().is_ok()
+            && font.hhea().is_ok()
+            && font.hmtx().is_ok()
+            && font.cmap().is_ok()
+    })
+}
+
+/// Sets the families of `query` to a resolved font stack.
+///
+/// A font stack which is empty or has no available families falls back to the sans-serif
+/// generic family, and the query falls back to the last-resort font if that has no
+/// families either.
+fn set_stack_families(query: &mut Query<'_>, fonts: &[fontique::FamilyId]) {
+    if fonts.is_empty() {
+        query.set_families([QueryFamily::Generic(fontique::GenericFamily::SansSerif)]);
+    } else {
+        query.set_families(fonts.iter().copied());
+    }
+}
+
+/// Maps the characters of `cluster` to the glyphs of `font`.
+fn map_cluster(cluster: &mut CharCluster, font: &skrifa::FontRef<'_>) -> MapStatus {
+    use skrifa::MetadataProvider;
+
+    let charmap = font.charmap();
+    cluster.map(|ch| {
+        charmap
+            .map(ch)
+            .map(|g| {
+                g.to_u32()
+                    .try_into()
+                    .expect("Swash requires u16 glyph, so we hope that the glyph fits")
+            })
+            .unwrap_or_default()
+    })
+}
+
+impl<B: Brush> partition::Selector for FontSelector<'_, '_, B> {
+    type SelectedFont = SelectedFont;
+
+    fn select_font(&mut self, cluster: &mut CharCluster) -> Option<Self::SelectedFont> {
+        #[cfg(feature = "stats")]
+        let start = std::time::Instant::now();
+        let font = self.select(cluster);
+        #[cfg(feature = "stats")]
+        {
+            self.duration += start.elapsed();
+        }
+        font
     }
 }
 
 struct SelectedFont {
-    font: QueryFont,
+    font: Font,
     synthesis: Synthesis,
 }
 
 impl PartialEq for SelectedFont {
     fn eq(&self, other: &Self) -> bool {
-        self.font.family == other.font.family && self.synthesis == other.synthesis
+        self.font.data.id() == other.font.data.id()
+            && self.font.index == other.font.index
+            && self.synthesis == other.synthesis
     }
 }
 
 impl partition::SelectedFont for SelectedFont {
     fn font(&self) -> FontRef<'_> {
-        FontRef::from_index(self.font.blob.as_ref(), self.font.index as _).unwrap()
+        FontRef::from_index(self.font.data.as_ref(), self.font.index as _).unwrap()
     }
 
     fn id_override(&self) -> Option<[u64; 2]> {
-        Some([self.font.blob.id(), self.font.index as _])
+        Some([self.font.data.id(), self.font.index as _])
     }
 
     fn synthesis(&self) -> Option<Synthesis> {
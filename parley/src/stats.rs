@@ -0,0 +1,61 @@
+// Copyright 2025 the Parley Authors
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Statistics about building and breaking layouts, for performance introspection.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::time::Duration;
+
+use crate::Font;
+
+/// Counts and durations of the phases of building, breaking and aligning a layout.
+///
+/// See [`LayoutContext::stats`](crate::LayoutContext::stats) and
+/// [`Layout::stats`](crate::Layout::stats).
+#[derive(Clone, Default, Debug)]
+pub struct LayoutStats {
+    /// Time spent analyzing the text for boundaries and bidi levels.
+    pub analysis: Duration,
+    /// Time spent selecting fonts for the text.
+    pub font_selection: Duration,
+    /// Time spent shaping the text, excluding font selection.
+    pub shaping: Duration,
+    /// Time spent breaking lines.
+    pub line_breaking: Duration,
+    /// The number of lines produced by line breaking.
+    pub line_count: usize,
+    /// Time spent aligning the lines.
+    pub alignment: Duration,
+    /// The runs of the layout, in the order that they were shaped or taken from the
+    /// paragraph cache.
+    pub runs: Vec<RunStats>,
+}
+
+impl LayoutStats {
+    /// Returns the number of runs which were shaped.
+    pub fn shaped_runs(&self) -> usize {
+        self.runs.iter().filter(|run| !run.is_cached).count()
+    }
+
+    /// Returns the number of runs which were taken from the paragraph cache.
+    pub fn cached_runs(&self) -> usize {
+        self.runs.iter().filter(|run| run.is_cached).count()
+    }
+}
+
+/// Statistics about a single run of a layout.
+#[derive(Clone, Debug)]
+pub struct RunStats {
+    /// Range of the source text.
+    pub text_range: Range<usize>,
+    /// The font that the run was shaped with.
+    pub font: Font,
+    /// Time spent shaping the run.
+    ///
+    /// This is zero for cached runs. Where shaping produced several runs at once, the
+    /// time is split evenly between them.
+    pub duration: Duration,
+    /// Whether the run was taken from the paragraph cache rather than shaped.
+    pub is_cached: bool,
+}
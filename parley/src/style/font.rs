@@ -5,7 +5,7 @@ use alloc::borrow::Cow;
 use alloc::borrow::ToOwned;
 use core::fmt;
 
-pub use fontique::{FontStyle, FontWeight, FontWidth, GenericFamily};
+pub use fontique::{Attributes as FontAttributes, FontStyle, FontWeight, FontWidth, GenericFamily};
 
 /// Setting for a font variation.
 pub type FontVariation = swash::Setting<f32>;
@@ -8,11 +8,15 @@ mod font;
 mod styleset;
 
 use alloc::borrow::Cow;
+use alloc::vec::Vec;
+#[cfg(feature = "libm")]
+#[allow(unused_imports)]
+use core_maths::CoreFloat;
 
 pub use brush::*;
 pub use font::{
-    FontFamily, FontFeature, FontSettings, FontStack, FontStyle, FontVariation, FontWeight,
-    FontWidth, GenericFamily,
+    FontAttributes, FontFamily, FontFeature, FontSettings, FontStack, FontStyle, FontVariation,
+    FontWeight, FontWidth, GenericFamily,
 };
 pub use styleset::StyleSet;
 pub use swash::text::WordBreakStrength;
@@ -23,6 +27,21 @@ pub enum WhiteSpaceCollapse {
     Preserve,
 }
 
+/// Which style a collapsed space takes when white space from differently-styled spans is
+/// collapsed together, such as the spaces of `<b>bold </b> normal`.
+///
+/// The style of the space determines its brush, decorations, and word and letter spacing.
+/// See [`TreeBuilder::set_boundary_space`](crate::TreeBuilder::set_boundary_space).
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum BoundarySpace {
+    /// The space takes the style of the span containing the first of the white space,
+    /// like CSS.
+    #[default]
+    Preceding,
+    /// The space takes the style of the span containing the last of the white space.
+    Following,
+}
+
 /// Control over "emergency" line-breaking.
 ///
 /// See <https://drafts.csswg.org/css-text/#overflow-wrap-property> for more information.
@@ -41,6 +60,267 @@ pub enum OverflowWrap {
     BreakWord,
 }
 
+/// The base direction of a paragraph of text.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TextDirection {
+    /// Left-to-right text.
+    LeftToRight,
+    /// Right-to-left text.
+    RightToLeft,
+}
+
+impl TextDirection {
+    /// Returns the direction corresponding to a bidi embedding level.
+    pub(crate) fn from_bidi_level(level: u8) -> Self {
+        if level & 1 != 0 {
+            Self::RightToLeft
+        } else {
+            Self::LeftToRight
+        }
+    }
+
+    /// Returns the paragraph embedding level for this direction.
+    pub(crate) fn bidi_level(self) -> u8 {
+        match self {
+            Self::LeftToRight => 0,
+            Self::RightToLeft => 1,
+        }
+    }
+}
+
+/// Controls how U+FFFD REPLACEMENT CHARACTER is laid out.
+///
+/// Replacement characters usually come from lossy decoding of invalid input, such as
+/// logs containing malformed UTF-8.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct ReplacementCharPolicy<B: Brush> {
+    /// Whether each sequence of consecutive replacement characters is displayed as a
+    /// single character.
+    ///
+    /// The other characters in the sequence keep their source offsets but contribute
+    /// no advance and produce no glyphs.
+    pub collapse: bool,
+    /// Brush for replacement characters, overriding the brush of their style.
+    pub brush: Option<B>,
+}
+
+/// Changes the case of text for display, like CSS `text-transform`.
+///
+/// The source text is unchanged, so clusters and cursors keep mapping onto it. Case
+/// mapping is tailored to the locale of the text when the `icu_casemap` feature is
+/// enabled, such as uppercasing `i` to `İ` in Turkish.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum TextTransform {
+    /// The text is displayed as it is.
+    #[default]
+    None,
+    /// The text is displayed in uppercase.
+    Uppercase,
+    /// The text is displayed in lowercase.
+    Lowercase,
+}
+
+/// Controls whether words are hyphenated when wrapping, like CSS `hyphens`.
+///
+/// Only soft hyphens (U+00AD) in the text are hyphenation opportunities, as there is no
+/// automatic hyphenation.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum Hyphens {
+    /// Words are never hyphenated, and lines are not wrapped at soft hyphens.
+    None,
+    /// Words may be hyphenated at soft hyphens.
+    #[default]
+    Manual,
+}
+
+/// Limits on where words are hyphenated, which avoid short fragments such as "e-vent".
+///
+/// A soft hyphen is only a hyphenation opportunity if its word and the parts of the
+/// word before and after it have at least the minimum number of characters, not
+/// counting soft hyphens. Words end at whitespace. The default has no limits.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct HyphenationLimits {
+    /// The fewest characters in a word which is hyphenated.
+    pub min_word_len: usize,
+    /// The fewest characters of a word before the hyphen.
+    pub min_prefix: usize,
+    /// The fewest characters of a word after the hyphen.
+    pub min_suffix: usize,
+    /// The most consecutive lines which may end with a hyphen, if limited.
+    pub max_consecutive_hyphenated_lines: Option<usize>,
+}
+
+impl HyphenationLimits {
+    /// Returns `true` if the limits can prevent a soft hyphen from hyphenating its word.
+    pub(crate) fn is_limited(&self) -> bool {
+        self.min_word_len > 0 || self.min_prefix > 0 || self.min_suffix > 0
+    }
+
+    /// Returns the byte offsets of the soft hyphens in `text` which may not hyphenate
+    /// their word, in ascending order.
+    ///
+    /// The characters of each word are counted once, in a single pass over the text.
+    pub(crate) fn limited_hyphens(&self, text: &str) -> Vec<usize> {
+        let mut limited = Vec::new();
+        // The soft hyphens of the current word, with the number of characters before each.
+        let mut hyphens = Vec::new();
+        let mut letters = 0;
+        let end = core::iter::once((text.len(), ' '));
+        for (index, ch) in text.char_indices().chain(end) {
+            if ch == '\u{AD}' {
+                hyphens.push((index, letters));
+            } else if !ch.is_whitespace() {
+                letters += 1;
+            } else {
+                for (hyphen, prefix) in hyphens.drain(..) {
+                    let suffix = letters - prefix;
+                    if prefix < self.min_prefix
+                        || suffix < self.min_suffix
+                        || letters < self.min_word_len
+                    {
+                        limited.push(hyphen);
+                    }
+                }
+                letters = 0;
+            }
+        }
+        limited
+    }
+}
+
+/// Indentation of the first line of a paragraph, like CSS `text-indent`.
+///
+/// The indent is applied at the start edge of the line, which is the left edge of
+/// left-to-right paragraphs and the right edge of right-to-left paragraphs.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct TextIndent {
+    /// The width of the indent in pixels.
+    ///
+    /// A negative indent produces a hanging indent, where the first line extends
+    /// past the start edge.
+    pub amount: f32,
+    /// Whether the first line of every paragraph is indented, rather than only the
+    /// first line of the text.
+    pub each_paragraph: bool,
+}
+
+/// Rounding of word and letter spacing, so that text with spacing stays aligned to a
+/// grid, such as the character grid of a code editor.
+///
+/// See [`LayoutContext::set_spacing_rounding`](crate::LayoutContext::set_spacing_rounding).
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub enum SpacingRounding {
+    /// Spacing is applied exactly.
+    #[default]
+    None,
+    /// The end of each cluster with spacing is rounded to a whole device pixel, measured
+    /// from the start of its line.
+    Pixel,
+    /// The end of each cluster with spacing is rounded to a multiple of the given width,
+    /// in device pixels, measured from the start of its line.
+    Grid(f32),
+}
+
+impl SpacingRounding {
+    /// Returns the width that cluster ends are rounded to, if any.
+    pub(crate) fn unit(self) -> Option<f32> {
+        match self {
+            Self::None => None,
+            Self::Pixel => Some(1.),
+            Self::Grid(unit) => (unit > 0.).then_some(unit),
+        }
+    }
+}
+
+/// Positions of the tab stops that tab characters advance to.
+///
+/// See [`LayoutContext::set_tab_stops`](crate::LayoutContext::set_tab_stops).
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub enum TabStops {
+    /// Tabs are shaped like other characters, with the advance of the font.
+    #[default]
+    None,
+    /// There is a tab stop at every multiple of the given width from the start of the
+    /// line, in device pixels.
+    ///
+    /// The start of a line is where its content starts, after the
+    /// [`TextIndent`](crate::TextIndent) and any leading boxes, so the tab stops of an
+    /// indented line are aligned to the indent. Each line, including one after a hard
+    /// break, measures its tab stops from its own start.
+    Every(f32),
+}
+
+impl TabStops {
+    /// Returns the advance of a tab which starts `x` from the start of the line, reaching
+    /// the next tab stop after `x`.
+    ///
+    /// Returns `None` if tabs keep the advance of the font.
+    pub fn advance(self, x: f32) -> Option<f32> {
+        match self {
+            Self::None => None,
+            Self::Every(interval) if interval > 0. => {
+                Some(((x / interval).floor() + 1.) * interval - x)
+            }
+            Self::Every(_) => None,
+        }
+    }
+}
+
+/// Where the ellipsis replaces text which doesn't fit, such as in
+/// [`layout_truncated_label`](crate::layout_truncated_label).
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum EllipsisPosition {
+    /// The start of the text is replaced, keeping its end.
+    Start,
+    /// The middle of the text is replaced, keeping about as many grapheme clusters of
+    /// its start as of its end.
+    Middle,
+    /// The end of the text is replaced, keeping its start.
+    #[default]
+    End,
+}
+
+/// Text which replaces truncated text in place of an ellipsis, such as in
+/// [`layout_truncated_label`](crate::layout_truncated_label).
+#[derive(Clone, PartialEq, Debug)]
+pub struct TruncationMarker<'a, B: Brush> {
+    /// The text of the marker, such as a localized "more".
+    pub text: &'a str,
+    /// The style of the marker, which does not inherit from the truncated text.
+    pub style: TextStyle<'a, B>,
+}
+
+/// The kind of break caused by a caller-specified hard break character.
+///
+/// See [`RangedBuilder::push_hard_break_char`](crate::RangedBuilder::push_hard_break_char).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HardBreak {
+    /// The character ends the line, like U+2028 LINE SEPARATOR.
+    Line,
+    /// The character ends the paragraph, like U+2029 PARAGRAPH SEPARATOR.
+    ///
+    /// Each paragraph gets its own base direction.
+    Paragraph,
+}
+
+/// Controls whether a range of text is displayed.
+///
+/// Text which is not visible keeps its source offsets, so cursors and selections
+/// still map onto the original text.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub enum Visibility {
+    /// The text is laid out and drawn normally.
+    #[default]
+    Visible,
+    /// The text contributes no advance and produces no glyphs.
+    Hidden,
+    /// The text is replaced by a blank of the given width, such as for a redacted
+    /// password.
+    ///
+    /// The redacted area can be retrieved with [`Line::redactions`](crate::Line::redactions).
+    Redacted(f32),
+}
+
 /// Properties that define a style.
 #[derive(Clone, PartialEq, Debug)]
 pub enum StyleProperty<'a, B: Brush> {
@@ -80,6 +360,8 @@ pub enum StyleProperty<'a, B: Brush> {
     StrikethroughBrush(Option<B>),
     /// Line height multiplier.
     LineHeight(f32),
+    /// Minimum line height in pixels, independent of the font size.
+    MinLineHeight(f32),
     /// Extra spacing between words.
     WordSpacing(f32),
     /// Extra spacing between letters.
@@ -88,6 +370,10 @@ pub enum StyleProperty<'a, B: Brush> {
     WordBreak(WordBreakStrength),
     /// Control over "emergency" line-breaking.
     OverflowWrap(OverflowWrap),
+    /// Change of case for display.
+    TextTransform(TextTransform),
+    /// Control over hyphenation at soft hyphens.
+    Hyphens(Hyphens),
 }
 
 /// Unresolved styles.
@@ -129,6 +415,8 @@ pub struct TextStyle<'a, B: Brush> {
     pub strikethrough_brush: Option<B>,
     /// Line height multiplier.
     pub line_height: f32,
+    /// Minimum line height in pixels, independent of the font size.
+    pub min_line_height: f32,
     /// Extra spacing between words.
     pub word_spacing: f32,
     /// Extra spacing between letters.
@@ -137,6 +425,10 @@ pub struct TextStyle<'a, B: Brush> {
     pub word_break: WordBreakStrength,
     /// Control over "emergency" line-breaking.
     pub overflow_wrap: OverflowWrap,
+    /// Change of case for display.
+    pub text_transform: TextTransform,
+    /// Control over hyphenation at soft hyphens.
+    pub hyphens: Hyphens,
 }
 
 impl<B: Brush> Default for TextStyle<'_, B> {
@@ -160,10 +452,13 @@ impl<B: Brush> Default for TextStyle<'_, B> {
             strikethrough_size: Default::default(),
             strikethrough_brush: Default::default(),
             line_height: 1.2,
+            min_line_height: 0.0,
             word_spacing: Default::default(),
             letter_spacing: Default::default(),
             word_break: Default::default(),
             overflow_wrap: Default::default(),
+            text_transform: Default::default(),
+            hyphens: Default::default(),
         }
     }
 }
@@ -0,0 +1,508 @@
+// Copyright 2025 the Parley Authors
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Randomized property testing for [`PlainEditor`] integrations.
+//!
+//! [`EditorFuzzer`] drives a [`PlainEditorDriver`] through a randomized sequence of
+//! [`EditOp`]s and checks a set of invariants after every step. The invariants are also
+//! available separately as [`check_editor_invariants`], so that embedders can run them
+//! against their own integration layer.
+//!
+//! This module requires the `testing` feature.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::fmt::{Debug, Display};
+use core::ops::Range;
+
+use crate::{Brush, Cluster, Layout, PlainEditor, PlainEditorDriver};
+
+/// Grapheme clusters which are inserted by [`EditorFuzzer`].
+///
+/// This covers ASCII, combining sequences, right-to-left letters and digits,
+/// emoji (including modifier, ZWJ and flag sequences), and hard line breaks.
+const GRAPHEMES: &[&str] = &[
+    "a",
+    "b",
+    "f",
+    "i",
+    "1",
+    " ",
+    "\n",
+    "e\u{301}",
+    "\u{627}",
+    "\u{628}",
+    "\u{634}",
+    "\u{661}",
+    "\u{1F44D}",
+    "\u{1F44D}\u{1F3FD}",
+    "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}",
+    "\u{1F1EF}\u{1F1F5}",
+];
+
+/// Widths which are applied by [`EditOp::SetWidth`] in [`EditorFuzzer`].
+const WIDTHS: &[Option<f32>] = &[None, Some(0.), Some(20.), Some(60.), Some(200.)];
+
+/// Tolerance used when comparing caret geometry against the layout bounds.
+const EPSILON: f64 = 1.0;
+
+/// A single operation on a [`PlainEditor`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditOp {
+    /// [`PlainEditorDriver::insert_or_replace_selection`].
+    Insert(String),
+    /// [`PlainEditorDriver::delete`].
+    Delete,
+    /// [`PlainEditorDriver::backdelete`].
+    Backdelete,
+    /// [`PlainEditorDriver::delete_word`].
+    DeleteWord,
+    /// [`PlainEditorDriver::backdelete_word`].
+    BackdeleteWord,
+    /// [`PlainEditorDriver::move_left`].
+    MoveLeft,
+    /// [`PlainEditorDriver::move_right`].
+    MoveRight,
+    /// [`PlainEditorDriver::move_up`].
+    MoveUp,
+    /// [`PlainEditorDriver::move_down`].
+    MoveDown,
+    /// [`PlainEditorDriver::move_word_left`].
+    MoveWordLeft,
+    /// [`PlainEditorDriver::move_word_right`].
+    MoveWordRight,
+    /// [`PlainEditorDriver::move_to_line_start`].
+    MoveToLineStart,
+    /// [`PlainEditorDriver::move_to_line_end`].
+    MoveToLineEnd,
+    /// [`PlainEditorDriver::move_to_text_start`].
+    MoveToTextStart,
+    /// [`PlainEditorDriver::move_to_text_end`].
+    MoveToTextEnd,
+    /// [`PlainEditorDriver::move_to_point`].
+    MoveToPoint(f32, f32),
+    /// [`PlainEditorDriver::select_left`].
+    SelectLeft,
+    /// [`PlainEditorDriver::select_right`].
+    SelectRight,
+    /// [`PlainEditorDriver::select_up`].
+    SelectUp,
+    /// [`PlainEditorDriver::select_down`].
+    SelectDown,
+    /// [`PlainEditorDriver::select_word_left`].
+    SelectWordLeft,
+    /// [`PlainEditorDriver::select_word_right`].
+    SelectWordRight,
+    /// [`PlainEditorDriver::select_all`].
+    SelectAll,
+    /// [`PlainEditorDriver::collapse_selection`].
+    CollapseSelection,
+    /// [`PlainEditorDriver::extend_selection_to_point`].
+    ExtendSelectionToPoint(f32, f32),
+    /// [`PlainEditorDriver::select_byte_range`].
+    SelectByteRange(usize, usize),
+    /// [`PlainEditorDriver::set_compose`].
+    SetCompose(String, Option<(usize, usize)>),
+    /// [`PlainEditorDriver::clear_compose`].
+    ClearCompose,
+    /// Commits IME text: [`PlainEditorDriver::clear_compose`] followed by
+    /// [`PlainEditorDriver::insert_or_replace_selection`].
+    CommitCompose(String),
+    /// [`PlainEditor::set_width`].
+    SetWidth(Option<f32>),
+}
+
+impl EditOp {
+    /// Returns `true` if the operation may be applied to `editor` in its current state.
+    ///
+    /// While composing, only IME operations and width changes are valid.
+    pub fn is_valid_for<T>(&self, editor: &PlainEditor<T>) -> bool
+    where
+        T: Brush + Clone + Debug + PartialEq + Default,
+    {
+        match self {
+            Self::SetCompose(text, cursor) => {
+                !text.is_empty()
+                    && cursor.is_none_or(|(start, end)| {
+                        start <= end && text.is_char_boundary(start) && text.is_char_boundary(end)
+                    })
+            }
+            Self::ClearCompose | Self::CommitCompose(_) | Self::SetWidth(_) => true,
+            _ => !editor.is_composing(),
+        }
+    }
+
+    /// Applies the operation through `driver`.
+    ///
+    /// Returns `false` without doing anything if the operation is not valid
+    /// for the current state of the editor (see [`EditOp::is_valid_for`]).
+    pub fn apply<T>(&self, driver: &mut PlainEditorDriver<'_, T>) -> bool
+    where
+        T: Brush + Clone + Debug + PartialEq + Default,
+    {
+        if !self.is_valid_for(driver.editor) {
+            return false;
+        }
+        match self {
+            Self::Insert(text) => driver.insert_or_replace_selection(text),
+            Self::Delete => driver.delete(),
+            Self::Backdelete => driver.backdelete(),
+            Self::DeleteWord => driver.delete_word(),
+            Self::BackdeleteWord => driver.backdelete_word(),
+            Self::MoveLeft => driver.move_left(),
+            Self::MoveRight => driver.move_right(),
+            Self::MoveUp => driver.move_up(),
+            Self::MoveDown => driver.move_down(),
+            Self::MoveWordLeft => driver.move_word_left(),
+            Self::MoveWordRight => driver.move_word_right(),
+            Self::MoveToLineStart => driver.move_to_line_start(),
+            Self::MoveToLineEnd => driver.move_to_line_end(),
+            Self::MoveToTextStart => driver.move_to_text_start(),
+            Self::MoveToTextEnd => driver.move_to_text_end(),
+            Self::MoveToPoint(x, y) => driver.move_to_point(*x, *y),
+            Self::SelectLeft => driver.select_left(),
+            Self::SelectRight => driver.select_right(),
+            Self::SelectUp => driver.select_up(),
+            Self::SelectDown => driver.select_down(),
+            Self::SelectWordLeft => driver.select_word_left(),
+            Self::SelectWordRight => driver.select_word_right(),
+            Self::SelectAll => driver.select_all(),
+            Self::CollapseSelection => driver.collapse_selection(),
+            Self::ExtendSelectionToPoint(x, y) => driver.extend_selection_to_point(*x, *y),
+            Self::SelectByteRange(start, end) => driver.select_byte_range(*start, *end),
+            Self::SetCompose(text, cursor) => driver.set_compose(text, *cursor),
+            Self::ClearCompose => driver.clear_compose(),
+            Self::CommitCompose(text) => {
+                driver.clear_compose();
+                driver.insert_or_replace_selection(text);
+            }
+            Self::SetWidth(width) => driver.editor.set_width(*width),
+        }
+        true
+    }
+
+    /// Returns the text that the operation is expected to produce, if it can be
+    /// predicted without shaping.
+    fn expected_text<T>(&self, editor: &PlainEditor<T>) -> Option<String>
+    where
+        T: Brush + Clone + Debug + PartialEq + Default,
+    {
+        let mut text = editor.text().to_string();
+        match self {
+            Self::Insert(insert) => {
+                text.replace_range(editor.raw_selection().text_range(), insert);
+                Some(text)
+            }
+            Self::CommitCompose(insert) => {
+                if editor.is_composing() {
+                    // The preedit text replaced the selection, so the insertion
+                    // point is the start of the preedit.
+                    let index = preedit_start(editor)?;
+                    text.insert_str(index, insert);
+                } else {
+                    text.replace_range(editor.raw_selection().text_range(), insert);
+                }
+                Some(text)
+            }
+            Self::Delete | Self::Backdelete | Self::DeleteWord | Self::BackdeleteWord => None,
+            Self::SetCompose(..) if !editor.is_composing() => {
+                // Starting composition replaces the selection with the preedit.
+                text.replace_range(editor.raw_selection().text_range(), "");
+                Some(text)
+            }
+            _ => Some(text),
+        }
+    }
+}
+
+/// Returns the start of the IME preedit region, if any.
+fn preedit_start<T>(editor: &PlainEditor<T>) -> Option<usize>
+where
+    T: Brush + Clone + Debug + PartialEq + Default,
+{
+    editor.compose.as_ref().map(|range| range.start)
+}
+
+/// An invariant violation found by [`EditorFuzzer`].
+#[derive(Clone, Debug)]
+pub struct FuzzFailure {
+    /// The seed of the failing run.
+    pub seed: u64,
+    /// The index of the step which violated an invariant.
+    pub step: usize,
+    /// The operation applied at the failing step.
+    pub op: EditOp,
+    /// The editor text, including any preedit, before the failing step.
+    pub text_before: String,
+    /// A description of the violated invariant.
+    pub message: String,
+}
+
+impl Display for FuzzFailure {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "seed {} step {}: {:?} on {:?}: {}",
+            self.seed, self.step, self.op, self.text_before, self.message
+        )
+    }
+}
+
+/// Drives a [`PlainEditor`] through randomized sequences of [`EditOp`]s.
+///
+/// Runs are fully determined by their seed, so a failing run can be replayed
+/// by constructing a fuzzer with [`FuzzFailure::seed`].
+#[derive(Clone, Debug)]
+pub struct EditorFuzzer {
+    seed: u64,
+    state: u64,
+}
+
+impl EditorFuzzer {
+    /// Creates a new fuzzer with the given seed.
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    /// Generates the next random operation for `editor`.
+    ///
+    /// The generated operation is always valid for the editor's current state.
+    pub fn next_op<T>(&mut self, editor: &PlainEditor<T>) -> EditOp
+    where
+        T: Brush + Clone + Debug + PartialEq + Default,
+    {
+        if editor.is_composing() {
+            return match self.below(4) {
+                0 => EditOp::ClearCompose,
+                1 => EditOp::CommitCompose(self.grapheme_string()),
+                _ => self.compose_op(),
+            };
+        }
+        let len = editor.raw_text().len();
+        match self.below(30) {
+            0..=7 => EditOp::Insert(self.grapheme_string()),
+            8 => EditOp::Delete,
+            9 => EditOp::Backdelete,
+            10 => EditOp::DeleteWord,
+            11 => EditOp::BackdeleteWord,
+            12 => EditOp::MoveLeft,
+            13 => EditOp::MoveRight,
+            14 => EditOp::MoveUp,
+            15 => EditOp::MoveDown,
+            16 => match self.below(2) {
+                0 => EditOp::MoveWordLeft,
+                _ => EditOp::MoveWordRight,
+            },
+            17 => match self.below(4) {
+                0 => EditOp::MoveToLineStart,
+                1 => EditOp::MoveToLineEnd,
+                2 => EditOp::MoveToTextStart,
+                _ => EditOp::MoveToTextEnd,
+            },
+            18 => EditOp::MoveToPoint(self.coordinate(), self.coordinate()),
+            19 => match self.below(4) {
+                0 => EditOp::SelectLeft,
+                1 => EditOp::SelectRight,
+                2 => EditOp::SelectUp,
+                _ => EditOp::SelectDown,
+            },
+            20 => match self.below(2) {
+                0 => EditOp::SelectWordLeft,
+                _ => EditOp::SelectWordRight,
+            },
+            21 => EditOp::SelectAll,
+            22 => EditOp::CollapseSelection,
+            23 => EditOp::ExtendSelectionToPoint(self.coordinate(), self.coordinate()),
+            24 => {
+                let start = self.below(len + 1);
+                let end = self.below(len + 1);
+                EditOp::SelectByteRange(start, end)
+            }
+            25 | 26 => self.compose_op(),
+            27 => EditOp::CommitCompose(self.grapheme_string()),
+            _ => EditOp::SetWidth(WIDTHS[self.below(WIDTHS.len())]),
+        }
+    }
+
+    /// Applies `steps` random operations to the editor behind `driver`, checking
+    /// [`check_editor_invariants`] after each of them.
+    pub fn run<T>(
+        &mut self,
+        driver: &mut PlainEditorDriver<'_, T>,
+        steps: usize,
+    ) -> Result<(), FuzzFailure>
+    where
+        T: Brush + Clone + Debug + PartialEq + Default,
+    {
+        for step in 0..steps {
+            let op = self.next_op(driver.editor);
+            let text_before = driver.editor.raw_text().to_string();
+            let expected = op.expected_text(driver.editor);
+            let fail = |message: String| FuzzFailure {
+                seed: self.seed,
+                step,
+                op: op.clone(),
+                text_before: text_before.clone(),
+                message,
+            };
+            op.apply(driver);
+            check_editor_invariants(driver).map_err(&fail)?;
+            let text = driver.editor.text().to_string();
+            if let Some(expected) = expected {
+                if text != expected {
+                    return Err(fail(format!("expected text {expected:?}, found {text:?}")));
+                }
+            } else if !is_removal(&text_before, &text) {
+                return Err(fail(format!(
+                    "deletion produced {text:?}, which is not a contiguous removal"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn compose_op(&mut self) -> EditOp {
+        let mut text = self.grapheme_string();
+        if self.below(2) == 0 {
+            text.push_str(self.grapheme());
+        }
+        let cursor = match self.below(3) {
+            0 => None,
+            1 => Some((text.len(), text.len())),
+            _ => Some((0, text.len())),
+        };
+        EditOp::SetCompose(text, cursor)
+    }
+
+    fn grapheme(&mut self) -> &'static str {
+        GRAPHEMES[self.below(GRAPHEMES.len())]
+    }
+
+    fn grapheme_string(&mut self) -> String {
+        self.grapheme().to_string()
+    }
+
+    fn coordinate(&mut self) -> f32 {
+        self.below(300) as f32 - 50.
+    }
+
+    /// Returns a random number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// SplitMix64.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Returns `true` if `after` is `before` with a single contiguous range removed.
+fn is_removal(before: &str, after: &str) -> bool {
+    if after.len() > before.len() {
+        return false;
+    }
+    let prefix = before
+        .bytes()
+        .zip(after.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let removed = before.len() - after.len();
+    before.get(prefix + removed..) == after.get(prefix..)
+}
+
+/// Checks the invariants of the editor behind `driver`, refreshing its layout first.
+///
+/// The following invariants are checked:
+/// - the layout was built from exactly the editor text, and its lines are contiguous;
+/// - both selection endpoints lie on grapheme cluster boundaries;
+/// - the caret geometry lies within the bounds of the layout;
+/// - [`PlainEditor::selected_text`] matches the selected range.
+pub fn check_editor_invariants<T>(driver: &mut PlainEditorDriver<'_, T>) -> Result<(), String>
+where
+    T: Brush + Clone + Debug + PartialEq + Default,
+{
+    driver.refresh_layout();
+    let editor = &*driver.editor;
+    let Some(layout) = editor.try_layout() else {
+        return Err("layout is dirty after refresh".to_string());
+    };
+    let text = editor.raw_text();
+
+    if layout.data.text.as_str() != text || layout.data.text_len != text.len() {
+        return Err(format!(
+            "layout text {:?} does not match editor text {text:?}",
+            layout.data.text.as_str()
+        ));
+    }
+    let mut line_start = 0;
+    for (index, line) in layout.lines().enumerate() {
+        let range = line.text_range();
+        if range.start != line_start || !is_char_range(text, &range) {
+            return Err(format!("line {index} has invalid text range {range:?}"));
+        }
+        line_start = range.end;
+    }
+
+    let selection = editor.raw_selection();
+    for (name, cursor) in [("anchor", selection.anchor()), ("focus", selection.focus())] {
+        let index = cursor.index();
+        if !is_grapheme_boundary(layout, text, index) {
+            return Err(format!(
+                "selection {name} at {index} is not on a grapheme cluster boundary"
+            ));
+        }
+    }
+
+    if let Some(caret) = editor.cursor_geometry(1.0) {
+        let full_width = layout.full_width() as f64;
+        let width = editor.width.unwrap_or(0.) as f64;
+        let min_x = (layout.width() as f64 - full_width).min(0.) - EPSILON;
+        let max_x = full_width.max(width) + 1.0 + EPSILON;
+        let max_y = layout.height() as f64 + EPSILON;
+        if caret.x0 < min_x || caret.x1 > max_x || caret.y0 < -EPSILON || caret.y1 > max_y {
+            return Err(format!(
+                "caret {caret:?} lies outside of the layout ({} x {})",
+                layout.full_width(),
+                layout.height()
+            ));
+        }
+    }
+
+    let expected = if editor.is_composing() || selection.is_collapsed() {
+        None
+    } else {
+        text.get(selection.text_range())
+    };
+    if editor.selected_text() != expected {
+        return Err(format!(
+            "selected text {:?} does not match the selected range {:?}",
+            editor.selected_text(),
+            selection.text_range()
+        ));
+    }
+
+    Ok(())
+}
+
+fn is_char_range(text: &str, range: &Range<usize>) -> bool {
+    range.start <= range.end
+        && text.is_char_boundary(range.start)
+        && text.is_char_boundary(range.end)
+}
+
+fn is_grapheme_boundary<B: Brush>(layout: &Layout<B>, text: &str, index: usize) -> bool {
+    if index == text.len() {
+        return true;
+    }
+    text.is_char_boundary(index)
+        && Cluster::from_byte_index(layout, index).is_some_and(|cluster| {
+            cluster.text_range().start == index && !cluster.is_grapheme_continuation()
+        })
+}
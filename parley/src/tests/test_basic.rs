@@ -1,10 +1,25 @@
 // Copyright 2024 the Parley Authors
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
+use core::ops::Range;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+
+use fontique::{Blob, FallbackKey, FontInfoOverride, GenericFamily};
+use peniko::color::palette::css;
 use peniko::kurbo::Size;
 
+use super::utils::{ColorBrush, TestEnv};
 use crate::data::LayoutData;
-use crate::{Alignment, AlignmentOptions, Brush, InlineBox, WhiteSpaceCollapse, testenv};
+use crate::{
+    Affinity, Alignment, AlignmentOptions, BoundarySpace, BreakReason, Brush, Cluster, Cursor,
+    EllipsisPosition, ExtraSpacing, Font, FontContext, FontError, FontFamily, FontStack, FontStyle,
+    FontWeight, FontWidth, GlyphOverride, HardBreak, HyphenationLimits, Hyphens, InlineBox,
+    JustifyContent, Layout, LayoutContext, LayoutWarning, LeadingBox, LinkData, OverflowWrap,
+    PositionedLayoutItem, ReplacementCharPolicy, SpacingRounding, StyleProperty, TabStops,
+    TextAnalysis, TextDirection, TextIndent, TextStyle, TextTransform, TruncationMarker,
+    Visibility, WhiteSpaceCollapse, YAxis, layout_label, layout_truncated_label, testenv,
+};
 
 #[test]
 fn plain_multiline_text() {
@@ -177,6 +192,62 @@ fn leading_whitespace() {
     }
 }
 
+#[test]
+fn collapse_across_pushes() {
+    let mut env = testenv!();
+
+    let mut build = |segments: &[&str]| {
+        let mut builder = env.tree_builder();
+        builder.set_white_space_mode(WhiteSpaceCollapse::Collapse);
+        for segment in segments {
+            builder.push_text(segment);
+        }
+        let (mut layout, text) = builder.build();
+        layout.break_all_lines(None);
+        (text, layout.width())
+    };
+    let (expected_text, expected_width) = build(&["a b"]);
+    for segments in [
+        &["a ", " b"][..],
+        &["a\n", "b"][..],
+        &["  a", "  ", " b  "][..],
+    ] {
+        let (text, width) = build(segments);
+        assert_eq!(text, expected_text, "collapsing {segments:?}");
+        assert_eq!(width, expected_width, "width of {segments:?}");
+    }
+
+    let mut builder = env.tree_builder();
+    builder.set_white_space_mode(WhiteSpaceCollapse::Collapse);
+    builder.push_text("a ");
+    builder.push_style_modification_span(&[StyleProperty::FontWeight(FontWeight::BOLD)]);
+    builder.push_text(" b ");
+    builder.pop_style_span();
+    builder.push_text(" c ");
+    let (_, text) = builder.build();
+    assert_eq!(text, "a b c", "collapsing across style spans");
+
+    let mut builder = env.tree_builder();
+    builder.set_white_space_mode(WhiteSpaceCollapse::Collapse);
+    builder.push_text("a ");
+    builder.push_inline_box(InlineBox {
+        id: 0,
+        index: 0,
+        width: 10.0,
+        height: 10.0,
+    });
+    builder.push_text("  b");
+    let (layout, text) = builder.build();
+    assert_eq!(
+        text, "a  b",
+        "an inline box separates white space like other characters"
+    );
+    assert_eq!(
+        layout.data.inline_boxes[0].index, 2,
+        "the inline box follows the first space"
+    );
+}
+
 #[test]
 fn base_level_alignment_ltr() {
     let mut env = testenv!();
@@ -362,6 +433,7 @@ fn realign_all() {
             None,
             AlignmentOptions {
                 align_when_overflowing: true,
+                ..Default::default()
             },
             "none",
             "awo_true",
@@ -370,6 +442,7 @@ fn realign_all() {
             None,
             AlignmentOptions {
                 align_when_overflowing: false,
+                ..Default::default()
             },
             "none",
             "awo_false",
@@ -460,3 +533,3920 @@ fn assert_eq_layout_alignments<B: Brush>(a: &LayoutData<B>, b: &LayoutData<B>, c
         );
     }
 }
+
+#[test]
+fn ignorable_marker_has_no_width() {
+    let mut env = testenv!();
+
+    let text = "Hello world";
+    let mut builder = env.ranged_builder(text);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    let expected_width = layout.width();
+
+    let text = "Hel\u{E000}lo world";
+    let mut builder = env.ranged_builder(text);
+    builder.push_ignorable_char('\u{E000}');
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    assert!(
+        (layout.width() - expected_width).abs() < 0.01,
+        "an ignorable character must not change the layout width"
+    );
+    let cluster = Cluster::from_byte_index(&layout, 3).unwrap();
+    assert_eq!(
+        cluster.text_range(),
+        3..6,
+        "ignorable text keeps its offsets"
+    );
+    assert_eq!(cluster.advance(), 0.0, "ignorable text has no advance");
+    assert_eq!(cluster.glyphs().count(), 0, "ignorable text has no glyphs");
+
+    let mut builder = env.ranged_builder(text);
+    builder.push_ignorable(3..6);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    assert!(
+        (layout.width() - expected_width).abs() < 0.01,
+        "an ignorable range must not change the layout width"
+    );
+
+    // Roboto kerns `AV`, which an ignorable character between them interrupts, as
+    // it is only stripped after shaping. The letters keep their own advances.
+    let mut width_of = |text: &str| {
+        let mut builder = env.ranged_builder(text);
+        builder.push_ignorable_char('\u{E000}');
+        let mut layout = builder.build(text);
+        layout.break_all_lines(None);
+        layout.width()
+    };
+    let unkerned = width_of("A") + width_of("V");
+    assert!(
+        width_of("AV") < unkerned - 0.01,
+        "the test font must kern the pair"
+    );
+    assert!(
+        (width_of("A\u{E000}V") - unkerned).abs() < 0.01,
+        "an ignorable character interrupts kerning but adds no width"
+    );
+}
+
+#[test]
+fn collapsed_replacement_chars() {
+    let mut env = testenv!();
+
+    let text = "log \u{FFFD} line";
+    let mut builder = env.ranged_builder(text);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    let expected_width = layout.width();
+
+    let text = "log \u{FFFD}\u{FFFD}\u{FFFD} line";
+    let mut builder = env.ranged_builder(text);
+    builder.replacement_char_policy(ReplacementCharPolicy {
+        collapse: true,
+        brush: None,
+    });
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    assert!(
+        (layout.width() - expected_width).abs() < 0.01,
+        "a run of replacement characters must be as wide as a single one"
+    );
+    let first = Cluster::from_byte_index(&layout, 4).unwrap();
+    assert!(
+        first.advance() > 0.0,
+        "the first replacement character is shown"
+    );
+    for index in [7, 10] {
+        let cluster = Cluster::from_byte_index(&layout, index).unwrap();
+        assert_eq!(
+            cluster.text_range(),
+            index..index + 3,
+            "collapsed replacement characters keep their offsets"
+        );
+        assert_eq!(
+            cluster.advance(),
+            0.0,
+            "collapsed replacement characters have no advance"
+        );
+    }
+}
+
+#[test]
+fn hidden_and_redacted_ranges() {
+    let mut env = testenv!();
+
+    let mut width_of = |text: &str| {
+        let mut builder = env.ranged_builder(text);
+        let mut layout = builder.build(text);
+        layout.break_all_lines(None);
+        layout.width()
+    };
+    let without_word = width_of("Hello again");
+    let without_word_and_space = width_of("Hello  again");
+    let word = width_of("world");
+
+    let text = "Hello world again";
+    let mut builder = env.ranged_builder(text);
+    builder.push_visibility(6..12, Visibility::Hidden);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    assert!(
+        (layout.width() - without_word).abs() < 0.01,
+        "hidden text must not contribute to the width"
+    );
+    let cluster = Cluster::from_byte_index(&layout, 7).unwrap();
+    assert_eq!(cluster.text_range(), 7..8, "hidden text keeps its offsets");
+    assert_eq!(cluster.advance(), 0.0, "hidden text has no advance");
+    assert_eq!(
+        layout.lines().next().unwrap().redactions().count(),
+        0,
+        "hidden text is not redacted"
+    );
+
+    let mut builder = env.ranged_builder(text);
+    builder.push_visibility(6..11, Visibility::Redacted(50.0));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    assert!(
+        (layout.width() - (without_word_and_space + 50.0)).abs() < 0.01,
+        "redacted text must be as wide as the redaction"
+    );
+    let line = layout.lines().next().unwrap();
+    let redactions: Vec<_> = line.redactions().collect();
+    assert_eq!(redactions.len(), 1, "a redacted range produces one area");
+    assert!(
+        (redactions[0].width() - 50.0).abs() < 0.01,
+        "the redacted area has the width of the redaction"
+    );
+    assert!(
+        Cluster::from_byte_index(&layout, 6).unwrap().is_redacted(),
+        "the first redacted cluster stands in for the range"
+    );
+    let Some(PositionedLayoutItem::GlyphRun(last_run)) = line.items().last() else {
+        panic!("expected the line to end with a glyph run");
+    };
+    assert!(
+        (last_run.offset() + last_run.advance() - layout.width()).abs() < 0.01,
+        "glyphs after a redaction are offset by its width"
+    );
+
+    let mut builder = env.ranged_builder(text);
+    builder.push_visibility(0..17, Visibility::Hidden);
+    builder.push_visibility(6..11, Visibility::Visible);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    assert!(
+        (layout.width() - word).abs() < 0.01,
+        "later ranges take precedence"
+    );
+}
+
+#[test]
+fn lines_in_range() {
+    let mut env = testenv!();
+
+    let text = (0..20)
+        .map(|i| format!("Line {i}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut builder = env.ranged_builder(&text);
+    let mut layout = builder.build(&text);
+    layout.break_all_lines(None);
+
+    let line_height = layout.get(0).unwrap().metrics().line_height;
+    for (y0, y1) in [
+        (0.0, line_height),
+        (line_height * 2.5, line_height * 5.5),
+        (-10.0, line_height * 0.5),
+        (line_height * 18.0, line_height * 30.0),
+        (line_height * 30.0, line_height * 40.0),
+    ] {
+        let expected: Vec<usize> = layout
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.metrics().max_coord > y0 && line.metrics().min_coord < y1)
+            .map(|(index, _)| index)
+            .collect();
+        let visible: Vec<usize> = layout
+            .lines_in_range(y0, y1)
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(visible, expected, "lines visible between {y0} and {y1}");
+    }
+    assert_eq!(
+        layout
+            .lines_in_range(line_height * 2.5, line_height * 5.5)
+            .count(),
+        4,
+        "a window of three lines starting mid-line touches four lines"
+    );
+}
+
+#[test]
+fn line_for_y() {
+    let mut env = testenv!();
+
+    let text = (0..1000)
+        .map(|i| format!("Line {i}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut builder = env.ranged_builder(&text);
+    let mut layout = builder.build(&text);
+    layout.break_all_lines(None);
+    assert_eq!(layout.len(), 1000, "every hard break starts a line");
+
+    let linear_scan = |layout: &Layout<ColorBrush>, y: f32| {
+        layout
+            .lines()
+            .position(|line| line.metrics().min_coord <= y && y < line.metrics().max_coord)
+    };
+    let mut y = -5.0;
+    while y < layout.height() + 5.0 {
+        assert_eq!(
+            layout.line_for_y(y),
+            linear_scan(&layout, y),
+            "line containing {y}"
+        );
+        y += 7.3;
+    }
+    for line in layout.lines().step_by(97) {
+        let metrics = line.metrics();
+        let index = layout.line_for_y(metrics.min_coord);
+        assert_eq!(
+            index
+                .and_then(|index| layout.get(index))
+                .map(|line| line.text_range()),
+            Some(line.text_range()),
+            "a line boundary belongs to the later line"
+        );
+    }
+}
+
+#[test]
+fn origin_and_y_axis_round_trip() {
+    let mut env = testenv!();
+
+    let text = "First line\nSecond line\nThird";
+    let mut builder = env.ranged_builder(text);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    layout.align(None, Alignment::Start, AlignmentOptions::default());
+
+    let glyph_ys = |layout: &Layout<ColorBrush>| -> Vec<f32> {
+        layout
+            .lines()
+            .flat_map(|line| line.items())
+            .filter_map(|item| match item {
+                PositionedLayoutItem::GlyphRun(glyph_run) => Some(
+                    glyph_run
+                        .positioned_glyphs()
+                        .map(|glyph| glyph.y)
+                        .collect::<Vec<_>>(),
+                ),
+                PositionedLayoutItem::InlineBox(_) => None,
+            })
+            .flatten()
+            .collect()
+    };
+    let baselines: Vec<f32> = layout.lines().map(|line| line.metrics().baseline).collect();
+    let ys = glyph_ys(&layout);
+
+    for (origin_x, origin_y, y_axis) in [
+        (0.0, 0.0, YAxis::Down),
+        (15.0, 40.0, YAxis::Down),
+        (15.0, 200.0, YAxis::Up),
+    ] {
+        layout.set_origin(origin_x, origin_y);
+        layout.set_y_axis(y_axis);
+        let to_user_y = |y: f32| match y_axis {
+            YAxis::Down => origin_y + y,
+            YAxis::Up => origin_y - y,
+        };
+        for (line, baseline) in layout.lines().zip(&baselines) {
+            assert_eq!(
+                line.metrics().baseline,
+                to_user_y(*baseline),
+                "line baseline with {y_axis:?}"
+            );
+        }
+        for (y, expected) in glyph_ys(&layout).into_iter().zip(&ys) {
+            assert_eq!(y, to_user_y(*expected), "glyph position with {y_axis:?}");
+        }
+        for index in [0, 3, 11, 15, 23, 26] {
+            let cursor = Cursor::from_byte_index(&layout, index, Affinity::Downstream);
+            let rect = cursor.geometry(&layout, 1.0);
+            let hit = Cursor::from_point(&layout, rect.x0 as f32, rect.center().y as f32);
+            assert_eq!(
+                hit.index(),
+                index,
+                "hit testing the caret at {index} with {y_axis:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn hermetic_layout_is_deterministic() {
+    fn font_data(dir: &str, file: &str) -> Blob<u8> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("assets")
+            .join(dir)
+            .join(file);
+        Blob::new(Arc::new(std::fs::read(path).unwrap()))
+    }
+
+    fn hermetic_context() -> FontContext {
+        let mut fcx =
+            FontContext::hermetic(font_data("noto_fonts", "NotoKufiArabic-Regular.otf")).unwrap();
+        fcx.collection
+            .register_fonts(font_data("roboto_fonts", "Roboto-Regular.ttf"), None);
+        fcx
+    }
+
+    fn layout_hash(fcx: &mut FontContext) -> u64 {
+        let text = "Hello \u{645}\u{631}\u{62D}\u{628}\u{627} world 123";
+        let mut lcx = LayoutContext::<ColorBrush>::new();
+        let mut builder = lcx.ranged_builder(fcx, text, 1.0);
+        builder.push_default(StyleProperty::FontStack(FontStack::Single(
+            FontFamily::Named("Roboto".into()),
+        )));
+        let mut layout = builder.try_build(text).unwrap();
+        layout.break_all_lines(Some(120.0));
+        layout.align(None, Alignment::Start, AlignmentOptions::default());
+
+        let mut hasher = DefaultHasher::new();
+        for line in layout.lines() {
+            line.text_range().hash(&mut hasher);
+            for item in line.items() {
+                let PositionedLayoutItem::GlyphRun(glyph_run) = item else {
+                    continue;
+                };
+                glyph_run.run().font().data.data().hash(&mut hasher);
+                for glyph in glyph_run.positioned_glyphs() {
+                    glyph.id.hash(&mut hasher);
+                    glyph.x.to_bits().hash(&mut hasher);
+                    glyph.y.to_bits().hash(&mut hasher);
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    assert_eq!(
+        layout_hash(&mut hermetic_context()),
+        layout_hash(&mut hermetic_context()),
+        "separately constructed hermetic contexts produce identical layouts"
+    );
+
+    let mut fcx = hermetic_context();
+    let mut lcx = LayoutContext::<ColorBrush>::new();
+    let text = "Serif";
+    let mut builder = lcx.ranged_builder(&mut fcx, text, 1.0);
+    builder.push_default(StyleProperty::FontStack(FontStack::Single(
+        FontFamily::Generic(GenericFamily::Serif),
+    )));
+    assert_eq!(
+        builder.try_build(text).err(),
+        Some(FontError::MissingGenericFamily(GenericFamily::Serif)),
+        "an unregistered generic family is an error"
+    );
+
+    assert_eq!(
+        FontContext::hermetic(Blob::new(Arc::new(Vec::new()))).err(),
+        Some(FontError::NoLastResortFont),
+        "a last-resort font is required"
+    );
+}
+
+#[test]
+fn paragraph_directions() {
+    let mut env = testenv!();
+
+    let text = "Hello\n\u{645}\u{631}\u{62D}\u{628}\u{627}\n123 world";
+    let mut builder = env.ranged_builder(text);
+    let layout = builder.build(text);
+    let directions: Vec<_> = layout.paragraph_directions().collect();
+    assert_eq!(
+        directions,
+        [
+            TextDirection::LeftToRight,
+            TextDirection::RightToLeft,
+            TextDirection::LeftToRight
+        ],
+        "each paragraph resolves its own base direction"
+    );
+    assert_eq!(
+        layout.base_direction(),
+        TextDirection::LeftToRight,
+        "the base direction is that of the first paragraph"
+    );
+
+    let mut builder = env.ranged_builder(text);
+    builder.set_base_direction(TextDirection::RightToLeft);
+    let layout = builder.build(text);
+    assert!(
+        layout
+            .paragraph_directions()
+            .all(|direction| direction == TextDirection::RightToLeft),
+        "a forced base direction applies to every paragraph"
+    );
+    assert!(
+        layout.is_rtl(),
+        "a forced base direction applies to the layout"
+    );
+}
+
+#[test]
+fn paragraph_cache_matches_uncached() {
+    fn signature(layout: &Layout<ColorBrush>) -> Vec<(Range<usize>, Vec<(u16, f32, f32)>)> {
+        let mut signature = Vec::new();
+        for line in layout.lines() {
+            for item in line.items() {
+                if let PositionedLayoutItem::GlyphRun(glyph_run) = item {
+                    let glyphs = glyph_run
+                        .positioned_glyphs()
+                        .map(|glyph| (glyph.id, glyph.x, glyph.y))
+                        .collect();
+                    signature.push((glyph_run.run().text_range(), glyphs));
+                }
+            }
+        }
+        signature
+    }
+
+    fn build(env: &mut TestEnv, text: &str) -> Vec<(Range<usize>, Vec<(u16, f32, f32)>)> {
+        let mut builder = env.ranged_builder(text);
+        builder.push(StyleProperty::FontWeight(FontWeight::BOLD), 6..15);
+        let mut layout = builder.build(text);
+        layout.break_all_lines(Some(100.0));
+        layout.align(None, Alignment::Start, AlignmentOptions::default());
+        signature(&layout)
+    }
+
+    let texts = [
+        "First paragraph\nSecond paragraph\n\u{645}\u{631}\u{62D}\u{628}\u{627} mixed\nLast",
+        "First paragraph\nSecond paragraph, edited\n\u{645}\u{631}\u{62D}\u{628}\u{627} mixed\nLast",
+    ];
+
+    let mut env = testenv!();
+    let uncached: Vec<_> = texts.iter().map(|text| build(&mut env, text)).collect();
+    env.layout_context().enable_paragraph_cache(16);
+    for _ in 0..2 {
+        for (text, expected) in texts.iter().zip(&uncached) {
+            assert_eq!(
+                &build(&mut env, text),
+                expected,
+                "cached layout of {text:?} differs from the uncached layout"
+            );
+        }
+    }
+}
+
+#[test]
+fn paragraph_cache_text_transform() {
+    let text = "hello world";
+    let glyph_ids = |env: &mut TestEnv, transform: TextTransform| {
+        let mut builder = env.ranged_builder(text);
+        builder.push_default(StyleProperty::TextTransform(transform));
+        let mut layout = builder.build(text);
+        layout.break_all_lines(None);
+        let mut ids = Vec::new();
+        for line in layout.lines() {
+            for run in line.runs() {
+                for cluster in run.visual_clusters() {
+                    ids.extend(cluster.glyphs().map(|glyph| glyph.id));
+                }
+            }
+        }
+        ids
+    };
+
+    let mut env = testenv!();
+    let uppercase = glyph_ids(&mut env, TextTransform::Uppercase);
+    env.layout_context().enable_paragraph_cache(16);
+    let lowercase = glyph_ids(&mut env, TextTransform::None);
+    assert_ne!(lowercase, uppercase, "the transform changes the glyphs");
+    assert_eq!(
+        glyph_ids(&mut env, TextTransform::Uppercase),
+        uppercase,
+        "the cached lowercase paragraph must not be reused for uppercase text"
+    );
+}
+
+#[test]
+fn paragraph_cache_reports_warnings() {
+    // None of the test fonts support Tibetan.
+    let text = "ab\n\u{0F00} c";
+    let warnings = |env: &mut TestEnv| env.ranged_builder(text).build(text).warnings().to_vec();
+
+    let mut env = testenv!();
+    env.layout_context().set_diagnostics(true);
+    let uncached = warnings(&mut env);
+    assert!(!uncached.is_empty(), "the unsupported text is reported");
+
+    env.layout_context().set_diagnostics(false);
+    env.layout_context().enable_paragraph_cache(16);
+    assert!(warnings(&mut env).is_empty(), "diagnostics are disabled");
+    env.layout_context().set_diagnostics(true);
+    for _ in 0..2 {
+        assert_eq!(
+            warnings(&mut env),
+            uncached,
+            "warnings don't depend on whether paragraphs are cached"
+        );
+    }
+}
+
+#[test]
+fn standalone_analysis_matches_layout() {
+    let mut env = testenv!();
+
+    let text = "Hello world\n\u{0645}\u{0631}\u{062D}\u{0628}\u{0627} 123 e\u{301}";
+    let analysis: TextAnalysis = env.layout_context().analyze(text);
+    assert_eq!(analysis.text_len(), text.len(), "analysis covers the text");
+    let mut builder = env.ranged_builder(text);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+
+    let clusters: Vec<_> = text
+        .char_indices()
+        .filter_map(|(offset, _)| Cluster::from_byte_index(&layout, offset))
+        .collect();
+    let mut cluster_starts: Vec<_> = clusters
+        .iter()
+        .map(|cluster| cluster.text_range().start)
+        .collect();
+    cluster_starts.dedup();
+    let graphemes: Vec<_> = analysis
+        .boundaries()
+        .filter(|(_, boundary)| boundary.grapheme)
+        .map(|(offset, _)| offset)
+        .collect();
+    assert_eq!(
+        graphemes, cluster_starts,
+        "graphemes must match the clusters of the layout"
+    );
+    for cluster in &clusters {
+        let start = cluster.text_range().start;
+        let boundary = analysis.boundary(start).unwrap();
+        assert_eq!(
+            boundary.word,
+            cluster.is_word_boundary(),
+            "word boundary at {start} must match the layout"
+        );
+        assert_eq!(
+            analysis.bidi_level(start).unwrap() & 1 == 1,
+            cluster.is_rtl(),
+            "direction at {start} must match the layout"
+        );
+    }
+    assert!(
+        analysis
+            .paragraphs()
+            .map(|(_, direction)| direction)
+            .eq(layout.paragraph_directions()),
+        "paragraph directions must match the layout"
+    );
+
+    let mut builder = env.ranged_builder(text);
+    let mut reused = builder.build_with_analysis(text, &analysis);
+    reused.break_all_lines(None);
+    for (offset, _) in text.char_indices() {
+        let expected = Cluster::from_byte_index(&layout, offset).unwrap();
+        let actual = Cluster::from_byte_index(&reused, offset).unwrap();
+        assert_eq!(
+            (actual.text_range(), actual.advance()),
+            (expected.text_range(), expected.advance()),
+            "building with an analysis must match building without one"
+        );
+    }
+}
+
+#[test]
+fn cluster_glyph_ranges() {
+    let mut env = testenv!();
+
+    // The test fonts don't cover Devanagari, so this uses the lam-alef ligature of
+    // Arabic, where two clusters share a single glyph.
+    let text = "\u{0644}\u{0627} abc";
+    let mut builder = env.ranged_builder(text);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+
+    let lam = Cluster::from_byte_index(&layout, 0).unwrap();
+    assert!(lam.is_ligature_start(), "lam-alef must form a ligature");
+    assert_eq!(lam.glyph_count(), 1, "the ligature is a single glyph");
+    let alef = Cluster::from_byte_index(&layout, 2).unwrap();
+    assert_eq!(
+        alef.glyph_count(),
+        0,
+        "the ligature continuation has no glyphs"
+    );
+    assert!(
+        alef.glyph_range().is_empty(),
+        "the ligature continuation has an empty glyph range"
+    );
+
+    for line in layout.lines() {
+        for run in line.runs() {
+            let mut next = 0;
+            for cluster in run.clusters() {
+                let range = cluster.glyph_range();
+                assert_eq!(range.start, next, "glyph ranges must be contiguous");
+                assert_eq!(
+                    range.len(),
+                    cluster.glyphs().count(),
+                    "glyph range must match the glyphs of the cluster"
+                );
+                next = range.end;
+            }
+        }
+    }
+}
+
+#[test]
+fn leading_box_narrows_first_lines() {
+    let mut env = testenv!();
+
+    let text = "word ".repeat(40);
+    let text = text.trim_end();
+    let mut builder = env.ranged_builder(text);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    let line_height = layout.lines().next().unwrap().metrics().line_height;
+
+    let (max_advance, box_width) = (200.0, 40.0);
+    let mut builder = env.ranged_builder(text);
+    builder.push_leading_box(LeadingBox {
+        id: 7,
+        index: 0,
+        width: box_width,
+        height: line_height * 2.5,
+    });
+    let mut layout = builder.build(text);
+    layout.break_all_lines(Some(max_advance));
+    layout.align(
+        Some(max_advance),
+        Alignment::Start,
+        AlignmentOptions::default(),
+    );
+
+    let lines: Vec<_> = layout.lines().map(|line| line.metrics()).collect();
+    assert!(lines.len() > 4, "the text must wrap past the box");
+    for (index, metrics) in lines[..3].iter().enumerate() {
+        assert!(
+            metrics.advance - metrics.trailing_whitespace <= max_advance - box_width,
+            "line {index} must be narrowed by the box"
+        );
+        assert_eq!(
+            metrics.offset, box_width,
+            "line {index} must start after the box"
+        );
+    }
+    assert!(
+        lines[3].advance - lines[3].trailing_whitespace > max_advance - box_width,
+        "the line below the box must use the full width"
+    );
+    assert_eq!(
+        lines[3].offset, 0.0,
+        "the line below the box starts at the edge"
+    );
+
+    let boxes: Vec<_> = layout.leading_boxes().collect();
+    assert_eq!(boxes.len(), 1, "the box must be reported");
+    let (id, rect) = boxes[0];
+    assert_eq!(id, 7, "the box keeps its id");
+    assert_eq!(
+        (rect.x0, rect.y0, rect.width()),
+        (0.0, 0.0, box_width as f64),
+        "the box is at the leading edge of the first line"
+    );
+
+    let first = &lines[0];
+    let (cluster, _) = Cluster::from_point(
+        &layout,
+        box_width + 1.0,
+        (first.min_coord + first.max_coord) / 2.0,
+    )
+    .unwrap();
+    assert_eq!(
+        cluster.text_range().start,
+        0,
+        "hit testing must account for the box"
+    );
+}
+
+#[test]
+fn hard_break_chars() {
+    let mut env = testenv!();
+
+    fn structure(layout: &Layout<ColorBrush>) -> (Vec<Range<usize>>, Vec<TextDirection>) {
+        (
+            layout.lines().map(|line| line.text_range()).collect(),
+            layout.paragraph_directions().collect(),
+        )
+    }
+    let ltr = TextDirection::LeftToRight;
+    let rtl = TextDirection::RightToLeft;
+
+    // U+2028 breaks the line without starting a new paragraph.
+    let text = "abc\u{2028}\u{0639}\u{0631}\u{0628}";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    assert_eq!(
+        structure(&layout),
+        (vec![0..6, 6..12], vec![ltr]),
+        "U+2028 must force a line break"
+    );
+
+    // U+2029 starts a new paragraph, with its own direction.
+    let text = "abc\u{2029}\u{0639}\u{0631}\u{0628}";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    assert_eq!(
+        structure(&layout),
+        (vec![0..6, 6..12], vec![ltr, rtl]),
+        "U+2029 must force a paragraph break"
+    );
+
+    // CRLF is a single break.
+    let text = "a\r\nb";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    assert_eq!(
+        structure(&layout),
+        (vec![0..3, 3..4], vec![ltr, ltr]),
+        "CRLF must be a single break"
+    );
+
+    // Caller-specified delimiters.
+    let text = "ab|cd;\u{0639}\u{0631}";
+    let mut builder = env.ranged_builder(text);
+    builder.push_hard_break_char('|', HardBreak::Line);
+    builder.push_hard_break_char(';', HardBreak::Paragraph);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    assert_eq!(
+        structure(&layout),
+        (vec![0..3, 3..6, 6..10], vec![ltr, rtl]),
+        "custom delimiters must force line and paragraph breaks"
+    );
+    let lines: Vec<_> = layout.lines().map(|line| line.metrics().advance).collect();
+    let mut plain = env.ranged_builder("ab").build("ab");
+    plain.break_all_lines(None);
+    assert_eq!(
+        lines[0],
+        plain.width(),
+        "a delimiter must contribute no advance"
+    );
+    assert_eq!(
+        layout.max_content_width(),
+        lines.iter().copied().fold(0.0, f32::max),
+        "content widths must account for the breaks"
+    );
+}
+
+#[test]
+fn decorations_do_not_split_glyph_runs() {
+    let mut env = testenv!();
+
+    let text = "one two three";
+    let glyph_runs = |layout: &Layout<ColorBrush>| {
+        layout
+            .lines()
+            .flat_map(|line| line.items().collect::<Vec<_>>())
+            .filter_map(|item| match item {
+                PositionedLayoutItem::GlyphRun(glyph_run) => Some(glyph_run),
+                PositionedLayoutItem::InlineBox(_) => None,
+            })
+            .map(|glyph_run| {
+                let spans: Vec<_> = glyph_run
+                    .style_spans()
+                    .map(|span| {
+                        (
+                            span.style.underline.is_some(),
+                            span.glyph_range,
+                            span.offset,
+                        )
+                    })
+                    .collect();
+                (glyph_run.positioned_glyphs().collect::<Vec<_>>(), spans)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut plain = env.ranged_builder(text).build(text);
+    plain.break_all_lines(None);
+    let plain = glyph_runs(&plain);
+
+    let mut builder = env.ranged_builder(text);
+    builder.push(StyleProperty::Underline(true), 4..7);
+    let mut underlined = builder.build(text);
+    underlined.break_all_lines(None);
+    let underlined = glyph_runs(&underlined);
+
+    assert_eq!(plain.len(), 1, "the plain text must be a single glyph run");
+    assert_eq!(
+        underlined.len(),
+        1,
+        "an underline must not split the glyph run"
+    );
+    let (plain_glyphs, _) = &plain[0];
+    let (glyphs, spans) = &underlined[0];
+    assert_eq!(
+        glyphs.iter().map(|g| (g.id, g.x)).collect::<Vec<_>>(),
+        plain_glyphs.iter().map(|g| (g.id, g.x)).collect::<Vec<_>>(),
+        "an underline must not change the glyphs"
+    );
+    assert_eq!(
+        spans
+            .iter()
+            .map(|(underline, range, _)| (*underline, range.clone()))
+            .collect::<Vec<_>>(),
+        vec![(false, 0..4), (true, 4..7), (false, 7..13)],
+        "the underlined word must be its own span"
+    );
+    assert_eq!(
+        spans[1].2, glyphs[4].x,
+        "the span must start at its first glyph"
+    );
+}
+
+#[test]
+fn slice_lines() {
+    let mut env = testenv!();
+
+    let text = (0..1000)
+        .map(|i| format!("Line {i}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut builder = env.ranged_builder(&text);
+    let mut layout = builder.build(&text);
+    layout.break_all_lines(None);
+
+    let summary =
+        |line: crate::Line<'_, ColorBrush>| (line.index(), line.text_range(), line.y_range());
+    let sliced: Vec<_> = layout.slice_lines(300..340).map(summary).collect();
+    let expected: Vec<_> = layout.lines().skip(300).take(40).map(summary).collect();
+    assert_eq!(sliced, expected, "a slice must match full iteration");
+    assert_eq!(sliced[0].0, 300, "a slice keeps the indices of the lines");
+
+    assert_eq!(
+        layout.slice_lines(990..2000).count(),
+        10,
+        "a slice is clamped to the layout"
+    );
+    assert_eq!(
+        layout.slice_lines(2000..3000).count(),
+        0,
+        "a slice past the end is empty"
+    );
+
+    let last = layout.get(999).unwrap().y_range();
+    assert_eq!(
+        last.end,
+        layout.height(),
+        "the last line ends at the bottom"
+    );
+    let first = layout.get(0).unwrap().y_range();
+    assert_eq!(first.start, 0.0, "the first line starts at the top");
+}
+
+#[test]
+fn wrapped_link() {
+    let mut env = testenv!();
+
+    let text = "Visit the documentation website for details";
+    let link_range = 10..31;
+    let mut builder = env.ranged_builder(text);
+    builder.push_link(42, link_range.clone());
+    let mut layout = builder.build(text);
+    // Wrap after "documentation", so that the link spans two lines.
+    let mut first_line = env.ranged_builder(&text[..23]).build(&text[..23]);
+    first_line.break_all_lines(None);
+    let max_advance = Some(first_line.width() + 1.0);
+    layout.break_all_lines(max_advance);
+    layout.align(max_advance, Alignment::Start, AlignmentOptions::default());
+
+    let link = &layout.links()[0];
+    assert_eq!(link.text_range, link_range, "the link keeps its text range");
+    let rects = layout.link_rects(link);
+    assert_eq!(rects.len(), 2, "the link must wrap onto two lines");
+    assert!(
+        rects[0].y1 <= rects[1].y0,
+        "the fragments are on separate lines"
+    );
+    for rect in &rects {
+        let center = rect.center();
+        assert_eq!(
+            layout
+                .link_at_point(center.x as f32, center.y as f32)
+                .map(|link| link.id),
+            Some(42),
+            "the link must be found on each of its fragments"
+        );
+    }
+
+    let first_line = layout.get(0).unwrap().metrics();
+    let y = (first_line.min_coord + first_line.max_coord) / 2.0;
+    assert_eq!(
+        layout.link_at_point(1.0, y),
+        None,
+        "text outside of the link has no link"
+    );
+}
+
+#[test]
+fn numeric_alignment() {
+    let mut env = testenv!();
+
+    let column_width = 200.0;
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+    let align_column = |env: &mut TestEnv, values: &[&str], separator: char| {
+        let mut layouts: Vec<_> = values
+            .iter()
+            .map(|value| {
+                let mut layout = env.ranged_builder(value).build(value);
+                layout.break_all_lines(None);
+                layout
+            })
+            .collect();
+        // The first pass finds the separator position which fits every cell.
+        let position = layouts
+            .iter()
+            .filter_map(|layout| layout.get(0)?.separator_advance(separator))
+            .fold(0.0, f32::max);
+        for layout in &mut layouts {
+            layout.align(
+                Some(column_width),
+                Alignment::Numeric { separator },
+                AlignmentOptions {
+                    numeric_position: position,
+                    ..AlignmentOptions::default()
+                },
+            );
+        }
+        (layouts, position)
+    };
+
+    let values = ["1.5", "-12.25", "100", "0.125", "n/a"];
+    let (layouts, position) = align_column(&mut env, &values, '.');
+    assert!(position > 0.0, "the column must have a separator position");
+    for (value, layout) in values.iter().zip(&layouts) {
+        let line = layout.get(0).unwrap();
+        let offset = line.metrics().offset;
+        match line.separator_advance('.') {
+            Some(advance) => assert!(
+                close(offset + advance, position),
+                "{value} must be aligned on its separator"
+            ),
+            None => assert!(
+                close(offset + line.metrics().advance, column_width),
+                "{value} has no digits, so it must be end aligned"
+            ),
+        }
+    }
+    let advance = |index: usize| layouts[index].get(0).unwrap().separator_advance('.');
+    assert!(
+        advance(1) > advance(0),
+        "the minus sign and extra digit come before the separator"
+    );
+    let integer = layouts[2].get(0).unwrap();
+    assert!(
+        close(
+            integer.separator_advance('.').unwrap(),
+            integer.metrics().advance
+        ),
+        "an integer is aligned on the end of its digits"
+    );
+    assert_eq!(advance(4), None, "text without digits has no separator");
+
+    // Locales which use a decimal comma.
+    let values = ["1.234,5", "12,25"];
+    let (layouts, position) = align_column(&mut env, &values, ',');
+    for layout in &layouts {
+        let line = layout.get(0).unwrap();
+        assert!(
+            close(
+                line.metrics().offset + line.separator_advance(',').unwrap(),
+                position
+            ),
+            "decimal commas must be aligned"
+        );
+    }
+
+    // The position is measured from the start of the content area, after the indent.
+    let indent = 30.0;
+    let mut builder = env.ranged_builder("12.5");
+    builder.set_text_indent(TextIndent {
+        amount: indent,
+        each_paragraph: false,
+    });
+    let mut layout = builder.build("12.5");
+    layout.break_all_lines(None);
+    layout.align(
+        Some(column_width),
+        Alignment::Numeric { separator: '.' },
+        AlignmentOptions {
+            numeric_position: 50.0,
+            ..AlignmentOptions::default()
+        },
+    );
+    let line = layout.get(0).unwrap();
+    assert!(
+        close(
+            line.metrics().offset + line.separator_advance('.').unwrap(),
+            indent + 50.0
+        ),
+        "the indent must be added to the separator position"
+    );
+}
+
+#[test]
+fn glyph_override() {
+    let mut env = testenv!();
+
+    let text = "A";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    let cluster = Cluster::from_byte_index(&layout, 0).unwrap();
+    let font = cluster.run().font().clone();
+    let glyph_id = cluster.glyphs().next().unwrap().id;
+
+    env.layout_context().set_glyph_override(
+        '\u{E000}',
+        GlyphOverride {
+            font: font.clone(),
+            glyph_id,
+            advance: 0.75,
+        },
+    );
+    let text = "x\u{E000}y";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    let cluster = Cluster::from_byte_index(&layout, 1).unwrap();
+    let glyphs: Vec<_> = cluster.glyphs().collect();
+    assert_eq!(glyphs.len(), 1, "the override is a single glyph");
+    assert_eq!(glyphs[0].id, glyph_id, "the override glyph must be used");
+    assert!(
+        (cluster.advance() - 0.75 * 16.0).abs() < 1e-3,
+        "the advance scales with the font size"
+    );
+    assert_eq!(
+        cluster.run().font(),
+        &font,
+        "the glyph comes from the override font"
+    );
+    let y = Cluster::from_byte_index(&layout, 4).unwrap();
+    assert_ne!(
+        y.glyphs().next().unwrap().id,
+        glyph_id,
+        "other characters are shaped"
+    );
+
+    let removed = env.layout_context().remove_glyph_override('\u{E000}');
+    assert!(
+        removed.is_some(),
+        "the override must be returned when removed"
+    );
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    let cluster = Cluster::from_byte_index(&layout, 1).unwrap();
+    assert_ne!(
+        cluster.glyphs().next().map(|glyph| glyph.id),
+        Some(glyph_id),
+        "the character is shaped normally once the override is removed"
+    );
+}
+
+#[test]
+fn width_delta() {
+    let mut env = testenv!();
+
+    let text = "Hello world";
+    let build = |env: &mut TestEnv| {
+        let mut builder = env.ranged_builder(text);
+        builder.push(StyleProperty::FontWeight(FontWeight::BOLD), 6..11);
+        builder.push(StyleProperty::FontSize(24.0), 6..11);
+        builder.build(text)
+    };
+    let layout = build(&mut env);
+    let (fcx, lcx) = env.contexts();
+    let delta = lcx.width_delta(fcx, &layout, 6..11, StyleProperty::LetterSpacing(2.0));
+    assert!(
+        delta.is_some_and(|delta| (delta - 10.0).abs() < 1e-3),
+        "letter spacing widens each of the five characters, got {delta:?}"
+    );
+    let delta = lcx.width_delta(fcx, &layout, 6..11, StyleProperty::FontSize(32.0));
+    assert!(
+        delta.is_some_and(|delta| delta > 0.0),
+        "a larger font size must widen the range"
+    );
+    // The bold text of the layout is measured, rather than the default style.
+    let delta = lcx.width_delta(fcx, &layout, 6..11, StyleProperty::FontSize(24.0));
+    assert!(
+        delta.is_some_and(|delta| delta.abs() < 1e-3),
+        "an unchanged style has no delta, got {delta:?}"
+    );
+    let delta = lcx.width_delta(
+        fcx,
+        &layout,
+        6..11,
+        StyleProperty::FontWeight(FontWeight::BOLD),
+    );
+    assert!(
+        delta.is_some_and(|delta| delta.abs() < 1e-3),
+        "bolding bold text has no delta, got {delta:?}"
+    );
+    let delta = lcx.width_delta(
+        fcx,
+        &layout,
+        0..5,
+        StyleProperty::FontWeight(FontWeight::BOLD),
+    );
+    assert!(delta.is_some(), "the regular text can be bolded");
+    let delta = lcx.width_delta(fcx, &layout, 6..6, StyleProperty::FontSize(32.0));
+    assert_eq!(delta, Some(0.0), "an empty range has no delta");
+
+    // The styles of the layout are lost once another layout is built.
+    let other = env.ranged_builder(text).build(text);
+    let (fcx, lcx) = env.contexts();
+    assert_eq!(
+        lcx.width_delta(fcx, &layout, 6..11, StyleProperty::FontSize(32.0)),
+        None,
+        "a layout which isn't the most recent build can't be measured"
+    );
+    assert!(
+        lcx.width_delta(fcx, &other, 6..11, StyleProperty::FontSize(32.0))
+            .is_some(),
+        "the most recent build can be measured"
+    );
+}
+
+#[test]
+fn unloadable_font_warning() {
+    let mut env = testenv!();
+
+    let text = "axb";
+    let layout = env.ranged_builder(text).build(text);
+    assert!(layout.warnings().is_empty(), "the test fonts load");
+
+    // A copy of Roboto whose character map is truncated to its header.
+    let mut data = std::fs::read(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("assets")
+            .join("roboto_fonts")
+            .join("Roboto-Regular.ttf"),
+    )
+    .unwrap();
+    let num_tables = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let record = (0..num_tables)
+        .map(|index| 12 + 16 * index)
+        .find(|record| &data[*record..*record + 4] == b"cmap")
+        .unwrap();
+    data[record + 12..record + 16].copy_from_slice(&4_u32.to_be_bytes());
+    let (fcx, _) = env.contexts();
+    fcx.collection.register_fonts(
+        Blob::new(Arc::new(data)),
+        Some(FontInfoOverride {
+            family_name: Some("Truncated Roboto"),
+            ..Default::default()
+        }),
+    );
+
+    // The text falls back to the next font in the stack.
+    let mut builder = env.ranged_builder(text);
+    builder.push(
+        StyleProperty::FontStack(FontStack::from("Truncated Roboto, Roboto")),
+        1..2,
+    );
+    let layout = builder.build(text);
+    let [LayoutWarning::FontLoadFailed { font, text_range }] = layout.warnings() else {
+        panic!("the font which failed to load must be reported");
+    };
+    assert_eq!(text_range, &(1..2), "only the text of the font is affected");
+    let cluster = Cluster::from_byte_index(&layout, 1).unwrap();
+    assert_ne!(cluster.run().font(), font, "the text must fall back");
+    assert_ne!(
+        cluster.glyphs().next().unwrap().id,
+        0,
+        "the fallback font has a glyph for the text"
+    );
+
+    // Without any other font in the layout, the text is shaped with the notdef glyph
+    // of a generic family rather than dropped.
+    let (fcx, _) = env.contexts();
+    let roboto = fcx.collection.family_by_name("Roboto").unwrap().id();
+    fcx.collection
+        .set_generic_families(GenericFamily::SansSerif, core::iter::once(roboto));
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontStack(FontStack::from(
+        "Truncated Roboto",
+    )));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    let [
+        LayoutWarning::FontLoadFailed { font, text_range },
+        LayoutWarning::NoUsableFont {
+            text_range: unusable_range,
+        },
+    ] = layout.warnings()
+    else {
+        panic!("the text must be reported as having no usable font");
+    };
+    assert_eq!(
+        text_range,
+        &(0..3),
+        "all of the text would have used the font"
+    );
+    assert_eq!(
+        unusable_range,
+        &(0..3),
+        "none of the text has a usable font"
+    );
+    let cluster = Cluster::from_byte_index(&layout, 1).unwrap();
+    assert_ne!(cluster.run().font(), font, "the broken font is not used");
+    assert_eq!(cluster.glyphs().next().unwrap().id, 0, "notdef is shown");
+    assert!(layout.width() > 0.0, "the text must still occupy space");
+}
+
+#[test]
+fn shaped_text_breaks_at_several_widths() {
+    let mut env = testenv!();
+
+    let text = "Shaped once and broken into lines at several candidate widths";
+    let shaped = env.ranged_builder(text).shape(text);
+    let line_ranges = |layout: &Layout<ColorBrush>| {
+        layout
+            .lines()
+            .map(|line| line.text_range())
+            .collect::<Vec<_>>()
+    };
+    for width in [80.0, 150.0, 300.0] {
+        let layout = shaped.break_and_align(Some(width), Alignment::End);
+        let mut expected = env.ranged_builder(text).build(text);
+        expected.break_all_lines(Some(width));
+        expected.align(Some(width), Alignment::End, AlignmentOptions::default());
+        assert_eq!(
+            line_ranges(&layout),
+            line_ranges(&expected),
+            "breaking shaped text at {width} must match a full build"
+        );
+        assert_eq!(
+            layout.get(0).unwrap().metrics().offset,
+            expected.get(0).unwrap().metrics().offset,
+            "shaped text at {width} must be aligned like a full build"
+        );
+    }
+    let layout = shaped.break_and_align(None, Alignment::Start);
+    assert_eq!(layout.len(), 1, "without a width the text is a single line");
+    assert_eq!(
+        shaped.max_content_width(),
+        layout.width(),
+        "the max content width is the width of the unbroken text"
+    );
+
+    let (fcx, lcx) = env.contexts();
+    let shaped = lcx.shape(
+        fcx,
+        text,
+        1.0,
+        [(
+            StyleProperty::FontStack(FontStack::from("Roboto")),
+            0..text.len(),
+        )],
+    );
+    assert_eq!(
+        line_ranges(&shaped.break_and_align(Some(150.0), Alignment::Start)),
+        line_ranges(&{
+            let mut expected = env.ranged_builder(text).build(text);
+            expected.break_all_lines(Some(150.0));
+            expected
+        }),
+        "shaping with the context matches the builder"
+    );
+}
+
+#[test]
+fn line_break_reasons() {
+    let mut env = testenv!();
+
+    let prefix = "Hyphen\u{AD}";
+    let prefix_width = env.ranged_builder(prefix).build(prefix).max_content_width();
+    let text = "Hyphen\u{AD}ation wraps softly\nIncomprehensibilities\nEnd";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::OverflowWrap(OverflowWrap::Anywhere));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(Some(prefix_width + 1.0));
+
+    let reasons: Vec<_> = layout.lines().map(|line| line.break_reason()).collect();
+    let line_text = |index: usize| &text[layout.get(index).unwrap().text_range()];
+    assert_eq!(
+        reasons[0],
+        BreakReason::Hyphenated,
+        "the first line ends at the soft hyphen"
+    );
+    assert_eq!(
+        line_text(0),
+        prefix,
+        "the first line ends at the soft hyphen"
+    );
+    assert!(
+        reasons.contains(&BreakReason::Regular),
+        "the words wrap at spaces: {reasons:?}"
+    );
+    assert!(
+        reasons.contains(&BreakReason::Emergency),
+        "the long word must be broken: {reasons:?}"
+    );
+    for (index, reason) in reasons.iter().enumerate() {
+        assert_eq!(
+            *reason == BreakReason::Explicit,
+            line_text(index).ends_with('\n'),
+            "only lines ending with a newline are explicit breaks: {reasons:?}"
+        );
+    }
+    assert_eq!(
+        reasons
+            .iter()
+            .filter(|r| **r == BreakReason::Explicit)
+            .count(),
+        2,
+        "each newline is an explicit break"
+    );
+    assert_eq!(
+        reasons.last(),
+        Some(&BreakReason::None),
+        "the last line ends at the end of the text"
+    );
+}
+
+#[test]
+fn text_indent_rtl() {
+    let mut env = testenv!();
+
+    let text = "سلام ".repeat(30);
+    let text = text.trim_end();
+    let max_advance = 200.0;
+    for amount in [30.0, -30.0] {
+        let mut builder = env.ranged_builder(text);
+        builder.set_text_indent(TextIndent {
+            amount,
+            each_paragraph: false,
+        });
+        let mut layout = builder.build(text);
+        layout.break_all_lines(Some(max_advance));
+        layout.align(
+            Some(max_advance),
+            Alignment::Start,
+            AlignmentOptions::default(),
+        );
+
+        assert!(layout.is_rtl(), "the paragraph must be right-to-left");
+        let lines: Vec<_> = layout.lines().map(|line| line.metrics()).collect();
+        assert!(lines.len() > 2, "the text must wrap");
+        let first = lines[0];
+        assert!(
+            first.advance - first.trailing_whitespace <= max_advance - amount,
+            "the first line must fit the width left by the indent of {amount}"
+        );
+        assert!(
+            (first.offset + first.advance - (max_advance - amount)).abs() < 0.01,
+            "the first line must end at the indent of {amount} from the right edge"
+        );
+        let second = lines[1];
+        assert!(
+            (second.offset + second.advance - max_advance).abs() < 0.01,
+            "the second line must not be indented"
+        );
+    }
+}
+
+/// Builds a single-line layout of `text` with an inline box before each `c` and `e`.
+fn layout_with_two_boxes(env: &mut TestEnv, text: &str) -> Layout<ColorBrush> {
+    let mut builder = env.ranged_builder(text);
+    for (id, ch) in ['c', 'e'].into_iter().enumerate() {
+        builder.push_inline_box(InlineBox {
+            id: id as u64,
+            index: text.find(ch).unwrap(),
+            width: 10.0,
+            height: 10.0,
+        });
+    }
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    layout.align(None, Alignment::Start, AlignmentOptions::default());
+    layout
+}
+
+#[test]
+fn accessible_text_utf16_round_trip() {
+    let mut env = testenv!();
+
+    let text = "a😀 cd ef";
+    let layout = layout_with_two_boxes(&mut env, text);
+    let accessible_text = layout.accessible_text(text);
+    assert_eq!(
+        accessible_text, "a😀 \u{FFFC}cd \u{FFFC}ef",
+        "each inline box is a U+FFFC before the text at its index"
+    );
+
+    for index in (0..=text.len()).filter(|index| text.is_char_boundary(*index)) {
+        let cursor = Cursor::from_byte_index(&layout, index, Affinity::Downstream);
+        let offset = layout.accessible_utf16_offset(text, cursor.index());
+        assert_eq!(
+            layout.byte_index_from_accessible_utf16(text, offset),
+            cursor.index(),
+            "the caret at byte {index} must round-trip through UTF-16 offset {offset}"
+        );
+    }
+
+    let first_box = text.find('c').unwrap();
+    let second_box = text.find('e').unwrap();
+    assert_eq!(
+        layout.accessible_utf16_offset(text, first_box),
+        5,
+        "a caret at a box is placed after its U+FFFC"
+    );
+    assert_eq!(
+        layout.accessible_utf16_offset(text, second_box),
+        9,
+        "offsets after a box include the boxes before it"
+    );
+    assert_eq!(
+        layout.accessible_utf16_offset(text, text.len()),
+        accessible_text.encode_utf16().count(),
+        "the end of the text maps to the end of the accessible text"
+    );
+    assert_eq!(
+        layout.byte_index_from_accessible_utf16(text, 4),
+        first_box,
+        "the offset before a U+FFFC maps to the index of its box"
+    );
+    assert_eq!(
+        layout.byte_index_from_accessible_utf16(text, 2),
+        1,
+        "an offset inside a surrogate pair moves back to the start of the character"
+    );
+}
+
+#[cfg(feature = "accesskit")]
+#[test]
+fn accessibility_inline_boxes() {
+    use accesskit::{Node, NodeId, Role, TreeUpdate};
+
+    let mut env = testenv!();
+
+    let text = "ab cd ef";
+    let layout = layout_with_two_boxes(&mut env, text);
+    let mut update = TreeUpdate {
+        nodes: vec![],
+        tree: None,
+        focus: NodeId(0),
+    };
+    let mut parent = Node::new(Role::TextInput);
+    let mut next_id = 0;
+    crate::LayoutAccessibility::default().build_nodes(
+        text,
+        &layout,
+        &mut update,
+        &mut parent,
+        || {
+            next_id += 1;
+            NodeId(next_id)
+        },
+        0.,
+        0.,
+    );
+
+    let accessible_text = layout.accessible_text(text);
+    let character_lengths: usize = update
+        .nodes
+        .iter()
+        .flat_map(|(_, node)| node.character_lengths())
+        .map(|length| usize::from(*length))
+        .sum();
+    assert_eq!(
+        character_lengths,
+        accessible_text.len(),
+        "the character lengths must cover the accessible text"
+    );
+    let values: String = update
+        .nodes
+        .iter()
+        .filter_map(|(_, node)| node.value())
+        .collect();
+    assert_eq!(
+        values, accessible_text,
+        "the text runs must spell out the accessible text"
+    );
+}
+
+#[test]
+fn grapheme_at_point_with_slop() {
+    let mut env = testenv!();
+
+    let text = "abc def";
+    let mut builder = env.ranged_builder(text);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    layout.align(None, Alignment::Start, AlignmentOptions::default());
+
+    let cluster = Cluster::from_byte_index(&layout, 5).unwrap();
+    let left = cluster.visual_offset().unwrap();
+    let bottom = layout.height();
+    let (range, rect) = layout
+        .grapheme_at_point(left + cluster.advance() * 0.5, bottom + 3.0, 5.0)
+        .expect("a point just below the text is within the slop");
+    assert_eq!(range, 5..6, "the grapheme below the point is `e`");
+    assert!(
+        (rect.x0 - f64::from(left)).abs() < 0.01
+            && (rect.width() - f64::from(cluster.advance())).abs() < 0.01,
+        "the area covers the grapheme"
+    );
+    assert!(
+        (rect.y1 - f64::from(bottom)).abs() < 0.01,
+        "the area covers the line"
+    );
+
+    assert_eq!(
+        layout.grapheme_at_point(left, bottom + 3.0, 2.0),
+        None,
+        "a point outside the slop has no grapheme"
+    );
+    let (range, _) = layout
+        .grapheme_at_point(layout.width() + 2.0, bottom * 0.5, 5.0)
+        .expect("a point just after the text is within the slop");
+    assert_eq!(
+        range,
+        6..7,
+        "the grapheme nearest the end of the text is `f`"
+    );
+}
+
+/// Returns the text range and glyphs of each cluster in the layout, in logical order.
+fn cluster_glyphs(layout: &Layout<ColorBrush>) -> Vec<(Range<usize>, Vec<swash::GlyphId>)> {
+    let mut clusters = Vec::new();
+    for line in layout.lines() {
+        for run in line.runs() {
+            for cluster in run.clusters() {
+                let glyphs = cluster.glyphs().map(|glyph| glyph.id).collect();
+                clusters.push((cluster.text_range(), glyphs));
+            }
+        }
+    }
+    clusters
+}
+
+/// Returns the glyphs of `text` laid out with `transform` and `locale`.
+fn transformed_glyphs(
+    env: &mut TestEnv,
+    text: &str,
+    transform: TextTransform,
+    locale: Option<&str>,
+) -> Vec<(Range<usize>, Vec<swash::GlyphId>)> {
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::TextTransform(transform));
+    builder.push_default(StyleProperty::Locale(locale));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    cluster_glyphs(&layout)
+}
+
+#[test]
+fn text_transform_final_sigma() {
+    let mut env = testenv!();
+
+    let source = transformed_glyphs(&mut env, "ΟΔΥΣΣΕΥΣ", TextTransform::Lowercase, None);
+    let expected = transformed_glyphs(&mut env, "οδυσσευς", TextTransform::None, None);
+    assert_eq!(
+        source, expected,
+        "only the sigma at the end of the word takes the final form"
+    );
+}
+
+#[test]
+fn text_transform_keeps_source_ranges() {
+    let mut env = testenv!();
+
+    let source = transformed_glyphs(&mut env, "straße", TextTransform::Uppercase, None);
+    let expected = transformed_glyphs(&mut env, "STRASSE", TextTransform::None, None);
+    let ranges: Vec<_> = source.iter().map(|(range, _)| range.clone()).collect();
+    assert_eq!(
+        ranges,
+        [0..1, 1..2, 2..3, 3..4, 4..6, 6..7],
+        "each cluster keeps the range of its source grapheme"
+    );
+    assert_eq!(
+        source
+            .iter()
+            .flat_map(|(_, glyphs)| glyphs)
+            .collect::<Vec<_>>(),
+        expected
+            .iter()
+            .flat_map(|(_, glyphs)| glyphs)
+            .collect::<Vec<_>>(),
+        "`ß` is displayed as `SS`"
+    );
+}
+
+#[cfg(feature = "icu_casemap")]
+#[test]
+fn text_transform_turkish_uppercase() {
+    let mut env = testenv!();
+
+    let source = transformed_glyphs(&mut env, "istanbul", TextTransform::Uppercase, Some("tr"));
+    let expected = transformed_glyphs(&mut env, "İSTANBUL", TextTransform::None, Some("tr"));
+    let ranges: Vec<_> = source.iter().map(|(range, _)| range.clone()).collect();
+    assert_eq!(
+        ranges,
+        (0..8).map(|index| index..index + 1).collect::<Vec<_>>(),
+        "each cluster keeps the range of its source character"
+    );
+    assert_eq!(
+        source.iter().map(|(_, glyphs)| glyphs).collect::<Vec<_>>(),
+        expected
+            .iter()
+            .map(|(_, glyphs)| glyphs)
+            .collect::<Vec<_>>(),
+        "`i` uppercases to `İ` in Turkish"
+    );
+}
+
+#[test]
+fn merge_runs_with_matching_fonts() {
+    let mut env = testenv!();
+
+    let text = "Hello world";
+    let build = |env: &mut TestEnv| {
+        let mut builder = env.ranged_builder(text);
+        builder.push(StyleProperty::Locale(Some("en-US")), 0..6);
+        builder.push(StyleProperty::Locale(Some("en-GB")), 6..11);
+        let mut layout = builder.build(text);
+        layout.break_all_lines(None);
+        layout
+    };
+
+    let split = build(&mut env);
+    let split_ranges: Vec<_> = split
+        .lines()
+        .flat_map(|line| line.runs())
+        .map(|run| run.text_range())
+        .collect();
+    assert_eq!(
+        split_ranges,
+        [0..6, 6..11],
+        "a change of locale splits the text into two runs"
+    );
+
+    env.layout_context().set_merge_runs(true);
+    let merged = build(&mut env);
+    let merged_ranges: Vec<_> = merged
+        .lines()
+        .flat_map(|line| line.runs())
+        .map(|run| run.text_range())
+        .collect();
+    assert_eq!(merged_ranges, [0..11], "the runs are merged into one");
+    assert_eq!(
+        cluster_glyphs(&merged),
+        cluster_glyphs(&split),
+        "merging keeps the glyphs and the text of each cluster"
+    );
+    assert!(
+        (merged.width() - split.width()).abs() < 0.01,
+        "merging does not change the advance of the text"
+    );
+}
+
+#[test]
+fn line_brushes_are_distinct() {
+    let mut env = testenv!();
+
+    let text = "red green red";
+    let red = ColorBrush::new(css::RED);
+    let green = ColorBrush::new(css::GREEN);
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::Brush(red));
+    builder.push(StyleProperty::Brush(green), 4..10);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+
+    let line = layout.get(0).unwrap();
+    let brushes: Vec<_> = line.brushes().collect();
+    assert_eq!(
+        brushes,
+        [&red, &green],
+        "each brush on the line is listed once, in visual order"
+    );
+}
+
+#[test]
+fn justify_inter_character() {
+    let mut env = testenv!();
+
+    let text = "日本語の文章を均等に配置する";
+    let mut layout = env.ranged_builder(text).build(text);
+    let width = layout.max_content_width() * 0.6;
+    let glyph_xs = |layout: &Layout<ColorBrush>, line: usize| {
+        let mut xs = Vec::new();
+        for item in layout.get(line).unwrap().items() {
+            if let PositionedLayoutItem::GlyphRun(run) = item {
+                xs.extend(
+                    run.positioned_glyphs()
+                        .map(|glyph| (glyph.x, glyph.advance)),
+                );
+            }
+        }
+        xs
+    };
+
+    layout.break_all_lines(Some(width));
+    layout.align(
+        Some(width),
+        Alignment::Justified,
+        AlignmentOptions::default(),
+    );
+    let (x, advance) = *glyph_xs(&layout, 0).last().unwrap();
+    assert!(
+        x + advance < width - 1.0,
+        "inter-word justification has no spaces to adjust in CJK text"
+    );
+
+    for justify_content in [JustifyContent::InterCharacter, JustifyContent::Distribute] {
+        let options = AlignmentOptions {
+            justify_content,
+            ..Default::default()
+        };
+        layout.break_all_lines(Some(width));
+        layout.align(Some(width), Alignment::Justified, options);
+        let xs = glyph_xs(&layout, 0);
+        assert!(xs.len() > 2, "the first line has several glyphs");
+        let step = xs[1].0 - xs[0].0;
+        for pair in xs.windows(2) {
+            assert!(
+                (pair[1].0 - pair[0].0 - step).abs() < 0.01,
+                "the glyphs are evenly spread with {justify_content:?}: {xs:?}"
+            );
+        }
+        let (x, advance) = *xs.last().unwrap();
+        assert!(
+            (x + advance - width).abs() < 0.01,
+            "the first line fills the width with {justify_content:?}"
+        );
+
+        let (x, advance) = *glyph_xs(&layout, layout.len() - 1).last().unwrap();
+        let fills_last_line = (x + advance - width).abs() < 0.01;
+        assert_eq!(
+            fills_last_line,
+            justify_content == JustifyContent::Distribute,
+            "only distribution justifies the last line"
+        );
+    }
+}
+
+#[test]
+fn justify_inter_character_keeps_graphemes_and_joins() {
+    let mut env = testenv!();
+
+    // The combining acute accent is shaped apart from its base, as its font size differs,
+    // and the Arabic word is written with letters which join to both sides.
+    let text = "ae\u{301}x \u{0628}\u{0633}\u{0645}";
+    let build = |env: &mut TestEnv, alignment: Alignment| {
+        let mut builder = env.ranged_builder(text);
+        builder.push(StyleProperty::FontSize(20.0), 2..4);
+        let mut layout = builder.build(text);
+        let width = layout.max_content_width() + 40.0;
+        layout.break_all_lines(Some(width));
+        let options = AlignmentOptions {
+            justify_content: JustifyContent::Distribute,
+            ..Default::default()
+        };
+        layout.align(Some(width), alignment, options);
+        layout
+    };
+    let plain = build(&mut env, Alignment::Start);
+    let justified = build(&mut env, Alignment::Justified);
+
+    let advance = |layout: &Layout<ColorBrush>, index: usize| {
+        Cluster::from_byte_index(layout, index).unwrap().advance()
+    };
+    let delta = |index: usize| advance(&justified, index) - advance(&plain, index);
+    assert_ne!(
+        Cluster::from_byte_index(&justified, 1)
+            .unwrap()
+            .text_range(),
+        Cluster::from_byte_index(&justified, 2)
+            .unwrap()
+            .text_range(),
+        "the base and the mark are separate clusters"
+    );
+    assert!(delta(0) > 1.0, "a gap follows 'a', got {}", delta(0));
+    assert!(
+        delta(1).abs() < 1e-3,
+        "no gap between the base and its mark, got {}",
+        delta(1)
+    );
+    assert!(delta(2) > 1.0, "a gap follows the mark, got {}", delta(2));
+    for ch in ['\u{0628}', '\u{0633}', '\u{0645}'] {
+        let index = text.find(ch).unwrap();
+        assert!(
+            delta(index).abs() < 1e-3,
+            "no gap within the joined Arabic word at {ch:?}, got {}",
+            delta(index)
+        );
+    }
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn stats_count_shaped_and_cached_runs() {
+    let mut env = testenv!();
+
+    let text = "First paragraph\nSecond paragraph\nThird";
+    let build = |env: &mut TestEnv| {
+        let mut layout = env.ranged_builder(text).build(text);
+        layout.break_all_lines(Some(100.0));
+        layout
+    };
+
+    let layout = build(&mut env);
+    let run_count = layout.data.runs.len();
+    let stats = layout.stats();
+    assert_eq!(stats.shaped_runs(), run_count, "every run is shaped");
+    assert_eq!(stats.cached_runs(), 0, "no runs are cached");
+    assert_eq!(stats.line_count, layout.len(), "every line is counted");
+    assert_eq!(
+        env.layout_context().stats().runs.len(),
+        run_count,
+        "the context records the runs of the last build"
+    );
+
+    // The first build with the cache enabled fills it.
+    env.layout_context().enable_paragraph_cache(16);
+    let _ = build(&mut env);
+    let layout = build(&mut env);
+    let stats = layout.stats();
+    assert_eq!(
+        stats.cached_runs(),
+        run_count,
+        "every run is taken from the cache"
+    );
+    assert_eq!(stats.shaped_runs(), 0, "no runs are shaped again");
+}
+
+#[test]
+fn isolate_bidi_levels() {
+    let mut env = testenv!();
+
+    // Expected levels from the reference implementation of the Unicode bidi algorithm.
+    let cases: [(&str, &[u8]); 3] = [
+        // An LTR isolate nested in an RTL isolate.
+        (
+            "abc \u{2067}אבג \u{2066}def\u{2069} הו\u{2069} ghi",
+            &[
+                0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 0, 0, 0, 0, 0,
+            ],
+        ),
+        // A first strong isolate resolved as RTL from its contents.
+        ("a\u{2068}אב c\u{2069}d", &[0, 0, 1, 1, 1, 2, 0, 0]),
+        // Trailing whitespace of an unterminated isolate returns to the paragraph level.
+        ("abc\u{2067}אב ", &[0, 0, 0, 0, 1, 1, 0]),
+    ];
+    for (text, expected) in cases {
+        let analysis = env.layout_context().analyze(text);
+        let levels: Vec<_> = text
+            .char_indices()
+            .map(|(offset, _)| analysis.bidi_level(offset).unwrap())
+            .collect();
+        assert_eq!(levels, expected, "levels of {text:?}");
+
+        let mut layout = env.ranged_builder(text).build(text);
+        layout.break_all_lines(None);
+        for line in layout.lines() {
+            for item in line.items() {
+                let PositionedLayoutItem::GlyphRun(glyph_run) = item else {
+                    continue;
+                };
+                let run = glyph_run.run();
+                let range = run.text_range();
+                for (offset, _) in text[range.clone()].char_indices() {
+                    assert_eq!(
+                        run.bidi_level(),
+                        analysis.bidi_level(range.start + offset).unwrap(),
+                        "level of the run at {range:?} in {text:?}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn default_line_metrics_match_probe() {
+    let mut env = testenv!();
+
+    let text = "Hello world";
+    let _ = env.ranged_builder(text).build(text);
+    let (fcx, lcx) = env.contexts();
+    let cached = lcx.default_line_metrics(fcx);
+    assert!(
+        cached.line_height > 0.,
+        "metrics are measured for the build"
+    );
+
+    let mut probe = env.ranged_builder("").build("");
+    probe.break_all_lines(None);
+    let metrics = probe.lines().next().unwrap().metrics();
+    assert_eq!(cached.ascent, metrics.ascent, "ascent of an empty line");
+    assert_eq!(cached.descent, metrics.descent, "descent of an empty line");
+    assert_eq!(cached.leading, metrics.leading, "leading of an empty line");
+    assert_eq!(
+        cached.line_height, metrics.line_height,
+        "line height of an empty line"
+    );
+    assert_eq!(
+        cached.baseline, metrics.baseline,
+        "baseline of an empty line"
+    );
+
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontSize(32.0));
+    let _ = builder.build(text);
+    let (fcx, lcx) = env.contexts();
+    let refreshed = lcx.default_line_metrics(fcx);
+    assert!(
+        refreshed.ascent > cached.ascent && refreshed.line_height > cached.line_height,
+        "metrics are measured again when the default style changes"
+    );
+}
+
+#[test]
+fn soft_hyphens_narrow_min_content_width() {
+    let mut env = testenv!();
+
+    let text = "Incompre\u{AD}hensibilities";
+    let mut build = |hyphens: Hyphens| {
+        let mut builder = env.ranged_builder(text);
+        builder.push_default(StyleProperty::Hyphens(hyphens));
+        builder.build(text)
+    };
+    let mut manual = build(Hyphens::Manual);
+    let mut none = build(Hyphens::None);
+    assert!(
+        manual.min_content_width() < none.min_content_width(),
+        "the fragments on either side of the soft hyphen are narrower than the word"
+    );
+    assert_eq!(
+        none.min_content_width(),
+        none.max_content_width(),
+        "the word is not broken without hyphenation"
+    );
+
+    let width = manual.min_content_width() + 1.0;
+    manual.break_all_lines(Some(width));
+    none.break_all_lines(Some(width));
+    assert_eq!(manual.len(), 2, "the word is hyphenated");
+    assert_eq!(
+        manual.get(0).unwrap().break_reason(),
+        BreakReason::Hyphenated
+    );
+    assert_eq!(none.len(), 1, "the word overflows rather than hyphenating");
+}
+
+#[test]
+fn diagnostics_report_fallback_and_missing_glyphs() {
+    let mut env = testenv!();
+
+    // None of the test fonts support Tibetan.
+    let text = "ab \u{0F00}";
+    let layout = env.ranged_builder(text).build(text);
+    assert!(
+        layout.warnings().is_empty(),
+        "diagnostics are not reported unless enabled"
+    );
+
+    env.layout_context().set_diagnostics(true);
+    let layout = env.ranged_builder(text).build(text);
+    assert!(
+        matches!(
+            layout.warnings(),
+            [LayoutWarning::MissingGlyphs { text_range, .. }] if *text_range == (3..6)
+        ),
+        "the unsupported text is reported, got {:?}",
+        layout.warnings()
+    );
+
+    let (fcx, _) = env.contexts();
+    let arabic = fcx
+        .collection
+        .family_by_name("Noto Kufi Arabic")
+        .unwrap()
+        .id();
+    fcx.collection
+        .append_fallbacks(FallbackKey::new("Arab", None), core::iter::once(arabic));
+    let text = "ab سلام";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontStack(FontStack::from("Roboto")));
+    let layout = builder.build(text);
+    let font = Cluster::from_byte_index(&layout, 3)
+        .unwrap()
+        .run()
+        .font()
+        .clone();
+    assert_eq!(
+        layout.warnings(),
+        &[LayoutWarning::FallbackFont {
+            font,
+            text_range: 3..11,
+        }],
+        "the Arabic text is shaped with the fallback font"
+    );
+}
+
+#[test]
+fn run_glyph_by_index() {
+    let mut env = testenv!();
+
+    let text = "Hello world";
+    let mut builder = env.ranged_builder(text);
+    builder.push(StyleProperty::FontSize(24.0), 6..11);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    layout.align(None, Alignment::Middle, AlignmentOptions::default());
+
+    let line = layout.get(0).unwrap();
+    let glyph_run = line
+        .items()
+        .find_map(|item| match item {
+            PositionedLayoutItem::GlyphRun(glyph_run) if glyph_run.run().index() == 1 => {
+                Some(glyph_run)
+            }
+            _ => None,
+        })
+        .expect("the larger text is the second run");
+    let run = glyph_run.run();
+    let glyphs = glyph_run.positioned_glyphs().collect::<Vec<_>>();
+    assert_eq!(run.glyph_count(), glyphs.len());
+
+    let glyph = run.glyph(2).unwrap();
+    let expected = glyphs[2];
+    assert_eq!(
+        (glyph.id, glyph.x, glyph.y, glyph.advance),
+        (expected.id, expected.x, expected.y, expected.advance),
+        "indexed access matches iteration"
+    );
+    assert!(glyph.x > 0.0, "the glyph is positioned within the layout");
+    assert!(run.glyph(run.glyph_count()).is_none());
+}
+
+#[test]
+fn collapsed_space_at_span_boundary() {
+    let mut env = testenv!();
+
+    let red = ColorBrush::new(css::RED);
+    let mut build = |boundary_space: BoundarySpace| {
+        let mut builder = env.tree_builder();
+        builder.set_white_space_mode(WhiteSpaceCollapse::Collapse);
+        builder.set_boundary_space(boundary_space);
+        builder.push_style_modification_span(&[
+            StyleProperty::FontSize(32.0),
+            StyleProperty::Brush(red),
+        ]);
+        builder.push_text("Bold ");
+        builder.pop_style_span();
+        builder.push_text(" normal");
+        builder.build()
+    };
+
+    let (mut preceding, text) = build(BoundarySpace::Preceding);
+    assert_eq!(text, "Bold normal");
+    let (mut following, text) = build(BoundarySpace::Following);
+    assert_eq!(text, "Bold normal", "only the style of the space changes");
+    preceding.break_all_lines(None);
+    following.break_all_lines(None);
+
+    let space = Cluster::from_byte_index(&preceding, 4).unwrap();
+    assert_eq!(
+        space.first_style().brush,
+        red,
+        "the space is in the first span"
+    );
+    assert_eq!(space.run().font_size(), 32.0);
+    let wide_advance = space.advance();
+
+    let space = Cluster::from_byte_index(&following, 4).unwrap();
+    assert_ne!(
+        space.first_style().brush,
+        red,
+        "the space is in the last span"
+    );
+    assert_eq!(space.run().font_size(), 16.0);
+    assert!(
+        space.advance() < wide_advance,
+        "the space has the metrics of the smaller text"
+    );
+}
+
+#[test]
+fn fit_text_width_and_char_limit() {
+    let mut env = testenv!();
+
+    let style = TextStyle {
+        font_stack: FontStack::from("Roboto"),
+        ..TextStyle::default()
+    };
+    let text = "abcdefghij";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    let prefix_width = |end: usize| {
+        let cluster = Cluster::from_byte_index(&layout, end).unwrap();
+        cluster.visual_offset().unwrap()
+    };
+    let (three, four) = (prefix_width(3), prefix_width(4));
+
+    let (fcx, lcx) = env.contexts();
+    assert_eq!(
+        lcx.fit_text(fcx, text, &style, 1000.0, 4),
+        4,
+        "the character limit binds on a wide line"
+    );
+    assert_eq!(
+        lcx.fit_text(fcx, text, &style, (three + four) * 0.5, 8),
+        3,
+        "the width binds with a generous character limit"
+    );
+    assert_eq!(lcx.fit_text(fcx, text, &style, 1000.0, 100), text.len());
+
+    // Each grapheme is two characters, so the limit of three only admits one.
+    let text = "e\u{301}e\u{301}e\u{301}";
+    assert_eq!(
+        lcx.fit_text(fcx, text, &style, 1000.0, 3),
+        3,
+        "a grapheme is not split"
+    );
+}
+
+#[test]
+fn forced_paragraph_direction() {
+    let mut env = testenv!();
+
+    let check = |layout: &mut Layout<ColorBrush>| {
+        layout.break_all_lines(None);
+        assert_eq!(
+            layout.paragraph_directions().collect::<Vec<_>>(),
+            [TextDirection::LeftToRight, TextDirection::RightToLeft]
+        );
+        let latin_level = |line: usize| {
+            let line = layout.get(line).unwrap();
+            let run = line
+                .runs()
+                .find(|run| run.text_range().contains(&line.text_range().start))
+                .unwrap();
+            (run.bidi_level(), line.runs().next().unwrap().is_rtl())
+        };
+        assert_eq!(
+            latin_level(0),
+            (0, false),
+            "the first paragraph starts with the Latin text"
+        );
+        assert_eq!(
+            latin_level(1),
+            (2, true),
+            "the second paragraph is reordered independently with a right-to-left base"
+        );
+    };
+
+    let text = "abc سلام\nabc سلام";
+    let mut builder = env.ranged_builder(text);
+    builder.set_paragraph_direction(13, TextDirection::RightToLeft);
+    check(&mut builder.build(text));
+
+    let mut builder = env.tree_builder();
+    builder.push_text("abc سلام\n");
+    builder.set_paragraph_direction(TextDirection::RightToLeft);
+    builder.push_text("abc سلام");
+    let (mut layout, text) = builder.build();
+    assert_eq!(text, "abc سلام\nabc سلام");
+    check(&mut layout);
+}
+
+#[test]
+fn spacing_rounding_aligns_clusters_to_pixels() {
+    let mut env = testenv!();
+
+    // The first word has no spacing, so the spaced run starts off the pixel grid.
+    let text = "Hello world, with spacing";
+    let build = |env: &mut TestEnv| {
+        let mut builder = env.ranged_builder(text);
+        builder.push_default(StyleProperty::LetterSpacing(0.3));
+        builder.push(StyleProperty::LetterSpacing(0.0), 0..6);
+        let mut layout = builder.build(text);
+        layout.break_all_lines(None);
+        layout.align(None, Alignment::Start, AlignmentOptions::default());
+        layout
+    };
+    let line_end = |layout: &Layout<ColorBrush>| {
+        let line = layout.get(0).unwrap();
+        let mut end = 0.;
+        for run in line.runs() {
+            end += run.clusters().map(|cluster| cluster.advance()).sum::<f32>();
+        }
+        end
+    };
+    let exact = build(&mut env);
+    env.layout_context()
+        .set_spacing_rounding(SpacingRounding::Pixel);
+    let mut rounded = build(&mut env);
+
+    assert_eq!(rounded.len(), 1);
+    let line = rounded.get(0).unwrap();
+    let start = line.runs().next().unwrap().advance();
+    assert!(
+        (start - start.round()).abs() > 1e-3,
+        "the spaced run starts at a fraction of a pixel, {start}"
+    );
+    let mut spaced = 0;
+    for run in line.runs() {
+        for cluster in run.clusters() {
+            if cluster.extra_spacing().letter == 0. {
+                continue;
+            }
+            spaced += 1;
+            let end = cluster.visual_offset().unwrap() + cluster.advance();
+            assert!(
+                (end - end.round()).abs() < 1e-3,
+                "cluster at {:?} ends at {end}",
+                cluster.text_range()
+            );
+        }
+    }
+    assert!(spaced > 0, "the clusters after the first word are spaced");
+    assert!(
+        (line_end(&rounded) - line_end(&exact)).abs() <= 0.5,
+        "rounding errors are carried, so the line end {} stays close to {}",
+        line_end(&rounded),
+        line_end(&exact)
+    );
+
+    rounded.break_all_lines(None);
+    assert!(
+        (line_end(&rounded) - line_end(&exact)).abs() < 1e-3,
+        "the rounding is undone before breaking the lines again"
+    );
+}
+
+#[test]
+fn line_overflow_amount() {
+    let mut env = testenv!();
+
+    let text = "A short line\nA much longer line of text ";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    let short = layout.get(0).unwrap();
+    let long = layout.get(1).unwrap();
+    let short_width = short.metrics().advance - short.metrics().trailing_whitespace;
+    let long_width = long.metrics().advance - long.metrics().trailing_whitespace;
+    assert!(long.metrics().trailing_whitespace > 0.0);
+
+    let container = (short_width + long_width) * 0.5;
+    let underflow = short.overflow_amount(container);
+    assert!(
+        (underflow - (short_width - container)).abs() < 1e-3 && underflow < 0.0,
+        "the short line fits with room to spare, got {underflow}"
+    );
+    let overflow = long.overflow_amount(container);
+    assert!(
+        (overflow - (long_width - container)).abs() < 1e-3 && overflow > 0.0,
+        "the long line overflows, excluding its trailing space, got {overflow}"
+    );
+}
+
+#[test]
+fn letter_spacing_keeps_marks_with_base() {
+    let mut env = testenv!();
+
+    // The combining acute accent is shaped apart from its base, as its font size differs.
+    let text = "ae\u{301}x";
+    let build = |env: &mut TestEnv, letter_spacing: f32| {
+        let mut builder = env.ranged_builder(text);
+        builder.push_default(StyleProperty::LetterSpacing(letter_spacing));
+        builder.push(StyleProperty::FontSize(20.0), 2..4);
+        let mut layout = builder.build(text);
+        layout.break_all_lines(None);
+        layout
+    };
+    let plain = build(&mut env, 0.0);
+    let spaced = build(&mut env, 5.0);
+
+    let advance = |layout: &Layout<ColorBrush>, index: usize| {
+        Cluster::from_byte_index(layout, index).unwrap().advance()
+    };
+    let delta = |index: usize| advance(&spaced, index) - advance(&plain, index);
+    assert_ne!(
+        Cluster::from_byte_index(&spaced, 1).unwrap().text_range(),
+        Cluster::from_byte_index(&spaced, 2).unwrap().text_range(),
+        "the base and the mark are separate clusters"
+    );
+    assert!((delta(0) - 5.0).abs() < 1e-3, "spacing follows 'a'");
+    assert!(
+        delta(1).abs() < 1e-3,
+        "no spacing between the base and its mark, got {}",
+        delta(1)
+    );
+    assert!((delta(2) - 5.0).abs() < 1e-3, "spacing follows the mark");
+    assert!((delta(4) - 5.0).abs() < 1e-3, "spacing follows 'x'");
+}
+
+#[test]
+fn layout_label_matches_manual_layout() {
+    let mut env = testenv!();
+
+    let style = TextStyle {
+        font_stack: FontStack::from("Roboto"),
+        ..TextStyle::default()
+    };
+    let text = "A label which wraps onto several lines";
+    let max_width = Some(120.0);
+    let (fcx, lcx) = env.contexts();
+
+    let mut builder = lcx.tree_builder(fcx, 1.0, &style);
+    builder.push_text(text);
+    let (mut manual, _) = builder.build();
+    manual.break_all_lines(max_width);
+    manual.align(max_width, Alignment::Middle, AlignmentOptions::default());
+
+    let label = layout_label(text, &style, max_width, Alignment::Middle, fcx, lcx);
+
+    assert!(label.len() > 1, "the label wraps");
+    assert_eq!(label.len(), manual.len());
+    assert_eq!(label.width(), manual.width());
+    assert_eq!(label.height(), manual.height());
+    for (label_line, manual_line) in label.lines().zip(manual.lines()) {
+        assert_eq!(label_line.text_range(), manual_line.text_range());
+        assert_eq!(label_line.metrics().offset, manual_line.metrics().offset);
+        assert_eq!(label_line.metrics().advance, manual_line.metrics().advance);
+    }
+}
+
+#[test]
+fn cursor_damage_rect() {
+    let mut env = testenv!();
+
+    let text = "Hello world";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+
+    let cursor = Cursor::from_byte_index(&layout, 3, Affinity::Downstream);
+    let caret = cursor.geometry(&layout, 1.5);
+    let damage = cursor.damage_rect(&layout, 1.5);
+    assert_eq!(
+        damage.union(caret),
+        damage,
+        "{damage:?} covers the caret {caret:?}"
+    );
+    assert!(damage.width() >= 1.5 && damage.height() >= caret.height());
+    assert_eq!(damage, damage.expand(), "damage is on whole pixels");
+
+    let moved = Cursor::from_byte_index(&layout, 8, Affinity::Downstream);
+    let both = moved.damage_rect_since(&layout, 1.5, damage);
+    assert_eq!(both.union(damage), both, "the previous caret is repainted");
+    assert_eq!(
+        both.union(moved.geometry(&layout, 1.5)),
+        both,
+        "the moved caret is repainted"
+    );
+}
+
+#[test]
+fn run_is_color() {
+    /// Returns `font` with an extra table, keeping the table records sorted by tag.
+    fn with_table(font: &[u8], tag: &[u8; 4], table: &[u8]) -> Vec<u8> {
+        let count = u16::from_be_bytes([font[4], font[5]]) as usize;
+        let directory_end = 12 + 16 * count;
+        let table_offset = (font.len() + 16).next_multiple_of(4);
+        let mut record = tag.to_vec();
+        record.extend(0_u32.to_be_bytes());
+        record.extend((table_offset as u32).to_be_bytes());
+        record.extend((table.len() as u32).to_be_bytes());
+
+        let mut out = font[..12].to_vec();
+        out[4..6].copy_from_slice(&(count as u16 + 1).to_be_bytes());
+        let mut record = Some(record);
+        for existing in font[12..directory_end].chunks(16) {
+            if existing[..4] > tag[..] {
+                out.extend(record.take().unwrap_or_default());
+            }
+            // The existing tables move after the new record.
+            let offset = u32::from_be_bytes(existing[8..12].try_into().unwrap()) + 16;
+            out.extend(&existing[..8]);
+            out.extend(offset.to_be_bytes());
+            out.extend(&existing[12..]);
+        }
+        out.extend(record.unwrap_or_default());
+        out.extend(&font[directory_end..]);
+        out.resize(table_offset, 0);
+        out.extend(table);
+        out
+    }
+
+    let mut env = testenv!();
+
+    // No color font is bundled, so give a copy of Roboto an empty version 0 `COLR` table.
+    let roboto = std::fs::read(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("assets")
+            .join("roboto_fonts")
+            .join("Roboto-Regular.ttf"),
+    )
+    .unwrap();
+    let color = with_table(&roboto, b"COLR", &[0; 14]);
+    let (fcx, _) = env.contexts();
+    fcx.collection.register_fonts(
+        Blob::new(Arc::new(color)),
+        Some(FontInfoOverride {
+            family_name: Some("Color Roboto"),
+            ..Default::default()
+        }),
+    );
+
+    let text = "ab";
+    let mut builder = env.ranged_builder(text);
+    builder.push(
+        StyleProperty::FontStack(FontStack::from("Color Roboto")),
+        0..1,
+    );
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+
+    let line = layout.get(0).unwrap();
+    let runs = line
+        .runs()
+        .map(|run| (run.text_range(), run.is_color()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        runs,
+        [(0..1, true), (1..2, false)],
+        "only the run of the color font reports color glyphs"
+    );
+}
+
+#[test]
+fn min_line_height() {
+    let mut env = testenv!();
+
+    let text = "small\nlarge";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontSize(8.0));
+    builder.push_default(StyleProperty::MinLineHeight(24.0));
+    builder.push(StyleProperty::FontSize(30.0), 6..11);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+
+    assert_eq!(layout.len(), 2);
+    let small = layout.get(0).unwrap().metrics();
+    assert!(
+        (small.line_height - 24.0).abs() < 1e-3,
+        "the small line is raised to the minimum, got {}",
+        small.line_height
+    );
+    let above = small.baseline - small.min_coord;
+    let below = small.max_coord - small.baseline;
+    assert!(
+        (above - below - (small.ascent - small.descent)).abs() < 1e-3,
+        "the content is centered in the line box"
+    );
+
+    let large = layout.get(1).unwrap().metrics();
+    assert!(
+        (large.line_height - 30.0).abs() < 1e-3,
+        "taller content keeps its own line height, got {}",
+        large.line_height
+    );
+}
+
+#[test]
+fn line_ends_with_inserted_hyphen() {
+    let mut env = testenv!();
+
+    let mut first_line = |prefix: &str, rest: &str| {
+        let width = env.ranged_builder(prefix).build(prefix).max_content_width();
+        let text = [prefix, rest].concat();
+        let mut layout = env.ranged_builder(&text).build(&text);
+        layout.break_all_lines(Some(width + 1.0));
+        let line = layout.get(0).unwrap();
+        assert_eq!(
+            &text[line.text_range()],
+            prefix,
+            "the line wraps after {prefix:?}"
+        );
+        line.ends_with_inserted_hyphen()
+    };
+    assert!(
+        first_line("Hyphen\u{AD}", "ation"),
+        "a line wrapped at a soft hyphen ends with an inserted hyphen"
+    );
+    assert!(
+        !first_line("Hyphen-", "ation"),
+        "a hyphen-minus is part of the text"
+    );
+}
+
+#[test]
+fn fallback_allowlist() {
+    let mut env = testenv!();
+
+    let (fcx, _) = env.contexts();
+    let arabic = fcx
+        .collection
+        .family_by_name("Noto Kufi Arabic")
+        .unwrap()
+        .id();
+    fcx.collection
+        .append_fallbacks(FallbackKey::new("Arab", None), core::iter::once(arabic));
+    let text = "ab سلام";
+    let build = |env: &mut TestEnv| {
+        let mut builder = env.ranged_builder(text);
+        builder.push_default(StyleProperty::FontStack(FontStack::from("Roboto")));
+        builder.build(text)
+    };
+    let font_at = |layout: &Layout<ColorBrush>, index: usize| {
+        Cluster::from_byte_index(layout, index)
+            .unwrap()
+            .run()
+            .font()
+            .clone()
+    };
+    let layout = build(&mut env);
+    let roboto = font_at(&layout, 0);
+    let kufi = font_at(&layout, 3);
+    assert_ne!(
+        roboto, kufi,
+        "the Arabic text falls back to Noto Kufi Arabic"
+    );
+
+    env.layout_context().set_diagnostics(true);
+    env.layout_context().set_fallback_allowlist(vec![]);
+    let layout = build(&mut env);
+    assert_eq!(
+        font_at(&layout, 3),
+        roboto,
+        "the collection's fallbacks are not searched"
+    );
+    assert_eq!(
+        layout.warnings(),
+        &[LayoutWarning::MissingGlyphs {
+            font: roboto.clone(),
+            text_range: 3..11,
+        }],
+        "the uncovered text is shown as missing glyphs"
+    );
+
+    env.layout_context()
+        .set_fallback_allowlist(vec![kufi.clone()]);
+    let layout = build(&mut env);
+    assert_eq!(
+        font_at(&layout, 3),
+        kufi,
+        "allowed fonts are used as fallbacks"
+    );
+
+    env.layout_context().clear_fallback_allowlist();
+    let layout = build(&mut env);
+    assert_eq!(font_at(&layout, 3), kufi);
+}
+
+#[test]
+fn tab_advance_reaches_next_stop() {
+    let mut env = testenv!();
+
+    let lcx = env.layout_context();
+    assert_eq!(
+        lcx.tab_advance(10.0),
+        0.0,
+        "tabs keep the advance of the font by default"
+    );
+    lcx.set_tab_stops(TabStops::Every(40.0));
+    for (x, advance) in [(0.0, 40.0), (10.0, 30.0), (40.0, 40.0), (75.0, 5.0)] {
+        assert_eq!(
+            lcx.tab_advance(x),
+            advance,
+            "a tab at {x} reaches the stop at {}",
+            x + advance
+        );
+    }
+
+    let text = "ab\tc\td";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    for (index, stop) in [(3, 40.0), (5, 80.0)] {
+        let offset = Cluster::from_byte_index(&layout, index)
+            .unwrap()
+            .visual_offset()
+            .unwrap();
+        assert!(
+            (offset - stop).abs() < 1e-3,
+            "the text after a tab starts on the stop at {stop}, got {offset}"
+        );
+    }
+}
+
+#[test]
+fn tab_expansion_keeps_shaped_advances() {
+    let mut env = testenv!();
+    env.layout_context().set_tab_stops(TabStops::Every(40.0));
+
+    let text = "ab\tc\td ef\tg";
+    let shaped = env.ranged_builder(text).build(text).content_widths();
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    let widths = layout.content_widths();
+    assert_eq!(
+        (widths.min, widths.max),
+        (shaped.min, shaped.max),
+        "content widths don't include the expansion of tabs by line breaking"
+    );
+
+    layout.break_all_lines(Some(60.0));
+    let mut fresh = env.ranged_builder(text).build(text);
+    fresh.break_all_lines(Some(60.0));
+    let offsets = |layout: &Layout<ColorBrush>| {
+        (0..text.len())
+            .filter_map(|index| Cluster::from_byte_index(layout, index)?.visual_offset())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        offsets(&layout),
+        offsets(&fresh),
+        "breaking again at another width starts from the shaped tab advances"
+    );
+    assert_eq!(
+        layout.width(),
+        fresh.width(),
+        "breaking again at another width gives the width of a fresh layout"
+    );
+}
+
+#[test]
+fn lines_bottom_up() {
+    let mut env = testenv!();
+
+    let text = "First line\nSecond line\nThird line";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+
+    let lines = layout.lines_bottom_up(500.0).collect::<Vec<_>>();
+    let indices = lines.iter().map(|(index, _)| *index).collect::<Vec<_>>();
+    assert_eq!(indices, [2, 1, 0], "the lines are visited from the bottom");
+
+    let (last, last_top) = lines[0];
+    let last_height = layout.get(last).unwrap().metrics().line_height;
+    assert!(
+        (last_top + last_height - 500.0).abs() < 1e-3,
+        "the last line is flush with the bottom"
+    );
+    for pair in lines.windows(2) {
+        let (below, below_top) = pair[0];
+        let (above, above_top) = pair[1];
+        let height = layout.get(above).unwrap().metrics().line_height;
+        assert!(
+            (above_top + height - below_top).abs() < 1e-3,
+            "line {above} ends where line {below} starts"
+        );
+    }
+
+    // The bottom is in the same coordinate space as the tops.
+    layout.set_origin(10.0, 20.0);
+    let shifted = layout.lines_bottom_up(500.0).collect::<Vec<_>>();
+    for ((_, top), (_, shifted_top)) in lines.iter().zip(&shifted) {
+        assert!(
+            (shifted_top - top).abs() < 1e-3,
+            "the last line is flush with the bottom whatever the origin"
+        );
+    }
+    layout.set_y_axis(YAxis::Up);
+    let flipped = layout.lines_bottom_up(500.0).collect::<Vec<_>>();
+    for ((_, top), (_, flipped_top)) in lines.iter().zip(&flipped) {
+        assert!(
+            (flipped_top - (1000.0 - top)).abs() < 1e-3,
+            "with the y-axis up, the lines extend upwards from the bottom"
+        );
+    }
+    let (_, flipped_last_top) = flipped[0];
+    assert!(
+        (flipped_last_top - last_height - 500.0).abs() < 1e-3,
+        "with the y-axis up, the last line is flush with the bottom"
+    );
+}
+
+#[test]
+fn hit_testing_skips_leading_format_characters() {
+    let mut env = testenv!();
+
+    for mark in ['\u{200F}', '\u{200E}'] {
+        let text = format!("{mark}abc");
+        let mut builder = env.ranged_builder(&text);
+        builder.set_base_direction(TextDirection::LeftToRight);
+        builder.push_ignorable_char(mark);
+        let mut layout = builder.build(&text);
+        layout.break_all_lines(None);
+
+        let first = Cluster::from_byte_index(&layout, mark.len_utf8()).unwrap();
+        let y = layout.get(0).unwrap().metrics().baseline;
+        for x in [0.0, first.advance() * 0.25] {
+            let cursor = Cursor::from_point(&layout, x, y);
+            assert_eq!(
+                cursor.index(),
+                mark.len_utf8(),
+                "clicking the first glyph at {x} of {mark:?}abc lands after the mark"
+            );
+        }
+    }
+}
+
+#[test]
+fn run_space_advance() {
+    let mut env = testenv!();
+
+    let text = "a b";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontSize(24.0));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+
+    let space = Cluster::from_byte_index(&layout, 1).unwrap();
+    assert!(space.is_space_or_nbsp());
+    let run = space.run();
+    assert!(run.space_advance() > 0.0);
+    assert!(
+        (run.space_advance() - space.advance()).abs() < 1e-3,
+        "the space advance of the run matches a shaped space"
+    );
+}
+
+#[test]
+fn empty_font_stack_falls_back_to_sans_serif() {
+    let mut env = testenv!();
+
+    let text = "Hello world";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontStack(FontStack::from("Roboto")));
+    let mut expected = builder.build(text);
+    expected.break_all_lines(None);
+
+    let (fcx, _) = env.contexts();
+    let roboto = fcx.collection.family_by_name("Roboto").unwrap().id();
+    fcx.collection
+        .set_generic_families(GenericFamily::SansSerif, core::iter::once(roboto));
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontStack(FontStack::List(Vec::new().into())));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+
+    assert!(layout.width() > 0.0, "the text is laid out");
+    assert_eq!(
+        layout.width(),
+        expected.width(),
+        "the empty font stack resolves to sans-serif"
+    );
+    assert_eq!(
+        layout.warnings(),
+        &[LayoutWarning::EmptyFontStack {
+            text_range: 0..text.len(),
+        }],
+        "the empty font stack is reported"
+    );
+
+    env.layout_context().set_fallback_allowlist(vec![]);
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontStack(FontStack::List(Vec::new().into())));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    assert_eq!(
+        layout.width(),
+        expected.width(),
+        "the sans-serif substitute is not filtered by the fallback allowlist"
+    );
+}
+
+#[test]
+fn tab_stops_are_relative_to_text_indent() {
+    let mut env = testenv!();
+
+    env.layout_context().set_tab_stops(TabStops::Every(40.0));
+    let text = "ab\tc\nab\tc";
+    let mut builder = env.ranged_builder(text);
+    builder.set_text_indent(TextIndent {
+        amount: 30.0,
+        each_paragraph: false,
+    });
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+
+    for (index, stop) in [(3, 70.0), (8, 40.0)] {
+        let offset = Cluster::from_byte_index(&layout, index)
+            .unwrap()
+            .visual_offset()
+            .unwrap();
+        assert!(
+            (offset - stop).abs() < 1e-3,
+            "the text after a tab starts on the stop at {stop}, got {offset}"
+        );
+    }
+}
+
+#[test]
+fn input_fingerprint() {
+    let text = "Hello world";
+    let styles = |size| [(StyleProperty::FontSize(size), 0..text.len())];
+    let options = AlignmentOptions::default();
+    let fingerprint = |lcx: &LayoutContext<ColorBrush>, size| {
+        lcx.input_fingerprint(
+            text,
+            1.0,
+            &styles(size),
+            Some(100.0),
+            Alignment::Start,
+            &options,
+        )
+    };
+
+    let mut lcx = LayoutContext::new();
+    let base = fingerprint(&lcx, 16.0);
+    assert_eq!(
+        base,
+        fingerprint(&LayoutContext::new(), 16.0),
+        "the fingerprint is stable across contexts"
+    );
+    assert_ne!(
+        base,
+        fingerprint(&lcx, 17.0),
+        "the fingerprint changes with the font size"
+    );
+    assert_ne!(
+        base,
+        lcx.input_fingerprint(text, 1.0, &styles(16.0), None, Alignment::Start, &options),
+        "the fingerprint changes with the width"
+    );
+    for (changed, name) in [
+        (
+            AlignmentOptions {
+                align_when_overflowing: true,
+                ..options
+            },
+            "overflow alignment",
+        ),
+        (
+            AlignmentOptions {
+                justify_content: JustifyContent::InterCharacter,
+                ..options
+            },
+            "justification",
+        ),
+        (
+            AlignmentOptions {
+                numeric_position: 40.0,
+                ..options
+            },
+            "numeric position",
+        ),
+    ] {
+        assert_ne!(
+            base,
+            lcx.input_fingerprint(
+                text,
+                1.0,
+                &styles(16.0),
+                Some(100.0),
+                Alignment::Start,
+                &changed
+            ),
+            "the fingerprint changes with the {name} of the alignment options"
+        );
+    }
+
+    lcx.set_diagnostics(true);
+    assert_eq!(
+        base,
+        fingerprint(&lcx, 16.0),
+        "diagnostics don't affect the layout"
+    );
+    lcx.set_tab_stops(TabStops::Every(40.0));
+    assert_ne!(
+        base,
+        fingerprint(&lcx, 16.0),
+        "the fingerprint changes with the tab stops"
+    );
+
+    // Fonts are fingerprinted by their data rather than by the identity of their blob.
+    let data = std::fs::read(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("assets")
+            .join("roboto_fonts")
+            .join("Roboto-Regular.ttf"),
+    )
+    .unwrap();
+    let load = || Font::new(Blob::new(Arc::new(data.clone())), 0);
+    let with_allowlist = |font: Font| {
+        let mut lcx = LayoutContext::new();
+        lcx.set_fallback_allowlist(vec![font]);
+        fingerprint(&lcx, 16.0)
+    };
+    assert_eq!(
+        with_allowlist(load()),
+        with_allowlist(load()),
+        "copies of a font have the same fingerprint"
+    );
+    let mut empty_allowlist = LayoutContext::new();
+    empty_allowlist.set_fallback_allowlist(Vec::new());
+    assert_ne!(
+        fingerprint(&LayoutContext::new(), 16.0),
+        fingerprint(&empty_allowlist, 16.0),
+        "an empty allowlist, which disables fallback, differs from no allowlist"
+    );
+    // An edited table has a different checksum in the table directory.
+    let mut other = data.clone();
+    let num_tables = u16::from_be_bytes([other[4], other[5]]) as usize;
+    let record = 12 + 16 * (num_tables - 1);
+    other[record + 4] ^= 0xFF;
+    assert_ne!(
+        with_allowlist(load()),
+        with_allowlist(Font::new(Blob::new(Arc::new(other)), 0)),
+        "a change to a table changes the fingerprint"
+    );
+}
+
+#[test]
+fn text_with_expanded_tabs() {
+    let mut env = testenv!();
+
+    let text = " ";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    let space_advance = layout
+        .get(0)
+        .unwrap()
+        .runs()
+        .next()
+        .unwrap()
+        .space_advance();
+
+    env.layout_context()
+        .set_tab_stops(TabStops::Every(space_advance * 4.0));
+    let text = "\tab\tc\nd";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+
+    let expanded = layout.text_with_expanded_tabs(text, 0..text.len());
+    assert!(
+        expanded.starts_with("    ab "),
+        "a tab at the start of a line is as wide as the tab size, got {expanded:?}"
+    );
+    assert!(
+        expanded.ends_with(" c\nd") && !expanded.contains('\t'),
+        "each tab is replaced by spaces, got {expanded:?}"
+    );
+    assert_eq!(
+        layout.text_with_expanded_tabs(text, 5..text.len()),
+        "\nd",
+        "text without tabs is unchanged"
+    );
+}
+
+#[test]
+fn underline_rect_snaps_to_pixels() {
+    let mut env = testenv!();
+
+    let text = "Underlined text";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontSize(15.3));
+    builder.push_default(StyleProperty::Underline(true));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    layout.set_origin(0.0, 0.35);
+
+    let line = layout.get(0).unwrap();
+    let Some(PositionedLayoutItem::GlyphRun(glyph_run)) = line.items().next() else {
+        panic!("the line starts with a glyph run");
+    };
+    let span = glyph_run.style_spans().next().unwrap();
+    let exact = glyph_run.underline_rect(&span, None).unwrap();
+    let snapped = glyph_run.underline_rect(&span, Some(1.0)).unwrap();
+
+    assert_eq!(
+        snapped.y0,
+        snapped.y0.round(),
+        "the underline starts on a pixel"
+    );
+    assert_eq!(
+        snapped.y1,
+        snapped.y1.round(),
+        "the underline ends on a pixel"
+    );
+    assert!(snapped.height() >= 1.0, "the underline covers a pixel row");
+    assert!((snapped.y0 - exact.y0).abs() <= 0.5);
+    let descender = glyph_run.baseline() + glyph_run.run().metrics().descent;
+    assert!(
+        snapped.y1 <= descender as f64,
+        "the underline stays within the descender"
+    );
+    assert_eq!((snapped.x0, snapped.x1), (exact.x0, exact.x1));
+}
+
+#[test]
+fn run_selected_attributes() {
+    let mut env = testenv!();
+
+    // The only Roboto face in the test fonts is the regular weight.
+    let text = "Heavy";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontStack(FontStack::from("Roboto")));
+    builder.push_default(StyleProperty::FontWeight(FontWeight::BLACK));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+
+    let run = layout.get(0).unwrap().runs().next().unwrap();
+    let attributes = run.selected_attributes();
+    assert_eq!(
+        attributes.weight,
+        FontWeight::NORMAL,
+        "the selected face is regular, not the requested black"
+    );
+    assert_eq!(attributes.style, FontStyle::Normal);
+    assert_eq!(attributes.width, FontWidth::NORMAL);
+}
+
+#[test]
+fn truncated_label_ellipsis_positions() {
+    let mut env = testenv!();
+
+    let style = TextStyle {
+        font_stack: FontStack::from("Roboto"),
+        ..TextStyle::default()
+    };
+    let path = "/home/user/projects/parley/src/layout/mod.rs";
+    let max_width = 150.0;
+    let (fcx, lcx) = env.contexts();
+
+    let truncate = |position, fcx: &mut FontContext, lcx: &mut LayoutContext<ColorBrush>| {
+        let (layout, text) = layout_truncated_label(
+            path,
+            &style,
+            max_width,
+            Alignment::Start,
+            position,
+            None,
+            fcx,
+            lcx,
+        );
+        assert_eq!(layout.len(), 1, "a truncated label is a single line");
+        assert!(
+            layout.get(0).unwrap().metrics().advance <= max_width,
+            "{text:?} fits"
+        );
+        let (head, tail) = text.split_once('\u{2026}').expect("the text is truncated");
+        assert!(path.starts_with(head) && path.ends_with(tail));
+        (head.to_owned(), tail.to_owned())
+    };
+
+    let (head, tail) = truncate(EllipsisPosition::Middle, fcx, lcx);
+    assert!(!head.is_empty() && !tail.is_empty());
+    assert!(
+        head.chars().count().abs_diff(tail.chars().count()) <= 1,
+        "the middle ellipsis keeps as many clusters on each side, got {head:?} and {tail:?}"
+    );
+
+    let (head, tail) = truncate(EllipsisPosition::Start, fcx, lcx);
+    assert!(head.is_empty(), "the start ellipsis keeps the tail");
+    assert!(tail.ends_with("mod.rs"));
+
+    let (head, tail) = truncate(EllipsisPosition::End, fcx, lcx);
+    assert!(tail.is_empty(), "the end ellipsis keeps the head");
+    assert!(head.starts_with("/home"));
+
+    let (layout, text) = layout_truncated_label(
+        "short",
+        &style,
+        max_width,
+        Alignment::Start,
+        EllipsisPosition::Middle,
+        None,
+        fcx,
+        lcx,
+    );
+    assert_eq!(text, "short", "text which fits is not truncated");
+    assert_eq!(layout.len(), 1);
+}
+
+#[test]
+fn paragraph_alignment_overrides_layout_alignment() {
+    let mut env = testenv!();
+
+    let text = "Left aligned\nCentered";
+    let centered = text.find("Centered").unwrap();
+    let mut builder = env.ranged_builder(text);
+    builder.set_paragraph_alignment(centered, Alignment::Middle);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    layout.align(Some(300.0), Alignment::Start, AlignmentOptions::default());
+
+    let left = layout.get(0).unwrap();
+    assert_eq!(left.alignment(), Alignment::Start);
+    assert_eq!(
+        left.metrics().offset,
+        0.0,
+        "the first paragraph is start aligned"
+    );
+
+    let center = layout.get(1).unwrap();
+    assert_eq!(center.alignment(), Alignment::Middle);
+    let metrics = center.metrics();
+    let free_space = 300.0 - metrics.advance + metrics.trailing_whitespace;
+    assert!(
+        (metrics.offset - free_space * 0.5).abs() < 1e-3,
+        "the second paragraph is centered"
+    );
+}
+
+#[test]
+fn justification_does_not_shrink_spaces() {
+    let mut env = testenv!();
+
+    let text = "A narrow column with extraordinarily long words and a few short ones";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(Some(90.0));
+    assert!(
+        layout.lines().any(|line| line.metrics().advance > 90.0),
+        "a word is wider than the column, so a line overflows it"
+    );
+    layout.align(
+        Some(90.0),
+        Alignment::Justified,
+        AlignmentOptions::default(),
+    );
+
+    let mut widened = false;
+    for (index, _) in text.match_indices(' ') {
+        let cluster = Cluster::from_byte_index(&layout, index).unwrap();
+        let natural = cluster.run().space_advance();
+        assert!(
+            cluster.advance() >= natural - 1e-3,
+            "the space at {index} is {} wide, narrower than its natural {natural}",
+            cluster.advance()
+        );
+        widened |= cluster.advance() > natural + 1e-3;
+    }
+    assert!(
+        widened,
+        "short lines are justified by widening their spaces"
+    );
+}
+
+#[test]
+fn run_cluster_for_glyph() {
+    let mut env = testenv!();
+
+    // Lam-alef is a ligature, where two characters produce a single glyph.
+    let text = "\u{0644}\u{0627} abc";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+
+    let lam = Cluster::from_byte_index(&layout, 0).unwrap();
+    let run = lam.run();
+    assert_eq!(
+        run.cluster_for_glyph(lam.glyph_range().start),
+        Some(0..4),
+        "the ligature glyph maps to both of its characters"
+    );
+
+    for line in layout.lines() {
+        for run in line.runs() {
+            for cluster in run.clusters() {
+                for glyph_index in cluster.glyph_range() {
+                    let range = run.cluster_for_glyph(glyph_index).unwrap();
+                    assert!(
+                        range.start <= cluster.text_range().start
+                            && cluster.text_range().end <= range.end,
+                        "glyph {glyph_index} maps back to its cluster"
+                    );
+                }
+            }
+            let glyph_count: usize = run.clusters().map(|cluster| cluster.glyph_count()).sum();
+            assert_eq!(run.cluster_for_glyph(glyph_count), None);
+        }
+    }
+}
+
+#[test]
+fn center_alignment_ignores_trailing_space() {
+    let mut env = testenv!();
+
+    let mut centered_offset = |text: &str| {
+        let mut layout = env.ranged_builder(text).build(text);
+        layout.break_all_lines(None);
+        layout.align(Some(100.0), Alignment::Middle, AlignmentOptions::default());
+        layout.get(0).unwrap().metrics().offset
+    };
+
+    let expected = centered_offset("hi");
+    let offset = centered_offset("hi ");
+    assert!(
+        (offset - expected).abs() < 1e-3,
+        "\"hi\" is centered ignoring the trailing space: {offset} != {expected}"
+    );
+}
+
+#[test]
+fn line_content_width_excludes_trailing_spaces() {
+    let mut env = testenv!();
+
+    let mut first_line = |text: &str| {
+        let mut layout = env.ranged_builder(text).build(text);
+        layout.break_all_lines(None);
+        let line = layout.get(0).unwrap();
+        (line.content_width(), line.metrics().advance)
+    };
+
+    let (content_width, advance) = first_line("Done.");
+    assert_eq!(content_width, advance, "no trailing spaces to exclude");
+
+    let (padded_content_width, padded_advance) = first_line("Done.   ");
+    assert!(padded_advance > advance, "the advance includes the spaces");
+    assert!(
+        (padded_content_width - content_width).abs() < 1e-3,
+        "content width excludes the spaces after the period: {padded_content_width} != {content_width}"
+    );
+}
+
+#[test]
+fn fallback_ranks_script_default_after_locale() {
+    let mut env = testenv!();
+
+    let (fcx, _) = env.contexts();
+    let roboto = fcx.collection.family_by_name("Roboto").unwrap().id();
+    let arabic = fcx
+        .collection
+        .family_by_name("Noto Kufi Arabic")
+        .unwrap()
+        .id();
+    fcx.collection
+        .set_fallbacks(FallbackKey::new("Arab", None), core::iter::once(arabic));
+    // Roboto does not cover Arabic, so Urdu text must continue to the default fallbacks.
+    fcx.collection
+        .set_fallbacks(("Arab", "ur"), core::iter::once(roboto));
+
+    let text = "سلام";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::FontStack(FontStack::from(
+        "Noto Kufi Arabic",
+    )));
+    let layout = builder.build(text);
+    let kufi = Cluster::from_byte_index(&layout, 0)
+        .unwrap()
+        .run()
+        .font()
+        .clone();
+
+    for locale in ["ar", "fa", "ur"] {
+        let mut builder = env.ranged_builder(text);
+        builder.push_default(StyleProperty::FontStack(FontStack::from("Roboto")));
+        builder.push_default(StyleProperty::Locale(Some(locale)));
+        let layout = builder.build(text);
+        assert_eq!(
+            Cluster::from_byte_index(&layout, 0).unwrap().run().font(),
+            &kufi,
+            "{locale} text falls back to the family of the script's default locale"
+        );
+    }
+}
+
+#[test]
+fn auto_height_clamps_lines() {
+    let mut env = testenv!();
+
+    let style = TextStyle {
+        font_stack: FontStack::from("Roboto"),
+        ..TextStyle::default()
+    };
+    let text = "Some words which wrap onto several lines in a narrow container";
+    let (fcx, lcx) = env.contexts();
+    let layout = layout_label(text, &style, Some(60.0), Alignment::Start, fcx, lcx);
+    assert!(
+        layout.len() > 3,
+        "the text wraps onto more than three lines"
+    );
+    let line_height = layout.get(0).unwrap().metrics().line_height;
+
+    let height = lcx.auto_height(fcx, text, &style, 60.0, 3);
+    assert!(
+        (height - 3.0 * line_height).abs() < 1e-3,
+        "three lines are kept: {height} != {}",
+        3.0 * line_height
+    );
+    assert_eq!(
+        lcx.auto_height(fcx, text, &style, 60.0, 100),
+        layout.height(),
+        "a clamp beyond the line count keeps every line"
+    );
+    assert_eq!(lcx.auto_height(fcx, text, &style, 60.0, 0), 0.0);
+}
+
+#[test]
+fn bidi_number_classes() {
+    use swash::text::BidiClass::{self, AN, EN, L, R};
+
+    let mut env = testenv!();
+    let lcx = env.layout_context();
+    let mut resolve = |text: &str| {
+        let analysis = lcx.analyze(text);
+        text.char_indices()
+            .map(|(index, _)| {
+                (
+                    analysis.bidi_class(index).unwrap(),
+                    analysis.bidi_level(index).unwrap(),
+                )
+            })
+            .collect::<Vec<(BidiClass, u8)>>()
+    };
+
+    // Cases from the weak type rules of the Unicode bidirectional algorithm.
+    let cases: &[(&str, &[(BidiClass, u8)], &str)] = &[
+        (
+            "abc ١٢٣ def",
+            &[
+                (L, 0),
+                (L, 0),
+                (L, 0),
+                (L, 0),
+                (AN, 2),
+                (AN, 2),
+                (AN, 2),
+                (L, 0),
+                (L, 0),
+                (L, 0),
+                (L, 0),
+            ],
+            "Arabic numbers keep their order within left-to-right text",
+        ),
+        (
+            "abc אב 12",
+            &[
+                (L, 0),
+                (L, 0),
+                (L, 0),
+                (L, 0),
+                (R, 1),
+                (R, 1),
+                (R, 1),
+                (EN, 2),
+                (EN, 2),
+            ],
+            "W7: European numbers after right-to-left text stay numbers",
+        ),
+        (
+            "abc 123",
+            &[(L, 0); 7],
+            "W7: European numbers after left-to-right text become L",
+        ),
+        (
+            "ا 123",
+            &[(R, 1), (R, 1), (AN, 2), (AN, 2), (AN, 2)],
+            "W2: European numbers after Arabic letters become Arabic numbers",
+        ),
+        (
+            "א 1+2",
+            &[(R, 1), (R, 1), (EN, 2), (EN, 2), (EN, 2)],
+            "W4: a European separator between European numbers",
+        ),
+        (
+            "ا 1+2",
+            &[(R, 1), (R, 1), (AN, 2), (R, 1), (AN, 2)],
+            "W4, W6: a European separator between Arabic numbers is neutral",
+        ),
+        (
+            "ا ١,٢",
+            &[(R, 1), (R, 1), (AN, 2), (AN, 2), (AN, 2)],
+            "W4: a common separator between Arabic numbers",
+        ),
+        (
+            "א 10%",
+            &[(R, 1), (R, 1), (EN, 2), (EN, 2), (EN, 2)],
+            "W5: a European terminator after European numbers",
+        ),
+        (
+            "ا ١٠%",
+            &[(R, 1), (R, 1), (AN, 2), (AN, 2), (R, 1)],
+            "W5, W6: a European terminator after Arabic numbers is neutral",
+        ),
+    ];
+    for (text, expected, rule) in cases {
+        assert_eq!(resolve(text), *expected, "{rule}: {text:?}");
+    }
+}
+
+#[test]
+fn truncated_label_custom_marker() {
+    let mut env = testenv!();
+
+    let style = TextStyle {
+        font_stack: FontStack::from("Roboto"),
+        font_size: 16.0,
+        ..TextStyle::default()
+    };
+    let red = ColorBrush::new(css::RED);
+    let marker = TruncationMarker {
+        text: "more",
+        style: TextStyle {
+            font_size: 24.0,
+            brush: red,
+            ..style.clone()
+        },
+    };
+    let max_width = 150.0;
+    let (fcx, lcx) = env.contexts();
+    let (layout, text) = layout_truncated_label(
+        "The quick brown fox jumps over the lazy dog",
+        &style,
+        max_width,
+        Alignment::Start,
+        EllipsisPosition::End,
+        Some(&marker),
+        fcx,
+        lcx,
+    );
+    assert!(
+        text.ends_with("more"),
+        "the marker replaces the end, got {text:?}"
+    );
+    assert!(!text.contains('\u{2026}'), "no ellipsis is added");
+    let line = layout.get(0).unwrap();
+    assert!(
+        line.metrics().advance <= max_width,
+        "the marker's width is reserved"
+    );
+
+    let marker_start = text.len() - "more".len();
+    let mut saw_marker = false;
+    for item in line.items() {
+        let PositionedLayoutItem::GlyphRun(glyph_run) = item else {
+            continue;
+        };
+        let run = glyph_run.run();
+        if run.text_range().start >= marker_start {
+            saw_marker = true;
+            assert_eq!(run.font_size(), 24.0, "the marker has its own size");
+            assert_eq!(glyph_run.style().brush, red, "the marker has its own brush");
+        } else {
+            assert_eq!(run.font_size(), 16.0);
+            assert_eq!(glyph_run.style().brush, style.brush);
+        }
+    }
+    assert!(saw_marker);
+}
+
+#[test]
+fn substring_geometry_of_token() {
+    let mut env = testenv!();
+
+    let text = "let value = compute(arg);\nnext line";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    let offset = |index: usize| {
+        Cluster::from_byte_index(&layout, index)
+            .unwrap()
+            .visual_offset()
+            .unwrap()
+    };
+
+    let start = text.find("compute").unwrap();
+    let end = start + "compute".len();
+    let fragments = layout.substring_geometry(start..end).collect::<Vec<_>>();
+    assert_eq!(fragments.len(), 1, "the token is on one line");
+    let (line_index, x, width) = fragments[0];
+    assert_eq!(line_index, 0);
+    assert!(
+        (x - offset(start)).abs() < 1e-3,
+        "the token starts mid-line"
+    );
+    assert!(
+        (width - (offset(end) - offset(start))).abs() < 1e-3,
+        "the width is that of the token alone"
+    );
+
+    // A range across the newline has a fragment on each line, without the newline.
+    let start = text.find("(arg);").unwrap();
+    let end = text.find(" line").unwrap();
+    let fragments = layout.substring_geometry(start..end).collect::<Vec<_>>();
+    assert_eq!(
+        fragments.iter().map(|(line, ..)| *line).collect::<Vec<_>>(),
+        [0, 1]
+    );
+    assert!(
+        (fragments[0].1 + fragments[0].2 - layout.get(0).unwrap().metrics().advance).abs() < 1e-3,
+        "the first fragment ends with the line"
+    );
+    assert_eq!(fragments[1].1, 0.0, "the second fragment starts the line");
+}
+
+#[test]
+fn hyphenation_limits_min_prefix() {
+    let mut env = testenv!();
+
+    let prefix = "aaaa e\u{AD}";
+    let prefix_width = env.ranged_builder(prefix).build(prefix).max_content_width();
+    let text = "aaaa e\u{AD}vent";
+    let build = |env: &mut TestEnv| {
+        let mut layout = env.ranged_builder(text).build(text);
+        layout.break_all_lines(Some(prefix_width + 1.0));
+        layout
+    };
+
+    let unlimited = build(&mut env);
+    let line = unlimited.get(0).unwrap();
+    assert_eq!(line.break_reason(), BreakReason::Hyphenated);
+    assert_eq!(&text[line.text_range()], prefix);
+
+    env.layout_context()
+        .set_hyphenation_limits(HyphenationLimits {
+            min_prefix: 2,
+            ..Default::default()
+        });
+    let limited = build(&mut env);
+    let line = limited.get(0).unwrap();
+    assert_eq!(
+        line.break_reason(),
+        BreakReason::Regular,
+        "a one letter prefix is too short to hyphenate"
+    );
+    assert_eq!(&text[line.text_range()], "aaaa ");
+}
+
+#[test]
+fn line_spacing_of_uniform_paragraph() {
+    let mut env = testenv!();
+
+    let text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor.";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(Some(100.0));
+    assert!(layout.len() > 2, "the paragraph wraps onto several lines");
+
+    let line_height = layout.get(0).unwrap().metrics().line_height;
+    for index in 0..layout.len() {
+        assert!(
+            (layout.line_spacing(index) - line_height).abs() < 1e-3,
+            "line {index} is one line height above the next"
+        );
+    }
+    assert_eq!(layout.line_spacing(layout.len()), 0.0);
+}
+
+#[test]
+fn push_spans_matches_push() {
+    let mut env = testenv!();
+
+    let text = "Some big and bold words";
+    let big = text.find("big").unwrap()..text.find(" and").unwrap();
+    let bold = text.find("bold").unwrap()..text.find(" words").unwrap();
+    let runs = |layout: &Layout<ColorBrush>| {
+        layout
+            .lines()
+            .flat_map(|line| {
+                line.runs()
+                    .map(|run| (run.text_range(), run.font_size(), run.advance()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut builder = env.ranged_builder(text);
+    builder.push(StyleProperty::FontSize(24.0), big.clone());
+    builder.push(StyleProperty::FontWeight(FontWeight::BOLD), bold.clone());
+    let mut pushed = builder.build(text);
+    pushed.break_all_lines(None);
+
+    let base = TextStyle {
+        font_stack: FontStack::from("Roboto"),
+        ..TextStyle::default()
+    };
+    let spans = [
+        (
+            big,
+            TextStyle {
+                font_size: 24.0,
+                ..base.clone()
+            },
+        ),
+        (
+            bold,
+            TextStyle {
+                font_weight: FontWeight::BOLD,
+                ..base
+            },
+        ),
+    ];
+    let mut builder = env.ranged_builder(text);
+    builder.push_spans(&spans);
+    let mut spanned = builder.build(text);
+    spanned.break_all_lines(None);
+
+    assert_eq!(
+        runs(&spanned).len(),
+        5,
+        "the spans split the text into runs"
+    );
+    assert_eq!(runs(&spanned), runs(&pushed));
+
+    // Ranges are clamped to the text, and the overlap with an earlier span is dropped.
+    let overlapping = [
+        (big.start..big.end + 2, spans[0].1.clone()),
+        (big.end..text.len() + 10, spans[1].1.clone()),
+    ];
+    let mut builder = env.ranged_builder(text);
+    builder.push_spans(&overlapping);
+    let mut clamped = builder.build(text);
+    clamped.break_all_lines(None);
+    let clamped = runs(&clamped);
+    assert!(
+        clamped
+            .iter()
+            .any(|(range, size, _)| *range == (big.start..big.end + 2) && *size == 24.0),
+        "the first span keeps its range, got {clamped:?}"
+    );
+    assert_eq!(
+        clamped.last().map(|(range, _, _)| range.end),
+        Some(text.len()),
+        "the last span ends with the text"
+    );
+}
+
+#[test]
+fn paragraph_styles_report_alignment() {
+    let mut env = testenv!();
+
+    let text = "First paragraph\nSecond paragraph";
+    let second = text.find("Second").unwrap();
+    let mut builder = env.ranged_builder(text);
+    builder.set_paragraph_alignment(second, Alignment::Right);
+    builder.set_text_indent(TextIndent {
+        amount: 20.0,
+        each_paragraph: false,
+    });
+    let layout = builder.build(text);
+
+    let styles = layout.paragraph_styles().collect::<Vec<_>>();
+    assert_eq!(styles.len(), 2);
+    let (first_range, first) = styles[0].clone();
+    let (second_range, second_style) = styles[1].clone();
+    assert_eq!(first_range, 0..second, "the range includes the newline");
+    assert_eq!(second_range, second..text.len());
+    assert_eq!(first.alignment, None);
+    assert_eq!(second_style.alignment, Some(Alignment::Right));
+    assert_ne!(first, second_style, "the paragraphs have distinct styles");
+    assert_eq!(first.direction, TextDirection::LeftToRight);
+    assert_eq!(first.indent, 20.0);
+    assert_eq!(
+        second_style.indent, 0.0,
+        "only the first paragraph is indented"
+    );
+}
+
+#[test]
+fn snapped_inline_box_position() {
+    let mut env = testenv!();
+
+    let text = "Hello world";
+    let build = |env: &mut TestEnv| {
+        let mut builder = env.ranged_builder(text);
+        builder.push_default(StyleProperty::FontSize(15.3));
+        builder.push_inline_box(InlineBox {
+            id: 0,
+            index: 5,
+            width: 10.5,
+            height: 10.3,
+        });
+        let mut layout = builder.build(text);
+        layout.break_all_lines(None);
+        layout
+    };
+    let items = |layout: &Layout<ColorBrush>| {
+        let mut boxes = Vec::new();
+        let mut runs = Vec::new();
+        for item in layout.get(0).unwrap().items() {
+            match item {
+                PositionedLayoutItem::InlineBox(inline_box) => boxes.push(inline_box),
+                PositionedLayoutItem::GlyphRun(glyph_run) => runs.push(glyph_run.offset()),
+            }
+        }
+        (boxes, runs)
+    };
+
+    let (exact_boxes, exact_runs) = items(&build(&mut env));
+    env.layout_context().set_snap_inline_boxes(true);
+    let (boxes, runs) = items(&build(&mut env));
+
+    assert_eq!(boxes.len(), 1);
+    let inline_box = &boxes[0];
+    assert_eq!(inline_box.x.fract(), 0.0, "the box is snapped horizontally");
+    assert_eq!(inline_box.y.fract(), 0.0, "the box is snapped vertically");
+    assert!((inline_box.x - exact_boxes[0].x).abs() <= 0.5);
+    assert!((inline_box.y - exact_boxes[0].y).abs() <= 0.5);
+    assert_eq!(inline_box.width, 10.5, "the size of the box is unchanged");
+    assert_eq!(runs, exact_runs, "the text is not moved by snapping");
+}
+
+#[test]
+fn cluster_extra_spacing() {
+    let mut env = testenv!();
+
+    // The first Arabic word is written with letters which join to both sides, and the
+    // second with letters which only join to the letter before them, followed by
+    // Arabic-Indic digits which don't join at all.
+    let text = "ab cd \u{0628}\u{0633}\u{0645} \u{062F}\u{0627}\u{0631} \u{0661}\u{0662}";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::LetterSpacing(2.0));
+    builder.push_default(StyleProperty::WordSpacing(3.0));
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    let spacing = |index: usize| {
+        Cluster::from_byte_index(&layout, index)
+            .unwrap()
+            .extra_spacing()
+    };
+
+    assert_eq!(
+        spacing(0),
+        ExtraSpacing {
+            letter: 2.0,
+            word: 0.0
+        },
+        "a letter is followed by letter spacing"
+    );
+    assert_eq!(
+        spacing(2),
+        ExtraSpacing {
+            letter: 2.0,
+            word: 3.0
+        },
+        "a space is followed by word spacing too"
+    );
+    let arabic = text.find('\u{0628}').unwrap();
+    assert_eq!(
+        spacing(arabic),
+        ExtraSpacing::default(),
+        "the join between cursive letters is not spaced"
+    );
+    assert_eq!(
+        spacing(text.find('\u{0645}').unwrap()).letter,
+        2.0,
+        "the last letter of the word is spaced"
+    );
+    for ch in ['\u{062F}', '\u{0627}', '\u{0661}'] {
+        assert_eq!(
+            spacing(text.find(ch).unwrap()).letter,
+            2.0,
+            "{ch:?} doesn't join the letter after it, so it is spaced"
+        );
+    }
+}
+
+#[test]
+fn shaped_text_break_lines_matches_rebuild() {
+    let mut env = testenv!();
+
+    let text = "Resizing a container breaks the same shaped text at many widths";
+    let shaped = env.ranged_builder(text).shape(text);
+    let lines = |layout: &Layout<ColorBrush>| {
+        layout
+            .lines()
+            .map(|line| (line.text_range(), line.metrics().advance))
+            .collect::<Vec<_>>()
+    };
+    for width in [60.0, 90.0, 120.0, 200.0, 400.0] {
+        let layout = shaped.break_lines(Some(width));
+        let mut expected = env.ranged_builder(text).build(text);
+        expected.break_all_lines(Some(width));
+        assert_eq!(
+            lines(&layout),
+            lines(&expected),
+            "breaking shaped text at {width} must match a full build"
+        );
+        assert_eq!(layout.height(), expected.height());
+    }
+
+    // The layouts share the shaped text until one of them modifies it.
+    let mut justified = shaped.break_lines(Some(120.0));
+    let other = shaped.break_lines(Some(120.0));
+    assert_eq!(
+        justified.data.clusters.as_ptr(),
+        other.data.clusters.as_ptr(),
+        "the clusters are shared"
+    );
+    let advances = |layout: &Layout<ColorBrush>| {
+        layout
+            .data
+            .clusters
+            .iter()
+            .map(|cluster| cluster.advance)
+            .collect::<Vec<_>>()
+    };
+    justified.align(
+        Some(120.0),
+        Alignment::Justified,
+        AlignmentOptions::default(),
+    );
+    assert_ne!(
+        advances(&justified),
+        advances(&other),
+        "justification changes the advances of spaces"
+    );
+    let expected = env.ranged_builder(text).build(text);
+    assert_eq!(
+        advances(&other),
+        advances(&expected),
+        "justifying one layout must not change the others"
+    );
+}
+
+#[test]
+fn analysis_of_other_text_is_not_reused() {
+    let mut env = testenv!();
+
+    // Each pair has the same length in bytes, and the second has fewer characters.
+    for (analyzed, text) in [
+        ("Hello world", "\u{05D0}\u{05D1}\u{05D2} abcdef"),
+        ("abcd", "a\u{E9}d"),
+    ] {
+        assert_eq!(analyzed.len(), text.len());
+        let analysis = env.layout_context().analyze(analyzed);
+        let mut reused = env
+            .ranged_builder(text)
+            .build_with_analysis(text, &analysis);
+        reused.break_all_lines(None);
+        let mut expected = env.ranged_builder(text).build(text);
+        expected.break_all_lines(None);
+        assert!(
+            reused
+                .paragraph_directions()
+                .eq(expected.paragraph_directions()),
+            "{text:?} is analyzed again"
+        );
+        let runs = |layout: &Layout<ColorBrush>| {
+            layout
+                .lines()
+                .flat_map(|line| line.runs().map(|run| run.text_range()).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(runs(&reused), runs(&expected));
+    }
+}
+
+#[test]
+fn tree_builder_links() {
+    let mut env = testenv!();
+
+    let mut builder = env.tree_builder();
+    builder.push_text("Read ");
+    builder.push_link(7, "the docs");
+    builder.push_text(" first");
+    let (mut layout, text) = builder.build();
+    layout.break_all_lines(None);
+
+    let link = &layout.links()[0];
+    assert_eq!(&text[link.text_range.clone()], "the docs");
+    let center = layout.link_rects(link)[0].center();
+    assert_eq!(
+        layout
+            .link_at_point(center.x as f32, center.y as f32)
+            .map(|link| link.id),
+        Some(7)
+    );
+
+    // Where links overlap, the one pushed first is found wherever it covers the point.
+    let text = "Read the docs first";
+    let mut builder = env.ranged_builder(text);
+    builder.push_link(1, 5..13);
+    builder.push_link(2, 0..19);
+    let mut layout = builder.build(text);
+    layout.break_all_lines(None);
+    let link_at = |range: Range<usize>| {
+        let rect = layout.link_rects(&LinkData {
+            id: 0,
+            text_range: range,
+        })[0];
+        let center = rect.center();
+        layout
+            .link_at_point(center.x as f32, center.y as f32)
+            .map(|link| link.id)
+    };
+    assert_eq!(link_at(9..13), Some(1));
+    assert_eq!(
+        link_at(0..4),
+        Some(2),
+        "the later link is found outside the first"
+    );
+}
@@ -2,7 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use crate::tests::utils::CursorTest;
-use crate::{Cursor, FontContext, LayoutContext};
+use crate::{
+    Affinity, Alignment, AlignmentOptions, CaretMovement, Cursor, FontContext, LayoutContext,
+    Selection, SelectionGeometryOptions, testenv,
+};
 
 #[test]
 fn cursor_previous_visual() {
@@ -29,3 +32,170 @@ fn cursor_next_visual() {
 
     layout.assert_cursor_is_after("ipsum d", cursor);
 }
+
+#[test]
+fn caret_movement() {
+    let mut env = testenv!();
+
+    let text = "ab عرب cd";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+    layout.align(None, Alignment::Start, AlignmentOptions::default());
+
+    let walk = |start: Cursor, step: &dyn Fn(Cursor) -> Cursor| {
+        let mut cursors = vec![start];
+        loop {
+            let next = step(*cursors.last().unwrap());
+            if next == *cursors.last().unwrap() || cursors.len() > 2 * text.len() {
+                break;
+            }
+            cursors.push(next);
+        }
+        cursors
+    };
+    let indices = |cursors: &[Cursor]| cursors.iter().map(Cursor::index).collect::<Vec<_>>();
+    let x = |cursor: &Cursor| cursor.geometry(&layout, 0.0).x0;
+
+    // Logical movement visits every boundary in text order, in either direction.
+    let boundaries: Vec<_> = text
+        .char_indices()
+        .map(|(index, _)| index)
+        .chain([text.len()])
+        .collect();
+    let start = Cursor::from_byte_index(&layout, 0, Affinity::Downstream);
+    let logical = walk(start, &|cursor| {
+        cursor.move_right(&layout, CaretMovement::Logical)
+    });
+    assert_eq!(
+        indices(&logical),
+        boundaries,
+        "right moves forward in the text"
+    );
+    let end = Cursor::from_byte_index(&layout, text.len(), Affinity::Downstream);
+    let logical = walk(end, &|cursor| {
+        cursor.move_left(&layout, CaretMovement::Logical)
+    });
+    let mut reversed = boundaries.clone();
+    reversed.reverse();
+    assert_eq!(
+        indices(&logical),
+        reversed,
+        "left moves backward in the text"
+    );
+
+    // Visual movement moves across the line, crossing into and out of the Arabic run.
+    let start = Cursor::from_point(&layout, 0.0, 1.0);
+    let visual = walk(start, &|cursor| {
+        cursor.move_right(&layout, CaretMovement::Visual)
+    });
+    for pair in visual.windows(2) {
+        assert!(
+            x(&pair[1]) >= x(&pair[0]) - 1e-3,
+            "right must not move the caret left, from {:?} to {:?}",
+            pair[0],
+            pair[1]
+        );
+    }
+    assert!(
+        (x(visual.last().unwrap()) - f64::from(layout.width())).abs() < 1e-3,
+        "right reaches the end of the line"
+    );
+    assert_ne!(
+        indices(&visual),
+        boundaries,
+        "visual order differs from logical order in the Arabic run"
+    );
+    let end = Cursor::from_point(&layout, layout.width(), 1.0);
+    let visual = walk(end, &|cursor| {
+        cursor.move_left(&layout, CaretMovement::Visual)
+    });
+    for pair in visual.windows(2) {
+        assert!(
+            x(&pair[1]) <= x(&pair[0]) + 1e-3,
+            "left must not move the caret right, from {:?} to {:?}",
+            pair[0],
+            pair[1]
+        );
+    }
+    assert!(
+        x(visual.last().unwrap()).abs() < 1e-3,
+        "left reaches the start of the line"
+    );
+
+    // A selection collapses to the logical end of the selected text.
+    let selection = Selection::new(
+        Cursor::from_byte_index(&layout, 5, Affinity::Downstream),
+        Cursor::from_byte_index(&layout, 1, Affinity::Downstream),
+    );
+    let moved = selection.move_right(&layout, CaretMovement::Logical, false);
+    assert!(moved.is_collapsed(), "the selection must collapse");
+    assert_eq!(moved.focus().index(), 5, "right collapses to the end");
+    let moved = selection.move_left(&layout, CaretMovement::Logical, true);
+    assert_eq!(
+        moved.text_range(),
+        0..5,
+        "extending moves the focus back from the start"
+    );
+}
+
+#[test]
+fn selection_caret_and_range() {
+    let mut env = testenv!();
+
+    let text = "hello world";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+
+    let caret = Selection::from_byte_index(&layout, 3, Affinity::Downstream);
+    assert!(caret.is_collapsed());
+    assert!(caret.is_empty());
+    assert_eq!(caret.caret(), Some(caret.focus()));
+    assert!(
+        caret.geometry(&layout).is_empty(),
+        "a caret has no selection highlight"
+    );
+    let rect = caret.caret().unwrap().geometry(&layout, 1.0);
+    assert!(
+        (rect.width() - 1.0).abs() < 1e-3,
+        "the caret is drawn as a single cursor"
+    );
+
+    let range = Selection::new(
+        Cursor::from_byte_index(&layout, 0, Affinity::Downstream),
+        Cursor::from_byte_index(&layout, 5, Affinity::Downstream),
+    );
+    assert!(!range.is_collapsed());
+    assert!(!range.is_empty());
+    assert_eq!(range.caret(), None);
+    assert_eq!(range.geometry(&layout).len(), 1);
+}
+
+#[test]
+fn selection_geometry_merges_vertically() {
+    let mut env = testenv!();
+
+    let text = "x\naaaa\naaaa\naaaa\ny";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout.break_all_lines(None);
+
+    // From the end of the first line to the start of the last, so the three lines
+    // between are selected in full.
+    let selection = Selection::new(
+        Cursor::from_byte_index(&layout, 1, Affinity::Downstream),
+        Cursor::from_byte_index(&layout, text.len() - 1, Affinity::Downstream),
+    );
+    let rects = selection.geometry(&layout);
+    assert_eq!(rects.len(), 4, "one rect per line, got {rects:?}");
+
+    let merged = selection.geometry_with_options(
+        &layout,
+        SelectionGeometryOptions {
+            merge_vertical: true,
+        },
+    );
+    assert_eq!(merged.len(), 2, "the full lines merge, got {merged:?}");
+    assert_eq!(merged[0], rects[0], "the first line has a different extent");
+    let (rect, line_idx) = merged[1];
+    assert_eq!(line_idx, 1, "the merged rect reports its first line");
+    assert_eq!(rect, rects[1].0.union(rects[2].0).union(rects[3].0));
+}
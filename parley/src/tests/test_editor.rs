@@ -1,7 +1,9 @@
 // Copyright 2024 the Parley Authors
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use crate::testenv;
+use super::utils::ColorBrush;
+use crate::testing::EditorFuzzer;
+use crate::{Alignment, Cluster, EditorState, GlyphOverride, Layout, testenv};
 
 // TODO - Use CursorTest API for these tests
 
@@ -39,3 +41,174 @@ fn editor_double_newline() {
     env.driver(&mut editor).select_all();
     env.check_editor_snapshot(&mut editor);
 }
+
+#[test]
+fn editor_fuzz_invariants() {
+    let mut env = testenv!();
+    for seed in 0..8 {
+        let mut editor = env.editor("Hi, all!\nNext");
+        let mut driver = env.driver(&mut editor);
+        if let Err(failure) = EditorFuzzer::new(seed).run(&mut driver, 200) {
+            panic!("{failure}");
+        }
+    }
+}
+
+#[test]
+fn editor_vertical_movement_keeps_column() {
+    let mut env = testenv!();
+    let mut editor = env.editor("A long first line\nShort\nA long first line");
+    env.driver(&mut editor).move_to_byte(12);
+    let (fcx, lcx) = env.contexts();
+    let selection = *editor.raw_selection();
+    let layout = editor.layout(fcx, lcx);
+    let x = selection.focus().geometry(layout, 0.0).x0 as f32;
+    assert_eq!(
+        selection.sticky_x(layout),
+        None,
+        "only vertical movement sets it"
+    );
+
+    env.driver(&mut editor).move_down();
+    assert_eq!(
+        editor.raw_selection().focus().index(),
+        23,
+        "the caret clamps to the end of the short line"
+    );
+    env.driver(&mut editor).move_down();
+    let (fcx, lcx) = env.contexts();
+    let selection = *editor.raw_selection();
+    let layout = editor.layout(fcx, lcx);
+    assert_eq!(selection.focus().index(), 24 + 12, "the column is restored");
+    assert_eq!(selection.sticky_x(layout), Some(x));
+
+    env.driver(&mut editor).move_up();
+    env.driver(&mut editor).move_up();
+    assert_eq!(
+        editor.raw_selection().focus().index(),
+        12,
+        "the column is restored moving up"
+    );
+
+    env.driver(&mut editor).move_left();
+    let (fcx, lcx) = env.contexts();
+    let selection = *editor.raw_selection();
+    let layout = editor.layout(fcx, lcx);
+    assert_eq!(
+        selection.sticky_x(layout),
+        None,
+        "horizontal movement clears the sticky position"
+    );
+}
+
+#[test]
+fn editor_width_changes_do_not_reshape() {
+    let mut env = testenv!();
+
+    let text = "Some text which wraps onto several lines";
+    let mut editor = env.editor(text);
+    editor.set_width(Some(400.0));
+    let (fcx, lcx) = env.contexts();
+    let layout = editor.layout(fcx, lcx);
+    let line_count = layout.len();
+    let first = Cluster::from_byte_index(layout, 0).unwrap();
+    let advance = first.advance();
+
+    // Glyph overrides only take effect when text is shaped, so they reveal any reshaping.
+    lcx.set_glyph_override(
+        'S',
+        GlyphOverride {
+            font: first.run().font().clone(),
+            glyph_id: 0,
+            advance: 2.0,
+        },
+    );
+    let first_advance =
+        |layout: &Layout<ColorBrush>| Cluster::from_byte_index(layout, 0).unwrap().advance();
+    for _ in 0..3 {
+        editor.set_width(Some(60.0));
+        let layout = editor.layout(fcx, lcx);
+        assert!(layout.len() > line_count, "a narrower width wraps the text");
+        assert_eq!(
+            first_advance(layout),
+            advance,
+            "the text must not be shaped again"
+        );
+
+        editor.set_width(Some(400.0));
+        editor.set_alignment(Alignment::Middle);
+        let layout = editor.layout(fcx, lcx);
+        assert_eq!(layout.len(), line_count, "the original width is restored");
+        assert!(
+            layout.get(0).unwrap().metrics().offset > 0.0,
+            "the text must be aligned again"
+        );
+        assert_eq!(
+            first_advance(layout),
+            advance,
+            "the text must not be shaped again"
+        );
+        editor.set_alignment(Alignment::Start);
+    }
+
+    editor.set_text(text);
+    let layout = editor.layout(fcx, lcx);
+    assert_eq!(
+        first_advance(layout),
+        32.0,
+        "changing the text shapes it again"
+    );
+}
+
+#[test]
+fn editor_snapshot_restore() {
+    let mut env = testenv!();
+
+    let mut editor = env.editor("Hello world");
+    env.driver(&mut editor).select_byte_range(0, 5);
+    let state = editor.snapshot();
+    assert_eq!(state.text, "Hello world");
+    assert_eq!((state.anchor, state.focus), (0, 5));
+
+    let mut driver = env.driver(&mut editor);
+    driver.move_to_text_end();
+    driver.insert_or_replace_selection("!");
+    assert_ne!(editor.snapshot(), state, "the editor was mutated");
+
+    let generation = editor.generation();
+    editor.restore(state.clone());
+    assert!(
+        editor.generation() != generation,
+        "restoring nudges the generation"
+    );
+    assert_eq!(editor.raw_text(), "Hello world");
+    assert_eq!(editor.snapshot(), state, "the snapshot is restored");
+
+    env.driver(&mut editor).refresh_layout();
+    assert_eq!(
+        editor.snapshot(),
+        state,
+        "the restored selection survives a relayout"
+    );
+    assert_eq!(editor.selected_text(), Some("Hello"));
+
+    editor.restore(EditorState {
+        text: "h\u{e9}llo".into(),
+        anchor: 2,
+        focus: 100,
+        compose: Some(1..2),
+        ..state
+    });
+    let restored = editor.snapshot();
+    assert_eq!(
+        (restored.anchor, restored.focus),
+        (1, 6),
+        "offsets are clamped to the text and its char boundaries"
+    );
+    assert_eq!(
+        restored.compose, None,
+        "an invalid preedit range is dropped"
+    );
+    env.driver(&mut editor).refresh_layout();
+    assert_eq!(editor.selected_text(), Some("\u{e9}llo"));
+}
@@ -3,7 +3,10 @@
 
 use peniko::color::palette::css;
 
-use crate::{Alignment, AlignmentOptions, OverflowWrap, StyleProperty, WordBreakStrength, testenv};
+use crate::{
+    Alignment, AlignmentOptions, Balance, Layout, OverflowWrap, StyleProperty, WordBreakStrength,
+    testenv,
+};
 
 use super::utils::{ColorBrush, TestEnv};
 
@@ -275,3 +278,206 @@ fn word_break_keep_all() {
     // Jamo decomposed on purpose
     test_text("애기판다 애기판다", "korean_hangul_jamos", 90.0);
 }
+
+#[test]
+fn emoji_sequences_wrap_as_a_unit() {
+    let mut env = testenv!();
+
+    let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}";
+    let sequences = [
+        family,
+        family,
+        "\u{1F1EB}\u{1F1F7}\u{1F1E9}\u{1F1EA}",
+        "1\u{FE0F}\u{20E3}",
+        family,
+    ];
+    let text = sequences.concat();
+    let mut boundaries = vec![0];
+    for sequence in sequences {
+        boundaries.push(boundaries.last().unwrap() + sequence.len());
+    }
+    // The flags are two sequences of two regional indicators each.
+    boundaries.insert(3, boundaries[2] + 8);
+
+    for (overflow_wrap, word_break) in [
+        (OverflowWrap::Anywhere, WordBreakStrength::Normal),
+        (OverflowWrap::Normal, WordBreakStrength::BreakAll),
+        (OverflowWrap::BreakWord, WordBreakStrength::BreakAll),
+    ] {
+        let mut builder = env.ranged_builder(&text);
+        builder.push_default(StyleProperty::OverflowWrap(overflow_wrap));
+        builder.push_default(StyleProperty::WordBreak(word_break));
+        let mut layout = builder.build(&text);
+        let max_width = layout.max_content_width();
+        // Step through widths which land in the middle of the sequences.
+        for step in 1..20 {
+            let width = max_width * step as f32 / 20.0;
+            layout.break_all_lines(Some(width));
+            for line in layout.lines() {
+                let range = line.text_range();
+                assert!(
+                    boundaries.contains(&range.start) && boundaries.contains(&range.end),
+                    "line {range:?} at width {width} with {overflow_wrap:?} and {word_break:?} \
+                     splits an emoji sequence"
+                );
+            }
+        }
+        assert!(
+            layout.min_content_width() >= max_width / (boundaries.len() - 1) as f32 - 0.01,
+            "the minimum content width must fit a whole sequence"
+        );
+    }
+}
+
+#[test]
+fn tiny_max_advance_is_bounded() {
+    let mut env = testenv!();
+
+    let text = "Lines that are far too wide for the container still wrap sensibly.";
+    let mut builder = env.ranged_builder(text);
+    builder.push_default(StyleProperty::OverflowWrap(OverflowWrap::Anywhere));
+    let mut layout = builder.build(text);
+    let is_finite = |layout: &Layout<ColorBrush>| {
+        layout.lines().all(|line| {
+            let metrics = line.metrics();
+            metrics.offset.is_finite()
+                && metrics.baseline.is_finite()
+                && metrics.advance.is_finite()
+        })
+    };
+
+    layout.break_all_lines(Some(1.0));
+    let cluster_count: usize = layout
+        .lines()
+        .flat_map(|line| line.runs())
+        .map(|run| run.clusters().count())
+        .sum();
+    assert!(
+        layout.len() <= cluster_count,
+        "a narrow container produces at most a line per cluster, got {} lines",
+        layout.len()
+    );
+    assert!(!layout.ignored_max_advance(), "a positive advance is used");
+    assert!(is_finite(&layout), "the lines must have finite positions");
+
+    let per_cluster = layout.len();
+    let min_content_width = layout.min_content_width();
+    layout
+        .break_lines()
+        .min_wrap_width(min_content_width)
+        .break_remaining(1.0);
+    assert!(
+        layout.len() < per_cluster,
+        "the minimum wrap width keeps whole words on a line, got {} lines",
+        layout.len()
+    );
+    assert!(is_finite(&layout), "the lines must have finite positions");
+
+    for max_advance in [0.0, -10.0, f32::NAN] {
+        layout.break_all_lines(Some(max_advance));
+        assert_eq!(
+            layout.len(),
+            1,
+            "an advance of {max_advance} is ignored rather than breaking every cluster"
+        );
+        assert!(
+            layout.ignored_max_advance(),
+            "an advance of {max_advance} is reported as ignored"
+        );
+        assert!(is_finite(&layout), "the lines must have finite positions");
+    }
+
+    let min_wrap_width = 100.0;
+    layout
+        .break_lines()
+        .min_wrap_width(min_wrap_width)
+        .break_remaining(1.0);
+    assert!(
+        layout.len() > 1,
+        "the text still wraps at the minimum width"
+    );
+    for line in layout.lines() {
+        let metrics = line.metrics();
+        assert!(
+            metrics.advance - metrics.trailing_whitespace <= min_wrap_width,
+            "lines are broken at the minimum wrap width"
+        );
+    }
+}
+
+#[test]
+fn target_lines_balance_evenly() {
+    let mut env = testenv!();
+
+    let text = "Subscribe to our weekly newsletter";
+    let mut layout = env.ranged_builder(text).build(text);
+    let widths = |layout: &Layout<ColorBrush>| {
+        layout
+            .lines()
+            .map(|line| line.metrics().advance)
+            .collect::<Vec<_>>()
+    };
+    layout.break_all_lines(None);
+    let full_width = layout.width();
+
+    // Greedy breaking leaves a single word on the second line.
+    let max_advance = full_width * 0.9;
+    layout.break_all_lines(Some(max_advance));
+    let greedy = widths(&layout);
+    assert_eq!(greedy.len(), 2, "the title wraps onto two lines");
+
+    layout
+        .break_lines()
+        .target_lines(2, Balance::Even)
+        .break_remaining(max_advance);
+    let balanced = widths(&layout);
+    assert_eq!(balanced.len(), 2, "the title is balanced across two lines");
+    let spread = |widths: &[f32]| (widths[0] - widths[1]).abs();
+    assert!(
+        spread(&balanced) < spread(&greedy),
+        "balanced lines {balanced:?} are more even than greedy lines {greedy:?}"
+    );
+    assert!(
+        balanced[0] < greedy[0],
+        "the first line gives up words to the second"
+    );
+
+    // The target is reached even when the text would fit on one line.
+    layout
+        .break_lines()
+        .target_lines(2, Balance::Even)
+        .break_remaining(full_width * 2.0);
+    assert_eq!(layout.len(), 2, "the text is spread over the target");
+
+    // Text with no break opportunities uses fewer lines.
+    let text = "Newsletter";
+    let mut layout = env.ranged_builder(text).build(text);
+    layout
+        .break_lines()
+        .target_lines(2, Balance::Even)
+        .break_remaining(f32::MAX);
+    assert_eq!(layout.len(), 1, "a single word can't be balanced");
+}
+
+#[test]
+fn break_lines_with_allowed_breaks() {
+    let mut env = testenv!();
+
+    let text = "aaa bbb ccc ddd";
+    let mut layout = env.ranged_builder(text).build(text);
+    let allowed = [4, 12];
+    layout
+        .break_lines()
+        .with_allowed_breaks(&allowed)
+        .break_remaining(10.0);
+
+    let starts = layout
+        .lines()
+        .map(|line| line.text_range().start)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        starts,
+        [0, 4, 12],
+        "lines only break at the allowed offsets, even when they overflow"
+    );
+}
@@ -198,6 +198,14 @@ impl TestEnv {
         builder
     }
 
+    pub(crate) fn layout_context(&mut self) -> &mut LayoutContext<ColorBrush> {
+        &mut self.layout_cx
+    }
+
+    pub(crate) fn contexts(&mut self) -> (&mut FontContext, &mut LayoutContext<ColorBrush>) {
+        (&mut self.font_cx, &mut self.layout_cx)
+    }
+
     pub(crate) fn driver<'a>(
         &'a mut self,
         editor: &'a mut PlainEditor<ColorBrush>,
@@ -7,7 +7,7 @@
 //! Note: Emoji rendering is not currently implemented in this example. See the swash example
 //! if you need emoji rendering.
 
-use crate::{GlyphRun, Layout, PositionedLayoutItem};
+use crate::{GlyphRun, Layout, PositionedLayoutItem, StyleSpan};
 use peniko::kurbo;
 use skrifa::{
     GlyphId, MetadataProvider, OutlineGlyph,
@@ -145,8 +145,6 @@ fn render_glyph_run(glyph_run: &GlyphRun<'_, ColorBrush>, pen: &mut TinySkiaPen<
     // Resolve properties of the GlyphRun
     let mut run_x = glyph_run.offset();
     let run_y = glyph_run.baseline();
-    let style = glyph_run.style();
-    let brush = style.brush;
 
     // Get the "Run" from the "GlyphRun"
     let run = glyph_run.run();
@@ -166,50 +164,71 @@ fn render_glyph_run(glyph_run: &GlyphRun<'_, ColorBrush>, pen: &mut TinySkiaPen<
     let font_ref = ReadFontsRef::from_index(font_collection_ref, font.index).unwrap();
     let outlines = font_ref.outline_glyphs();
 
-    // Iterates over the glyphs in the GlyphRun
-    for glyph in glyph_run.glyphs() {
-        let glyph_x = run_x + glyph.x + padding as f32;
-        let glyph_y = run_y - glyph.y + padding as f32;
-        run_x += glyph.advance;
-
-        let glyph_id = GlyphId::from(glyph.id);
-        if let Some(glyph_outline) = outlines.get(glyph_id) {
-            pen.set_origin(glyph_x, glyph_y);
-            pen.set_color(brush.color);
-            pen.draw_glyph(&glyph_outline, font_size, &normalized_coords);
+    // Iterates over the glyphs in the GlyphRun, drawing each span of glyphs with its own brush
+    let glyphs: Vec<_> = glyph_run.glyphs().collect();
+    for span in glyph_run.style_spans() {
+        for glyph in &glyphs[span.glyph_range.clone()] {
+            let glyph_x = run_x + glyph.x + padding as f32;
+            let glyph_y = run_y - glyph.y + padding as f32;
+            run_x += glyph.advance;
+
+            let glyph_id = GlyphId::from(glyph.id);
+            if let Some(glyph_outline) = outlines.get(glyph_id) {
+                pen.set_origin(glyph_x, glyph_y);
+                pen.set_color(span.style.brush.color);
+                pen.draw_glyph(&glyph_outline, font_size, &normalized_coords);
+            }
         }
     }
 
     // Draw decorations: underline & strikethrough
-    let style = glyph_run.style();
     let run_metrics = run.metrics();
-    if let Some(decoration) = &style.underline {
-        let offset = decoration.offset.unwrap_or(run_metrics.underline_offset);
-        let size = decoration.size.unwrap_or(run_metrics.underline_size);
-        render_decoration(pen, glyph_run, decoration.brush, offset, size, padding);
-    }
-    if let Some(decoration) = &style.strikethrough {
-        let offset = decoration
-            .offset
-            .unwrap_or(run_metrics.strikethrough_offset);
-        let size = decoration.size.unwrap_or(run_metrics.strikethrough_size);
-        render_decoration(pen, glyph_run, decoration.brush, offset, size, padding);
+    for span in glyph_run.style_spans() {
+        if let Some(decoration) = &span.style.underline {
+            let offset = decoration.offset.unwrap_or(run_metrics.underline_offset);
+            let size = decoration.size.unwrap_or(run_metrics.underline_size);
+            render_decoration(
+                pen,
+                glyph_run,
+                &span,
+                decoration.brush,
+                offset,
+                size,
+                padding,
+            );
+        }
+        if let Some(decoration) = &span.style.strikethrough {
+            let offset = decoration
+                .offset
+                .unwrap_or(run_metrics.strikethrough_offset);
+            let size = decoration.size.unwrap_or(run_metrics.strikethrough_size);
+            render_decoration(
+                pen,
+                glyph_run,
+                &span,
+                decoration.brush,
+                offset,
+                size,
+                padding,
+            );
+        }
     }
 }
 
 fn render_decoration(
     pen: &mut TinySkiaPen<'_>,
     glyph_run: &GlyphRun<'_, ColorBrush>,
+    span: &StyleSpan<'_, ColorBrush>,
     brush: ColorBrush,
     offset: f32,
     width: f32,
     padding: u32,
 ) {
     let y = glyph_run.baseline() - offset + padding as f32;
-    let x = glyph_run.offset() + padding as f32;
+    let x = span.offset + padding as f32;
     pen.set_color(brush.color);
     pen.set_origin(x, y);
-    pen.fill_rect(glyph_run.advance(), width);
+    pen.fill_rect(span.advance, width);
 }
 
 struct TinySkiaPen<'a> {
@@ -0,0 +1,169 @@
+// Copyright 2025 the Parley Authors
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Case mapping for [`TextTransform`].
+
+use crate::style::TextTransform;
+use alloc::{string::String, vec::Vec};
+use core::ops::Range;
+use swash::text::cluster::{Boundary, CharInfo, Token};
+use swash::text::{Codepoint as _, Language};
+
+/// Pushes the tokens for shaping `text[range]` to `tokens`, with the characters case
+/// mapped according to `transform` and the `locale` of the text.
+///
+/// Each character is mapped together with the combining marks which follow it, as some
+/// mappings depend on them. The mapped characters keep the source range of the characters
+/// they replace, so that clusters still map onto the source text:
+/// - If there are fewer mapped characters, the last one also covers the rest of the source.
+/// - If there are more, the extra characters have an empty source range at the end, and
+///   join the cluster of the first one.
+pub(crate) fn push_tokens(
+    text: &str,
+    range: Range<usize>,
+    infos: &[(CharInfo, u16)],
+    transform: TextTransform,
+    locale: Option<Language>,
+    tokens: &mut Vec<Token>,
+) {
+    let mut chars = text[range.clone()]
+        .char_indices()
+        .zip(infos)
+        .map(|((offset, ch), (info, style_index))| Token {
+            ch,
+            offset: (range.start + offset) as u32,
+            len: ch.len_utf8() as u8,
+            info: *info,
+            data: *style_index as _,
+        })
+        .peekable();
+    if transform == TextTransform::None {
+        tokens.extend(chars);
+        return;
+    }
+
+    let mut unit = Vec::new();
+    let mut source = String::new();
+    let mut mapped = String::new();
+    while let Some(token) = chars.next() {
+        unit.clear();
+        unit.push(token);
+        while let Some(mark) = chars.next_if(|token| is_combining_mark(token.ch)) {
+            unit.push(mark);
+        }
+        let start = token.offset as usize;
+        let end = unit
+            .last()
+            .map_or(start, |token| token.offset as usize + token.len as usize);
+
+        source.clear();
+        source.extend(unit.iter().map(|token| token.ch));
+        mapped.clear();
+        map_case(&source, transform, locale, &mut mapped);
+        // Mapping each character on its own loses the context of the word, so pick the
+        // final form of sigma here.
+        if transform == TextTransform::Lowercase
+            && token.ch == 'Σ'
+            && mapped.starts_with('σ')
+            && is_final_sigma(text, start, end)
+        {
+            mapped.replace_range(..'σ'.len_utf8(), "ς");
+        }
+        if mapped.is_empty() {
+            tokens.extend_from_slice(&unit);
+            continue;
+        }
+
+        let count = mapped.chars().count();
+        for (index, ch) in mapped.chars().enumerate() {
+            let token = match unit.get(index) {
+                Some(token) => Token {
+                    ch,
+                    len: if index + 1 == count {
+                        (end - token.offset as usize) as u8
+                    } else {
+                        token.len
+                    },
+                    ..*token
+                },
+                None => {
+                    // Letters are given the properties of a combining mark, so that they
+                    // extend the cluster rather than starting a new one with no source text.
+                    let properties = if is_combining_mark(ch) {
+                        ch
+                    } else {
+                        '\u{0308}'
+                    };
+                    Token {
+                        ch,
+                        offset: end as u32,
+                        len: 0,
+                        info: CharInfo::new(properties.properties(), Boundary::None),
+                        data: unit[unit.len() - 1].data,
+                    }
+                }
+            };
+            tokens.push(token);
+        }
+    }
+}
+
+/// Appends the case mapping of `source` to `mapped`, ignoring the locale.
+#[cfg(not(feature = "icu_casemap"))]
+fn map_case(
+    source: &str,
+    transform: TextTransform,
+    _locale: Option<Language>,
+    mapped: &mut String,
+) {
+    match transform {
+        TextTransform::None => mapped.push_str(source),
+        TextTransform::Uppercase => mapped.extend(source.chars().flat_map(char::to_uppercase)),
+        TextTransform::Lowercase => mapped.extend(source.chars().flat_map(char::to_lowercase)),
+    }
+}
+
+/// Appends the case mapping of `source` to `mapped`, tailored to the locale.
+#[cfg(feature = "icu_casemap")]
+fn map_case(source: &str, transform: TextTransform, locale: Option<Language>, mapped: &mut String) {
+    use icu_casemap::CaseMapper;
+    use icu_locid::LanguageIdentifier;
+
+    let language = locale
+        .and_then(|locale| LanguageIdentifier::try_from_bytes(locale.language().as_bytes()).ok())
+        .unwrap_or_default();
+    let mapper = CaseMapper::new();
+    match transform {
+        TextTransform::None => mapped.push_str(source),
+        TextTransform::Uppercase => {
+            mapped.push_str(&mapper.uppercase_to_string(source, &language));
+        }
+        TextTransform::Lowercase => {
+            mapped.push_str(&mapper.lowercase_to_string(source, &language));
+        }
+    }
+}
+
+/// Returns `true` if the capital sigma at `text[start..end]` ends a word, and so lowercases
+/// to the final form `ς`.
+fn is_final_sigma(text: &str, start: usize, end: usize) -> bool {
+    let is_cased = |ch: char| ch.is_lowercase() || ch.is_uppercase();
+    let mut before = text[..start]
+        .chars()
+        .rev()
+        .skip_while(|ch| is_case_ignorable(*ch));
+    let mut after = text[end..].chars().skip_while(|ch| is_case_ignorable(*ch));
+    before.next().is_some_and(is_cased) && !after.next().is_some_and(is_cased)
+}
+
+/// Returns `true` for the combining diacritical marks which case mappings depend on, such
+/// as U+0307 COMBINING DOT ABOVE.
+fn is_combining_mark(ch: char) -> bool {
+    matches!(ch, '\u{0300}'..='\u{036F}')
+}
+
+/// Returns `true` for characters which are skipped when looking for the cased letters
+/// around a sigma.
+fn is_case_ignorable(ch: char) -> bool {
+    is_combining_mark(ch) || matches!(ch, '\'' | '.' | ':' | '\u{00AD}' | '\u{00B7}' | '\u{2019}')
+}
@@ -3,6 +3,11 @@
 
 //! Misc helpers.
 
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
 #[cfg(feature = "libm")]
 #[allow(unused_imports)]
 use core_maths::CoreFloat;
@@ -14,3 +19,99 @@ pub(crate) fn nearly_eq(x: f32, y: f32) -> bool {
 pub(crate) fn nearly_zero(x: f32) -> bool {
     nearly_eq(x, 0.)
 }
+
+/// A 64-bit FNV-1a hasher, whose output doesn't depend on a random seed.
+///
+/// Formatted values can be hashed with `write!`, for types which don't implement `Hash`.
+#[derive(Clone, Debug)]
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl core::hash::Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+impl core::fmt::Write for StableHasher {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        core::hash::Hasher::write(self, s.as_bytes());
+        Ok(())
+    }
+}
+
+/// A value which is shared between clones until one of them is modified.
+///
+/// The layouts broken from a [`ShapedText`](crate::ShapedText) share its shaped text
+/// this way, and only copy it when they modify it, such as when justifying lines.
+#[derive(Clone, Default)]
+pub(crate) struct Shared<T>(Arc<T>);
+
+impl<T> Shared<Vec<T>> {
+    /// Removes all elements, replacing rather than copying them if they are shared.
+    pub(crate) fn clear(&mut self) {
+        match Arc::get_mut(&mut self.0) {
+            Some(vec) => vec.clear(),
+            None => self.0 = Arc::default(),
+        }
+    }
+}
+
+impl Shared<String> {
+    /// Removes the text, replacing rather than copying it if it is shared.
+    pub(crate) fn clear(&mut self) {
+        match Arc::get_mut(&mut self.0) {
+            Some(text) => text.clear(),
+            None => self.0 = Arc::default(),
+        }
+    }
+}
+
+impl<T> From<T> for Shared<T> {
+    fn from(value: T) -> Self {
+        Self(Arc::new(value))
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Clone> DerefMut for Shared<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        Arc::make_mut(&mut self.0)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Shared<Vec<T>> {
+    type Item = &'a T;
+    type IntoIter = core::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<'a, T: Clone> IntoIterator for &'a mut Shared<Vec<T>> {
+    type Item = &'a mut T;
+    type IntoIter = core::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Arc::make_mut(&mut self.0).iter_mut()
+    }
+}